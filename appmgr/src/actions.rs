@@ -1,4 +1,5 @@
 use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
 use std::process::Stdio;
 
 use linear_map::set::LinearSet;
@@ -6,6 +7,7 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Error as IoE
 use yajrc::RpcError;
 
 use crate::apps::DockerStatus;
+use crate::runtime::{Mount, RunConfig};
 
 pub const STATUS_NOT_ALLOWED: i32 = -2;
 pub const INVALID_COMMAND: i32 = -3;
@@ -62,34 +64,26 @@ impl Action {
                 data: None,
             });
         }
+        let runtime = crate::runtime::current();
         let mut cmd = if status == DockerStatus::Running {
-            let mut cmd = tokio::process::Command::new("docker");
-            cmd.arg("exec").arg(&app_id).args(&self.command);
-            cmd
+            runtime.exec(app_id, &self.command)
         } else {
-            let mut cmd = tokio::process::Command::new("docker");
             let entrypoint = self.command.get(0).ok_or_else(|| RpcError {
                 code: INVALID_COMMAND,
                 message: "Command Cannot Be Empty".to_owned(),
                 data: None,
             })?;
-            cmd.arg("run")
-                .arg("--rm")
-                .arg("--name")
-                .arg(format!("{}_{}", app_id, self.id))
-                .arg("--mount")
-                .arg(format!(
-                    "type=bind,src={}/{},dst={}",
-                    crate::VOLUMES,
-                    app_id,
-                    man.mount.display()
-                ))
-                .arg("--entrypoint")
-                .arg(entrypoint)
-                .arg(format!("start9/{}", app_id))
-                .args(&self.command[1..]);
             // TODO: 0.3.0: net, tor, shm
-            cmd
+            runtime.run(&RunConfig {
+                name: format!("{}_{}", app_id, self.id),
+                image: runtime.image_name(app_id),
+                mounts: vec![Mount {
+                    source: Path::new(crate::VOLUMES).join(app_id),
+                    target: man.mount.clone(),
+                }],
+                entrypoint: entrypoint.clone(),
+                args: self.command[1..].to_vec(),
+            })
         };
         cmd.stdout(Stdio::piped());
         cmd.stderr(Stdio::piped());
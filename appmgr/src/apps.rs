@@ -4,6 +4,7 @@ use linear_map::{set::LinearSet, LinearMap};
 use rand::SeedableRng;
 
 use crate::dependencies::AppDependencies;
+use crate::health::{CheckState, HealthStatus};
 use crate::manifest::{Manifest, ManifestLatest};
 use crate::util::Apply;
 use crate::util::{from_yaml_async_reader, PersistencePath, YamlUpdateHandle};
@@ -40,21 +41,45 @@ pub struct AppInfo {
     pub needs_restart: bool,
 }
 
-#[derive(Clone, Debug, serde::Serialize)]
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct AppStatus {
     pub status: DockerStatus,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i64>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub oom_killed: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<HealthStatus>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "LinearMap::is_empty")]
+    pub health_checks: LinearMap<String, CheckState>,
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct AppConfig {
     pub spec: crate::config::ConfigSpec,
     pub rules: Vec<crate::config::ConfigRuleEntry>,
     pub config: Option<crate::config::Config>,
 }
+impl AppConfig {
+    /// Replaces every secret in the config with `config::secret::REDACTED`.
+    pub fn redact(&mut self) {
+        self.config = self
+            .config
+            .as_ref()
+            .map(|c| crate::config::secret::redact(&self.spec, c));
+    }
+}
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct AppInfoFull {
     #[serde(flatten)]
@@ -132,25 +157,15 @@ pub async fn remove(id: &str) -> Result<(), failure::Error> {
 }
 
 pub async fn status(id: &str, remap_crashed: bool) -> Result<AppStatus, Error> {
-    let output = std::process::Command::new("docker")
-        .args(&["inspect", id, "--format", "{{.State.Status}}"])
-        .stdout(std::process::Stdio::piped())
-        .stderr(match log::max_level() {
-            log::LevelFilter::Error => std::process::Stdio::null(),
-            _ => std::process::Stdio::inherit(),
-        })
-        .spawn()?
-        .wait_with_output()?;
-    crate::ensure_code!(
-        output.status.success(),
-        crate::error::DOCKER_ERROR,
-        "{}: Docker Error: {}",
-        id,
-        std::str::from_utf8(&output.stderr).no_code()?
-    );
-    let status = std::str::from_utf8(&output.stdout).no_code()?;
+    let state = crate::runtime::current().inspect(id).await?;
+    let status = state.status.as_str();
+    let (health, health_checks) = if state.running {
+        crate::health::status(id, &state.started_at).await?
+    } else {
+        (None, LinearMap::new())
+    };
     Ok(AppStatus {
-        status: match status.trim() {
+        status: match status {
             "running" => DockerStatus::Running,
             "restarting" => DockerStatus::Restarting,
             "removing" => DockerStatus::Removing,
@@ -172,6 +187,16 @@ pub async fn status(id: &str, remap_crashed: bool) -> Result<AppStatus, Error> {
             "paused" => DockerStatus::Paused,
             _ => Err(format_err!("unknown status: {}", status))?,
         },
+        exit_code: if state.running || state.status == "created" {
+            None
+        } else {
+            Some(state.exit_code)
+        },
+        oom_killed: state.oom_killed,
+        started_at: Some(state.started_at.clone())
+            .filter(|s| !s.is_empty() && !s.starts_with("0001-")),
+        health,
+        health_checks,
     })
 }
 
@@ -213,7 +238,7 @@ pub async fn config(id: &str) -> Result<AppConfig, Error> {
         .apply(OptionFuture::from)
         .await
     {
-        Some(Ok(cfg)) => Some(cfg),
+        Some(Ok(cfg)) => Some(crate::config::secret::unseal(&spec, cfg).await?),
         #[cfg(not(feature = "production"))]
         Some(Err(e)) => return Err(e),
         _ => {
@@ -222,24 +247,22 @@ pub async fn config(id: &str) -> Result<AppConfig, Error> {
                 .join("start9")
                 .join("config.yaml");
             if volume_config.exists() {
-                let cfg_path = config.path();
-                tokio::fs::copy(&volume_config, &cfg_path)
-                    .await
-                    .with_context(|e| {
-                        format!(
-                            "{}: {} -> {}",
-                            e,
-                            volume_config.display(),
-                            cfg_path.display()
-                        )
-                    })
-                    .with_code(crate::error::FILESYSTEM_ERROR)?;
                 let mut f = tokio::fs::File::open(&volume_config)
                     .await
                     .with_context(|e| format!("{}: {}", e, volume_config.display()))
                     .with_code(crate::error::FILESYSTEM_ERROR)?;
                 match from_yaml_async_reader(&mut f).await {
-                    Ok(a) => Some(a),
+                    Ok(cfg) => {
+                        // the volume copy is plaintext, so seal it before saving it back
+                        let mut file = config.write(None).await?;
+                        crate::util::to_yaml_async_writer(
+                            file.as_mut(),
+                            &crate::config::secret::seal(&spec, cfg.clone()).await?,
+                        )
+                        .await?;
+                        file.commit().await?;
+                        Some(cfg)
+                    }
                     #[cfg(not(feature = "production"))]
                     Err(e) => return Err(e),
                     #[cfg(feature = "production")]
@@ -284,6 +307,7 @@ pub async fn info_full(
     with_manifest: bool,
     with_config: bool,
     with_dependencies: bool,
+    reveal: bool,
 ) -> Result<AppInfoFull, Error> {
     Ok(AppInfoFull {
         info: info(id).await?,
@@ -298,7 +322,11 @@ pub async fn info_full(
             None
         },
         config: if with_config {
-            Some(config(id).await?)
+            let mut config = config(id).await?;
+            if !reveal {
+                config.redact();
+            }
+            Some(config)
         } else {
             None
         },
@@ -388,6 +416,7 @@ pub async fn list(
     with_manifest: bool,
     with_config: bool,
     with_dependencies: bool,
+    reveal: bool,
 ) -> Result<LinearMap<String, AppInfoFull>, Error> {
     let info = list_info().await?;
     futures::future::join_all(info.into_iter().map(move |(id, info)| async move {
@@ -404,8 +433,18 @@ pub async fn list(
                 None
             })
             .map(Option::transpose),
-            OptionFuture::from(if with_config { Some(config(&id)) } else { None })
-                .map(Option::transpose),
+            OptionFuture::from(if with_config {
+                Some(async {
+                    let mut config = config(&id).await?;
+                    if !reveal {
+                        config.redact();
+                    }
+                    Ok::<_, Error>(config)
+                })
+            } else {
+                None
+            })
+            .map(Option::transpose),
             OptionFuture::from(if with_dependencies {
                 Some(dependencies(&id, true))
             } else {
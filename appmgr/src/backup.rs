@@ -1,5 +1,6 @@
+use std::future::Future;
 use std::os::unix::process::ExitStatusExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use argon2::Config;
 use emver::Version;
@@ -23,10 +24,74 @@ pub struct Metadata {
     pub os_version: &'static Version,
 }
 
+async fn dir_size(path: PathBuf) -> u64 {
+    fn walk(path: &Path) -> u64 {
+        match std::fs::symlink_metadata(path) {
+            Ok(m) if m.is_dir() => std::fs::read_dir(path)
+                .map(|entries| entries.flatten().map(|e| walk(&e.path())).sum())
+                .unwrap_or(0),
+            Ok(m) => m.len(),
+            Err(_) => 0,
+        }
+    }
+    tokio::task::spawn_blocking(move || walk(&path))
+        .await
+        .unwrap_or(0)
+}
+
+/// Runs `f`, reporting how much has been written to `dst` so far out of `total`.
+/// Duplicity compresses backups, so the counts are an estimate.
+async fn watch<F: Future>(
+    app_id: &str,
+    phase: crate::progress::Phase,
+    dst: &Path,
+    total: u64,
+    f: F,
+) -> F::Output {
+    let mut reporter = crate::progress::Reporter::new(Some(app_id), phase, Some(total));
+    let mut interval = tokio::time::interval(crate::progress::INTERVAL);
+    tokio::pin!(f);
+    loop {
+        tokio::select! {
+            res = &mut f => {
+                reporter.report(dir_size(dst.to_owned()).await, true).await;
+                return res;
+            }
+            _ = interval.tick() => reporter.report(dir_size(dst.to_owned()).await, false).await,
+        }
+    }
+}
+
+async fn report_result(app_id: &str, res: &Result<(), Error>) {
+    match res {
+        Ok(()) => {
+            crate::progress::Event::new(Some(app_id), crate::progress::Phase::Complete)
+                .emit()
+                .await
+        }
+        Err(e) => {
+            crate::progress::Event::new(Some(app_id), crate::progress::Phase::Failed)
+                .message(e)
+                .emit()
+                .await
+        }
+    }
+}
+
 pub async fn create_backup<P: AsRef<Path>>(
     path: P,
     app_id: &str,
     password: &str,
+) -> Result<(), Error> {
+    let res = create_backup_inner(path, app_id, password).await;
+    report_result(app_id, &res).await;
+    res
+}
+
+async fn create_backup_inner<P: AsRef<Path>>(
+    path: P,
+    app_id: &str,
+    password: &str,
 ) -> Result<(), Error> {
     let path = tokio::fs::canonicalize(path).await?;
     crate::ensure_code!(
@@ -107,12 +172,19 @@ pub async fn create_backup<P: AsRef<Path>>(
             data_cmd.arg(format!("--exclude={}", volume_path.join(exclude).display()));
         }
     }
-    let data_res = data_cmd
-        .env("PASSPHRASE", password)
-        .arg(volume_path)
-        .arg(format!("file://{}", data_path.display()))
-        .invoke("Duplicity")
-        .await;
+    let total = dir_size(volume_path.clone()).await;
+    let data_res = watch(
+        app_id,
+        crate::progress::Phase::BackingUp,
+        &data_path,
+        total,
+        data_cmd
+            .env("PASSPHRASE", password)
+            .arg(volume_path)
+            .arg(format!("file://{}", data_path.display()))
+            .invoke("Duplicity"),
+    )
+    .await;
     let tor_res = tokio::process::Command::new("duplicity")
         .env("PASSPHRASE", password)
         .arg(hidden_service_path)
@@ -136,6 +208,16 @@ pub async fn restore_backup<P: AsRef<Path>>(
     path: P,
     app_id: &str,
     password: &str,
+) -> Result<(), Error> {
+    let res = restore_backup_inner(path, app_id, password).await;
+    report_result(app_id, &res).await;
+    res
+}
+
+async fn restore_backup_inner<P: AsRef<Path>>(
+    path: P,
+    app_id: &str,
+    password: &str,
 ) -> Result<(), Error> {
     let path = tokio::fs::canonicalize(path).await?;
     crate::ensure_code!(
@@ -185,7 +267,15 @@ pub async fn restore_backup<P: AsRef<Path>>(
         .arg(format!("file://{}", tor_path.display()))
         .arg(&hidden_service_path);
 
-    let (data_output, tor_output) = try_join!(data_cmd.status(), tor_cmd.status())?;
+    let total = dir_size(data_path.clone()).await;
+    let (data_output, tor_output) = watch(
+        app_id,
+        crate::progress::Phase::Restoring,
+        &volume_path,
+        total,
+        async { try_join!(data_cmd.status(), tor_cmd.status()) },
+    )
+    .await?;
     crate::ensure_code!(
         data_output.success(),
         crate::error::GENERAL_ERROR,
@@ -220,7 +310,10 @@ pub async fn restore_backup<P: AsRef<Path>>(
         .join("config.yaml");
     if cfg_path.exists() {
         let cfg = from_yaml_async_reader(tokio::fs::File::open(cfg_path).await?).await?;
-        if let Err(e) = crate::config::configure(app_id, cfg, None, false).await {
+        if let Err(e) =
+            crate::config::configure(app_id, cfg, None, false, crate::config::Trigger::Restore)
+                .await
+        {
             log::warn!("Could not restore backup configuration: {}", e);
         }
     }
@@ -0,0 +1,280 @@
+use std::time::Duration;
+
+use linear_map::LinearMap;
+
+use super::secret;
+use super::value::{Config, Value};
+use super::ConfigurationRes;
+use crate::util::{from_yaml_async_reader, PersistencePath, YamlUpdateHandle};
+use crate::Error;
+use crate::ResultExt as _;
+
+/// How many revisions are kept for each app.
+pub const HISTORY_LIMIT: usize = 100;
+
+/// What caused an app to be configured.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "kebab-case")]
+pub enum Trigger {
+    User,
+    Install,
+    Start,
+    Restore,
+    AutoConfigure {
+        dependent: String,
+    },
+    /// Reconfigured because the config of an app it depends on changed.
+    Dependency {
+        id: String,
+    },
+    Rollback {
+        rev: u64,
+    },
+}
+impl std::fmt::Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trigger::User => write!(f, "user"),
+            Trigger::Install => write!(f, "install"),
+            Trigger::Start => write!(f, "start"),
+            Trigger::Restore => write!(f, "restore"),
+            Trigger::AutoConfigure { dependent } => write!(f, "auto-configure for {}", dependent),
+            Trigger::Dependency { id } => write!(f, "dependency {}", id),
+            Trigger::Rollback { rev } => write!(f, "rollback to {}", rev),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Revision {
+    pub rev: u64,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub trigger: Trigger,
+    /// Other apps reconfigured along with this one.
+    pub changed: Vec<String>,
+    pub config: Config,
+    /// The paths of the secrets in `config`, as the spec may have changed since.
+    #[serde(default)]
+    pub secrets: Vec<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Change {
+    pub path: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+fn history_path(id: &str) -> PersistencePath {
+    PersistencePath::from_ref("apps")
+        .join(id)
+        .join("config-history.yaml")
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+async fn load(id: &str) -> Result<Vec<Revision>, Error> {
+    let history: Vec<Revision> = match history_path(id).maybe_read(false).await.transpose()? {
+        Some(mut f) => from_yaml_async_reader(&mut *f).await?,
+        None => return Ok(Vec::new()),
+    };
+    let mut res = Vec::with_capacity(history.len());
+    for mut rev in history {
+        rev.config = secret::unseal_paths(&rev.secrets, rev.config).await?;
+        res.push(rev);
+    }
+    Ok(res)
+}
+
+/// The saved revisions of `id`, with secrets redacted unless `reveal` is set.
+pub async fn history(id: &str, reveal: bool) -> Result<Vec<Revision>, Error> {
+    let mut history = load(id).await?;
+    if !reveal {
+        for rev in &mut history {
+            rev.config = secret::redact_paths(&rev.secrets, &rev.config);
+        }
+    }
+    Ok(history)
+}
+
+pub async fn revision(id: &str, rev: u64) -> Result<Revision, Error> {
+    load(id)
+        .await?
+        .into_iter()
+        .find(|r| r.rev == rev)
+        .ok_or_else(|| format_err!("{} Has No Config Revision {}", id, rev))
+        .with_code(crate::error::NOT_FOUND)
+}
+
+async fn record(
+    id: &str,
+    trigger: Trigger,
+    changed: Vec<String>,
+    config: Config,
+) -> Result<(), Error> {
+    let spec = super::load_spec(id).await?;
+    let secrets = secret::paths(&spec, &config);
+    let config = secret::seal(&spec, config).await?;
+    let mut history = YamlUpdateHandle::<Vec<Revision>>::new_or_default(history_path(id)).await?;
+    let rev = history.last().map(|r| r.rev + 1).unwrap_or(1);
+    history.push(Revision {
+        rev,
+        timestamp: now(),
+        trigger,
+        changed,
+        config,
+        secrets,
+    });
+    let excess = history.len().saturating_sub(HISTORY_LIMIT);
+    history.drain(..excess);
+    history.commit().await
+}
+
+/// Appends a revision for every app a successful configure of `id` changed.
+pub async fn record_all(id: &str, trigger: Trigger, res: &ConfigurationRes) -> Result<(), Error> {
+    for (app, config) in &res.changed {
+        if app == id {
+            let cascaded = res.changed.keys().filter(|a| *a != id).cloned().collect();
+            record(app, trigger.clone(), cascaded, config.clone()).await?;
+        } else {
+            record(
+                app,
+                Trigger::Dependency { id: id.to_owned() },
+                Vec::new(),
+                config.clone(),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+fn diff_value(path: &str, old: Option<&Value>, new: Option<&Value>, res: &mut Vec<Change>) {
+    match (old, new) {
+        (Some(Value::Object(old)), Some(Value::Object(new))) => diff_config(path, old, new, res),
+        (old, new) if old != new => res.push(Change {
+            path: path.to_owned(),
+            old: old.cloned(),
+            new: new.cloned(),
+        }),
+        _ => (),
+    }
+}
+
+fn diff_config(prefix: &str, old: &Config, new: &Config, res: &mut Vec<Change>) {
+    let mut keys: LinearMap<&String, ()> = old.0.keys().map(|k| (k, ())).collect();
+    keys.extend(new.0.keys().map(|k| (k, ())));
+    for key in keys.keys() {
+        let path = if prefix.is_empty() {
+            (*key).clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        diff_value(&path, old.0.get(*key), new.0.get(*key), res);
+    }
+}
+
+/// The changes from `old` to `new`, by dotted path.
+pub fn diff(old: &Config, new: &Config) -> Vec<Change> {
+    let mut res = Vec::new();
+    diff_config("", old, new, &mut res);
+    res
+}
+
+fn lookup<'a>(config: &'a Config, path: &str) -> Option<&'a Value> {
+    let mut segs = path.split('.');
+    let mut value = config.0.get(segs.next()?)?;
+    for seg in segs {
+        value = match value {
+            Value::Object(o) => o.0.get(seg)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// The changes to the config of `id` since revision `rev`, with secrets redacted.
+pub async fn diff_since(id: &str, rev: u64) -> Result<Vec<Change>, Error> {
+    let old = revision(id, rev).await?;
+    let current = crate::apps::config(id).await?;
+    let new = current.config.unwrap_or_default();
+    let mut changes = diff(&old.config, &new);
+    // diff the real values so a changed secret still shows up, then hide them
+    let old = secret::redact_paths(&old.secrets, &old.config);
+    let new = secret::redact(&current.spec, &new);
+    for change in &mut changes {
+        change.old = change.old.as_ref().and(lookup(&old, &change.path).cloned());
+        change.new = change.new.as_ref().and(lookup(&new, &change.path).cloned());
+    }
+    Ok(changes)
+}
+
+/// Configures `id` with its config at revision `rev`, reconfiguring its dependents as usual.
+pub async fn rollback(
+    id: &str,
+    rev: u64,
+    timeout: Option<Duration>,
+    dry_run: bool,
+) -> Result<ConfigurationRes, Error> {
+    let config = revision(id, rev).await?.config;
+    super::configure(
+        id,
+        Some(config),
+        timeout,
+        dry_run,
+        Trigger::Rollback { rev },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nested_diff() {
+        let old: Config =
+            serde_yaml::from_str("rpc:\n  user: bitcoin\n  port: 8332\npeers: [a]\nlisten: true\n")
+                .unwrap();
+        let new: Config =
+            serde_yaml::from_str("rpc:\n  user: bitcoin\n  port: 8333\npeers: [a, b]\ntor: null\n")
+                .unwrap();
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Change {
+                    path: "rpc.port".to_owned(),
+                    old: Some(Value::Number(8332.0)),
+                    new: Some(Value::Number(8333.0)),
+                },
+                Change {
+                    path: "peers".to_owned(),
+                    old: Some(Value::List(vec![Value::String("a".to_owned())])),
+                    new: Some(Value::List(vec![
+                        Value::String("a".to_owned()),
+                        Value::String("b".to_owned())
+                    ])),
+                },
+                Change {
+                    path: "listen".to_owned(),
+                    old: Some(Value::Bool(true)),
+                    new: None,
+                },
+                Change {
+                    path: "tor".to_owned(),
+                    old: None,
+                    new: Some(Value::Null),
+                },
+            ]
+        );
+    }
+}
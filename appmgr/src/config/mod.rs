@@ -14,11 +14,15 @@ use crate::util::PersistencePath;
 use crate::util::{from_yaml_async_reader, to_yaml_async_writer};
 use crate::ResultExt as _;
 
+pub mod history;
 pub mod rules;
+pub mod schema;
+pub mod secret;
 pub mod spec;
 pub mod util;
 pub mod value;
 
+pub use history::Trigger;
 pub use rules::{ConfigRuleEntry, ConfigRuleEntryWithSuggestions};
 pub use spec::{ConfigSpec, Defaultable};
 use util::NumRange;
@@ -106,9 +110,100 @@ pub enum MatchError {
     InvalidKey(String),
     #[fail(display = "Value In List Is Not Unique")]
     ListUniquenessViolation,
+    #[fail(display = "{:?} Is Not A Valid {}", _0, _1)]
+    InvalidFormat(String, &'static str),
+    #[fail(display = "URL Scheme {:?} Is Not One Of {:?}", _0, _1)]
+    InvalidScheme(String, Vec<String>),
+    #[fail(display = "Value Out Of Range: expected {}, actual: {}", _0, _1)]
+    QuantityOutOfRange(String, String),
+    #[fail(display = "Port {} Is Already In Use By {}", _0, _1)]
+    PortConflict(u16, String),
+}
+impl MatchError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MatchError::Pattern(..) => "pattern",
+            MatchError::Enum(..) => "enum",
+            MatchError::NotNullable => "not-nullable",
+            MatchError::LengthMismatch(..) => "length-mismatch",
+            MatchError::InvalidType(..) => "invalid-type",
+            MatchError::OutOfRange(..) => "out-of-range",
+            MatchError::NonIntegral(..) => "non-integral",
+            MatchError::Union(..) => "union",
+            MatchError::MissingTag(..) => "missing-tag",
+            MatchError::PropertyMatchesUnionTag(..) => "property-matches-union-tag",
+            MatchError::PropertyNameMatchesMapTag(..) => "property-name-matches-map-tag",
+            MatchError::InvalidPointer(..) => "invalid-pointer",
+            MatchError::InvalidKey(..) => "invalid-key",
+            MatchError::ListUniquenessViolation => "list-uniqueness-violation",
+            MatchError::InvalidFormat(..) => "invalid-format",
+            MatchError::InvalidScheme(..) => "invalid-scheme",
+            MatchError::QuantityOutOfRange(..) => "out-of-range",
+            MatchError::PortConflict(..) => "port-conflict",
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SpecViolation {
+    pub path: String,
+    pub kind: String,
+    pub message: String,
+}
+impl From<NoMatchWithPath> for SpecViolation {
+    fn from(e: NoMatchWithPath) -> Self {
+        SpecViolation {
+            path: e.path.iter().rev().join("."),
+            kind: e.error.kind().to_owned(),
+            message: format!("{}", e.error),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RuleViolation {
+    pub rule: String,
+    pub description: String,
+}
+
+/// Everything wrong with a config, rather than only the first problem found.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ValidationReport {
+    pub spec: Vec<SpecViolation>,
+    pub rules: Vec<RuleViolation>,
+    /// Rules that depend on pointers, which are not resolved while the config violates its spec.
+    #[serde(default)]
+    pub not_evaluated: Vec<RuleViolation>,
+}
+impl ValidationReport {
+    pub fn is_empty(&self) -> bool {
+        self.spec.is_empty() && self.rules.is_empty()
+    }
+
+    /// The exit code for the report: spec violations take precedence over rule violations.
+    pub fn code(&self) -> Option<i32> {
+        if !self.spec.is_empty() {
+            Some(crate::error::CFG_SPEC_VIOLATION)
+        } else if !self.rules.is_empty() {
+            Some(crate::error::CFG_RULES_VIOLATION)
+        } else {
+            None
+        }
+    }
 }
 
-#[derive(Clone, Debug, Default, serde::Serialize)]
+/// The outcome of `configure --dry-run`: what configuring would do is only known if the report is clean.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DryRunRes {
+    pub report: ValidationReport,
+    pub result: Option<ConfigurationRes>,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct ConfigurationRes {
     pub changed: LinearMap<String, Config>,
@@ -116,12 +211,133 @@ pub struct ConfigurationRes {
     pub stopped: LinearMap<String, TaggedDependencyError>,
 }
 
+impl ConfigurationRes {
+    /// Replaces every secret in the changed configs with `secret::REDACTED`.
+    pub async fn redact(&mut self) -> Result<(), crate::Error> {
+        for (name, config) in self.changed.iter_mut() {
+            *config = secret::redact(&load_spec(name).await?, config);
+        }
+        Ok(())
+    }
+}
+
+async fn load_spec(name: &str) -> Result<ConfigSpec, crate::Error> {
+    from_yaml_async_reader(
+        &mut *PersistencePath::from_ref("apps")
+            .join(name)
+            .join("config_spec.yaml")
+            .read(false)
+            .await?,
+    )
+    .await
+}
+
+async fn load(
+    name: &str,
+) -> Result<(ConfigSpec, Vec<ConfigRuleEntry>, Option<Config>), crate::Error> {
+    let rules_path = PersistencePath::from_ref("apps")
+        .join(name)
+        .join("config_rules.yaml");
+    let config_path = PersistencePath::from_ref("apps")
+        .join(name)
+        .join("config.yaml");
+    let spec = load_spec(name).await?;
+    let rules: Vec<ConfigRuleEntry> =
+        from_yaml_async_reader(&mut *rules_path.read(false).await?).await?;
+    let config: Option<Config> =
+        if let Some(mut f) = config_path.maybe_read(false).await.transpose()? {
+            Some(secret::unseal(&spec, from_yaml_async_reader(&mut *f).await?).await?)
+        } else {
+            None
+        };
+    Ok((spec, rules, config))
+}
+
+/// The ports other apps serve on the LAN, which no port in the config of `name` may use.
+async fn taken_ports(name: &str) -> Result<LinearMap<u16, String>, crate::Error> {
+    let services =
+        crate::tor::services_map(&PersistencePath::from_ref(crate::SERVICES_YAML)).await?;
+    let mut taken = LinearMap::new();
+    for (id, service) in services.map {
+        if id == name {
+            continue;
+        }
+        for mapping in service.ports {
+            if let Some(crate::tor::LanOptions::Custom { port }) = mapping.lan {
+                taken.insert(port, id.clone());
+            }
+        }
+    }
+    Ok(taken)
+}
+
+/// Checks `config`, or the current config of `name`, against the spec and every rule of `name`.
+pub async fn validate(
+    name: &str,
+    config: Option<Config>,
+) -> Result<ValidationReport, crate::Error> {
+    let (spec, rules, old_config) = load(name).await?;
+    let mut config = match config {
+        Some(mut config) => {
+            secret::restore_redacted(&spec, &mut config, old_config.as_ref());
+            config
+        }
+        None => match old_config {
+            Some(config) => config,
+            None => spec
+                .gen(&mut rand::rngs::StdRng::from_entropy(), &None)
+                .with_code(crate::error::CFG_SPEC_VIOLATION)?,
+        },
+    };
+    let mut report = ValidationReport::default();
+    report.spec = spec
+        .matches_all(&config)
+        .into_iter()
+        .map(SpecViolation::from)
+        .collect();
+    report.spec.extend(
+        spec.port_conflicts(&config, &taken_ports(name).await?)
+            .into_iter()
+            .map(SpecViolation::from),
+    );
+    let unresolved = if report.spec.is_empty() {
+        spec.update(&mut config)
+            .await
+            .with_code(crate::error::CFG_SPEC_VIOLATION)?;
+        LinearSet::new()
+    } else {
+        spec.pointer_keys()
+    };
+    let mut cfgs = LinearMap::new();
+    cfgs.insert(name, Cow::Borrowed(&config));
+    for rule in rules {
+        let depends_on_pointer = !unresolved.is_empty()
+            && match rule.rule.keys() {
+                Some(keys) => keys.iter().any(|key| unresolved.contains(key)),
+                None => true,
+            };
+        if depends_on_pointer {
+            report.not_evaluated.push(RuleViolation {
+                rule: rule.rule.src,
+                description: rule.description,
+            });
+        } else if rule.check(&config, &cfgs).is_err() {
+            report.rules.push(RuleViolation {
+                rule: rule.rule.src,
+                description: rule.description,
+            });
+        }
+    }
+    Ok(report)
+}
+
 // returns apps with changed configurations
 pub async fn configure(
     name: &str,
     config: Option<Config>,
     timeout: Option<Duration>,
     dry_run: bool,
+    trigger: Trigger,
 ) -> Result<ConfigurationRes, crate::Error> {
     async fn handle_broken_dependent(
         name: &str,
@@ -166,26 +382,12 @@ pub async fn configure(
                 .ok_or_else(|| failure::format_err!("{} is not installed", name))
                 .with_code(crate::error::NOT_FOUND)?;
             let mut rng = rand::rngs::StdRng::from_entropy();
-            let spec_path = PersistencePath::from_ref("apps")
-                .join(name)
-                .join("config_spec.yaml");
-            let rules_path = PersistencePath::from_ref("apps")
-                .join(name)
-                .join("config_rules.yaml");
             let config_path = PersistencePath::from_ref("apps")
                 .join(name)
                 .join("config.yaml");
-            let spec: ConfigSpec =
-                from_yaml_async_reader(&mut *spec_path.read(false).await?).await?;
-            let rules: Vec<ConfigRuleEntry> =
-                from_yaml_async_reader(&mut *rules_path.read(false).await?).await?;
-            let old_config: Option<Config> =
-                if let Some(mut f) = config_path.maybe_read(false).await.transpose()? {
-                    Some(from_yaml_async_reader(&mut *f).await?)
-                } else {
-                    None
-                };
-            let mut config = if let Some(cfg) = config {
+            let (spec, rules, old_config) = load(name).await?;
+            let mut config = if let Some(mut cfg) = config {
+                secret::restore_redacted(&spec, &mut cfg, old_config.as_ref());
                 cfg
             } else {
                 if let Some(old) = &old_config {
@@ -197,6 +399,13 @@ pub async fn configure(
             };
             spec.matches(&config)
                 .with_code(crate::error::CFG_SPEC_VIOLATION)?;
+            if let Some(conflict) = spec
+                .port_conflicts(&config, &taken_ports(name).await?)
+                .into_iter()
+                .next()
+            {
+                return Err(conflict).with_code(crate::error::CFG_SPEC_VIOLATION);
+            }
             spec.update(&mut config)
                 .await
                 .with_code(crate::error::CFG_SPEC_VIOLATION)?;
@@ -265,23 +474,21 @@ pub async fn configure(
             }
             if !dry_run {
                 let mut file = config_path.write(None).await?;
-                to_yaml_async_writer(file.as_mut(), &config).await?;
+                to_yaml_async_writer(file.as_mut(), &secret::seal(&spec, config.clone()).await?)
+                    .await?;
                 file.commit().await?;
+                // the app reads its secrets from the volume, so only this copy is decrypted
                 let volume_config = Path::new(crate::VOLUMES)
                     .join(name)
                     .join("start9")
                     .join("config.yaml");
-                tokio::fs::copy(config_path.path(), &volume_config)
-                    .await
-                    .with_context(|e| {
-                        format!(
-                            "{}: {} -> {}",
-                            e,
-                            config_path.path().display(),
-                            volume_config.display()
-                        )
-                    })
-                    .with_code(crate::error::FILESYSTEM_ERROR)?;
+                tokio::fs::write(
+                    &volume_config,
+                    serde_yaml::to_vec(&config).with_code(crate::error::SERDE_ERROR)?,
+                )
+                .await
+                .with_context(|e| format!("{}: {}", e, volume_config.display()))
+                .with_code(crate::error::FILESYSTEM_ERROR)?;
                 crate::apps::set_configured(name, true).await?;
                 crate::apps::set_recoverable(name, false).await?;
             }
@@ -298,6 +505,10 @@ pub async fn configure(
     }
     let mut res = ConfigurationRes::default();
     configure_rec(name, config, timeout, dry_run, &mut res).await?;
+    if !dry_run {
+        history::record_all(name, trigger, &res).await?;
+    }
+    res.redact().await?;
     Ok(res)
 }
 
@@ -1,12 +1,12 @@
 use std::borrow::Cow;
 use std::sync::Arc;
 
-use linear_map::LinearMap;
+use linear_map::{set::LinearSet, LinearMap};
 use pest::iterators::Pairs;
 use pest::Parser;
 use rand::SeedableRng;
 
-use super::util::STATIC_NULL;
+use super::util::{ByteSize, HumanDuration, STATIC_NULL};
 use super::value::{Config, Value};
 
 #[derive(Parser)]
@@ -80,6 +80,23 @@ impl std::fmt::Debug for ConfigRule {
             .finish()
     }
 }
+impl ConfigRule {
+    /// The names of the keys the rule refers to, at any depth,
+    /// or `None` if it computes a key from an expression.
+    pub fn keys(&self) -> Option<LinearSet<String>> {
+        let mut keys = LinearSet::new();
+        for pair in RuleParser::parse(Rule::rule, &self.src).ok()?.flatten() {
+            match pair.as_rule() {
+                Rule::sub_ident_regular_base => {
+                    keys.insert(pair.as_str().to_owned());
+                }
+                Rule::sub_ident_regular_expr => return None,
+                _ => (),
+            }
+        }
+        Some(keys)
+    }
+}
 impl<'de> serde::de::Deserialize<'de> for ConfigRule {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -777,9 +794,18 @@ fn compile_num_var(var: Pairs<Rule>) -> CompiledExpr<VarRes<f64>> {
     Box::new(move |cfg, cfgs| {
         var(cfg, cfgs).map(|a| match a {
             Value::Number(n) => n,
+            // durations are read as seconds and byte sizes as bytes
             Value::String(s) => match s.parse() {
                 Ok(n) => n,
-                Err(_) => std::f64::NAN,
+                Err(_) => {
+                    if let Ok(d) = s.parse::<HumanDuration>() {
+                        d.0.as_secs_f64()
+                    } else if let Ok(b) = s.parse::<ByteSize>() {
+                        b.0 as f64
+                    } else {
+                        std::f64::NAN
+                    }
+                }
             },
             Value::Bool(b) => {
                 if b {
@@ -1230,6 +1256,20 @@ mod test {
             .expect("compile failed"))(&cfg, &cfgs));
     }
 
+    #[test]
+    fn test_quantities() {
+        let mut cfg = Config::default();
+        let mut cfgs = LinearMap::new();
+        cfg.0
+            .insert("dbcache".to_owned(), Value::String("2GiB".to_owned()));
+        cfg.0
+            .insert("timeout".to_owned(), Value::String("1h30m".to_owned()));
+        cfgs.insert("my-app", Cow::Borrowed(&cfg));
+        assert!((compile("#dbcache = 2 * 1024 ^ 3 AND #timeout > 5000")
+            .map_err(|e| eprintln!("{}", e))
+            .expect("compile failed"))(&cfg, &cfgs));
+    }
+
     #[test]
     fn test_app_id() {
         let mut dependent_cfg = Config::default();
@@ -1249,4 +1289,18 @@ mod test {
             &dependency_cfg, &cfgs
         ))
     }
+
+    #[test]
+    fn test_keys() {
+        let rule = |src: &str| ConfigRule {
+            src: src.to_owned(),
+            compiled: Arc::new(compile(src).unwrap()),
+        };
+        let keys = rule("rpc.enable? AND #rpc.port > 1024").keys().unwrap();
+        assert_eq!(keys.len(), 3);
+        assert!(keys.contains("rpc") && keys.contains("enable") && keys.contains("port"));
+        let keys = rule("'[lnd].alias = 'alias").keys().unwrap();
+        assert!(keys.contains("lnd") && keys.contains("alias"));
+        assert!(rule("#foo.[\"ba\" + \"r\"] = 3").keys().is_none());
+    }
 }
@@ -0,0 +1,480 @@
+use std::ops::{Bound, RangeBounds};
+
+use serde_json::{json, Map, Value as JsonValue};
+
+use super::spec::{
+    ConfigSpec, DefaultString, IpVersion, ValueSpecAny, ValueSpecList, ValueSpecNumber,
+    ValueSpecObject, ValueSpecPointer, ValueSpecString, ValueSpecUnion, WithDescription,
+};
+use super::util::NumRange;
+
+pub const DRAFT: &'static str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Converts a config spec into a JSON Schema that the configs it matches validate against.
+pub fn json_schema(spec: &ConfigSpec) -> JsonValue {
+    let mut schema = object_schema(spec);
+    schema.insert("$schema".to_owned(), json!(DRAFT));
+    JsonValue::Object(schema)
+}
+
+fn object_schema(spec: &ConfigSpec) -> Map<String, JsonValue> {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for (key, value_spec) in &spec.0 {
+        if !accepts_null(value_spec) {
+            required.push(json!(key));
+        }
+        properties.insert(key.clone(), JsonValue::Object(value_schema(value_spec)));
+    }
+    let mut schema = Map::new();
+    schema.insert("type".to_owned(), json!("object"));
+    schema.insert("properties".to_owned(), JsonValue::Object(properties));
+    schema.insert("required".to_owned(), JsonValue::Array(required));
+    schema
+}
+
+// a missing key is read as null, so only keys that accept null may be left out
+fn accepts_null(spec: &ValueSpecAny) -> bool {
+    match spec {
+        ValueSpecAny::Number(n) => n.inner.inner.nullable,
+        ValueSpecAny::Object(o) => o.inner.nullable,
+        ValueSpecAny::String(s) => s.inner.inner.nullable,
+        ValueSpecAny::Secret(s) => s.inner.inner.nullable,
+        ValueSpecAny::Pointer(_) => true,
+        ValueSpecAny::Port(p) => p.inner.inner.nullable,
+        ValueSpecAny::Ip(i) => i.inner.inner.nullable,
+        ValueSpecAny::Url(u) => u.inner.inner.nullable,
+        ValueSpecAny::Duration(d) => d.inner.inner.nullable,
+        ValueSpecAny::ByteSize(b) => b.inner.inner.nullable,
+        _ => false,
+    }
+}
+
+fn describe<T>(spec: &WithDescription<T>, schema: &mut Map<String, JsonValue>) {
+    schema.insert("title".to_owned(), json!(spec.name));
+    if let Some(description) = &spec.description {
+        schema.insert("description".to_owned(), json!(description));
+    }
+}
+
+fn nullable(schema: &mut Map<String, JsonValue>, nullable: bool) {
+    if nullable {
+        match schema.get_mut("type") {
+            Some(JsonValue::Array(types)) => types.push(json!("null")),
+            Some(ty) => *ty = json!([ty.clone(), "null"]),
+            None => (),
+        }
+    }
+}
+
+fn value_schema(spec: &ValueSpecAny) -> Map<String, JsonValue> {
+    let mut schema = Map::new();
+    match spec {
+        ValueSpecAny::Boolean(b) => {
+            describe(b, &mut schema);
+            schema.insert("type".to_owned(), json!("boolean"));
+        }
+        ValueSpecAny::Enum(e) => {
+            describe(e, &mut schema);
+            schema.extend(enum_schema(&e.inner.inner.values));
+        }
+        ValueSpecAny::List(l) => list_schema(l, &mut schema),
+        ValueSpecAny::Number(n) => {
+            describe(n, &mut schema);
+            schema.extend(number_schema(&n.inner.inner.inner));
+            nullable(&mut schema, n.inner.inner.nullable);
+        }
+        ValueSpecAny::Object(o) => {
+            describe(o, &mut schema);
+            schema.extend(object_schema(&o.inner.inner.spec));
+            nullable(&mut schema, o.inner.nullable);
+        }
+        ValueSpecAny::String(s) => {
+            describe(s, &mut schema);
+            schema.extend(string_schema(&s.inner.inner.inner));
+            nullable(&mut schema, s.inner.inner.nullable);
+        }
+        ValueSpecAny::Secret(s) => {
+            describe(s, &mut schema);
+            schema.extend(string_schema(&s.inner.inner.inner.as_string()));
+            schema.insert("writeOnly".to_owned(), json!(true));
+            nullable(&mut schema, s.inner.inner.nullable);
+        }
+        ValueSpecAny::Union(u) => {
+            describe(u, &mut schema);
+            schema.extend(union_schema(&u.inner.inner));
+        }
+        ValueSpecAny::Pointer(p) => {
+            describe(p, &mut schema);
+            // filled in by appmgr, whatever the value submitted
+            schema.insert("readOnly".to_owned(), json!(true));
+            if let ValueSpecPointer::System(_) = &p.inner {
+                schema.insert("type".to_owned(), json!(["string", "null"]));
+            }
+        }
+        ValueSpecAny::Port(p) => {
+            describe(p, &mut schema);
+            schema.insert("type".to_owned(), json!("integer"));
+            schema.insert("minimum".to_owned(), json!(1));
+            schema.insert("maximum".to_owned(), json!(65535));
+            nullable(&mut schema, p.inner.inner.nullable);
+        }
+        ValueSpecAny::Ip(i) => {
+            describe(i, &mut schema);
+            schema.insert("type".to_owned(), json!("string"));
+            match (i.inner.inner.inner.version, i.inner.inner.inner.cidr) {
+                (Some(IpVersion::V4), false) => schema.insert("format".to_owned(), json!("ipv4")),
+                (Some(IpVersion::V6), false) => schema.insert("format".to_owned(), json!("ipv6")),
+                _ => None,
+            };
+            nullable(&mut schema, i.inner.inner.nullable);
+        }
+        ValueSpecAny::Url(u) => {
+            describe(u, &mut schema);
+            schema.insert("type".to_owned(), json!("string"));
+            schema.insert("format".to_owned(), json!("uri"));
+            nullable(&mut schema, u.inner.inner.nullable);
+        }
+        // ranges and units are checked by appmgr only, plain numbers being seconds or bytes
+        ValueSpecAny::Duration(d) => {
+            describe(d, &mut schema);
+            schema.insert("type".to_owned(), json!(["string", "number"]));
+            nullable(&mut schema, d.inner.inner.nullable);
+        }
+        ValueSpecAny::ByteSize(b) => {
+            describe(b, &mut schema);
+            schema.insert("type".to_owned(), json!(["string", "number"]));
+            nullable(&mut schema, b.inner.inner.nullable);
+        }
+    }
+    if let Some(default) = default_value(spec) {
+        schema.insert("default".to_owned(), default);
+    }
+    schema
+}
+
+fn enum_schema<'a, I: IntoIterator<Item = &'a String>>(values: I) -> Map<String, JsonValue> {
+    let mut schema = Map::new();
+    schema.insert("type".to_owned(), json!("string"));
+    schema.insert(
+        "enum".to_owned(),
+        values.into_iter().map(|v| json!(v)).collect(),
+    );
+    schema
+}
+
+fn number_schema(spec: &ValueSpecNumber) -> Map<String, JsonValue> {
+    let mut schema = Map::new();
+    schema.insert(
+        "type".to_owned(),
+        json!(if spec.integral { "integer" } else { "number" }),
+    );
+    if let Some(range) = &spec.range {
+        match range.start_bound() {
+            Bound::Included(n) => schema.insert("minimum".to_owned(), json!(n)),
+            Bound::Excluded(n) => schema.insert("exclusiveMinimum".to_owned(), json!(n)),
+            Bound::Unbounded => None,
+        };
+        match range.end_bound() {
+            Bound::Included(n) => schema.insert("maximum".to_owned(), json!(n)),
+            Bound::Excluded(n) => schema.insert("exclusiveMaximum".to_owned(), json!(n)),
+            Bound::Unbounded => None,
+        };
+    }
+    schema
+}
+
+fn string_schema(spec: &ValueSpecString) -> Map<String, JsonValue> {
+    let mut schema = Map::new();
+    schema.insert("type".to_owned(), json!("string"));
+    if let Some(pattern) = &spec.pattern {
+        schema.insert("pattern".to_owned(), json!(pattern.pattern.as_str()));
+    }
+    schema
+}
+
+fn union_schema(spec: &ValueSpecUnion) -> Map<String, JsonValue> {
+    let tag = &spec.tag;
+    let variants: Vec<JsonValue> = spec
+        .variants
+        .iter()
+        .map(|(variant, variant_spec)| {
+            let mut schema = object_schema(variant_spec);
+            let mut tag_schema = Map::new();
+            tag_schema.insert("const".to_owned(), json!(variant));
+            if let Some(name) = tag.variant_names.get(variant) {
+                tag_schema.insert("title".to_owned(), json!(name));
+            }
+            if let Some(JsonValue::Object(properties)) = schema.get_mut("properties") {
+                properties.insert(tag.id.clone(), JsonValue::Object(tag_schema));
+            }
+            if let Some(JsonValue::Array(required)) = schema.get_mut("required") {
+                required.insert(0, json!(tag.id));
+            }
+            JsonValue::Object(schema)
+        })
+        .collect();
+    let mut schema = Map::new();
+    schema.insert("type".to_owned(), json!("object"));
+    schema.insert("required".to_owned(), json!([tag.id]));
+    schema.insert("oneOf".to_owned(), JsonValue::Array(variants));
+    schema
+}
+
+fn length_bounds(range: &NumRange<usize>, schema: &mut Map<String, JsonValue>) {
+    match range.start_bound() {
+        Bound::Included(n) => schema.insert("minItems".to_owned(), json!(n)),
+        Bound::Excluded(n) => schema.insert("minItems".to_owned(), json!(n + 1)),
+        Bound::Unbounded => None,
+    };
+    match range.end_bound() {
+        Bound::Included(n) => schema.insert("maxItems".to_owned(), json!(n)),
+        Bound::Excluded(n) => schema.insert("maxItems".to_owned(), json!(n.saturating_sub(1))),
+        Bound::Unbounded => None,
+    };
+}
+
+fn list_schema(spec: &ValueSpecList, schema: &mut Map<String, JsonValue>) {
+    let (range, items, unique) = match spec {
+        ValueSpecList::Enum(l) => {
+            describe(l, schema);
+            let list = &l.inner.inner;
+            (&list.range, enum_schema(&list.spec.values), true)
+        }
+        ValueSpecList::Number(l) => {
+            describe(l, schema);
+            let list = &l.inner.inner;
+            (&list.range, number_schema(&list.spec), true)
+        }
+        ValueSpecList::Object(l) => {
+            describe(l, schema);
+            let list = &l.inner.inner;
+            (&list.range, object_schema(&list.spec.spec), false)
+        }
+        ValueSpecList::String(l) => {
+            describe(l, schema);
+            let list = &l.inner.inner;
+            (&list.range, string_schema(&list.spec), true)
+        }
+        ValueSpecList::Union(l) => {
+            describe(l, schema);
+            let list = &l.inner.inner;
+            (&list.range, union_schema(&list.spec.inner), false)
+        }
+    };
+    schema.insert("type".to_owned(), json!("array"));
+    schema.insert("items".to_owned(), JsonValue::Object(items));
+    length_bounds(range, schema);
+    if unique {
+        schema.insert("uniqueItems".to_owned(), json!(true));
+    }
+}
+
+fn default_string(spec: &Option<DefaultString>) -> Option<JsonValue> {
+    match spec {
+        Some(DefaultString::Literal(s)) => Some(json!(s)),
+        // generated at random when the app is configured
+        Some(DefaultString::Entropy(_)) => None,
+        None => Some(JsonValue::Null),
+    }
+}
+
+fn default_object(spec: &ConfigSpec) -> Option<JsonValue> {
+    let mut object = Map::new();
+    for (key, value_spec) in &spec.0 {
+        match value_spec {
+            ValueSpecAny::Pointer(_) => (),
+            _ => {
+                object.insert(key.clone(), default_value(value_spec)?);
+            }
+        }
+    }
+    Some(JsonValue::Object(object))
+}
+
+fn default_object_spec(spec: &ValueSpecObject) -> Option<JsonValue> {
+    if spec.null_by_default {
+        Some(JsonValue::Null)
+    } else {
+        default_object(&spec.spec)
+    }
+}
+
+fn default_union(spec: &ValueSpecUnion, variant: &str) -> Option<JsonValue> {
+    let mut object = match default_object(spec.variants.get(variant)?)? {
+        JsonValue::Object(object) => object,
+        _ => return None,
+    };
+    object.insert(spec.tag.id.clone(), json!(variant));
+    Some(JsonValue::Object(object))
+}
+
+/// The default an app is configured with, unless it is generated at random.
+fn default_value(spec: &ValueSpecAny) -> Option<JsonValue> {
+    match spec {
+        ValueSpecAny::Boolean(b) => Some(json!(b.inner.default)),
+        ValueSpecAny::Enum(e) => Some(json!(e.inner.default)),
+        ValueSpecAny::List(ValueSpecList::Enum(l)) => Some(json!(l.inner.default)),
+        ValueSpecAny::List(ValueSpecList::Number(l)) => l
+            .inner
+            .default
+            .iter()
+            .map(|n| Some(n.map(|n| json!(n.0)).unwrap_or(JsonValue::Null)))
+            .collect(),
+        ValueSpecAny::List(ValueSpecList::Object(l)) => Some(json!(l.inner.default)),
+        ValueSpecAny::List(ValueSpecList::String(l)) => {
+            l.inner.default.iter().map(default_string).collect()
+        }
+        ValueSpecAny::List(ValueSpecList::Union(l)) => l
+            .inner
+            .default
+            .iter()
+            .map(|variant| default_union(&l.inner.inner.spec.inner, variant))
+            .collect(),
+        ValueSpecAny::Number(n) => Some(
+            n.inner
+                .default
+                .map(|n| json!(n.0))
+                .unwrap_or(JsonValue::Null),
+        ),
+        ValueSpecAny::Object(o) => default_object_spec(&o.inner.inner),
+        ValueSpecAny::String(s) => default_string(&s.inner.default),
+        ValueSpecAny::Secret(s) => default_string(&s.inner.default),
+        ValueSpecAny::Union(u) => default_union(&u.inner.inner, &u.inner.default),
+        ValueSpecAny::Pointer(_) => None,
+        ValueSpecAny::Port(p) => Some(json!(p.inner.default)),
+        ValueSpecAny::Ip(i) => Some(json!(i.inner.default)),
+        ValueSpecAny::Url(u) => Some(json!(u.inner.default)),
+        ValueSpecAny::Duration(d) => Some(json!(d.inner.default)),
+        ValueSpecAny::ByteSize(b) => Some(json!(b.inner.default)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spec_to_schema() {
+        let spec: ConfigSpec = serde_json::from_value(json!({
+            "port": {
+                "name": "Port",
+                "type": "number",
+                "description": null,
+                "nullable": false,
+                "default": 8332,
+                "range": "[1024,65535)",
+                "integral": true
+            },
+            "alias": {
+                "name": "Alias",
+                "type": "string",
+                "description": "A name for the node",
+                "nullable": true,
+                "default": null,
+                "pattern": "^[a-z]+$",
+                "patternDescription": "lowercase letters"
+            },
+            "password": {
+                "name": "Password",
+                "type": "string",
+                "description": null,
+                "nullable": false,
+                "default": {"charset": "a-z", "len": 20}
+            },
+            "peers": {
+                "name": "Peers",
+                "type": "list",
+                "subtype": "string",
+                "description": null,
+                "range": "(0,10]",
+                "spec": {},
+                "default": ["127.0.0.1"]
+            },
+            "timeout": {
+                "name": "Timeout",
+                "type": "duration",
+                "description": null,
+                "nullable": false,
+                "default": "10m",
+                "range": "[1s,1d]"
+            },
+            "dbcache": {
+                "name": "Database Cache",
+                "type": "byte-size",
+                "description": null,
+                "nullable": true,
+                "default": null
+            },
+            "backend": {
+                "name": "Backend",
+                "type": "union",
+                "description": null,
+                "tag": "type",
+                "default": "internal",
+                "variants": {
+                    "internal": {},
+                    "external": {
+                        "url": {
+                            "name": "URL",
+                            "type": "string",
+                            "description": null,
+                            "nullable": false,
+                            "default": "http://localhost"
+                        }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+        let schema = json_schema(&spec);
+        assert_eq!(schema["$schema"], json!(DRAFT));
+        // the spec is read from a json object, which orders its keys
+        assert_eq!(
+            schema["required"],
+            json!(["backend", "password", "peers", "port", "timeout"])
+        );
+        let properties = &schema["properties"];
+        assert_eq!(
+            properties["port"],
+            json!({
+                "title": "Port",
+                "type": "integer",
+                "minimum": 1024.0,
+                "exclusiveMaximum": 65535.0,
+                "default": 8332.0
+            })
+        );
+        assert_eq!(
+            properties["alias"],
+            json!({
+                "title": "Alias",
+                "description": "A name for the node",
+                "type": ["string", "null"],
+                "pattern": "^[a-z]+$",
+                "default": null
+            })
+        );
+        assert_eq!(properties["timeout"]["type"], json!(["string", "number"]));
+        assert_eq!(
+            properties["dbcache"]["type"],
+            json!(["string", "number", "null"])
+        );
+        assert!(properties["password"].get("default").is_none());
+        assert_eq!(properties["peers"]["minItems"], json!(1));
+        assert_eq!(properties["peers"]["maxItems"], json!(10));
+        assert_eq!(properties["peers"]["default"], json!(["127.0.0.1"]));
+        assert_eq!(
+            properties["backend"]["oneOf"][0]["properties"]["type"],
+            json!({"const": "external", "title": "external"})
+        );
+        assert_eq!(
+            properties["backend"]["oneOf"][0]["required"],
+            json!(["type", "url"])
+        );
+        assert_eq!(
+            properties["backend"]["default"],
+            json!({"type": "internal"})
+        );
+    }
+}
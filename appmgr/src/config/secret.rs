@@ -0,0 +1,367 @@
+use failure::ResultExt as _;
+use openssl::hash::MessageDigest;
+use openssl::symm::Cipher;
+
+use super::spec::{ConfigSpec, ValueSpecAny, ValueSpecList, ValueSpecUnion};
+use super::value::{Config, Value};
+use crate::Error;
+use crate::ResultExt as _;
+
+/// Marks a secret encrypted at rest. What follows is the base64 of the nonce, tag and ciphertext.
+pub const PREFIX: &'static str = "encrypted:";
+/// Shown in place of a secret. Configuring a secret with it keeps the value already saved.
+pub const REDACTED: &'static str = "********";
+
+const SALT: &'static [u8] = b"appmgr config secrets";
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+type Key = [u8; 32];
+
+async fn device_key() -> Result<Key, Error> {
+    let product_key = tokio::fs::read_to_string(crate::PRODUCT_KEY)
+        .await
+        .with_context(|e| format!("{}: {}", crate::PRODUCT_KEY, e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    let mut key = [0; 32];
+    openssl::pkcs5::pbkdf2_hmac(
+        product_key
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .as_bytes(),
+        SALT,
+        10000,
+        MessageDigest::sha256(),
+        &mut key,
+    )
+    .with_code(crate::error::GENERAL_ERROR)?;
+    Ok(key)
+}
+
+fn encrypt(key: &Key, plaintext: &str) -> Result<String, Error> {
+    let mut nonce = [0; NONCE_LEN];
+    openssl::rand::rand_bytes(&mut nonce).with_code(crate::error::GENERAL_ERROR)?;
+    let mut tag = [0; TAG_LEN];
+    let ciphertext = openssl::symm::encrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&nonce),
+        &[],
+        plaintext.as_bytes(),
+        &mut tag,
+    )
+    .with_code(crate::error::GENERAL_ERROR)?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&tag);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(format!(
+        "{}{}",
+        PREFIX,
+        openssl::base64::encode_block(&sealed)
+    ))
+}
+
+// the error never includes the value, only that it could not be read
+fn decrypt(key: &Key, sealed: &str) -> Result<String, Error> {
+    let invalid = || format_err!("Could Not Decrypt Secret: the device key may have changed");
+    let sealed = openssl::base64::decode_block(&sealed[PREFIX.len()..])
+        .map_err(|_| invalid())
+        .with_code(crate::error::GENERAL_ERROR)?;
+    crate::ensure_code!(
+        sealed.len() >= NONCE_LEN + TAG_LEN,
+        crate::error::GENERAL_ERROR,
+        "Could Not Decrypt Secret: value is truncated"
+    );
+    let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+    let (tag, ciphertext) = sealed.split_at(TAG_LEN);
+    let plaintext = openssl::symm::decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(nonce),
+        &[],
+        ciphertext,
+        tag,
+    )
+    .map_err(|_| invalid())
+    .with_code(crate::error::GENERAL_ERROR)?;
+    String::from_utf8(plaintext)
+        .map_err(|_| invalid())
+        .with_code(crate::error::GENERAL_ERROR)
+}
+
+fn visit_union<E, F>(
+    spec: &ValueSpecUnion,
+    config: &mut Config,
+    path: &mut Vec<String>,
+    f: &mut F,
+) -> Result<(), E>
+where
+    F: FnMut(&[String], &mut String) -> Result<(), E>,
+{
+    let variant = match config.0.get(&spec.tag.id) {
+        Some(Value::String(tag)) => spec.variants.get(tag),
+        _ => None,
+    };
+    match variant {
+        Some(variant) => visit(variant, config, path, f),
+        None => Ok(()),
+    }
+}
+
+fn visit_value<E, F>(
+    spec: &ValueSpecAny,
+    value: &mut Value,
+    path: &mut Vec<String>,
+    f: &mut F,
+) -> Result<(), E>
+where
+    F: FnMut(&[String], &mut String) -> Result<(), E>,
+{
+    match (spec, value) {
+        (ValueSpecAny::Secret(_), Value::String(s)) => f(path, s),
+        (ValueSpecAny::Object(o), Value::Object(config)) => {
+            visit(&o.inner.inner.spec, config, path, f)
+        }
+        (ValueSpecAny::Union(u), Value::Object(config)) => {
+            visit_union(&u.inner.inner, config, path, f)
+        }
+        (ValueSpecAny::List(list), Value::List(values)) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                path.push(format!("{}", i));
+                match (list, value) {
+                    (ValueSpecList::Object(l), Value::Object(config)) => {
+                        visit(&l.inner.inner.spec.spec, config, path, f)?
+                    }
+                    (ValueSpecList::Union(l), Value::Object(config)) => {
+                        visit_union(&l.inner.inner.spec.inner, config, path, f)?
+                    }
+                    _ => (),
+                }
+                path.pop();
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+// calls f with the path and value of every secret in config
+fn visit<E, F>(
+    spec: &ConfigSpec,
+    config: &mut Config,
+    path: &mut Vec<String>,
+    f: &mut F,
+) -> Result<(), E>
+where
+    F: FnMut(&[String], &mut String) -> Result<(), E>,
+{
+    for (key, value_spec) in spec.0.iter() {
+        if let Some(value) = config.0.get_mut(key) {
+            path.push(key.clone());
+            visit_value(value_spec, value, path, f)?;
+            path.pop();
+        }
+    }
+    Ok(())
+}
+
+fn count(spec: &ConfigSpec, config: &mut Config) -> usize {
+    let mut count = 0;
+    visit(spec, config, &mut Vec::new(), &mut |_, _| {
+        count += 1;
+        Ok(())
+    })
+    .unwrap_or_else(crate::util::absurd);
+    count
+}
+
+fn seal_with(key: &Key, spec: &ConfigSpec, config: &mut Config) -> Result<(), Error> {
+    visit(spec, config, &mut Vec::new(), &mut |_, s| {
+        *s = encrypt(key, s)?;
+        Ok(())
+    })
+}
+
+fn is_sealed(spec: &ConfigSpec, config: &mut Config) -> bool {
+    let mut sealed = false;
+    visit(spec, config, &mut Vec::new(), &mut |_, s| {
+        sealed |= s.starts_with(PREFIX);
+        Ok(())
+    })
+    .unwrap_or_else(crate::util::absurd);
+    sealed
+}
+
+fn unseal_with(key: &Key, spec: &ConfigSpec, config: &mut Config) -> Result<(), Error> {
+    visit(spec, config, &mut Vec::new(), &mut |_, s| {
+        if s.starts_with(PREFIX) {
+            *s = decrypt(key, s)?;
+        }
+        Ok(())
+    })
+}
+
+/// Encrypts every secret in `config`, for writing it to disk.
+pub async fn seal(spec: &ConfigSpec, mut config: Config) -> Result<Config, Error> {
+    if count(spec, &mut config) > 0 {
+        seal_with(&device_key().await?, spec, &mut config)?;
+    }
+    Ok(config)
+}
+
+/// Decrypts every secret in `config`, as read from disk.
+pub async fn unseal(spec: &ConfigSpec, mut config: Config) -> Result<Config, Error> {
+    if is_sealed(spec, &mut config) {
+        unseal_with(&device_key().await?, spec, &mut config)?;
+    }
+    Ok(config)
+}
+
+/// Replaces every secret in `config` with `REDACTED`.
+pub fn redact(spec: &ConfigSpec, config: &Config) -> Config {
+    let mut config = config.clone();
+    visit(spec, &mut config, &mut Vec::new(), &mut |_, s| {
+        *s = REDACTED.to_owned();
+        Ok(())
+    })
+    .unwrap_or_else(crate::util::absurd);
+    config
+}
+
+fn get<'a>(config: &'a Config, path: &[String]) -> Option<&'a Value> {
+    let (first, rest) = path.split_first()?;
+    let mut value = config.0.get(first)?;
+    for seg in rest {
+        value = match value {
+            Value::Object(o) => o.0.get(seg)?,
+            Value::List(l) => l.get(seg.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+fn get_mut<'a>(config: &'a mut Config, path: &[String]) -> Option<&'a mut Value> {
+    let (first, rest) = path.split_first()?;
+    let mut value = config.0.get_mut(first)?;
+    for seg in rest {
+        value = match value {
+            Value::Object(o) => o.0.get_mut(seg)?,
+            Value::List(l) => l.get_mut(seg.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// The paths of the secrets in `config`, for finding them after `spec` has changed.
+pub fn paths(spec: &ConfigSpec, config: &Config) -> Vec<Vec<String>> {
+    let mut config = config.clone();
+    let mut paths = Vec::new();
+    visit(spec, &mut config, &mut Vec::new(), &mut |path, _| {
+        paths.push(path.to_vec());
+        Ok(())
+    })
+    .unwrap_or_else(crate::util::absurd);
+    paths
+}
+
+/// Like `unseal`, for the secrets at `paths`.
+pub async fn unseal_paths(paths: &[Vec<String>], mut config: Config) -> Result<Config, Error> {
+    let mut key = None;
+    for path in paths {
+        if let Some(Value::String(s)) = get_mut(&mut config, path) {
+            if s.starts_with(PREFIX) {
+                if key.is_none() {
+                    key = Some(device_key().await?);
+                }
+                *s = decrypt(key.as_ref().unwrap(), s)?;
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// Like `redact`, for the secrets at `paths`.
+pub fn redact_paths(paths: &[Vec<String>], config: &Config) -> Config {
+    let mut config = config.clone();
+    for path in paths {
+        if let Some(Value::String(s)) = get_mut(&mut config, path) {
+            *s = REDACTED.to_owned();
+        }
+    }
+    config
+}
+
+/// Puts back the saved value of every secret in `config` that was submitted as `REDACTED`.
+pub fn restore_redacted(spec: &ConfigSpec, config: &mut Config, old: Option<&Config>) {
+    visit(spec, config, &mut Vec::new(), &mut |path, s| {
+        if s == REDACTED {
+            if let Some(Value::String(saved)) = old.and_then(|old| get(old, path)) {
+                *s = saved.clone();
+            }
+        }
+        Ok(())
+    })
+    .unwrap_or_else(crate::util::absurd)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seal_unseal_redact() {
+        let spec: ConfigSpec = serde_yaml::from_str(
+            "rpc:\n  name: RPC\n  type: object\n  description: null\n  nullable: false\n  spec:\n    user:\n      name: User\n      type: string\n      description: null\n      nullable: false\n      default: bitcoin\n    password:\n      name: Password\n      type: secret\n      description: null\n      nullable: false\n      default:\n        charset: a-z\n        len: 20\n",
+        )
+        .unwrap();
+        let config: Config =
+            serde_yaml::from_str("rpc:\n  user: bitcoin\n  password: hunter2\n").unwrap();
+        let key = [7; 32];
+
+        let mut sealed = config.clone();
+        seal_with(&key, &spec, &mut sealed).unwrap();
+        let password = get(&sealed, &["rpc".to_owned(), "password".to_owned()]).unwrap();
+        match password {
+            Value::String(s) => assert!(s.starts_with(PREFIX) && !s.contains("hunter2")),
+            _ => panic!("secret is not a string"),
+        }
+        assert_eq!(
+            get(&sealed, &["rpc".to_owned(), "user".to_owned()]),
+            Some(&Value::String("bitcoin".to_owned()))
+        );
+
+        let mut unsealed = sealed.clone();
+        assert!(is_sealed(&spec, &mut unsealed));
+        unseal_with(&key, &spec, &mut unsealed).unwrap();
+        assert_eq!(unsealed, config);
+        assert!(unseal_with(&[8; 32], &spec, &mut sealed.clone()).is_err());
+
+        // only secrets are decrypted, whatever other strings look like
+        let mut plain: Config =
+            serde_yaml::from_str("rpc:\n  user: \"encrypted:bitcoin\"\n  password: hunter2\n")
+                .unwrap();
+        assert!(!is_sealed(&spec, &mut plain));
+        seal_with(&key, &spec, &mut plain).unwrap();
+        unseal_with(&key, &spec, &mut plain).unwrap();
+        assert_eq!(
+            get(&plain, &["rpc".to_owned(), "user".to_owned()]),
+            Some(&Value::String("encrypted:bitcoin".to_owned()))
+        );
+
+        let mut redacted = redact(&spec, &config);
+        assert_eq!(
+            get(&redacted, &["rpc".to_owned(), "password".to_owned()]),
+            Some(&Value::String(REDACTED.to_owned()))
+        );
+        restore_redacted(&spec, &mut redacted, Some(&config));
+        assert_eq!(redacted, config);
+
+        let secrets = paths(&spec, &config);
+        assert_eq!(secrets, vec![vec!["rpc".to_owned(), "password".to_owned()]]);
+        assert_eq!(redact_paths(&secrets, &config), redact(&spec, &config));
+    }
+}
@@ -11,7 +11,7 @@ use linear_map::{set::LinearSet, LinearMap};
 use rand::{CryptoRng, Rng};
 use regex::Regex;
 
-use super::util::{self, CharSet, NumRange, UniqueBy, STATIC_NULL};
+use super::util::{self, ByteSize, CharSet, HumanDuration, NumRange, UniqueBy, STATIC_NULL};
 use super::value::{Config, Value};
 use super::{MatchError, NoMatchWithPath, TimeoutError};
 
@@ -25,6 +25,10 @@ pub trait ValueSpec {
     // This function defines whether the value supplied in the argument is
     // consistent with the spec in &self
     fn matches(&self, value: &Value) -> Result<(), NoMatchWithPath>;
+    // Like matches, but collects every mismatch instead of stopping at the first
+    fn matches_all(&self, value: &Value) -> Vec<NoMatchWithPath> {
+        self.matches(value).err().into_iter().collect()
+    }
     // This function checks whether the value spec is consistent with itself,
     // since not all invariants can be checked by the type
     fn validate(&self, manifest: &ManifestLatest) -> Result<(), NoMatchWithPath>;
@@ -133,6 +137,9 @@ where
     fn matches(&self, value: &Value) -> Result<(), NoMatchWithPath> {
         self.inner.matches(value)
     }
+    fn matches_all(&self, value: &Value) -> Vec<NoMatchWithPath> {
+        self.inner.matches_all(value)
+    }
     fn validate(&self, manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
         self.inner.validate(manifest)
     }
@@ -165,6 +172,12 @@ where
             _ => self.inner.matches(value),
         }
     }
+    fn matches_all(&self, value: &Value) -> Vec<NoMatchWithPath> {
+        match (self.nullable, value) {
+            (true, &Value::Null) => Vec::new(),
+            _ => self.inner.matches_all(value),
+        }
+    }
     fn validate(&self, manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
         self.inner.validate(manifest)
     }
@@ -230,6 +243,9 @@ where
     fn matches(&self, value: &Value) -> Result<(), NoMatchWithPath> {
         self.inner.matches(value)
     }
+    fn matches_all(&self, value: &Value) -> Vec<NoMatchWithPath> {
+        self.inner.matches_all(value)
+    }
     fn validate(&self, manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
         self.inner.validate(manifest)
     }
@@ -288,6 +304,12 @@ pub enum ValueSpecAny {
     String(WithDescription<WithDefault<WithNullable<ValueSpecString>>>),
     Union(WithDescription<WithDefault<ValueSpecUnion>>),
     Pointer(WithDescription<ValueSpecPointer>),
+    Port(WithDescription<WithDefault<WithNullable<ValueSpecPort>>>),
+    Ip(WithDescription<WithDefault<WithNullable<ValueSpecIp>>>),
+    Url(WithDescription<WithDefault<WithNullable<ValueSpecUrl>>>),
+    Duration(WithDescription<WithDefault<WithNullable<ValueSpecDuration>>>),
+    ByteSize(WithDescription<WithDefault<WithNullable<ValueSpecByteSize>>>),
+    Secret(WithDescription<WithDefault<WithNullable<ValueSpecSecret>>>),
 }
 impl ValueSpecAny {
     pub fn name<'a>(&'a self) -> &'a str {
@@ -306,6 +328,12 @@ impl ValueSpecAny {
             ValueSpecAny::Pointer(p) => p.name.as_str(),
             ValueSpecAny::String(s) => s.name.as_str(),
             ValueSpecAny::Union(u) => u.name.as_str(),
+            ValueSpecAny::Port(p) => p.name.as_str(),
+            ValueSpecAny::Ip(i) => i.name.as_str(),
+            ValueSpecAny::Url(u) => u.name.as_str(),
+            ValueSpecAny::Duration(d) => d.name.as_str(),
+            ValueSpecAny::ByteSize(b) => b.name.as_str(),
+            ValueSpecAny::Secret(s) => s.name.as_str(),
         }
     }
 }
@@ -321,6 +349,30 @@ impl ValueSpec for ValueSpecAny {
             ValueSpecAny::String(a) => a.matches(value),
             ValueSpecAny::Union(a) => a.matches(value),
             ValueSpecAny::Pointer(a) => a.matches(value),
+            ValueSpecAny::Port(a) => a.matches(value),
+            ValueSpecAny::Ip(a) => a.matches(value),
+            ValueSpecAny::Url(a) => a.matches(value),
+            ValueSpecAny::Duration(a) => a.matches(value),
+            ValueSpecAny::ByteSize(a) => a.matches(value),
+            ValueSpecAny::Secret(a) => a.matches(value),
+        }
+    }
+    fn matches_all(&self, value: &Value) -> Vec<NoMatchWithPath> {
+        match self {
+            ValueSpecAny::Boolean(a) => a.matches_all(value),
+            ValueSpecAny::Enum(a) => a.matches_all(value),
+            ValueSpecAny::List(a) => a.matches_all(value),
+            ValueSpecAny::Number(a) => a.matches_all(value),
+            ValueSpecAny::Object(a) => a.matches_all(value),
+            ValueSpecAny::String(a) => a.matches_all(value),
+            ValueSpecAny::Union(a) => a.matches_all(value),
+            ValueSpecAny::Pointer(a) => a.matches_all(value),
+            ValueSpecAny::Port(a) => a.matches_all(value),
+            ValueSpecAny::Ip(a) => a.matches_all(value),
+            ValueSpecAny::Url(a) => a.matches_all(value),
+            ValueSpecAny::Duration(a) => a.matches_all(value),
+            ValueSpecAny::ByteSize(a) => a.matches_all(value),
+            ValueSpecAny::Secret(a) => a.matches_all(value),
         }
     }
     fn validate(&self, manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
@@ -333,6 +385,12 @@ impl ValueSpec for ValueSpecAny {
             ValueSpecAny::String(a) => a.validate(manifest),
             ValueSpecAny::Union(a) => a.validate(manifest),
             ValueSpecAny::Pointer(a) => a.validate(manifest),
+            ValueSpecAny::Port(a) => a.validate(manifest),
+            ValueSpecAny::Ip(a) => a.validate(manifest),
+            ValueSpecAny::Url(a) => a.validate(manifest),
+            ValueSpecAny::Duration(a) => a.validate(manifest),
+            ValueSpecAny::ByteSize(a) => a.validate(manifest),
+            ValueSpecAny::Secret(a) => a.validate(manifest),
         }
     }
     async fn update(&self, value: &mut Value) -> Result<(), ConfigurationError> {
@@ -345,6 +403,12 @@ impl ValueSpec for ValueSpecAny {
             ValueSpecAny::String(a) => a.update(value).await,
             ValueSpecAny::Union(a) => a.update(value).await,
             ValueSpecAny::Pointer(a) => a.update(value).await,
+            ValueSpecAny::Port(a) => a.update(value).await,
+            ValueSpecAny::Ip(a) => a.update(value).await,
+            ValueSpecAny::Url(a) => a.update(value).await,
+            ValueSpecAny::Duration(a) => a.update(value).await,
+            ValueSpecAny::ByteSize(a) => a.update(value).await,
+            ValueSpecAny::Secret(a) => a.update(value).await,
         }
     }
     fn requires(&self, id: &str, value: &Value) -> bool {
@@ -357,6 +421,12 @@ impl ValueSpec for ValueSpecAny {
             ValueSpecAny::String(a) => a.requires(id, value),
             ValueSpecAny::Union(a) => a.requires(id, value),
             ValueSpecAny::Pointer(a) => a.requires(id, value),
+            ValueSpecAny::Port(a) => a.requires(id, value),
+            ValueSpecAny::Ip(a) => a.requires(id, value),
+            ValueSpecAny::Url(a) => a.requires(id, value),
+            ValueSpecAny::Duration(a) => a.requires(id, value),
+            ValueSpecAny::ByteSize(a) => a.requires(id, value),
+            ValueSpecAny::Secret(a) => a.requires(id, value),
         }
     }
     fn eq(&self, lhs: &Value, rhs: &Value) -> bool {
@@ -369,6 +439,12 @@ impl ValueSpec for ValueSpecAny {
             ValueSpecAny::String(a) => a.eq(lhs, rhs),
             ValueSpecAny::Union(a) => a.eq(lhs, rhs),
             ValueSpecAny::Pointer(a) => a.eq(lhs, rhs),
+            ValueSpecAny::Port(a) => a.eq(lhs, rhs),
+            ValueSpecAny::Ip(a) => a.eq(lhs, rhs),
+            ValueSpecAny::Url(a) => a.eq(lhs, rhs),
+            ValueSpecAny::Duration(a) => a.eq(lhs, rhs),
+            ValueSpecAny::ByteSize(a) => a.eq(lhs, rhs),
+            ValueSpecAny::Secret(a) => a.eq(lhs, rhs),
         }
     }
 }
@@ -389,6 +465,12 @@ impl Defaultable for ValueSpecAny {
             ValueSpecAny::String(a) => a.gen(rng, timeout).map_err(ConfigurationError::from),
             ValueSpecAny::Union(a) => a.gen(rng, timeout),
             ValueSpecAny::Pointer(a) => a.gen(rng, timeout),
+            ValueSpecAny::Port(a) => a.gen(rng, timeout).map_err(crate::util::absurd),
+            ValueSpecAny::Ip(a) => a.gen(rng, timeout).map_err(crate::util::absurd),
+            ValueSpecAny::Url(a) => a.gen(rng, timeout).map_err(crate::util::absurd),
+            ValueSpecAny::Duration(a) => a.gen(rng, timeout).map_err(crate::util::absurd),
+            ValueSpecAny::ByteSize(a) => a.gen(rng, timeout).map_err(crate::util::absurd),
+            ValueSpecAny::Secret(a) => a.gen(rng, timeout).map_err(ConfigurationError::from),
         }
     }
 }
@@ -562,6 +644,37 @@ where
             ))),
         }
     }
+    fn matches_all(&self, value: &Value) -> Vec<NoMatchWithPath> {
+        let l = match value {
+            Value::List(l) => l,
+            _ => return self.matches(value).err().into_iter().collect(),
+        };
+        let mut errors = Vec::new();
+        if !self.range.contains(&l.len()) {
+            errors.push(NoMatchWithPath::new(MatchError::LengthMismatch(
+                self.range.clone(),
+                l.len(),
+            )));
+        }
+        for (i, v) in l.iter().enumerate() {
+            errors.extend(
+                self.spec
+                    .matches_all(v)
+                    .into_iter()
+                    .map(|e| e.prepend(format!("{}", i))),
+            );
+            if l.iter()
+                .enumerate()
+                .any(|(i2, v2)| i != i2 && self.spec.eq(v, v2))
+            {
+                errors.push(
+                    NoMatchWithPath::new(MatchError::ListUniquenessViolation)
+                        .prepend(format!("{}", i)),
+                );
+            }
+        }
+        errors
+    }
     fn validate(&self, manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
         self.spec.validate(manifest)
     }
@@ -649,6 +762,15 @@ impl ValueSpec for ValueSpecList {
             ValueSpecList::Union(a) => a.matches(value),
         }
     }
+    fn matches_all(&self, value: &Value) -> Vec<NoMatchWithPath> {
+        match self {
+            ValueSpecList::Enum(a) => a.matches_all(value),
+            ValueSpecList::Number(a) => a.matches_all(value),
+            ValueSpecList::Object(a) => a.matches_all(value),
+            ValueSpecList::String(a) => a.matches_all(value),
+            ValueSpecList::Union(a) => a.matches_all(value),
+        }
+    }
     fn validate(&self, manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
         match self {
             ValueSpecList::Enum(a) => a.validate(manifest),
@@ -731,11 +853,11 @@ impl Defaultable for ValueSpecList {
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct ValueSpecNumber {
-    range: Option<NumRange<f64>>,
+    pub range: Option<NumRange<f64>>,
     #[serde(default)]
-    integral: bool,
+    pub integral: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
-    units: Option<String>,
+    pub units: Option<String>,
 }
 #[async_trait]
 impl ValueSpec for ValueSpecNumber {
@@ -863,6 +985,12 @@ impl ValueSpec for ValueSpecObject {
             ))),
         }
     }
+    fn matches_all(&self, value: &Value) -> Vec<NoMatchWithPath> {
+        match value {
+            Value::Object(o) => self.spec.matches_all(o),
+            _ => self.matches(value).err().into_iter().collect(),
+        }
+    }
     fn validate(&self, manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
         self.spec.validate(manifest)
     }
@@ -937,6 +1065,37 @@ impl ConfigSpec {
         Ok(())
     }
 
+    pub fn matches_all(&self, value: &Config) -> Vec<NoMatchWithPath> {
+        let mut errors = Vec::new();
+        for (key, val) in self.0.iter() {
+            errors.extend(
+                val.matches_all(value.0.get(key).unwrap_or(&STATIC_NULL))
+                    .into_iter()
+                    .map(|e| e.prepend(key.clone())),
+            );
+        }
+        errors
+    }
+
+    /// The ports in `value` that are in `taken`, a map of ports to the apps using them.
+    pub fn port_conflicts(
+        &self,
+        value: &Config,
+        taken: &LinearMap<u16, String>,
+    ) -> Vec<NoMatchWithPath> {
+        let mut errors = Vec::new();
+        for (key, val) in self.0.iter() {
+            if let Some(v) = value.0.get(key) {
+                errors.extend(
+                    port_conflicts(val, v, taken)
+                        .into_iter()
+                        .map(|e| e.prepend(key.clone())),
+                );
+            }
+        }
+        errors
+    }
+
     pub fn gen<R: Rng + CryptoRng + Sync + Send>(
         &self,
         rng: &mut R,
@@ -981,6 +1140,41 @@ impl ConfigSpec {
             .iter()
             .any(|(k, v)| v.requires(id, cfg.0.get(k).unwrap_or(&STATIC_NULL)))
     }
+
+    /// The keys of every pointer, and of every value containing one, at any depth.
+    pub fn pointer_keys(&self) -> LinearSet<String> {
+        fn pointer_keys_rec(spec: &ConfigSpec, keys: &mut LinearSet<String>) -> bool {
+            let mut found = false;
+            for (key, value_spec) in spec.0.iter() {
+                let specs: Vec<&ConfigSpec> = match value_spec {
+                    ValueSpecAny::Pointer(_) => {
+                        keys.insert(key.clone());
+                        found = true;
+                        continue;
+                    }
+                    ValueSpecAny::Object(o) => vec![&o.inner.inner.spec],
+                    ValueSpecAny::Union(u) => u.inner.inner.variants.values().collect(),
+                    ValueSpecAny::List(ValueSpecList::Object(l)) => vec![&l.inner.inner.spec.spec],
+                    ValueSpecAny::List(ValueSpecList::Union(l)) => {
+                        l.inner.inner.spec.inner.variants.values().collect()
+                    }
+                    _ => Vec::new(),
+                };
+                let mut contains = false;
+                for spec in specs {
+                    contains |= pointer_keys_rec(spec, keys);
+                }
+                if contains {
+                    keys.insert(key.clone());
+                    found = true;
+                }
+            }
+            found
+        }
+        let mut keys = LinearSet::new();
+        pointer_keys_rec(self, &mut keys);
+        keys
+    }
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -1077,6 +1271,61 @@ impl DefaultableWith for ValueSpecString {
     }
 }
 
+// stored encrypted and hidden from output unless asked for, see config::secret
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ValueSpecSecret {
+    #[serde(flatten)]
+    pub pattern: Option<Pattern>,
+    #[serde(default)]
+    pub copyable: bool,
+}
+impl ValueSpecSecret {
+    pub fn as_string(&self) -> ValueSpecString {
+        ValueSpecString {
+            pattern: self.pattern.clone(),
+            copyable: self.copyable,
+            masked: true,
+        }
+    }
+}
+#[async_trait]
+impl ValueSpec for ValueSpecSecret {
+    fn matches(&self, value: &Value) -> Result<(), NoMatchWithPath> {
+        self.as_string().matches(value).map_err(|e| match e.error {
+            MatchError::Pattern(_, pattern) => NoMatchWithPath::new(MatchError::Pattern(
+                super::secret::REDACTED.to_owned(),
+                pattern,
+            )),
+            _ => e,
+        })
+    }
+    fn validate(&self, _manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
+        Ok(())
+    }
+    async fn update(&self, _value: &mut Value) -> Result<(), ConfigurationError> {
+        Ok(())
+    }
+    fn requires(&self, _id: &str, _value: &Value) -> bool {
+        false
+    }
+    fn eq(&self, lhs: &Value, rhs: &Value) -> bool {
+        self.as_string().eq(lhs, rhs)
+    }
+}
+impl DefaultableWith for ValueSpecSecret {
+    type DefaultSpec = Option<DefaultString>;
+    type Error = TimeoutError;
+
+    fn gen_with<R: Rng + CryptoRng + Sync + Send>(
+        &self,
+        spec: &Self::DefaultSpec,
+        rng: &mut R,
+        timeout: &Option<Duration>,
+    ) -> Result<Value, TimeoutError> {
+        self.as_string().gen_with(spec, rng, timeout)
+    }
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 #[serde(untagged)]
 pub enum DefaultString {
@@ -1109,6 +1358,402 @@ impl Entropy {
     }
 }
 
+fn port_conflicts(
+    spec: &ValueSpecAny,
+    value: &Value,
+    taken: &LinearMap<u16, String>,
+) -> Vec<NoMatchWithPath> {
+    fn union_conflicts(
+        spec: &ValueSpecUnion,
+        value: &Config,
+        taken: &LinearMap<u16, String>,
+    ) -> Vec<NoMatchWithPath> {
+        match value.0.get(&spec.tag.id) {
+            Some(Value::String(tag)) => spec
+                .variants
+                .get(tag)
+                .map(|variant| variant.port_conflicts(value, taken))
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+    match (spec, value) {
+        (ValueSpecAny::Port(_), Value::Number(n))
+            if n.fract() == 0.0 && *n >= 1.0 && *n <= 65535.0 =>
+        {
+            taken
+                .get(&(*n as u16))
+                .map(|app| NoMatchWithPath::new(MatchError::PortConflict(*n as u16, app.clone())))
+                .into_iter()
+                .collect()
+        }
+        (ValueSpecAny::Object(o), Value::Object(value)) => {
+            o.inner.inner.spec.port_conflicts(value, taken)
+        }
+        (ValueSpecAny::Union(u), Value::Object(value)) => {
+            union_conflicts(&u.inner.inner, value, taken)
+        }
+        (ValueSpecAny::List(list), Value::List(values)) => values
+            .iter()
+            .enumerate()
+            .flat_map(|(i, v)| {
+                match (list, v) {
+                    (ValueSpecList::Object(l), Value::Object(v)) => {
+                        l.inner.inner.spec.spec.port_conflicts(v, taken)
+                    }
+                    (ValueSpecList::Union(l), Value::Object(v)) => {
+                        union_conflicts(&l.inner.inner.spec.inner, v, taken)
+                    }
+                    _ => Vec::new(),
+                }
+                .into_iter()
+                .map(move |e| e.prepend(format!("{}", i)))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ValueSpecPort {}
+#[async_trait]
+impl ValueSpec for ValueSpecPort {
+    fn matches(&self, value: &Value) -> Result<(), NoMatchWithPath> {
+        match value {
+            Value::Number(n) => {
+                if n.floor() != *n {
+                    return Err(NoMatchWithPath::new(MatchError::NonIntegral(*n)));
+                }
+                let range = NumRange((
+                    std::ops::Bound::Included(1.0),
+                    std::ops::Bound::Included(65535.0),
+                ));
+                if !range.contains(n) {
+                    return Err(NoMatchWithPath::new(MatchError::OutOfRange(range, *n)));
+                }
+                Ok(())
+            }
+            Value::Null => Err(NoMatchWithPath::new(MatchError::NotNullable)),
+            a => Err(NoMatchWithPath::new(MatchError::InvalidType(
+                "number",
+                a.type_of(),
+            ))),
+        }
+    }
+    fn validate(&self, _manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
+        Ok(())
+    }
+    async fn update(&self, _value: &mut Value) -> Result<(), ConfigurationError> {
+        Ok(())
+    }
+    fn requires(&self, _id: &str, _value: &Value) -> bool {
+        false
+    }
+    fn eq(&self, lhs: &Value, rhs: &Value) -> bool {
+        match (lhs, rhs) {
+            (Value::Number(lhs), Value::Number(rhs)) => lhs == rhs,
+            _ => false,
+        }
+    }
+}
+impl DefaultableWith for ValueSpecPort {
+    type DefaultSpec = Option<u16>;
+    type Error = crate::util::Never;
+
+    fn gen_with<R: Rng + CryptoRng + Sync + Send>(
+        &self,
+        spec: &Self::DefaultSpec,
+        _rng: &mut R,
+        _timeout: &Option<Duration>,
+    ) -> Result<Value, Self::Error> {
+        Ok(spec.map(|s| Value::Number(s.into())).unwrap_or(Value::Null))
+    }
+}
+
+fn gen_literal(spec: &Option<String>) -> Value {
+    spec.clone().map(Value::String).unwrap_or(Value::Null)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ValueSpecIp {
+    #[serde(default)]
+    pub version: Option<IpVersion>,
+    // whether the value is a network such as 10.0.0.0/8 rather than a single address
+    #[serde(default)]
+    pub cidr: bool,
+}
+impl ValueSpecIp {
+    fn format(&self) -> &'static str {
+        match (self.version, self.cidr) {
+            (None, false) => "IP Address",
+            (Some(IpVersion::V4), false) => "IPv4 Address",
+            (Some(IpVersion::V6), false) => "IPv6 Address",
+            (None, true) => "CIDR",
+            (Some(IpVersion::V4), true) => "IPv4 CIDR",
+            (Some(IpVersion::V6), true) => "IPv6 CIDR",
+        }
+    }
+    fn parse(&self, s: &str) -> Option<(std::net::IpAddr, Option<u8>)> {
+        let mut split = s.splitn(2, '/');
+        let ip: std::net::IpAddr = split.next()?.parse().ok()?;
+        let prefix = match split.next() {
+            Some(prefix) if self.cidr => {
+                let prefix: u8 = prefix.parse().ok()?;
+                if prefix > if ip.is_ipv4() { 32 } else { 128 } {
+                    return None;
+                }
+                Some(prefix)
+            }
+            None if !self.cidr => None,
+            _ => return None,
+        };
+        match (self.version, ip) {
+            (Some(IpVersion::V4), std::net::IpAddr::V6(_))
+            | (Some(IpVersion::V6), std::net::IpAddr::V4(_)) => None,
+            _ => Some((ip, prefix)),
+        }
+    }
+}
+#[async_trait]
+impl ValueSpec for ValueSpecIp {
+    fn matches(&self, value: &Value) -> Result<(), NoMatchWithPath> {
+        match value {
+            Value::String(s) => match self.parse(s) {
+                Some(_) => Ok(()),
+                None => Err(NoMatchWithPath::new(MatchError::InvalidFormat(
+                    s.clone(),
+                    self.format(),
+                ))),
+            },
+            Value::Null => Err(NoMatchWithPath::new(MatchError::NotNullable)),
+            a => Err(NoMatchWithPath::new(MatchError::InvalidType(
+                "string",
+                a.type_of(),
+            ))),
+        }
+    }
+    fn validate(&self, _manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
+        Ok(())
+    }
+    async fn update(&self, _value: &mut Value) -> Result<(), ConfigurationError> {
+        Ok(())
+    }
+    fn requires(&self, _id: &str, _value: &Value) -> bool {
+        false
+    }
+    fn eq(&self, lhs: &Value, rhs: &Value) -> bool {
+        match (lhs, rhs) {
+            (Value::String(lhs), Value::String(rhs)) => match (self.parse(lhs), self.parse(rhs)) {
+                (Some(lhs), Some(rhs)) => lhs == rhs,
+                _ => lhs == rhs,
+            },
+            _ => false,
+        }
+    }
+}
+impl DefaultableWith for ValueSpecIp {
+    type DefaultSpec = Option<String>;
+    type Error = crate::util::Never;
+
+    fn gen_with<R: Rng + CryptoRng + Sync + Send>(
+        &self,
+        spec: &Self::DefaultSpec,
+        _rng: &mut R,
+        _timeout: &Option<Duration>,
+    ) -> Result<Value, Self::Error> {
+        Ok(gen_literal(spec))
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ValueSpecUrl {
+    // any scheme is allowed if empty
+    #[serde(default)]
+    pub schemes: Vec<String>,
+}
+#[async_trait]
+impl ValueSpec for ValueSpecUrl {
+    fn matches(&self, value: &Value) -> Result<(), NoMatchWithPath> {
+        match value {
+            Value::String(s) => {
+                let url = reqwest::Url::parse(s).map_err(|_| {
+                    NoMatchWithPath::new(MatchError::InvalidFormat(s.clone(), "URL"))
+                })?;
+                if !self.schemes.is_empty()
+                    && !self.schemes.iter().any(|scheme| scheme == url.scheme())
+                {
+                    return Err(NoMatchWithPath::new(MatchError::InvalidScheme(
+                        url.scheme().to_owned(),
+                        self.schemes.clone(),
+                    )));
+                }
+                Ok(())
+            }
+            Value::Null => Err(NoMatchWithPath::new(MatchError::NotNullable)),
+            a => Err(NoMatchWithPath::new(MatchError::InvalidType(
+                "string",
+                a.type_of(),
+            ))),
+        }
+    }
+    fn validate(&self, _manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
+        Ok(())
+    }
+    async fn update(&self, _value: &mut Value) -> Result<(), ConfigurationError> {
+        Ok(())
+    }
+    fn requires(&self, _id: &str, _value: &Value) -> bool {
+        false
+    }
+    fn eq(&self, lhs: &Value, rhs: &Value) -> bool {
+        match (lhs, rhs) {
+            (Value::String(lhs), Value::String(rhs)) => {
+                match (reqwest::Url::parse(lhs), reqwest::Url::parse(rhs)) {
+                    (Ok(lhs), Ok(rhs)) => lhs == rhs,
+                    _ => lhs == rhs,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+impl DefaultableWith for ValueSpecUrl {
+    type DefaultSpec = Option<String>;
+    type Error = crate::util::Never;
+
+    fn gen_with<R: Rng + CryptoRng + Sync + Send>(
+        &self,
+        spec: &Self::DefaultSpec,
+        _rng: &mut R,
+        _timeout: &Option<Duration>,
+    ) -> Result<Value, Self::Error> {
+        Ok(gen_literal(spec))
+    }
+}
+
+// durations and byte sizes are written with units, or as a bare number of seconds or bytes
+fn parse_quantity<T: std::str::FromStr>(
+    value: &Value,
+    format: &'static str,
+) -> Result<T, NoMatchWithPath> {
+    let s = match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => format!("{}", n),
+        Value::Null => return Err(NoMatchWithPath::new(MatchError::NotNullable)),
+        a => {
+            return Err(NoMatchWithPath::new(MatchError::InvalidType(
+                "string",
+                a.type_of(),
+            )))
+        }
+    };
+    s.parse()
+        .map_err(|_| NoMatchWithPath::new(MatchError::InvalidFormat(s, format)))
+}
+
+fn check_quantity<T>(range: &Option<NumRange<T>>, quantity: T) -> Result<(), NoMatchWithPath>
+where
+    T: std::str::FromStr + std::fmt::Display + std::cmp::PartialOrd,
+{
+    match range {
+        Some(range) if !range.contains(&quantity) => Err(NoMatchWithPath::new(
+            MatchError::QuantityOutOfRange(format!("{}", range), format!("{}", quantity)),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ValueSpecDuration {
+    pub range: Option<NumRange<HumanDuration>>,
+}
+#[async_trait]
+impl ValueSpec for ValueSpecDuration {
+    fn matches(&self, value: &Value) -> Result<(), NoMatchWithPath> {
+        check_quantity(&self.range, parse_quantity(value, "Duration")?)
+    }
+    fn validate(&self, _manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
+        Ok(())
+    }
+    async fn update(&self, _value: &mut Value) -> Result<(), ConfigurationError> {
+        Ok(())
+    }
+    fn requires(&self, _id: &str, _value: &Value) -> bool {
+        false
+    }
+    fn eq(&self, lhs: &Value, rhs: &Value) -> bool {
+        match (
+            parse_quantity::<HumanDuration>(lhs, "Duration"),
+            parse_quantity::<HumanDuration>(rhs, "Duration"),
+        ) {
+            (Ok(lhs), Ok(rhs)) => lhs == rhs,
+            _ => false,
+        }
+    }
+}
+impl DefaultableWith for ValueSpecDuration {
+    type DefaultSpec = Option<String>;
+    type Error = crate::util::Never;
+
+    fn gen_with<R: Rng + CryptoRng + Sync + Send>(
+        &self,
+        spec: &Self::DefaultSpec,
+        _rng: &mut R,
+        _timeout: &Option<Duration>,
+    ) -> Result<Value, Self::Error> {
+        Ok(gen_literal(spec))
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ValueSpecByteSize {
+    pub range: Option<NumRange<ByteSize>>,
+}
+#[async_trait]
+impl ValueSpec for ValueSpecByteSize {
+    fn matches(&self, value: &Value) -> Result<(), NoMatchWithPath> {
+        check_quantity(&self.range, parse_quantity(value, "Byte Size")?)
+    }
+    fn validate(&self, _manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
+        Ok(())
+    }
+    async fn update(&self, _value: &mut Value) -> Result<(), ConfigurationError> {
+        Ok(())
+    }
+    fn requires(&self, _id: &str, _value: &Value) -> bool {
+        false
+    }
+    fn eq(&self, lhs: &Value, rhs: &Value) -> bool {
+        match (
+            parse_quantity::<ByteSize>(lhs, "Byte Size"),
+            parse_quantity::<ByteSize>(rhs, "Byte Size"),
+        ) {
+            (Ok(lhs), Ok(rhs)) => lhs == rhs,
+            _ => false,
+        }
+    }
+}
+impl DefaultableWith for ValueSpecByteSize {
+    type DefaultSpec = Option<String>;
+    type Error = crate::util::Never;
+
+    fn gen_with<R: Rng + CryptoRng + Sync + Send>(
+        &self,
+        spec: &Self::DefaultSpec,
+        _rng: &mut R,
+        _timeout: &Option<Duration>,
+    ) -> Result<Value, Self::Error> {
+        Ok(gen_literal(spec))
+    }
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UnionTag {
@@ -1217,6 +1862,22 @@ impl ValueSpec for ValueSpecUnion {
             ))),
         }
     }
+    fn matches_all(&self, value: &Value) -> Vec<NoMatchWithPath> {
+        match value {
+            Value::Object(o) => match o.0.get(&self.tag.id) {
+                Some(Value::String(tag)) => match self.variants.get(tag) {
+                    Some(obj_spec) => {
+                        let mut without_tag = o.clone();
+                        without_tag.0.remove(&self.tag.id);
+                        obj_spec.matches_all(&without_tag)
+                    }
+                    None => self.matches(value).err().into_iter().collect(),
+                },
+                _ => self.matches(value).err().into_iter().collect(),
+            },
+            _ => self.matches(value).err().into_iter().collect(),
+        }
+    }
     fn validate(&self, manifest: &ManifestLatest) -> Result<(), NoMatchWithPath> {
         for (name, variant) in &self.variants {
             if variant.0.get(&self.tag.id).is_some() {
@@ -1840,6 +2501,7 @@ mod test {
                 mount_shared: false,
                 optional: Some("Could be external.".to_owned()),
                 config: Vec::new(),
+                readiness: None,
             },
         );
         spec.validate(&crate::manifest::ManifestV0 {
@@ -1853,6 +2515,8 @@ mod test {
             release_notes: "Some things changed".to_owned(),
             ports: Vec::new(),
             image: crate::manifest::ImageConfig::Tar,
+            layers: LinearMap::new(),
+            thin: false,
             shm_size_mb: None,
             mount: "/root".parse().unwrap(),
             public: None,
@@ -1864,8 +2528,12 @@ mod test {
             hidden_service_version: crate::tor::HiddenServiceVersion::V3,
             dependencies: deps,
             extra: LinearMap::new(),
+            actions: Vec::new(),
+            readiness_probe: None,
+            health_checks: LinearMap::new(),
             install_alert: None,
             restore_alert: None,
+            start_alert: None,
             uninstall_alert: None,
         })
         .unwrap();
@@ -1874,4 +2542,229 @@ mod test {
             .unwrap();
         spec.matches(&config).unwrap();
     }
+
+    #[test]
+    fn pointer_keys() {
+        let spec: ConfigSpec = serde_json::from_value(serde_json::json!({
+            "port": {
+                "name": "Port",
+                "type": "number",
+                "description": null,
+                "nullable": false,
+                "default": 8332,
+                "range": "[1024,65535]",
+                "integral": true
+            },
+            "node": {
+                "name": "Node",
+                "type": "union",
+                "description": null,
+                "default": "internal",
+                "tag": {
+                    "id": "type",
+                    "name": "Type",
+                    "variantNames": {}
+                },
+                "variants": {
+                    "internal": {
+                        "address": {
+                            "name": "Address",
+                            "type": "pointer",
+                            "subtype": "app",
+                            "target": "lan-address",
+                            "app-id": "bitcoind",
+                            "description": null
+                        }
+                    },
+                    "external": {
+                        "host": {
+                            "name": "Host",
+                            "type": "string",
+                            "description": null,
+                            "nullable": false,
+                            "default": "bitcoinnode.com"
+                        }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+        let keys = spec.pointer_keys();
+        assert!(keys.contains("node"));
+        assert!(keys.contains("address"));
+        assert!(!keys.contains("port"));
+        assert!(!keys.contains("host"));
+    }
+
+    #[test]
+    fn matches_all_collects_every_error() {
+        let spec: ConfigSpec = serde_json::from_value(serde_json::json!({
+            "port": {
+                "name": "Port",
+                "type": "number",
+                "description": null,
+                "nullable": false,
+                "default": 8332,
+                "range": "[1024,65535]",
+                "integral": true
+            },
+            "rpc": {
+                "name": "RPC",
+                "type": "object",
+                "description": null,
+                "nullable": false,
+                "spec": {
+                    "user": {
+                        "name": "User",
+                        "type": "string",
+                        "description": null,
+                        "nullable": false,
+                        "default": "bitcoin",
+                        "pattern": "^[a-z]+$",
+                        "patternDescription": "lowercase letters"
+                    },
+                    "password": {
+                        "name": "Password",
+                        "type": "string",
+                        "description": null,
+                        "nullable": false,
+                        "default": {"charset": "a-z", "len": 20}
+                    }
+                }
+            },
+            "peers": {
+                "name": "Peers",
+                "type": "list",
+                "subtype": "string",
+                "description": null,
+                "range": "[0,10]",
+                "spec": {},
+                "default": []
+            }
+        }))
+        .unwrap();
+        let config: Config = serde_yaml::from_str(
+            "port: 80\nrpc:\n  user: Bitcoin\n  password: null\npeers: [a, b, a]\n",
+        )
+        .unwrap();
+        // the spec is read from a json object, which orders its keys
+        let errors = spec
+            .matches_all(&config)
+            .into_iter()
+            .map(|e| (e.path.iter().rev().join("."), e.error.kind()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                ("peers.0".to_owned(), "list-uniqueness-violation"),
+                ("peers.2".to_owned(), "list-uniqueness-violation"),
+                ("port".to_owned(), "out-of-range"),
+                ("rpc.password".to_owned(), "not-nullable"),
+                ("rpc.user".to_owned(), "pattern"),
+            ]
+        );
+        assert!(spec.matches(&config).is_err());
+    }
+
+    #[test]
+    fn network_and_quantity_types() {
+        let spec: ConfigSpec = serde_json::from_value(serde_json::json!({
+            "port": {
+                "name": "Port",
+                "type": "port",
+                "description": null,
+                "nullable": false,
+                "default": 8332
+            },
+            "bind": {
+                "name": "Bind",
+                "type": "ip",
+                "description": null,
+                "nullable": false,
+                "default": "0.0.0.0",
+                "version": "v4"
+            },
+            "whitelist": {
+                "name": "Whitelist",
+                "type": "ip",
+                "description": null,
+                "nullable": true,
+                "default": null,
+                "cidr": true
+            },
+            "upstream": {
+                "name": "Upstream",
+                "type": "url",
+                "description": null,
+                "nullable": false,
+                "default": "https://example.com",
+                "schemes": ["http", "https"]
+            },
+            "timeout": {
+                "name": "Timeout",
+                "type": "duration",
+                "description": null,
+                "nullable": false,
+                "default": "10m",
+                "range": "[1s,1d]"
+            },
+            "dbcache": {
+                "name": "Database Cache",
+                "type": "byte-size",
+                "description": null,
+                "nullable": false,
+                "default": "450MiB",
+                "range": "[4MiB,16GiB]"
+            }
+        }))
+        .unwrap();
+        let config = spec
+            .gen(&mut rand::rngs::StdRng::from_entropy(), &None)
+            .unwrap();
+        spec.matches(&config).unwrap();
+        assert_eq!(
+            config.0.get("timeout"),
+            Some(&Value::String("10m".to_owned()))
+        );
+
+        let config: Config = serde_yaml::from_str(
+            "port: 70000\nbind: \"::1\"\nwhitelist: 10.0.0.0/33\nupstream: ftp://example.com\ntimeout: 2d\ndbcache: 2GB\n",
+        )
+        .unwrap();
+        let errors = spec
+            .matches_all(&config)
+            .into_iter()
+            .map(|e| (e.path.iter().rev().join("."), e.error.kind()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                ("bind".to_owned(), "invalid-format"),
+                ("port".to_owned(), "out-of-range"),
+                ("timeout".to_owned(), "out-of-range"),
+                ("upstream".to_owned(), "invalid-scheme"),
+                ("whitelist".to_owned(), "invalid-format"),
+            ]
+        );
+
+        let config: Config = serde_yaml::from_str(
+            "port: 8333\nbind: 127.0.0.1\nwhitelist: 10.0.0.0/8\nupstream: http://example.com\ntimeout: 1h30m\ndbcache: 1GiB\n",
+        )
+        .unwrap();
+        spec.matches(&config).unwrap();
+        let mut taken = LinearMap::new();
+        taken.insert(8333, "other-app".to_owned());
+        let conflicts = spec.port_conflicts(&config, &taken);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, vec!["port".to_owned()]);
+
+        // durations that do not fit a Duration are rejected rather than panicking
+        let duration = ValueSpecDuration { range: None };
+        for invalid in &["inf", "1e30", "99999999999999999999999w"] {
+            let error = duration
+                .matches(&Value::String((*invalid).to_owned()))
+                .unwrap_err();
+            assert_eq!(error.error.kind(), "invalid-format");
+        }
+    }
 }
@@ -365,3 +365,162 @@ impl serde::ser::Serialize for UniqueBy {
         }
     }
 }
+
+/// A duration written with units, e.g. `10m` or `1h30m`. A bare number is in seconds.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct HumanDuration(pub std::time::Duration);
+impl HumanDuration {
+    // `Duration::from_secs_f64` panics on anything it cannot represent
+    fn from_secs(secs: f64, s: &str) -> Result<Self, failure::Error> {
+        ensure!(secs.is_finite(), "Invalid Duration: {}", s);
+        ensure!(secs >= 0.0, "Negative Duration: {}", s);
+        ensure!(secs < std::u64::MAX as f64, "Duration Too Long: {}", s);
+        Ok(HumanDuration(std::time::Duration::from_secs_f64(secs)))
+    }
+}
+impl std::str::FromStr for HumanDuration {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(secs) = s.parse::<f64>() {
+            return HumanDuration::from_secs(secs, s);
+        }
+        ensure!(!s.is_empty(), "Empty Duration");
+        let mut secs = 0.0;
+        let mut rest = s;
+        while !rest.is_empty() {
+            let num_len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let unit_len = rest[num_len..]
+                .find(|c: char| c.is_ascii_digit() || c == '.')
+                .unwrap_or(rest.len() - num_len);
+            let num: f64 = rest[..num_len]
+                .parse()
+                .map_err(|_| format_err!("Invalid Duration: {}", s))?;
+            secs += num
+                * match &rest[num_len..num_len + unit_len] {
+                    "ms" => 0.001,
+                    "s" => 1.0,
+                    "m" => 60.0,
+                    "h" => 3600.0,
+                    "d" => 86400.0,
+                    "w" => 604800.0,
+                    unit => bail!("Invalid Duration Unit {:?}: {}", unit, s),
+                };
+            rest = &rest[num_len + unit_len..];
+        }
+        HumanDuration::from_secs(secs, s)
+    }
+}
+impl std::fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut ms = self.0.as_millis();
+        if ms == 0 {
+            return write!(f, "0s");
+        }
+        for (unit, len) in &[
+            ("w", 604_800_000),
+            ("d", 86_400_000),
+            ("h", 3_600_000),
+            ("m", 60_000),
+            ("s", 1000),
+            ("ms", 1),
+        ] {
+            if ms >= *len {
+                write!(f, "{}{}", ms / len, unit)?;
+                ms %= len;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A number of bytes written with units, e.g. `512MB` or `2GiB`. A bare number is in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ByteSize(pub u64);
+const BYTE_UNITS: &'static [(&'static str, u64)] = &[
+    ("TiB", 1 << 40),
+    ("GiB", 1 << 30),
+    ("MiB", 1 << 20),
+    ("KiB", 1 << 10),
+    ("TB", 1_000_000_000_000),
+    ("GB", 1_000_000_000),
+    ("MB", 1_000_000),
+    ("kB", 1000),
+    ("KB", 1000),
+    ("B", 1),
+];
+impl std::str::FromStr for ByteSize {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let num_len = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let num: f64 = s[..num_len]
+            .parse()
+            .map_err(|_| format_err!("Invalid Byte Size: {}", s))?;
+        let unit = s[num_len..].trim();
+        let mult = if unit.is_empty() {
+            1
+        } else {
+            BYTE_UNITS
+                .iter()
+                .find(|(u, _)| *u == unit)
+                .map(|(_, mult)| *mult)
+                .ok_or_else(|| format_err!("Invalid Byte Size Unit {:?}: {}", unit, s))?
+        };
+        let bytes = (num * mult as f64).round();
+        ensure!(bytes < std::u64::MAX as f64, "Byte Size Too Large: {}", s);
+        Ok(ByteSize(bytes as u64))
+    }
+}
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (unit, mult) = BYTE_UNITS
+            .iter()
+            .find(|(_, mult)| self.0 != 0 && self.0 % mult == 0)
+            .unwrap_or(&("B", 1));
+        write!(f, "{}{}", self.0 / mult, unit)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quantities() {
+        let d = |s: &str| s.parse::<HumanDuration>().map(|d| d.0.as_secs_f64());
+        assert_eq!(d("90").unwrap(), 90.0);
+        assert_eq!(d("1h30m").unwrap(), 5400.0);
+        assert_eq!(d("1.5s").unwrap(), 1.5);
+        for invalid in &[
+            "",
+            "-1",
+            "inf",
+            "NaN",
+            "1e30",
+            "1x",
+            "99999999999999999999999w",
+        ] {
+            assert!(d(invalid).is_err(), "{:?} parsed", invalid);
+        }
+
+        let b = |s: &str| s.parse::<ByteSize>().map(|b| b.0);
+        assert_eq!(b("2GiB").unwrap(), 2 << 30);
+        assert_eq!(b("512MB").unwrap(), 512_000_000);
+        assert_eq!(b("10").unwrap(), 10);
+        for invalid in &["", "1XB", "inf", "99999999999999999999TiB"] {
+            assert!(b(invalid).is_err(), "{:?} parsed", invalid);
+        }
+        assert_eq!(format!("{}", ByteSize(3 << 20)), "3MiB");
+        assert_eq!(
+            format!("{}", HumanDuration(std::time::Duration::from_secs(5400))),
+            "1h30m"
+        );
+    }
+}
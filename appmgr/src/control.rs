@@ -4,6 +4,7 @@ use futures::future::{BoxFuture, FutureExt};
 use linear_map::{set::LinearSet, LinearMap};
 
 use crate::dependencies::{DependencyError, TaggedDependencyError};
+use crate::graph::DependencyGraph;
 use crate::util::{from_yaml_async_reader, PersistencePath, YamlUpdateHandle};
 use crate::Error;
 
@@ -22,8 +23,10 @@ pub async fn start_app(name: &str, update_metadata: bool) -> Result<(), Error> {
     .await?;
     let status = crate::apps::status(name, false).await?.status;
     if status == crate::apps::DockerStatus::Stopped {
+        crate::dependencies::ensure_ready(name).await?;
         if update_metadata {
-            crate::config::configure(name, None, None, false).await?;
+            crate::config::configure(name, None, None, false, crate::config::Trigger::Start)
+                .await?;
             crate::dependencies::update_binds(name).await?;
         }
         crate::apps::set_needs_restart(name, false).await?;
@@ -31,17 +34,7 @@ pub async fn start_app(name: &str, update_metadata: bool) -> Result<(), Error> {
             PersistencePath::from_ref("running.yaml"),
         )
         .await?;
-        let output = tokio::process::Command::new("docker")
-            .args(&["start", name])
-            .stdout(std::process::Stdio::null())
-            .output()
-            .await?;
-        crate::ensure_code!(
-            output.status.success(),
-            crate::error::DOCKER_ERROR,
-            "Failed to Start Application: {}",
-            std::str::from_utf8(&output.stderr).unwrap_or("Unknown Error")
-        );
+        crate::runtime::current().start(name).await?;
         running.insert(name.to_owned());
         running.commit().await?;
     } else if status == crate::apps::DockerStatus::Paused {
@@ -78,17 +71,7 @@ pub async fn stop_app(
         )
         .await?;
         log::info!("Stopping {}", name);
-        let output = tokio::process::Command::new("docker")
-            .args(&["stop", "-t", "25", name])
-            .stdout(std::process::Stdio::null())
-            .output()
-            .await?;
-        crate::ensure_code!(
-            output.status.success(),
-            crate::error::DOCKER_ERROR,
-            "Failed to Stop Application: {}",
-            std::str::from_utf8(&output.stderr).unwrap_or("Unknown Error")
-        );
+        crate::runtime::current().stop(name, 25).await?;
         running.remove(name);
         running.commit().await?;
         crate::util::unlock(lock).await?;
@@ -161,17 +144,7 @@ pub async fn pause_app(name: &str) -> Result<(), Error> {
         true,
     )
     .await?;
-    let output = tokio::process::Command::new("docker")
-        .args(&["pause", name])
-        .stdout(std::process::Stdio::null())
-        .output()
-        .await?;
-    crate::ensure_code!(
-        output.status.success(),
-        crate::error::DOCKER_ERROR,
-        "Failed to Pause Application: {}",
-        std::str::from_utf8(&output.stderr).unwrap_or("Unknown Error")
-    );
+    crate::runtime::current().pause(name).await?;
 
     crate::util::unlock(lock).await?;
     Ok(())
@@ -190,48 +163,122 @@ pub async fn resume_app(name: &str) -> Result<(), Error> {
         true,
     )
     .await?;
-    let output = tokio::process::Command::new("docker")
-        .args(&["unpause", name])
-        .stdout(std::process::Stdio::null())
-        .output()
-        .await?;
-    crate::ensure_code!(
-        output.status.success(),
-        crate::error::DOCKER_ERROR,
-        "Failed to Resume Application: {}",
-        std::str::from_utf8(&output.stderr).unwrap_or("Unknown Error")
-    );
+    crate::runtime::current().unpause(name).await?;
     crate::util::unlock(lock).await?;
     Ok(())
 }
 
-pub async fn repair_app_status() -> Result<(), Error> {
+/// Rolls back installs that were interrupted, then restarts the apps that were running before,
+/// dependencies first.
+/// Apps whose dependencies are not ready yet are started once they are, in the background
+/// if `background` is set, which only the daemon can do, as it outlives the request.
+pub async fn repair_app_status(background: bool) -> Result<(), Error> {
+    crate::journal::recover_all().await?;
     let mut running_file = PersistencePath::from_ref("running.yaml")
         .maybe_read(false)
         .await
         .transpose()?;
-    let running: Vec<String> = if let Some(f) = running_file.as_mut() {
+    let running: LinearSet<String> = if let Some(f) = running_file.as_mut() {
         from_yaml_async_reader::<_, &mut tokio::fs::File>(f).await?
     } else {
-        Vec::new()
+        LinearSet::new()
     };
-    for name in running {
-        let lock = crate::util::lock_file(
-            format!(
-                "{}",
-                Path::new(crate::PERSISTENCE_DIR)
-                    .join("apps")
-                    .join(&name)
-                    .join("control.lock")
-                    .display()
-            ),
-            true,
-        )
+    let res = DependencyGraph::load()
+        .await?
+        .subgraph(&running)
+        .walk(false, move |name| async move {
+            if crate::dependencies::ensure_ready(&name).await.is_ok() {
+                return repair_app(&name).await;
+            }
+            if !background {
+                crate::dependencies::await_ready(&name).await?;
+                return repair_app(&name).await;
+            }
+            // don't hold up boot on dependencies that are still starting
+            tokio::spawn(async move {
+                let res = match crate::dependencies::await_ready(&name).await {
+                    Ok(()) => repair_app(&name).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res {
+                    log::error!("Failed to Start {}: {}", name, e);
+                }
+            });
+            Ok(())
+        })
+        .await?;
+    ensure_all("Start", res)
+}
+
+/// Starts the app if it was left stopped, e.g. by a reboot.
+async fn repair_app(name: &str) -> Result<(), Error> {
+    let lock = crate::util::lock_file(
+        format!(
+            "{}",
+            Path::new(crate::PERSISTENCE_DIR)
+                .join("apps")
+                .join(name)
+                .join("control.lock")
+                .display()
+        ),
+        true,
+    )
+    .await?;
+    if crate::apps::status(name, false).await?.status == crate::apps::DockerStatus::Stopped {
+        start_app(name, true).await?;
+    }
+    crate::util::unlock(lock).await?;
+    Ok(())
+}
+
+/// Starts every installed app, dependencies first.
+pub async fn start_all() -> Result<(), Error> {
+    start_ordered(&DependencyGraph::load().await?).await
+}
+
+async fn start_ordered(graph: &DependencyGraph) -> Result<(), Error> {
+    let res = graph
+        .walk(false, |name| async move {
+            if crate::apps::status(&name, false).await?.status != crate::apps::DockerStatus::Running
+            {
+                crate::dependencies::await_ready(&name).await?;
+                start_app(&name, true).await?;
+            }
+            Ok(())
+        })
         .await?;
-        if crate::apps::status(&name, false).await?.status == crate::apps::DockerStatus::Stopped {
-            start_app(&name, true).await?;
+    ensure_all("Start", res)
+}
+
+/// Stops every running app, dependents first.
+pub async fn stop_all() -> Result<(), Error> {
+    let res = DependencyGraph::load()
+        .await?
+        .walk(true, |name| async move {
+            if crate::apps::status(&name, false).await?.status != crate::apps::DockerStatus::Stopped
+            {
+                stop_app(&name, false, false).await?;
+            }
+            Ok(())
+        })
+        .await?;
+    ensure_all("Stop", res)
+}
+
+fn ensure_all(action: &str, res: LinearMap<String, Result<(), Error>>) -> Result<(), Error> {
+    let mut failed = Vec::new();
+    for (name, r) in res {
+        if let Err(e) = r {
+            log::error!("Failed to {} {}: {}", action, name, e);
+            failed.push(name);
         }
-        crate::util::unlock(lock).await?;
     }
+    crate::ensure_code!(
+        failed.is_empty(),
+        crate::error::GENERAL_ERROR,
+        "Failed to {}: {}",
+        action,
+        failed.join(", ")
+    );
     Ok(())
 }
@@ -0,0 +1,690 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+use failure::ResultExt as _;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use yajrc::RpcError;
+
+use crate::util::Apply;
+use crate::{Config, Error, ResultExt as _};
+
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+
+/// Only the user the daemon runs as may connect to its socket.
+pub const SOCKET_MODE: u32 = 0o600;
+
+#[derive(Debug, serde::Deserialize)]
+struct Request {
+    /// `None` for a notification, which gets no response. A `null` id is still a request.
+    #[serde(default, deserialize_with = "deserialize_id")]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+fn deserialize_id<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Value>, D::Error> {
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Response {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Option<Value>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+impl Response {
+    fn new(id: Option<Value>, res: Result<Value, RpcError>) -> Self {
+        let (result, error) = match res {
+            Ok(a) => (Some(a), None),
+            Err(e) => (None, Some(e)),
+        };
+        Response {
+            jsonrpc: "2.0".to_owned(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+fn rpc_error(e: Error) -> RpcError {
+    RpcError {
+        code: e.code.unwrap_or(crate::error::GENERAL_ERROR),
+        message: format!("{}", e.failure),
+        data: None,
+    }
+}
+
+fn from_rpc_error(e: RpcError) -> Error {
+    Error::new(
+        format_err!("{}", e.message),
+        Some(if e.code > 0 {
+            e.code
+        } else {
+            crate::error::GENERAL_ERROR
+        }),
+    )
+}
+
+fn params<T: for<'de> serde::Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(match params {
+        Value::Null => Value::Object(Default::default()),
+        a => a,
+    })
+    .map_err(|e| RpcError {
+        code: INVALID_PARAMS,
+        message: format!("Invalid Params: {}", e),
+        data: None,
+    })
+}
+
+fn to_value<T: serde::Serialize>(t: Result<T, Error>) -> Result<Value, RpcError> {
+    serde_json::to_value(t.map_err(rpc_error)?)
+        .with_code(crate::error::SERDE_ERROR)
+        .map_err(rpc_error)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct IdParams {
+    id: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DryRunParams {
+    id: String,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct InstallParams {
+    target: String,
+    #[serde(default)]
+    no_cache: bool,
+    #[serde(default)]
+    with_deps: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ResolveParams {
+    target: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ConfigureParams {
+    id: String,
+    #[serde(default)]
+    config: Option<Config>,
+    #[serde(default)]
+    timeout: Option<u64>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ConfigValidateParams {
+    id: String,
+    #[serde(default)]
+    config: Option<Config>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ConfigRollbackParams {
+    id: String,
+    rev: u64,
+    #[serde(default)]
+    timeout: Option<u64>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CheckDependenciesParams {
+    id: String,
+    #[serde(default)]
+    local_only: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AutoconfigureParams {
+    id: String,
+    dependency: String,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct RemoveParams {
+    id: String,
+    #[serde(default)]
+    purge: bool,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct IncludeParams {
+    #[serde(default)]
+    include_status: bool,
+    #[serde(default)]
+    include_manifest: bool,
+    #[serde(default)]
+    include_config: bool,
+    #[serde(default)]
+    include_dependencies: bool,
+    #[serde(default)]
+    reveal: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct InfoParams {
+    id: String,
+    #[serde(flatten)]
+    include: IncludeParams,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LogsParams {
+    id: String,
+    #[serde(default)]
+    details: bool,
+    #[serde(default)]
+    since: Option<String>,
+    #[serde(default)]
+    until: Option<String>,
+    #[serde(default)]
+    tail: Option<usize>,
+    #[serde(default)]
+    timestamps: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GraphParams {
+    #[serde(default)]
+    include_registry: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct BackupParams {
+    partition: String,
+    id: String,
+    password: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ActionParams {
+    service: String,
+    action: String,
+}
+
+async fn handle(method: &str, p: Value) -> Result<Value, RpcError> {
+    match method {
+        "install" => {
+            let p: InstallParams = params(p)?;
+            if p.with_deps {
+                to_value(crate::resolver::install_target(&p.target, !p.no_cache).await)
+            } else {
+                to_value(crate::install::install_target(&p.target, !p.no_cache).await)
+            }
+        }
+        "install.resolve" => {
+            let p: ResolveParams = params(p)?;
+            to_value(crate::resolver::resolve_target(&p.target).await)
+        }
+        "update" => {
+            let p: DryRunParams = params(p)?;
+            to_value(crate::update(&p.id, p.dry_run).await)
+        }
+        "update.plan" => {
+            let p: IdParams = params(p)?;
+            to_value(crate::resolver::plan_update_target(&p.id).await)
+        }
+        "start" => {
+            let p: IdParams = params(p)?;
+            to_value(crate::start_app(&p.id, true).await)
+        }
+        "stop" => {
+            let p: DryRunParams = params(p)?;
+            to_value(crate::stop_app(&p.id, true, p.dry_run).await)
+        }
+        "restart" => {
+            let p: IdParams = params(p)?;
+            to_value(crate::restart_app(&p.id).await)
+        }
+        "configure" => {
+            let p: ConfigureParams = params(p)?;
+            to_value(
+                crate::configure(
+                    &p.id,
+                    p.config,
+                    p.timeout.map(Duration::from_secs),
+                    p.dry_run,
+                    crate::config::Trigger::User,
+                )
+                .await,
+            )
+        }
+        "config.validate" => {
+            let p: ConfigValidateParams = params(p)?;
+            to_value(crate::config::validate(&p.id, p.config).await)
+        }
+        "config.rollback" => {
+            let p: ConfigRollbackParams = params(p)?;
+            to_value(
+                crate::config::history::rollback(
+                    &p.id,
+                    p.rev,
+                    p.timeout.map(Duration::from_secs),
+                    p.dry_run,
+                )
+                .await,
+            )
+        }
+        "check-dependencies" => {
+            let p: CheckDependenciesParams = params(p)?;
+            to_value(crate::apps::dependencies(&p.id, p.local_only).await)
+        }
+        "dependencies.graph" => {
+            let p: GraphParams = params(p)?;
+            to_value(crate::graph::export(p.include_registry).await)
+        }
+        "autoconfigure-dependency" => {
+            let p: AutoconfigureParams = params(p)?;
+            to_value(crate::dependencies::auto_configure(&p.id, &p.dependency, p.dry_run).await)
+        }
+        "remove" => {
+            let p: RemoveParams = params(p)?;
+            to_value(crate::remove(&p.id, p.purge, p.dry_run).await)
+        }
+        "tor.show" => {
+            let p: IdParams = params(p)?;
+            to_value(crate::tor::read_tor_address(&p.id, None).await)
+        }
+        "tor.reload" => to_value(crate::tor::reload().await),
+        "info" => {
+            let p: InfoParams = params(p)?;
+            to_value(
+                crate::apps::info_full(
+                    &p.id,
+                    p.include.include_status,
+                    p.include.include_manifest,
+                    p.include.include_config,
+                    p.include.include_dependencies,
+                    p.include.reveal,
+                )
+                .await,
+            )
+        }
+        "list" => {
+            let p: IncludeParams = params(p)?;
+            to_value(
+                crate::apps::list(
+                    p.include_status,
+                    p.include_manifest,
+                    p.include_config,
+                    p.include_dependencies,
+                    p.reveal,
+                )
+                .await,
+            )
+        }
+        "logs" => {
+            let p: LogsParams = params(p)?;
+            to_value(
+                crate::logs::fetch_logs(
+                    &p.id,
+                    crate::LogOptions {
+                        details: p.details,
+                        follow: false,
+                        since: p.since,
+                        until: p.until,
+                        tail: p.tail,
+                        timestamps: p.timestamps,
+                    },
+                )
+                .await,
+            )
+        }
+        "notifications" => {
+            let p: IdParams = params(p)?;
+            to_value(crate::notifications(&p.id).await)
+        }
+        "stats" => {
+            let p: IdParams = params(p)?;
+            to_value(crate::stats(&p.id).await)
+        }
+        "disks.list" => to_value(crate::disks::list().await),
+        "backup.create" => {
+            let p: BackupParams = params(p)?;
+            to_value(crate::backup::backup_to_partition(&p.partition, &p.id, &p.password).await)
+        }
+        "backup.restore" => {
+            let p: BackupParams = params(p)?;
+            to_value(crate::backup::restore_from_partition(&p.partition, &p.id, &p.password).await)
+        }
+        "start-all" => to_value(crate::control::start_all().await),
+        "stop-all" => to_value(crate::control::stop_all().await),
+        "repair-app-status" => to_value(crate::control::repair_app_status(true).await),
+        "actions" => {
+            let p: ActionParams = params(p)?;
+            let man = crate::apps::manifest(&p.service).await.map_err(rpc_error)?;
+            man.actions
+                .iter()
+                .filter(|a| &a.id == &p.action)
+                .next()
+                .ok_or_else(|| format_err!("action {} does not exist for {}", p.action, man.id))
+                .with_code(crate::error::NOT_FOUND)
+                .map_err(rpc_error)?
+                .perform(&man.id)
+                .await
+                .map(Value::String)
+        }
+        _ => Err(RpcError {
+            code: METHOD_NOT_FOUND,
+            message: format!("Method Not Found: {}", method),
+            data: None,
+        }),
+    }
+}
+
+/// Handles a request, returning its response, or handles a notification, returning nothing.
+async fn handle_line(line: &str) -> Option<Response> {
+    let req: Request = match serde_json::from_str(line) {
+        Ok(a) => a,
+        Err(e) => {
+            return Some(Response::new(
+                None,
+                Err(RpcError {
+                    code: PARSE_ERROR,
+                    message: format!("Parse Error: {}", e),
+                    data: None,
+                }),
+            ))
+        }
+    };
+    log::info!("Handling {}.", req.method);
+    let mut params = req.params;
+    // the operation a client relays the progress events of
+    let operation = match &mut params {
+        Value::Object(p) => p
+            .remove("operation")
+            .and_then(|o| o.as_str().map(str::to_owned)),
+        _ => None,
+    };
+    let res = crate::progress::scope(operation, handle(&req.method, params)).await;
+    if let Err(e) = &res {
+        log::warn!("{}: {}", req.method, e.message);
+    }
+    req.id.map(|id| Response::new(Some(id), res))
+}
+
+async fn handle_connection(stream: UnixStream) -> Result<(), Error> {
+    let (r, mut w) = tokio::io::split(stream);
+    let mut lines = BufReader::new(r).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Ok(req) = serde_json::from_str::<Request>(&line) {
+            if req.method == "progress.subscribe" {
+                let operation = req
+                    .params
+                    .get("operation")
+                    .and_then(|o| o.as_str())
+                    .map(str::to_owned);
+                // subscribe before responding so no event after the response is missed
+                let subscription = crate::progress::subscribe();
+                let mut buf = serde_json::to_vec(&Response::new(req.id, Ok(Value::Null)))
+                    .with_code(crate::error::SERDE_ERROR)?;
+                buf.push(b'\n');
+                w.write_all(&buf).await?;
+                // the connection only carries progress events from here on
+                return crate::progress::forward(&mut w, subscription, operation.as_deref()).await;
+            }
+        }
+        if let Some(res) = handle_line(&line).await {
+            let mut buf = serde_json::to_vec(&res).with_code(crate::error::SERDE_ERROR)?;
+            buf.push(b'\n');
+            w.write_all(&buf).await?;
+            w.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Binds the socket at `path`, which must not exist, and restricts it to `SOCKET_MODE`.
+fn bind(path: &Path) -> Result<UnixListener, Error> {
+    let listener = UnixListener::bind(path)
+        .with_context(|e| format!("{}: {}", path.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(SOCKET_MODE))
+        .with_context(|e| format!("{}: {}", path.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    Ok(listener)
+}
+
+async fn accept(listener: UnixListener) -> Result<(), Error> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream).await {
+                log::error!("Connection Error: {}", e);
+            }
+        });
+    }
+}
+
+pub async fn serve<P: AsRef<Path>>(path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+    if path.exists() {
+        crate::ensure_code!(
+            UnixStream::connect(path).await.is_err(),
+            crate::error::GENERAL_ERROR,
+            "Daemon Already Running: {}",
+            path.display()
+        );
+        tokio::fs::remove_file(path)
+            .await
+            .with_context(|e| format!("{}: {}", path.display(), e))
+            .with_code(crate::error::FILESYSTEM_ERROR)?;
+    }
+    let listener = bind(path)?;
+    *crate::QUIET.write().await = true;
+    tokio::spawn(crate::health::monitor());
+    log::info!("Listening on {}.", path.display());
+    accept(listener).await
+}
+
+pub struct Client {
+    stream: BufReader<UnixStream>,
+    next_id: u64,
+}
+impl Client {
+    pub async fn connect<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        Ok(Client {
+            stream: UnixStream::connect(path)
+                .await
+                .with_context(|e| format!("{}: {}", path.display(), e))
+                .with_code(crate::error::FILESYSTEM_ERROR)?
+                .apply(BufReader::new),
+            next_id: 0,
+        })
+    }
+
+    /// Connects to the daemon if one is listening on the default socket.
+    pub async fn connect_if_running() -> Option<Self> {
+        if Path::new(crate::DAEMON_SOCKET).exists() {
+            Self::connect(crate::DAEMON_SOCKET).await.ok()
+        } else {
+            None
+        }
+    }
+
+    pub async fn call_raw(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> Result<Result<Value, RpcError>, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut params = params;
+        if let Value::Object(p) = &mut params {
+            if let Some(operation) = crate::progress::request_operation() {
+                p.insert("operation".to_owned(), Value::String(operation));
+            }
+        }
+        let mut buf = serde_json::to_vec(&serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }))
+        .with_code(crate::error::SERDE_ERROR)?;
+        buf.push(b'\n');
+        self.stream.get_mut().write_all(&buf).await?;
+        self.stream.get_mut().flush().await?;
+        loop {
+            let mut line = String::new();
+            crate::ensure_code!(
+                self.stream.read_line(&mut line).await? > 0,
+                crate::error::GENERAL_ERROR,
+                "Daemon Closed Connection"
+            );
+            let res: Response = serde_json::from_str(&line).with_code(crate::error::SERDE_ERROR)?;
+            if res.id != Some(Value::from(id)) {
+                continue;
+            }
+            return Ok(match (res.result, res.error) {
+                (_, Some(e)) => Err(e),
+                (Some(a), None) => Ok(a),
+                (None, None) => Ok(Value::Null),
+            });
+        }
+    }
+
+    pub async fn call<T: for<'de> serde::Deserialize<'de>>(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, Error> {
+        let res = self
+            .call_raw(method, params)
+            .await?
+            .map_err(from_rpc_error)?;
+        serde_json::from_value(res).with_code(crate::error::SERDE_ERROR)
+    }
+}
+
+/// Forwards the call to the daemon if it is running, otherwise runs it in process.
+pub async fn call_or<T, F>(method: &str, params: Value, local: F) -> Result<T, Error>
+where
+    T: for<'de> serde::Deserialize<'de>,
+    F: std::future::Future<Output = Result<T, Error>>,
+{
+    if let Some(mut client) = Client::connect_if_running().await {
+        client.call(method, params).await
+    } else {
+        local.await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn read_response(stream: &mut BufReader<UnixStream>, req: &str) -> Value {
+        stream.get_mut().write_all(req.as_bytes()).await.unwrap();
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn socket_round_trip() {
+        let dir = crate::fixtures::tmp_dir("daemon").await;
+        let path = dir.join("appmgr.sock");
+        let listener = bind(&path).unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            SOCKET_MODE
+        );
+        tokio::spawn(accept(listener));
+
+        let mut client = Client::connect(&path).await.unwrap();
+        let e = client
+            .call_raw("no-such-method", Value::Null)
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(e.code, METHOD_NOT_FOUND);
+        let e = client
+            .call_raw("start", serde_json::json!({}))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(e.code, INVALID_PARAMS);
+
+        let mut stream = BufReader::new(UnixStream::connect(&path).await.unwrap());
+        // notifications get no response, so the first response is to the request after them
+        let res = read_response(
+            &mut stream,
+            "{\"jsonrpc\":\"2.0\",\"method\":\"no-such-method\"}\n\
+             {\"jsonrpc\":\"2.0\",\"method\":\"start\",\"params\":{}}\n\
+             {\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"no-such-method\"}\n",
+        )
+        .await;
+        assert_eq!(res["id"], 7);
+        assert_eq!(res["error"]["code"], METHOD_NOT_FOUND);
+        let res = read_response(
+            &mut stream,
+            "{\"jsonrpc\":\"2.0\",\"id\":null,\"method\":\"no-such-method\"}\n",
+        )
+        .await;
+        assert_eq!(res["id"], Value::Null);
+        assert_eq!(res["error"]["code"], METHOD_NOT_FOUND);
+        let res = read_response(&mut stream, "{\"jsonrpc\":\n").await;
+        assert_eq!(res["id"], Value::Null);
+        assert_eq!(res["error"]["code"], PARSE_ERROR);
+        let res = read_response(
+            &mut stream,
+            "{\"jsonrpc\":\"2.0\",\"id\":\"sub\",\"method\":\"progress.subscribe\"}\n",
+        )
+        .await;
+        assert_eq!(res["id"], "sub");
+        assert_eq!(res["result"], Value::Null);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use std::borrow::Cow;
 use std::path::Path;
+use std::time::Duration;
 
 use emver::{Version, VersionRange};
 use linear_map::LinearMap;
@@ -10,7 +11,9 @@ use crate::manifest::ManifestLatest;
 use crate::Error;
 use crate::ResultExt as _;
 
-#[derive(Clone, Debug, Fail, serde::Serialize)]
+pub const READINESS_TIMEOUT: u64 = 300;
+
+#[derive(Clone, Debug, Fail, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum DependencyError {
     NotInstalled, // "not-installed"
@@ -21,6 +24,10 @@ pub enum DependencyError {
     }, // { "incorrect-version": { "expected": "0.1.0", "received": "^0.2.0" } }
     ConfigUnsatisfied(Vec<String>), // { "config-unsatisfied": ["Bitcoin Core must have pruning set to manual."] }
     PointerUpdateError(String), // { "pointer-update-error": "Bitcoin Core RPC Port must not be 18332" }
+    NotHealthy {
+        check: String,
+        message: String,
+    }, // { "not-healthy": { "check": "synced", "message": "Block height 600000 of 650000" } }
     Other(String),              // { "other": "Well fuck." }
 }
 impl std::fmt::Display for DependencyError {
@@ -38,12 +45,15 @@ impl std::fmt::Display for DependencyError {
                 write!(f, "Configuration Rule(s) Violated: {}", rules.join(", "))
             }
             PointerUpdateError(e) => write!(f, "Pointer Update Caused {}", e),
+            NotHealthy { check, message } => {
+                write!(f, "Health Check {} Not Passing: {}", check, message)
+            }
             Other(e) => write!(f, "System Error: {}", e),
         }
     }
 }
 
-#[derive(Clone, Debug, serde::Serialize)]
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct TaggedDependencyError {
     pub dependency: String,
@@ -70,6 +80,9 @@ pub struct DepInfo {
     pub mount_shared: bool,
     #[serde(default)]
     pub config: Vec<ConfigRuleEntryWithSuggestions>,
+    /// Health check of the dependency that must be passing, e.g. "synced".
+    #[serde(default)]
+    pub readiness: Option<String>,
 }
 impl DepInfo {
     pub async fn satisfied(
@@ -120,11 +133,56 @@ impl DepInfo {
         {
             return Ok(Err(DependencyError::NotRunning));
         }
+        if let Some(check) = &self.readiness {
+            return check_readiness(dependency_id, check).await;
+        }
         Ok(Ok(()))
     }
 }
 
-#[derive(Debug, serde::Serialize)]
+async fn check_readiness(
+    dependency_id: &str,
+    check: &str,
+) -> Result<Result<(), DependencyError>, Error> {
+    if !crate::apps::manifest(dependency_id)
+        .await?
+        .health_checks
+        .contains_key(check)
+    {
+        return Ok(Err(DependencyError::NotHealthy {
+            check: check.to_owned(),
+            message: format!("{} Has No Health Check Named {}", dependency_id, check),
+        }));
+    }
+    Ok(check_passing(
+        check,
+        crate::apps::status(dependency_id, false)
+            .await?
+            .health_checks
+            .remove(check),
+    ))
+}
+
+/// Whether the recorded `state` of the health check `check` lets dependents start.
+fn check_passing(
+    check: &str,
+    state: Option<crate::health::CheckState>,
+) -> Result<(), DependencyError> {
+    let message = match state {
+        Some(state) if state.passed && state.message.is_none() => return Ok(()),
+        Some(crate::health::CheckState {
+            message: Some(message),
+            ..
+        }) => message,
+        _ => "Not Yet Checked".to_owned(),
+    };
+    Err(DependencyError::NotHealthy {
+        check: check.to_owned(),
+        message,
+    })
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct AppDepInfo {
     #[serde(flatten)]
@@ -134,7 +192,7 @@ pub struct AppDepInfo {
     pub error: Option<DependencyError>,
 }
 
-#[derive(Debug, Default, serde::Serialize)]
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct AppDependencies(pub LinearMap<String, AppDepInfo>);
 
@@ -161,6 +219,47 @@ pub async fn check_dependencies(
     Ok(deps)
 }
 
+/// Fails if a required dependency of `dependent_id` is not passing the health check it names.
+pub async fn ensure_ready(dependent_id: &str) -> Result<(), Error> {
+    match not_ready(crate::apps::dependencies(dependent_id, true).await?) {
+        Some(e) => Err(failure::format_err!("{}", e)).with_code(crate::error::GENERAL_ERROR),
+        None => Ok(()),
+    }
+}
+
+/// The first required dependency that names a health check and is not running or passing it.
+fn not_ready(deps: AppDependencies) -> Option<TaggedDependencyError> {
+    for (dependency_id, dep) in deps.0 {
+        if !dep.required || dep.info.readiness.is_none() {
+            continue;
+        }
+        match dep.error {
+            Some(e @ DependencyError::NotRunning)
+            | Some(e @ DependencyError::NotHealthy { .. }) => {
+                return Some(TaggedDependencyError {
+                    dependency: dependency_id,
+                    error: e,
+                })
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Waits for the dependencies of `dependent_id` to pass the health checks it names.
+pub async fn await_ready(dependent_id: &str) -> Result<(), Error> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(READINESS_TIMEOUT);
+    loop {
+        match ensure_ready(dependent_id).await {
+            Ok(()) => return Ok(()),
+            Err(e) if tokio::time::Instant::now() >= deadline => return Err(e),
+            Err(e) => log::debug!("{} Waiting On Dependencies: {}", dependent_id, e),
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
 pub async fn auto_configure(
     dependent: &str,
     dependency: &str,
@@ -185,7 +284,16 @@ pub async fn auto_configure(
             log::warn!("Rule Unsatisfied After Applying Suggestions: {}", e);
         }
     }
-    crate::config::configure(dependency, Some(dependency_config), None, dry_run).await
+    crate::config::configure(
+        dependency,
+        Some(dependency_config),
+        None,
+        dry_run,
+        crate::config::Trigger::AutoConfigure {
+            dependent: dependent.to_owned(),
+        },
+    )
+    .await
 }
 
 pub async fn update_binds(dependent_id: &str) -> Result<(), Error> {
@@ -258,3 +366,73 @@ pub async fn update_binds(dependent_id: &str) -> Result<(), Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::health::CheckState;
+
+    fn dependency(readiness: Option<&str>, required: bool, state: CheckState) -> AppDepInfo {
+        let mut info: DepInfo =
+            serde_yaml::from_str("version: '>=0.20.0'\noptional: null\ndescription: null\n")
+                .unwrap();
+        info.readiness = readiness.map(|r| r.to_owned());
+        AppDepInfo {
+            error: readiness.and_then(|check| check_passing(check, Some(state)).err()),
+            required,
+            info,
+        }
+    }
+
+    fn state(passed: bool, message: Option<&str>) -> CheckState {
+        CheckState {
+            last_checked: 1,
+            passed,
+            failures: if message.is_some() { 1 } else { 0 },
+            message: message.map(|m| m.to_owned()),
+        }
+    }
+
+    #[test]
+    fn readiness_gating() {
+        let failing = || state(false, Some("Block height 600000 of 650000"));
+        let mut deps = AppDependencies::default();
+        deps.0.insert(
+            "bitcoind".to_owned(),
+            dependency(Some("synced"), true, failing()),
+        );
+        match not_ready(deps) {
+            Some(TaggedDependencyError {
+                dependency,
+                error: DependencyError::NotHealthy { check, message },
+            }) => {
+                assert_eq!(dependency, "bitcoind");
+                assert_eq!(check, "synced");
+                assert_eq!(message, "Block height 600000 of 650000");
+            }
+            e => panic!("expected bitcoind to not be healthy, got {:?}", e),
+        }
+
+        // a check that has not run yet does not pass either
+        assert!(matches!(
+            check_passing("synced", None),
+            Err(DependencyError::NotHealthy { check, .. }) if check == "synced"
+        ));
+        assert!(check_passing("synced", Some(state(true, None))).is_ok());
+
+        let mut deps = AppDependencies::default();
+        deps.0.insert(
+            "bitcoind".to_owned(),
+            dependency(Some("synced"), true, state(true, None)),
+        );
+        deps.0.insert(
+            "electrs".to_owned(),
+            dependency(Some("synced"), false, failing()),
+        );
+        // only dependencies with a readiness check gate starting
+        let mut lnd = dependency(None, true, failing());
+        lnd.error = Some(DependencyError::NotRunning);
+        deps.0.insert("lnd".to_owned(), lnd);
+        assert!(not_ready(deps).is_none());
+    }
+}
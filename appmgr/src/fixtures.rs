@@ -0,0 +1,11 @@
+use std::path::PathBuf;
+
+/// An empty directory under the system temp dir, unique to `name` and this process.
+pub async fn tmp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("appmgr-{}-{}", name, std::process::id()));
+    if dir.exists() {
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    dir
+}
@@ -0,0 +1,427 @@
+use std::future::Future;
+
+use emver::{Version, VersionRange};
+use futures::stream::{FuturesUnordered, StreamExt};
+use linear_map::{set::LinearSet, LinearMap};
+
+use crate::manifest::ManifestLatest;
+use crate::Error;
+use crate::ResultExt as _;
+
+/// Maps each app to the apps it depends on.
+#[derive(Clone, Debug, Default)]
+pub struct DependencyGraph(pub LinearMap<String, LinearSet<String>>);
+impl DependencyGraph {
+    /// Builds the graph of installed apps from their manifests.
+    /// Dependencies that are not installed are left out.
+    pub async fn load() -> Result<Self, Error> {
+        let apps = crate::apps::list_info().await?;
+        let mut graph = DependencyGraph::default();
+        for id in apps.keys() {
+            let manifest = crate::apps::manifest(id).await?;
+            graph.insert(
+                id,
+                manifest
+                    .dependencies
+                    .0
+                    .into_iter()
+                    .map(|(dep, _)| dep)
+                    .filter(|dep| apps.contains_key(dep)),
+            );
+        }
+        Ok(graph)
+    }
+
+    pub fn insert<I: IntoIterator<Item = String>>(&mut self, id: &str, dependencies: I) {
+        self.0
+            .insert(id.to_owned(), dependencies.into_iter().collect());
+    }
+
+    /// Restricts the graph to `ids`.
+    pub fn subgraph(&self, ids: &LinearSet<String>) -> Self {
+        DependencyGraph(
+            self.0
+                .iter()
+                .filter(|(id, _)| ids.contains(*id))
+                .map(|(id, deps)| {
+                    (
+                        id.clone(),
+                        deps.iter().filter(|d| ids.contains(*d)).cloned().collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub fn dependencies<'a>(&'a self, id: &str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .get(id)
+            .into_iter()
+            .flatten()
+            .map(|d| d.as_str())
+            .filter(move |d| self.0.contains_key(*d))
+    }
+
+    /// Returns a dependency cycle as a path that starts and ends with the same app.
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        fn visit<'a>(
+            graph: &'a DependencyGraph,
+            id: &'a str,
+            path: &mut Vec<&'a str>,
+            done: &mut LinearSet<&'a str>,
+        ) -> Option<Vec<String>> {
+            if let Some(pos) = path.iter().position(|p| *p == id) {
+                let mut cycle: Vec<String> = path[pos..].iter().map(|p| (*p).to_owned()).collect();
+                cycle.push(id.to_owned());
+                return Some(cycle);
+            }
+            if done.contains(id) {
+                return None;
+            }
+            path.push(id);
+            for dep in graph.dependencies(id) {
+                if let Some(cycle) = visit(graph, dep, path, done) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            done.insert(id);
+            None
+        }
+        let mut done = LinearSet::new();
+        for id in self.0.keys() {
+            if let Some(cycle) = visit(self, id, &mut Vec::new(), &mut done) {
+                return Some(cycle);
+            }
+        }
+        None
+    }
+
+    pub fn ensure_acyclic(&self) -> Result<(), Error> {
+        if let Some(cycle) = self.find_cycle() {
+            return Err(format_err!("Dependency Cycle: {}", cycle.join(" -> ")))
+                .with_code(crate::error::GENERAL_ERROR);
+        }
+        Ok(())
+    }
+
+    /// Orders the apps so that every app comes after its dependencies.
+    pub fn topo_sort(&self) -> Result<Vec<String>, Error> {
+        fn visit(graph: &DependencyGraph, id: &str, res: &mut Vec<String>) {
+            if res.iter().any(|r| r == id) {
+                return;
+            }
+            for dep in graph.dependencies(id) {
+                visit(graph, dep, res);
+            }
+            res.push(id.to_owned());
+        }
+        self.ensure_acyclic()?;
+        let mut res = Vec::with_capacity(self.0.len());
+        for id in self.0.keys() {
+            visit(self, id, &mut res);
+        }
+        Ok(res)
+    }
+
+    /// Runs `f` on every app once its dependencies are done, or once its dependents are done if `reverse`.
+    /// Independent apps are run concurrently. Apps waiting on a failed app are skipped.
+    pub async fn walk<F, Fut>(
+        &self,
+        reverse: bool,
+        f: F,
+    ) -> Result<LinearMap<String, Result<(), Error>>, Error>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<(), Error>>,
+    {
+        self.ensure_acyclic()?;
+        let mut waiting: LinearMap<&str, usize> = LinearMap::new();
+        let mut unblocks: LinearMap<&str, Vec<&str>> = LinearMap::new();
+        for id in self.0.keys() {
+            waiting.entry(id.as_str()).or_insert(0);
+            for dep in self.dependencies(id) {
+                let (blocked, blocker) = if reverse {
+                    (dep, id.as_str())
+                } else {
+                    (id.as_str(), dep)
+                };
+                *waiting.entry(blocked).or_insert(0) += 1;
+                unblocks
+                    .entry(blocker)
+                    .or_insert_with(Vec::new)
+                    .push(blocked);
+            }
+        }
+        let run = |id: &str| {
+            let fut = f(id.to_owned());
+            let id = id.to_owned();
+            async move { (id, fut.await) }
+        };
+        let mut running = FuturesUnordered::new();
+        for (id, n) in &waiting {
+            if *n == 0 {
+                running.push(run(id));
+            }
+        }
+        let mut failed_blocker: LinearMap<&str, String> = LinearMap::new();
+        let mut done: Vec<(String, Result<(), Error>)> = Vec::new();
+        let mut res = LinearMap::new();
+        loop {
+            while let Some((id, r)) = done.pop() {
+                let ok = r.is_ok();
+                for &next in unblocks.get(id.as_str()).into_iter().flatten() {
+                    if !ok {
+                        failed_blocker.insert(next, id.clone());
+                    }
+                    let n = waiting.get_mut(next).unwrap();
+                    *n -= 1;
+                    if *n == 0 {
+                        if let Some(blocker) = failed_blocker.get(next) {
+                            done.push((
+                                next.to_owned(),
+                                Err(format_err!("Skipped Because {} Failed", blocker)).no_code(),
+                            ));
+                        } else {
+                            running.push(run(next));
+                        }
+                    }
+                }
+                res.insert(id, r);
+            }
+            match running.next().await {
+                Some(a) => done.push(a),
+                None => break,
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GraphNode {
+    pub version: Version,
+    pub installed: bool,
+    pub dependencies: LinearMap<String, GraphEdge>,
+}
+impl GraphNode {
+    fn new(manifest: &ManifestLatest, installed: bool) -> Self {
+        GraphNode {
+            version: manifest.version.clone(),
+            installed,
+            dependencies: manifest
+                .dependencies
+                .0
+                .iter()
+                .map(|(id, info)| {
+                    (
+                        id.clone(),
+                        GraphEdge {
+                            version: info.version.clone(),
+                            optional: info.optional.is_some(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GraphEdge {
+    pub version: VersionRange,
+    pub optional: bool,
+}
+
+/// Exports the dependency graph of installed apps.
+/// With `include_registry`, dependencies that are not installed are looked up in the registry,
+/// along with their own dependencies.
+pub async fn export(include_registry: bool) -> Result<LinearMap<String, GraphNode>, Error> {
+    let mut nodes = LinearMap::new();
+    for id in crate::apps::list_info().await?.keys() {
+        nodes.insert(
+            id.clone(),
+            GraphNode::new(&crate::apps::manifest(id).await?, true),
+        );
+    }
+    if include_registry {
+        fn edges(node: &GraphNode) -> impl Iterator<Item = (String, VersionRange)> + '_ {
+            node.dependencies
+                .iter()
+                .map(|(id, edge)| (id.clone(), edge.version.clone()))
+        }
+        let mut missing: Vec<_> = nodes.values().flat_map(edges).collect();
+        while let Some((id, version)) = missing.pop() {
+            if nodes.contains_key(&id) {
+                continue;
+            }
+            match crate::registry::manifest(&id, &version).await {
+                Ok(manifest) => {
+                    let node = GraphNode::new(&manifest, false);
+                    missing.extend(edges(&node));
+                    nodes.insert(id, node);
+                }
+                Err(e) => log::warn!("Failed to Fetch Manifest for {}: {}", id, e),
+            }
+        }
+    }
+    Ok(nodes)
+}
+
+fn dot_id(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Renders an exported graph in Graphviz DOT.
+/// Apps that are not installed and optional dependencies are drawn dashed.
+pub fn to_dot(nodes: &LinearMap<String, GraphNode>) -> String {
+    let mut res = String::from("digraph dependencies {\n");
+    for (id, node) in nodes {
+        res += &format!(
+            "    {} [label={}{}];\n",
+            dot_id(id),
+            dot_id(&format!("{} {}", id, node.version)),
+            if node.installed { "" } else { ", style=dashed" }
+        );
+    }
+    for (id, node) in nodes {
+        for (dep, edge) in &node.dependencies {
+            res += &format!(
+                "    {} -> {} [label={}{}];\n",
+                dot_id(id),
+                dot_id(dep),
+                dot_id(&format!("{}", edge.version)),
+                if edge.optional { ", style=dashed" } else { "" }
+            );
+        }
+    }
+    res += "}\n";
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn graph(edges: &[(&str, &[&str])]) -> DependencyGraph {
+        let mut graph = DependencyGraph::default();
+        for (id, deps) in edges {
+            graph.insert(id, deps.iter().map(|d| (*d).to_owned()));
+        }
+        graph
+    }
+
+    fn position(order: &[String], id: &str) -> usize {
+        order.iter().position(|a| a == id).unwrap()
+    }
+
+    #[test]
+    fn topo_sort() {
+        let graph = graph(&[
+            ("lnd", &["bitcoind"]),
+            ("rtl", &["lnd", "bitcoind"]),
+            ("bitcoind", &[]),
+            ("electrs", &["bitcoind", "not-installed"]),
+        ]);
+        let order = graph.topo_sort().unwrap();
+        assert_eq!(order.len(), 4);
+        assert!(position(&order, "bitcoind") < position(&order, "lnd"));
+        assert!(position(&order, "lnd") < position(&order, "rtl"));
+        assert!(position(&order, "bitcoind") < position(&order, "electrs"));
+    }
+
+    #[test]
+    fn find_cycle() {
+        let mut graph = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &[]), ("d", &["a"])]);
+        assert!(graph.find_cycle().is_none());
+        graph.insert("c", vec!["a".to_owned()]);
+        let cycle = graph.find_cycle().unwrap();
+        assert_eq!(cycle.first(), cycle.last());
+        assert_eq!(cycle.len(), 4);
+        assert!(graph.topo_sort().is_err());
+        let mut ids = LinearSet::new();
+        ids.insert("a".to_owned());
+        ids.insert("c".to_owned());
+        assert!(graph.subgraph(&ids).find_cycle().is_none());
+    }
+
+    #[test]
+    fn dot() {
+        let mut dependencies = LinearMap::new();
+        dependencies.insert(
+            "bitcoind".to_owned(),
+            GraphEdge {
+                version: "^0.20.0".parse().unwrap(),
+                optional: true,
+            },
+        );
+        let mut nodes = LinearMap::new();
+        nodes.insert(
+            "lnd".to_owned(),
+            GraphNode {
+                version: "0.11.1".parse().unwrap(),
+                installed: false,
+                dependencies,
+            },
+        );
+        let dot = to_dot(&nodes);
+        assert!(dot.starts_with("digraph dependencies {\n"));
+        let lines: Vec<&str> = dot.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("    \"lnd\" [label=\"lnd "));
+        assert!(lines[1].ends_with(", style=dashed];"));
+        assert!(lines[2].starts_with("    \"lnd\" -> \"bitcoind\" [label="));
+        assert!(lines[2].ends_with(", style=dashed];"));
+        assert_eq!(dot_id("a\"b"), "\"a\\\"b\"");
+    }
+
+    #[tokio::test]
+    async fn walk() {
+        let graph = graph(&[
+            ("lnd", &["bitcoind"]),
+            ("rtl", &["lnd"]),
+            ("bitcoind", &[]),
+            ("electrs", &["bitcoind"]),
+        ]);
+        let order = Mutex::new(Vec::new());
+        let res = graph
+            .walk(false, |id| {
+                let order = &order;
+                async move {
+                    order.lock().unwrap().push(id.clone());
+                    crate::ensure_code!(id != "lnd", crate::error::GENERAL_ERROR, "lnd failed");
+                    Ok(())
+                }
+            })
+            .await
+            .unwrap();
+        let order = order.into_inner().unwrap();
+        assert_eq!(order.len(), 3);
+        assert_eq!(order[0], "bitcoind");
+        assert!(res["electrs"].is_ok());
+        assert!(res["lnd"].is_err());
+        assert!(res["rtl"].is_err());
+        assert!(!order.iter().any(|a| a == "rtl"));
+
+        let order = Mutex::new(Vec::new());
+        graph
+            .walk(true, |id| {
+                let order = &order;
+                async move {
+                    order.lock().unwrap().push(id);
+                    Ok(())
+                }
+            })
+            .await
+            .unwrap();
+        let order = order.into_inner().unwrap();
+        assert_eq!(order.len(), 4);
+        assert!(position(&order, "rtl") < position(&order, "lnd"));
+        assert!(position(&order, "lnd") < position(&order, "bitcoind"));
+        assert!(position(&order, "electrs") < position(&order, "bitcoind"));
+    }
+}
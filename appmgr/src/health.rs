@@ -0,0 +1,431 @@
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use failure::ResultExt as _;
+use linear_map::LinearMap;
+use tokio_compat_02::FutureExt;
+
+use crate::util::{from_yaml_async_reader, to_yaml_async_writer, PersistencePath};
+use crate::Error;
+use crate::ResultExt as _;
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "kebab-case")]
+pub enum Probe {
+    /// Succeeds if the command exits 0 inside the container.
+    Exec { command: Vec<String> },
+    /// Succeeds on any 2xx response.
+    Http {
+        port: u16,
+        #[serde(default = "default_http_path")]
+        path: String,
+    },
+    /// Succeeds if the port accepts a connection.
+    Tcp { port: u16 },
+}
+fn default_http_path() -> String {
+    "/".to_owned()
+}
+impl Probe {
+    pub async fn check(&self, id: &str) -> Result<(), Error> {
+        match self {
+            Probe::Exec { command } => {
+                // dropped on timeout, which must not leave the command running
+                let output = crate::runtime::current()
+                    .exec(id, command)
+                    .kill_on_drop(true)
+                    .output()
+                    .await?;
+                crate::ensure_code!(
+                    output.status.success(),
+                    crate::error::GENERAL_ERROR,
+                    "Probe Command Failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            Probe::Http { port, path } => {
+                let url = format!("http://{}:{}{}", app_ip(id).await?, port, path);
+                let res = reqwest::get(&url)
+                    .compat()
+                    .await
+                    .with_context(|e| format!("GET {}: {}", url, e))
+                    .with_code(crate::error::NETWORK_ERROR)?;
+                crate::ensure_code!(
+                    res.status().is_success(),
+                    crate::error::NETWORK_ERROR,
+                    "GET {}: {}",
+                    url,
+                    res.status()
+                );
+            }
+            Probe::Tcp { port } => {
+                let addr = (app_ip(id).await?, *port);
+                tokio::net::TcpStream::connect(addr)
+                    .await
+                    .with_context(|e| format!("{}:{}: {}", addr.0, addr.1, e))
+                    .with_code(crate::error::NETWORK_ERROR)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReadinessProbe {
+    #[serde(flatten)]
+    pub probe: Probe,
+    /// Seconds the container must stay up after the probe first passes.
+    #[serde(default = "default_grace_period")]
+    pub grace_period: u64,
+    /// Seconds to wait for the probe to pass.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+fn default_grace_period() -> u64 {
+    30
+}
+fn default_timeout() -> u64 {
+    300
+}
+
+pub async fn app_ip(id: &str) -> Result<Ipv4Addr, Error> {
+    Ok(
+        crate::tor::services_map(&PersistencePath::from_ref(crate::SERVICES_YAML))
+            .await?
+            .map
+            .get(id)
+            .ok_or_else(|| format_err!("{} Has No Network Address", id))
+            .with_code(crate::error::NOT_FOUND)?
+            .ip,
+    )
+}
+
+async fn ensure_running(id: &str) -> Result<(), Error> {
+    let state = crate::runtime::current().inspect(id).await?;
+    crate::ensure_code!(
+        state.running,
+        crate::error::DOCKER_ERROR,
+        "{} Exited With Code {}{}",
+        id,
+        state.exit_code,
+        if state.oom_killed {
+            " (Out of Memory)"
+        } else {
+            ""
+        }
+    );
+    Ok(())
+}
+
+/// Waits for a started app to pass its readiness probe and then stay up for the grace period.
+/// Apps without a probe only need to survive the default grace period.
+pub async fn await_ready(id: &str, readiness: Option<&ReadinessProbe>) -> Result<(), Error> {
+    log::info!("Waiting for {} to become ready.", id);
+    if let Some(readiness) = readiness {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(readiness.timeout);
+        loop {
+            ensure_running(id).await?;
+            match readiness.probe.check(id).await {
+                Ok(()) => break,
+                Err(e) if tokio::time::Instant::now() >= deadline => {
+                    return Err(format_err!("{} Did Not Become Ready: {}", id, e))
+                        .with_code(crate::error::GENERAL_ERROR)
+                }
+                Err(e) => log::debug!("{} Not Ready: {}", id, e),
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+    let grace_period = readiness
+        .map(|r| r.grace_period)
+        .unwrap_or_else(default_grace_period);
+    for _ in 0..grace_period {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        ensure_running(id).await?;
+    }
+    if let Some(readiness) = readiness {
+        readiness.probe.check(id).await?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthCheck {
+    #[serde(flatten)]
+    pub probe: Probe,
+    /// Seconds between checks.
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// Seconds before a single check is considered failed.
+    #[serde(default = "default_check_timeout")]
+    pub timeout: u64,
+    /// Consecutive failures before the app is unhealthy.
+    #[serde(default = "default_retries")]
+    pub retries: usize,
+    /// Seconds after the container starts during which failures are not counted.
+    #[serde(default)]
+    pub start_period: u64,
+}
+fn default_interval() -> u64 {
+    30
+}
+fn default_check_timeout() -> u64 {
+    10
+}
+fn default_retries() -> usize {
+    3
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum HealthStatus {
+    Healthy,
+    Unhealthy,
+    Starting,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CheckState {
+    pub last_checked: u64,
+    pub passed: bool,
+    pub failures: usize,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Check results for the current run of a container.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthState {
+    pub started_at: String,
+    pub first_seen: u64,
+    pub checks: LinearMap<String, CheckState>,
+}
+
+static MONITORING: AtomicBool = AtomicBool::new(false);
+
+fn health_path(id: &str) -> PersistencePath {
+    PersistencePath::from_ref("apps")
+        .join(id)
+        .join("health.yaml")
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Consecutive failures after which `check` is unhealthy. A check that has not passed yet is
+/// allowed one more, so that its first failure reports the app as starting rather than unhealthy.
+fn failure_threshold(check: &HealthCheck, state: &CheckState) -> usize {
+    let retries = check.retries.max(1);
+    if state.passed {
+        retries
+    } else {
+        retries + 1
+    }
+}
+
+pub fn summarize(checks: &LinearMap<String, HealthCheck>, state: &HealthState) -> HealthStatus {
+    let mut res = HealthStatus::Healthy;
+    for (name, check) in checks {
+        match state.checks.get(name) {
+            Some(s) if s.failures >= failure_threshold(check, s) => return HealthStatus::Unhealthy,
+            Some(s) if s.passed => (),
+            _ => res = HealthStatus::Starting,
+        }
+    }
+    res
+}
+
+pub async fn read_state(id: &str) -> Result<HealthState, Error> {
+    match health_path(id).maybe_read(false).await.transpose()? {
+        Some(mut f) => from_yaml_async_reader(&mut *f).await,
+        None => Ok(HealthState::default()),
+    }
+}
+
+/// Summarizes the health of `id` for a container started at `started_at`,
+/// evaluating due checks first unless the daemon keeps them up to date.
+/// Apps without health checks have no health status.
+pub async fn status(
+    id: &str,
+    started_at: &str,
+) -> Result<(Option<HealthStatus>, LinearMap<String, CheckState>), Error> {
+    let manifest_path = PersistencePath::from_ref("apps")
+        .join(id)
+        .join("manifest.yaml");
+    if !manifest_path.exists().await {
+        return Ok((None, LinearMap::new()));
+    }
+    let manifest = crate::apps::manifest(id).await?;
+    if manifest.health_checks.is_empty() {
+        return Ok((None, LinearMap::new()));
+    }
+    if let Err(e) = refresh(id).await {
+        log::warn!("Failed to Evaluate Health Checks of {}: {}", id, e);
+    }
+    let mut state = read_state(id).await?;
+    if state.started_at != started_at {
+        state = HealthState::default();
+    }
+    Ok((
+        Some(summarize(&manifest.health_checks, &state)),
+        state.checks,
+    ))
+}
+
+/// Runs every health check of `id` that is due and records the results.
+pub async fn evaluate(id: &str) -> Result<(), Error> {
+    let manifest = crate::apps::manifest(id).await?;
+    if manifest.health_checks.is_empty() {
+        return Ok(());
+    }
+    let container = crate::runtime::current().inspect(id).await?;
+    if !container.running {
+        return Ok(());
+    }
+    let now = now();
+    let mut state = read_state(id).await?;
+    if state.started_at != container.started_at {
+        state = HealthState {
+            started_at: container.started_at.clone(),
+            first_seen: now,
+            checks: LinearMap::new(),
+        };
+    }
+    let mut changed = false;
+    for (name, check) in &manifest.health_checks {
+        if state.checks.get(name).is_none() {
+            state.checks.insert(name.clone(), CheckState::default());
+        }
+        let in_start_period = now < state.first_seen + check.start_period;
+        let entry = state.checks.get_mut(name).unwrap();
+        if entry.last_checked + check.interval > now {
+            continue;
+        }
+        let res =
+            match tokio::time::timeout(Duration::from_secs(check.timeout), check.probe.check(id))
+                .await
+            {
+                Ok(res) => res.map_err(|e| format!("{}", e)),
+                Err(_) => Err(format!("Timed Out After {}s", check.timeout)),
+            };
+        entry.last_checked = now;
+        match res {
+            Ok(()) => {
+                entry.passed = true;
+                entry.failures = 0;
+                entry.message = None;
+            }
+            Err(e) => {
+                log::debug!("{}: Health Check {} Failed: {}", id, name, e);
+                if entry.passed || !in_start_period {
+                    entry.failures += 1;
+                }
+                entry.message = Some(e);
+            }
+        }
+        changed = true;
+    }
+    if changed {
+        let mut f = health_path(id).write(None).await?;
+        to_yaml_async_writer(&mut *f, &state).await?;
+        f.commit().await?;
+    }
+    Ok(())
+}
+
+/// Evaluates due health checks unless the daemon is already monitoring them.
+pub async fn refresh(id: &str) -> Result<(), Error> {
+    if !MONITORING.load(Ordering::SeqCst) {
+        evaluate(id).await?;
+    }
+    Ok(())
+}
+
+/// Keeps the health state of every installed app up to date.
+pub async fn monitor() {
+    MONITORING.store(true, Ordering::SeqCst);
+    loop {
+        match crate::apps::list_info().await {
+            Ok(apps) => {
+                futures::future::join_all(apps.keys().map(|id| async move {
+                    if let Err(e) = evaluate(id).await {
+                        log::warn!("{}: Failed to Evaluate Health Checks: {}", id, e);
+                    }
+                }))
+                .await;
+            }
+            Err(e) => log::error!("Failed to List Apps: {}", e),
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(retries: usize) -> HealthCheck {
+        HealthCheck {
+            probe: Probe::Tcp { port: 80 },
+            interval: 30,
+            timeout: 10,
+            retries,
+            start_period: 0,
+        }
+    }
+
+    fn state(passed: bool, failures: usize) -> CheckState {
+        CheckState {
+            last_checked: 0,
+            passed,
+            failures,
+            message: None,
+        }
+    }
+
+    #[test]
+    fn summarize_checks() {
+        let mut checks = LinearMap::new();
+        checks.insert("web".to_owned(), check(3));
+        checks.insert("rpc".to_owned(), check(1));
+        let mut health = HealthState::default();
+        assert_eq!(summarize(&checks, &health), HealthStatus::Starting);
+        health.checks.insert("web".to_owned(), state(true, 2));
+        health.checks.insert("rpc".to_owned(), state(true, 0));
+        assert_eq!(summarize(&checks, &health), HealthStatus::Healthy);
+        health.checks.insert("rpc".to_owned(), state(false, 0));
+        assert_eq!(summarize(&checks, &health), HealthStatus::Starting);
+        health.checks.insert("web".to_owned(), state(true, 3));
+        assert_eq!(summarize(&checks, &health), HealthStatus::Unhealthy);
+    }
+
+    #[test]
+    fn summarize_without_retries() {
+        let mut checks = LinearMap::new();
+        checks.insert("web".to_owned(), check(0));
+        let mut health = HealthState::default();
+        assert_eq!(summarize(&checks, &health), HealthStatus::Starting);
+        // failures in the start period are not counted
+        health.checks.insert("web".to_owned(), state(false, 0));
+        assert_eq!(summarize(&checks, &health), HealthStatus::Starting);
+        health.checks.insert("web".to_owned(), state(false, 1));
+        assert_eq!(summarize(&checks, &health), HealthStatus::Starting);
+        health.checks.insert("web".to_owned(), state(false, 2));
+        assert_eq!(summarize(&checks, &health), HealthStatus::Unhealthy);
+        health.checks.insert("web".to_owned(), state(true, 0));
+        assert_eq!(summarize(&checks, &health), HealthStatus::Healthy);
+        health.checks.insert("web".to_owned(), state(true, 1));
+        assert_eq!(summarize(&checks, &health), HealthStatus::Unhealthy);
+    }
+}
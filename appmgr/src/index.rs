@@ -1,7 +1,7 @@
 use std::cmp::Ord;
 use std::ffi::OsStr;
 use std::iter::FromIterator;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use emver::{Version, VersionRange};
 use futures::future::{BoxFuture, FutureExt};
@@ -96,10 +96,29 @@ impl FromIterator<ManifestLatest> for AppIndex {
 }
 
 pub async fn index<P: AsRef<Path>>(dir: P) -> Result<AppIndex, Error> {
+    Ok(packages(dir)
+        .await?
+        .into_iter()
+        .map(|(_, manifest)| manifest)
+        .collect())
+}
+
+/// Finds every package under `dir`, skipping directories that contain a `.ignore` file.
+pub async fn packages<P: AsRef<Path>>(dir: P) -> Result<Vec<(PathBuf, ManifestLatest)>, Error> {
+    let mut res = Vec::new();
+    for path in package_paths(dir).await? {
+        let manifest = package_manifest(&path).await?;
+        res.push((path, manifest));
+    }
+    Ok(res)
+}
+
+/// Finds the path of every package under `dir`, without reading them.
+pub async fn package_paths<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, Error> {
     let dir_path = dir.as_ref();
-    let mut idx = AppIndex::default();
-    fn index_rec<'a, P: AsRef<Path> + Send + Sync + 'a>(
-        idx: &'a mut AppIndex,
+    let mut res = Vec::new();
+    fn packages_rec<'a, P: AsRef<Path> + Send + Sync + 'a>(
+        res: &'a mut Vec<PathBuf>,
         dir: P,
     ) -> BoxFuture<'a, Result<(), Error>> {
         async move {
@@ -115,19 +134,23 @@ pub async fn index<P: AsRef<Path>>(dir: P) -> Result<AppIndex, Error> {
                 if metadata.is_file() {
                     let ext = path.extension();
                     if ext == Some(OsStr::new("s9pk")) {
-                        let info = info_full(&path, true, false)
-                            .await
-                            .with_ctx(|e| (e.code.clone(), format!("{}: {}", path.display(), e)))?;
-                        idx.add(info.manifest.unwrap());
+                        res.push(path);
                     }
                 } else if metadata.is_dir() {
-                    index_rec(idx, &path).await?;
+                    packages_rec(res, &path).await?;
                 }
             }
             Ok(())
         }
         .boxed()
     }
-    index_rec(&mut idx, dir_path).await?;
-    Ok(idx)
+    packages_rec(&mut res, dir_path).await?;
+    Ok(res)
+}
+
+pub async fn package_manifest(path: &Path) -> Result<ManifestLatest, Error> {
+    let info = info_full(path, true, false)
+        .await
+        .with_ctx(|e| (e.code.clone(), format!("{}: {}", path.display(), e)))?;
+    Ok(info.manifest.unwrap())
 }
@@ -1,11 +1,12 @@
 use std::path::Path;
 
-use failure::ResultExt as _;
 use futures::stream::StreamExt;
+use tokio::io::{AsyncRead, AsyncWriteExt};
 use tokio_tar as tar;
 
 use crate::config::{ConfigRuleEntry, ConfigSpec};
 use crate::manifest::{Manifest, ManifestLatest};
+use crate::s9pk::S9pk;
 use crate::util::from_cbor_async_reader;
 use crate::version::VersionT;
 use crate::Error;
@@ -43,35 +44,32 @@ pub async fn info_full<P: AsRef<Path>>(
 ) -> Result<AppInfoFull, Error> {
     let p = path.as_ref();
     log::info!("Opening file.");
-    let r = tokio::fs::File::open(p)
-        .await
-        .with_context(|e| format!("{}: {}", p.display(), e))
-        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    let mut s9pk = S9pk::open(p).await?;
+    if s9pk.toc().is_some() {
+        log::info!("Reading manifest.");
+        let manifest = check_manifest(s9pk.read_cbor("manifest.cbor").await?)?;
+        let config = if with_config {
+            log::info!("Reading config spec and rules.");
+            Some(AppConfig {
+                spec: s9pk.read_cbor("config_spec.cbor").await?,
+                rules: s9pk.read_cbor("config_rules.cbor").await?,
+            })
+        } else {
+            None
+        };
+        return Ok(AppInfoFull {
+            info: AppInfo {
+                title: manifest.title.clone(),
+                version: manifest.version.clone(),
+            },
+            manifest: if with_manifest { Some(manifest) } else { None },
+            config,
+        });
+    }
     log::info!("Extracting archive.");
-    let mut pkg = tar::Archive::new(r);
+    let mut pkg = tar::Archive::new(s9pk.into_archive().await?);
     let mut entries = pkg.entries()?;
-    log::info!("Opening manifest from archive.");
-    let manifest = entries
-        .next()
-        .await
-        .ok_or(crate::install::Error::CorruptedPkgFile("missing manifest"))
-        .no_code()??;
-    crate::ensure_code!(
-        manifest.path()?.to_str() == Some("manifest.cbor"),
-        crate::error::GENERAL_ERROR,
-        "Package File Invalid or Corrupted"
-    );
-    log::trace!("Deserializing manifest.");
-    let manifest: Manifest = from_cbor_async_reader(manifest).await?;
-    let manifest = manifest.into_latest();
-    crate::ensure_code!(
-        crate::version::Current::new()
-            .semver()
-            .satisfies(&manifest.os_version_required),
-        crate::error::VERSION_INCOMPATIBLE,
-        "AppMgr Version Not Compatible: needs {}",
-        manifest.os_version_required
-    );
+    let manifest = read_manifest(&mut entries).await?;
     Ok(AppInfoFull {
         info: AppInfo {
             title: manifest.title.clone(),
@@ -119,35 +117,19 @@ pub async fn info_full<P: AsRef<Path>>(
 pub async fn print_instructions<P: AsRef<Path>>(path: P) -> Result<(), Error> {
     let p = path.as_ref();
     log::info!("Opening file.");
-    let r = tokio::fs::File::open(p)
-        .await
-        .with_context(|e| format!("{}: {}", p.display(), e))
-        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    let mut s9pk = S9pk::open(p).await?;
+    if s9pk.toc().is_some() {
+        let manifest = check_manifest(s9pk.read_cbor("manifest.cbor").await?)?;
+        if !manifest.has_instructions {
+            return Err(failure::format_err!("No instructions for {}", p.display()))
+                .with_code(crate::error::NOT_FOUND);
+        }
+        return write_stdout(&mut s9pk.read("instructions.md").await?.as_slice()).await;
+    }
     log::info!("Extracting archive.");
-    let mut pkg = tar::Archive::new(r);
+    let mut pkg = tar::Archive::new(s9pk.into_archive().await?);
     let mut entries = pkg.entries()?;
-    log::info!("Opening manifest from archive.");
-    let manifest = entries
-        .next()
-        .await
-        .ok_or(crate::install::Error::CorruptedPkgFile("missing manifest"))
-        .no_code()??;
-    crate::ensure_code!(
-        manifest.path()?.to_str() == Some("manifest.cbor"),
-        crate::error::GENERAL_ERROR,
-        "Package File Invalid or Corrupted"
-    );
-    log::trace!("Deserializing manifest.");
-    let manifest: Manifest = from_cbor_async_reader(manifest).await?;
-    let manifest = manifest.into_latest();
-    crate::ensure_code!(
-        crate::version::Current::new()
-            .semver()
-            .satisfies(&manifest.os_version_required),
-        crate::error::VERSION_INCOMPATIBLE,
-        "AppMgr Version Not Compatible: needs {}",
-        manifest.os_version_required
-    );
+    let manifest = read_manifest(&mut entries).await?;
     entries
         .next()
         .await
@@ -164,8 +146,6 @@ pub async fn print_instructions<P: AsRef<Path>>(path: P) -> Result<(), Error> {
         .no_code()??;
 
     if manifest.has_instructions {
-        use tokio::io::AsyncWriteExt;
-
         let mut instructions = entries
             .next()
             .await
@@ -174,22 +154,61 @@ pub async fn print_instructions<P: AsRef<Path>>(path: P) -> Result<(), Error> {
             ))
             .no_code()??;
 
-        let mut stdout = tokio::io::stdout();
-        tokio::io::copy(&mut instructions, &mut stdout)
-            .await
-            .with_code(crate::error::FILESYSTEM_ERROR)?;
-        stdout
-            .flush()
-            .await
-            .with_code(crate::error::FILESYSTEM_ERROR)?;
-        stdout
-            .shutdown()
-            .await
-            .with_code(crate::error::FILESYSTEM_ERROR)?;
+        write_stdout(&mut instructions).await
     } else {
-        return Err(failure::format_err!("No instructions for {}", p.display()))
-            .with_code(crate::error::NOT_FOUND);
+        Err(failure::format_err!("No instructions for {}", p.display()))
+            .with_code(crate::error::NOT_FOUND)
     }
+}
+
+/// Reads the icon of a package. Only versioned packages can carry one.
+pub async fn icon<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, Error> {
+    S9pk::open(path).await?.read(crate::s9pk::ICON).await
+}
+
+async fn read_manifest<R: AsyncRead + Unpin>(
+    entries: &mut tar::Entries<R>,
+) -> Result<ManifestLatest, Error> {
+    log::info!("Opening manifest from archive.");
+    let manifest = entries
+        .next()
+        .await
+        .ok_or(crate::install::Error::CorruptedPkgFile("missing manifest"))
+        .no_code()??;
+    crate::ensure_code!(
+        manifest.path()?.to_str() == Some("manifest.cbor"),
+        crate::error::GENERAL_ERROR,
+        "Package File Invalid or Corrupted"
+    );
+    log::trace!("Deserializing manifest.");
+    check_manifest(from_cbor_async_reader(manifest).await?)
+}
+
+fn check_manifest(manifest: Manifest) -> Result<ManifestLatest, Error> {
+    let manifest = manifest.into_latest();
+    crate::ensure_code!(
+        crate::version::Current::new()
+            .semver()
+            .satisfies(&manifest.os_version_required),
+        crate::error::VERSION_INCOMPATIBLE,
+        "AppMgr Version Not Compatible: needs {}",
+        manifest.os_version_required
+    );
+    Ok(manifest)
+}
 
+async fn write_stdout<R: AsyncRead + Unpin>(r: &mut R) -> Result<(), Error> {
+    let mut stdout = tokio::io::stdout();
+    tokio::io::copy(r, &mut stdout)
+        .await
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    stdout
+        .flush()
+        .await
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    stdout
+        .shutdown()
+        .await
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
     Ok(())
 }
@@ -1,5 +1,3 @@
-use std::borrow::Cow;
-use std::ffi::{OsStr, OsString};
 use std::marker::Unpin;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
@@ -14,17 +12,28 @@ use std::time::Duration;
 use failure::ResultExt as _;
 use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
-use tokio::io::AsyncWriteExt;
-use tokio::io::{AsyncRead, ReadBuf};
+use tokio::io::{AsyncRead, AsyncSeekExt, ReadBuf};
 use tokio_compat_02::FutureExt;
 use tokio_tar as tar;
 
 use crate::config::{ConfigRuleEntry, ConfigSpec};
-use crate::manifest::{ImageConfig, Manifest, ManifestV0};
+use crate::journal::{InstallJournal, InstallLock, Step};
+use crate::manifest::{Arch, ImageConfig, Manifest, ManifestV0};
+use crate::runtime::{ContainerConfig, Mount};
 use crate::util::{from_cbor_async_reader, to_yaml_async_writer, AsyncCompat, PersistencePath};
 use crate::version::VersionT;
 use crate::ResultExt as _;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstallMode {
+    Install,
+    /// Replacing an installed version. If `restart` is set, the new version is started
+    /// and must pass its readiness probe before the previous version is discarded.
+    Update {
+        restart: bool,
+    },
+}
+
 #[derive(Fail, Debug, Clone)]
 pub enum Error {
     #[fail(display = "Package File Invalid or Corrupted: {}", _0)]
@@ -33,24 +42,23 @@ pub enum Error {
     InvalidFileName,
 }
 
+pub async fn install_target(target: &str, use_cache: bool) -> Result<(), crate::Error> {
+    if target.starts_with("https://") || target.starts_with("http://") {
+        install_url(target, None).await
+    } else if target.ends_with(".s9pk") {
+        install_path(target, None).await
+    } else {
+        install_name(target, use_cache).await
+    }
+}
+
 pub async fn install_name(name_version: &str, use_cache: bool) -> Result<(), crate::Error> {
     let name = name_version.split("@").next().unwrap();
-    let tmp_path = Path::new(crate::TMP_DIR).join(format!("{}.s9pk", name));
-    if !use_cache || !tmp_path.exists() {
-        download_name(name_version).await?;
-    }
-    install_path(
-        &tmp_path
-            .as_os_str()
-            .to_str()
-            .ok_or(Error::InvalidFileName)
-            .with_code(crate::error::FILESYSTEM_ERROR)?,
-        Some(name),
-    )
-    .await?;
-    tokio::fs::remove_file(&tmp_path)
+    let (path, registry) = download_name(name_version, use_cache).await?;
+    install_path_as(&path, Some(name), Some(&registry), InstallMode::Install).await?;
+    tokio::fs::remove_file(&path)
         .await
-        .with_context(|e| format!("{}: {}", tmp_path.display(), e))
+        .with_context(|e| format!("{}: {}", path.display(), e))
         .with_code(crate::error::FILESYSTEM_ERROR)?;
     Ok(())
 }
@@ -76,44 +84,178 @@ where
     }
 }
 
-pub async fn download_name(name_version: &str) -> Result<PathBuf, crate::Error> {
+/// Downloads the newest package of `name@range` into the download cache,
+/// along with the registry it came from.
+/// Without `use_cache` any cached or partial download of that version is discarded first.
+pub async fn download_name(
+    name_version: &str,
+    use_cache: bool,
+) -> Result<(PathBuf, crate::registry::RegistryInfo), crate::Error> {
     let mut split = name_version.split("@");
     let name = split.next().unwrap();
     let req: Option<emver::VersionRange> = split.next().map(|a| a.parse()).transpose().no_code()?;
-    if let Some(req) = req {
-        download(
-            &format!("{}/{}.s9pk?spec={}", &*crate::APP_REGISTRY_URL, name, req),
-            Some(name),
-        )
-        .await
-    } else {
-        download(
-            &format!("{}/{}.s9pk", &*crate::APP_REGISTRY_URL, name),
-            Some(name),
-        )
+    crate::registry::download(
+        name,
+        &req.unwrap_or_else(emver::VersionRange::any),
+        use_cache,
+    )
+    .await
+}
+
+/// Where a downloaded package of `id` at `version` is kept until it is installed.
+pub fn cache_path(id: &str, version: &emver::Version) -> PathBuf {
+    Path::new(crate::TMP_DIR)
+        .join("cache")
+        .join(format!("{}-{}.s9pk", id, version))
+}
+
+/// The file a download into `path` is written to until it is complete.
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Hex encoded SHA-256 of the file at `path`.
+pub async fn sha256<P: AsRef<Path>>(path: P) -> Result<String, crate::Error> {
+    let mut f = tokio::fs::File::open(path.as_ref())
         .await
+        .with_context(|e| format!("{}: {}", path.as_ref().display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    Ok(sha256_reader(&mut f).await?.0)
+}
+
+/// Hex encoded SHA-256 and length of everything read from `r`.
+pub async fn sha256_reader<R: AsyncRead + Unpin>(r: &mut R) -> Result<(String, u64), crate::Error> {
+    use tokio::io::AsyncReadExt;
+
+    let mut hasher = openssl::sha::Sha256::new();
+    let mut buf = vec![0; 1 << 16];
+    let mut len = 0;
+    loop {
+        let n = r.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        len += n as u64;
+        hasher.update(&buf[..n]);
     }
+    Ok((
+        hasher
+            .finish()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+        len,
+    ))
 }
 
-pub async fn download(url: &str, name: Option<&str>) -> Result<PathBuf, crate::Error> {
+/// Checks the file at `path` against a published SHA-256, removing it if it does not match.
+pub async fn verify_sha256<P: AsRef<Path>>(path: P, expected: &str) -> Result<(), crate::Error> {
+    let path = path.as_ref();
+    let actual = sha256(path).await?;
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        return Ok(());
+    }
+    tokio::fs::remove_file(path).await?;
+    Err(format_err!(
+        "{}: Checksum Mismatch: Expected {}, Got {}",
+        path.display(),
+        expected,
+        actual
+    ))
+    .with_code(crate::error::REGISTRY_ERROR)
+}
+
+pub const DOWNLOAD_ATTEMPTS: u32 = 6;
+pub const DOWNLOAD_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Runs `f` until it succeeds or fails with anything other than a network error,
+/// doubling the delay between attempts.
+pub async fn retry<T, F, Fut>(what: &str, mut f: F) -> Result<T, crate::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, crate::Error>>,
+{
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(e)
+                if e.code == Some(crate::error::NETWORK_ERROR) && attempt < DOWNLOAD_ATTEMPTS =>
+            {
+                log::warn!(
+                    "{} Failed (Attempt {}/{}): {}. Retrying in {}s.",
+                    what,
+                    attempt,
+                    DOWNLOAD_ATTEMPTS,
+                    e,
+                    backoff.as_secs()
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = std::cmp::min(backoff * 2, DOWNLOAD_MAX_BACKOFF);
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Downloads `url` into `dst`, resuming from the end of `dst` if the server supports ranges.
+pub async fn download_to<P: AsRef<Path>>(
+    url: &str,
+    dst: P,
+    id: Option<&str>,
+) -> Result<(), crate::Error> {
+    let dst = dst.as_ref();
     let url = reqwest::Url::parse(url).no_code()?;
-    log::info!("Downloading {}.", url.as_str());
-    let response = reqwest::get(url)
+    let offset = match tokio::fs::metadata(dst).await {
+        Ok(m) => m.len(),
+        Err(_) => 0,
+    };
+    let mut request = crate::registry::proxy::client(url.as_str())
+        .await?
+        .get(url.clone());
+    if offset > 0 {
+        log::info!("Resuming {} at {}KiB.", url.as_str(), offset / 1024);
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+    } else {
+        log::info!("Downloading {}.", url.as_str());
+    }
+    let response = request
+        .send()
         .compat()
         .await
-        .with_code(crate::error::NETWORK_ERROR)?
+        .with_code(crate::error::NETWORK_ERROR)?;
+    let status = response.status();
+    if offset > 0 && status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        // nothing left to download
+        return Ok(());
+    }
+    let response = response
         .error_for_status()
-        .with_code(crate::error::REGISTRY_ERROR)?;
-    tokio::fs::create_dir_all(crate::TMP_DIR).await?;
-    let tmp_file_path =
-        Path::new(crate::TMP_DIR).join(&format!("{}.s9pk", name.unwrap_or("download")));
-    let mut f = tokio::fs::File::create(&tmp_file_path).await?;
+        .with_code(if status.is_server_error() {
+            crate::error::NETWORK_ERROR
+        } else {
+            crate::error::REGISTRY_ERROR
+        })?;
+    if let Some(parent) = dst.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let (mut f, offset) = if status == reqwest::StatusCode::PARTIAL_CONTENT {
+        (
+            tokio::fs::OpenOptions::new().append(true).open(dst).await?,
+            offset,
+        )
+    } else {
+        (tokio::fs::File::create(dst).await?, 0)
+    };
     let len: Option<u64> = response.content_length().map(|a| {
         log::info!("{}KiB to download.", a / 1024);
-        a
+        a + offset
     });
     let done = Arc::new(AtomicBool::new(false));
-    let counter = Arc::new(AtomicU64::new(0));
+    let counter = Arc::new(AtomicU64::new(offset));
     let mut reader = CountingReader(
         AsyncCompat(
             response
@@ -129,10 +271,12 @@ pub async fn download(url: &str, name: Option<&str>) -> Result<PathBuf, crate::E
         done_handle.store(true, atomic::Ordering::SeqCst);
         res
     });
+    let mut reporter = crate::progress::Reporter::new(id, crate::progress::Phase::Downloading, len);
     let poll_handle = tokio::spawn(async move {
         loop {
             let is_done = done.load(atomic::Ordering::SeqCst);
             let downloaded_bytes = counter.load(atomic::Ordering::SeqCst);
+            reporter.report(downloaded_bytes, is_done).await;
             if !*crate::QUIET.read().await {
                 if let Some(len) = len {
                     print!("\rDownloading... {}%", downloaded_bytes * 100 / len);
@@ -149,14 +293,38 @@ pub async fn download(url: &str, name: Option<&str>) -> Result<PathBuf, crate::E
             println!("\rDownloading... 100%");
         }
     });
-    download_handle.await.unwrap()?;
+    let res = download_handle.await.unwrap();
     poll_handle.await.unwrap();
+    res.with_code(crate::error::NETWORK_ERROR)?;
+    Ok(())
+}
+
+pub async fn download(url: &str, name: Option<&str>) -> Result<PathBuf, crate::Error> {
+    let tmp_file_path =
+        Path::new(crate::TMP_DIR).join(&format!("{}.s9pk", name.unwrap_or("download")));
+    let part = partial_path(&tmp_file_path);
+    if part.exists() {
+        // may be left over from a different url
+        tokio::fs::remove_file(&part).await?;
+    }
+    retry(&format!("Download of {}", url), || {
+        download_to(url, &part, name)
+    })
+    .await?;
+    tokio::fs::rename(&part, &tmp_file_path).await?;
     Ok(tmp_file_path)
 }
 
 pub async fn install_url(url: &str, name: Option<&str>) -> Result<(), crate::Error> {
     let tmp_file_path = download(url, name).await?;
-    install_path(&tmp_file_path, name).await?;
+    let registry = crate::registry::for_url(url).await?;
+    install_path_as(
+        &tmp_file_path,
+        name,
+        registry.as_ref(),
+        InstallMode::Install,
+    )
+    .await?;
     tokio::fs::remove_file(&tmp_file_path)
         .await
         .with_context(|e| format!("{}: {}", tmp_file_path.display(), e))
@@ -164,7 +332,28 @@ pub async fn install_url(url: &str, name: Option<&str>) -> Result<(), crate::Err
     Ok(())
 }
 
+/// Installs a package from a local file. Only keys in the local trust store may sign it.
 pub async fn install_path<P: AsRef<Path>>(p: P, name: Option<&str>) -> Result<(), crate::Error> {
+    install_path_as(p, name, None, InstallMode::Install).await
+}
+
+/// Installs a new version of `name` fetched from `registry` over the current one,
+/// keeping the current version until the new one is ready.
+pub async fn update_path<P: AsRef<Path>>(
+    p: P,
+    name: &str,
+    registry: Option<&crate::registry::RegistryInfo>,
+    restart: bool,
+) -> Result<(), crate::Error> {
+    install_path_as(p, Some(name), registry, InstallMode::Update { restart }).await
+}
+
+async fn install_path_as<P: AsRef<Path>>(
+    p: P,
+    name: Option<&str>,
+    registry: Option<&crate::registry::RegistryInfo>,
+    mode: InstallMode,
+) -> Result<(), crate::Error> {
     let path = p.as_ref();
     log::info!(
         "Starting install of {}.",
@@ -173,20 +362,33 @@ pub async fn install_path<P: AsRef<Path>>(p: P, name: Option<&str>) -> Result<()
             .ok_or(Error::InvalidFileName)
             .no_code()?
     );
-    let file = tokio::fs::File::open(&path)
-        .await
-        .with_context(|e| format!("{}: {}", path.display(), e))
-        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    crate::progress::Event::new(name, crate::progress::Phase::Verifying)
+        .emit()
+        .await;
+    // verify and extract through the same handle, so the file cannot be swapped in between
+    let mut file = crate::s9pk::S9pk::open(&path).await?.into_archive().await?;
+    let start = file.seek(std::io::SeekFrom::Current(0)).await?;
+    if let Err(e) = crate::signing::verify_archive(&mut file, registry).await {
+        crate::progress::Event::new(name, crate::progress::Phase::Failed)
+            .message(&e)
+            .emit()
+            .await;
+        return Err(e);
+    }
+    file.seek(std::io::SeekFrom::Start(start)).await?;
     let len = file.metadata().await?.len();
     let done = Arc::new(AtomicBool::new(false));
     let counter = Arc::new(AtomicU64::new(0));
     let done_handle = done.clone();
     let name_clone = name.map(|a| a.to_owned());
     let counter_clone = counter.clone();
+    let mut reporter =
+        crate::progress::Reporter::new(name, crate::progress::Phase::Extracting, Some(len));
     let poll_handle = tokio::spawn(async move {
         loop {
             let is_done = done.load(atomic::Ordering::SeqCst);
             let installed_bytes = counter.load(atomic::Ordering::SeqCst);
+            reporter.report(installed_bytes, is_done).await;
             if !*crate::QUIET.read().await {
                 print!("\rInstalling... {}%", installed_bytes * 100 / len);
             }
@@ -200,10 +402,19 @@ pub async fn install_path<P: AsRef<Path>>(p: P, name: Option<&str>) -> Result<()
         }
     });
     let reader = CountingReader(file, counter_clone);
-    let res = install(reader, name_clone.as_ref().map(|a| a.as_str())).await;
+    let res = install(reader, name_clone.as_ref().map(|a| a.as_str()), mode).await;
     done_handle.store(true, atomic::Ordering::SeqCst);
-    res?;
     poll_handle.await.unwrap();
+    if let Err(e) = res {
+        crate::progress::Event::new(name, crate::progress::Phase::Failed)
+            .message(&e)
+            .emit()
+            .await;
+        return Err(e);
+    }
+    crate::progress::Event::new(name, crate::progress::Phase::Complete)
+        .emit()
+        .await;
     if !*crate::QUIET.read().await {
         println!("Complete.");
     }
@@ -213,6 +424,7 @@ pub async fn install_path<P: AsRef<Path>>(p: P, name: Option<&str>) -> Result<()
 pub async fn install<R: AsyncRead + Unpin + Send + Sync>(
     r: R,
     name: Option<&str>,
+    mode: InstallMode,
 ) -> Result<(), crate::Error> {
     log::info!("Extracting archive.");
     let mut pkg = tar::Archive::new(r);
@@ -231,7 +443,7 @@ pub async fn install<R: AsyncRead + Unpin + Send + Sync>(
     log::trace!("Deserializing manifest.");
     let manifest: Manifest = from_cbor_async_reader(manifest).await.no_code()?;
     match manifest {
-        Manifest::V0(m) => install_v0(m, entries, name).await?,
+        Manifest::V0(m) => install_v0(m, entries, name, mode).await?,
     };
     Ok(())
 }
@@ -240,6 +452,7 @@ pub async fn install_v0<R: AsyncRead + Unpin + Send + Sync>(
     manifest: ManifestV0,
     mut entries: tar::Entries<R>,
     name: Option<&str>,
+    mode: InstallMode,
 ) -> Result<(), crate::Error> {
     crate::ensure_code!(
         crate::version::Current::new()
@@ -256,36 +469,120 @@ pub async fn install_v0<R: AsyncRead + Unpin + Send + Sync>(
             "Package Name Does Not Match Expected"
         );
     }
-
-    log::info!(
-        "Creating metadata directory: {}/apps/{}",
-        crate::PERSISTENCE_DIR,
-        manifest.id
+    crate::ensure_code!(
+        manifest.image.supports(Arch::host()),
+        crate::error::VERSION_INCOMPATIBLE,
+        "{} Has No Image for {}",
+        manifest.id,
+        std::env::consts::ARCH
     );
-    let app_dir = PersistencePath::from_ref("apps").join(&manifest.id);
-    let app_dir_path = app_dir.path();
-    if app_dir_path.exists() {
-        tokio::fs::remove_dir_all(&app_dir_path).await?;
+
+    let mut graph = crate::graph::DependencyGraph::load().await?;
+    graph.insert(&manifest.id, manifest.dependencies.0.keys().cloned());
+    if let Some(cycle) = graph.find_cycle() {
+        return Err(format_err!(
+            "Installing {} Would Create a Dependency Cycle: {}",
+            manifest.id,
+            cycle.join(" -> ")
+        ))
+        .with_code(crate::error::GENERAL_ERROR);
     }
-    tokio::fs::create_dir_all(&app_dir_path).await?;
 
-    let (ip, tor_addr, tor_key) = crate::tor::set_svc(
-        &manifest.id,
-        crate::tor::NewService {
-            ports: manifest.ports.clone(),
-            hidden_service_version: manifest.hidden_service_version,
-        },
-    )
-    .await?;
+    let lock = InstallLock::acquire(&manifest.id).await?;
+    let mut journal = InstallJournal::begin(&lock).await?;
+    let mut res = install_v0_journaled(&manifest, &mut entries, &mut journal).await;
+    if res.is_ok() && mode == (InstallMode::Update { restart: true }) {
+        res = crate::update::await_ready(&manifest).await;
+    }
+    if let Err(e) = res {
+        log::error!("Failed to Install {}: {}", manifest.id, e);
+        if let Err(e) = journal.rollback().await {
+            log::error!("Failed to Roll Back Install: {}", e);
+        }
+        return Err(e);
+    }
+    journal.commit().await?;
+    drop(lock);
+    for (dep_id, dep_info) in manifest.dependencies.0 {
+        if dep_info.mount_shared
+            && crate::apps::list_info().await?.get(&dep_id).is_some()
+            && crate::apps::manifest(&dep_id).await?.shared.is_some()
+        {
+            match crate::apps::status(&dep_id, false).await?.status {
+                crate::apps::DockerStatus::Stopped => (),
+                crate::apps::DockerStatus::Running => crate::control::restart_app(&dep_id).await?,
+                _ => crate::apps::set_needs_restart(&dep_id, true).await?,
+            }
+        }
+    }
 
-    let recoverable = Path::new(crate::VOLUMES).join(&manifest.id).exists();
+    Ok(())
+}
 
-    log::info!("Creating volume {}/{}.", crate::VOLUMES, manifest.id);
-    tokio::fs::create_dir_all(Path::new(crate::VOLUMES).join(&manifest.id)).await?;
+/// Loads an image archive from a package. Thin packages go through a temporary file,
+/// so that the layers left out can be filled in from the local store or the registries.
+async fn load_image<R: AsyncRead + Unpin + Send>(
+    manifest: &ManifestV0,
+    image_file: &str,
+    image: &mut R,
+) -> Result<Vec<String>, crate::Error> {
+    let runtime = crate::runtime::current();
+    let layers = manifest
+        .layers
+        .get(image_file)
+        .map(|l| l.as_slice())
+        .unwrap_or(&[]);
+    if !manifest.thin || layers.is_empty() {
+        return runtime.load_image(image).await;
+    }
+    tokio::fs::create_dir_all(crate::TMP_DIR).await?;
+    let tmp_path = Path::new(crate::TMP_DIR).join(format!("{}-{}", manifest.id, image_file));
+    let res = async {
+        crate::layers::assemble(&mut *image, layers, &tmp_path).await?;
+        runtime
+            .load_image(&mut tokio::fs::File::open(&tmp_path).await?)
+            .await
+    }
+    .await;
+    if let Err(e) = tokio::fs::remove_file(&tmp_path).await {
+        log::warn!("{}: {}", tmp_path.display(), e);
+    }
+    res
+}
 
+/// Copies the files of `from` that `to` does not have.
+async fn carry_over(from: &Path, to: &Path) -> Result<(), crate::Error> {
+    let mut entries = tokio::fs::read_dir(from).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let dst = to.join(entry.file_name());
+        if entry.file_type().await?.is_file() && !dst.exists() {
+            tokio::fs::copy(entry.path(), &dst).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn install_v0_journaled<R: AsyncRead + Unpin + Send + Sync>(
+    manifest: &ManifestV0,
+    entries: &mut tar::Entries<R>,
+    journal: &mut InstallJournal,
+) -> Result<(), crate::Error> {
+    let app_dir = PersistencePath::from_ref("apps").join(&manifest.id);
+    let app_dir_path = app_dir.path();
+    // the metadata of the new version replaces the current one only once its image is loaded
+    let staging = journal.metadata_staging();
+    let staging_path = staging.path();
+    log::info!("Creating metadata directory: {}", staging_path.display());
+    journal.record(Step::MetadataStaged).await?;
+    if staging_path.exists() {
+        tokio::fs::remove_dir_all(&staging_path).await?;
+    }
+    tokio::fs::create_dir_all(&staging_path).await?;
+
+    tokio::fs::create_dir_all(PersistencePath::from_ref("apps").path()).await?;
     let _lock = app_dir.lock(true).await?;
     log::info!("Saving manifest.");
-    let mut manifest_out = app_dir.join("manifest.yaml").write(None).await?;
+    let mut manifest_out = staging.join("manifest.yaml").write(None).await?;
     to_yaml_async_writer(&mut *manifest_out, &Manifest::V0(manifest.clone())).await?;
     manifest_out.commit().await?;
     log::info!("Opening config spec from archive.");
@@ -302,7 +599,7 @@ pub async fn install_v0<R: AsyncRead + Unpin + Send + Sync>(
     log::trace!("Deserializing config spec.");
     let config_spec: ConfigSpec = from_cbor_async_reader(config_spec).await?;
     log::info!("Saving config spec.");
-    let mut config_spec_out = app_dir.join("config_spec.yaml").write(None).await?;
+    let mut config_spec_out = staging.join("config_spec.yaml").write(None).await?;
     to_yaml_async_writer(&mut *config_spec_out, &config_spec).await?;
     config_spec_out.commit().await?;
     log::info!("Opening config rules from archive.");
@@ -319,7 +616,7 @@ pub async fn install_v0<R: AsyncRead + Unpin + Send + Sync>(
     log::trace!("Deserializing config rules.");
     let config_rules: Vec<ConfigRuleEntry> = from_cbor_async_reader(config_rules).await?;
     log::info!("Saving config rules.");
-    let mut config_rules_out = app_dir.join("config_rules.yaml").write(None).await?;
+    let mut config_rules_out = staging.join("config_rules.yaml").write(None).await?;
     to_yaml_async_writer(&mut *config_rules_out, &config_rules).await?;
     config_rules_out.commit().await?;
     if manifest.has_instructions {
@@ -335,13 +632,47 @@ pub async fn install_v0<R: AsyncRead + Unpin + Send + Sync>(
             "Package File Invalid or Corrupted"
         );
         log::info!("Saving instructions.");
-        let mut instructions_out = app_dir.join("instructions.md").write(None).await?;
+        let mut instructions_out = staging.join("instructions.md").write(None).await?;
         tokio::io::copy(&mut instructions, &mut *instructions_out)
             .await
             .with_code(crate::error::FILESYSTEM_ERROR)?;
         instructions_out.commit().await?;
     }
 
+    let previous = crate::tor::services_map(&PersistencePath::from_ref(crate::SERVICES_YAML))
+        .await?
+        .map
+        .get(&manifest.id)
+        .cloned();
+    journal.record(Step::ServiceSet { previous }).await?;
+    let (ip, tor_addr, tor_key) = crate::tor::set_svc(
+        &manifest.id,
+        crate::tor::NewService {
+            ports: manifest.ports.clone(),
+            hidden_service_version: manifest.hidden_service_version,
+        },
+    )
+    .await?;
+
+    let recoverable = Path::new(crate::VOLUMES).join(&manifest.id).exists();
+
+    log::info!("Creating volume {}/{}.", crate::VOLUMES, manifest.id);
+    if recoverable {
+        let volume_config = journal.volume_config();
+        let existed = volume_config.exists();
+        journal.record(Step::VolumeConfigSaved { existed }).await?;
+        if existed {
+            let backup = journal.config_backup().path();
+            if let Some(parent) = backup.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::copy(&volume_config, &backup).await?;
+        }
+    } else {
+        journal.record(Step::VolumeCreated).await?;
+    }
+    tokio::fs::create_dir_all(Path::new(crate::VOLUMES).join(&manifest.id)).await?;
+
     log::info!("Copying over assets.");
     for asset in manifest.assets.iter() {
         let dst_path = Path::new(crate::VOLUMES)
@@ -398,44 +729,31 @@ pub async fn install_v0<R: AsyncRead + Unpin + Send + Sync>(
         }
     }
 
-    let tag = match &manifest.image {
+    // the new image is tagged by its version next to the previous one, which keeps running meanwhile
+    let runtime = crate::runtime::current();
+    let image_name = runtime.image_name(&manifest.id);
+    let version_tag = format!("{}", manifest.version);
+    let latest = format!("{}:latest", image_name);
+    let tag = format!("{}:{}", image_name, version_tag);
+    if runtime.image_exists(&latest).await? {
+        let previous = crate::apps::list_info()
+            .await?
+            .get(&manifest.id)
+            .map(|info| format!("{}", info.version))
+            .filter(|v| v != &version_tag)
+            .unwrap_or_else(|| crate::journal::ROLLBACK_TAG.to_owned());
+        journal
+            .record(Step::ImageTagged {
+                image: image_name.clone(),
+                tag: previous.clone(),
+            })
+            .await?;
+        runtime
+            .tag(&latest, &format!("{}:{}", image_name, previous))
+            .await?;
+    }
+    match &manifest.image {
         ImageConfig::Tar => {
-            let image_name = format!("start9/{}", manifest.id);
-            let tag = format!("{}:latest", image_name);
-            if tokio::process::Command::new("docker")
-                .arg("images")
-                .arg("-q")
-                .arg(&image_name)
-                .output()
-                .await?
-                .stdout
-                .len()
-                > 0
-            {
-                tokio::process::Command::new("docker")
-                    .arg("stop")
-                    .arg(&manifest.id)
-                    .spawn()?
-                    .wait()
-                    .await?;
-                tokio::process::Command::new("docker")
-                    .arg("rm")
-                    .arg(&manifest.id)
-                    .spawn()?
-                    .wait()
-                    .await?;
-                crate::ensure_code!(
-                    tokio::process::Command::new("docker")
-                        .arg("rmi")
-                        .arg(&image_name)
-                        .output()
-                        .await?
-                        .status
-                        .success(),
-                    crate::error::DOCKER_ERROR,
-                    "Failed to Remove Existing Image"
-                )
-            }
             log::info!("Opening image.tar from archive.");
             let mut image = entries
                 .next()
@@ -449,95 +767,137 @@ pub async fn install_v0<R: AsyncRead + Unpin + Send + Sync>(
                     image_path.display()
                 )));
             }
-            log::info!(
-                "Loading docker image start9/{} from image.tar.",
-                manifest.id
-            );
-            let mut child = tokio::process::Command::new("docker")
-                .arg("load")
-                .stdin(std::process::Stdio::piped())
-                .stdout(std::process::Stdio::inherit())
-                .stderr(match log::max_level() {
-                    log::LevelFilter::Error => std::process::Stdio::null(),
-                    _ => std::process::Stdio::inherit(),
+            log::info!("Loading image {} from image.tar.", image_name);
+            crate::progress::Event::new(Some(&manifest.id), crate::progress::Phase::LoadingImage)
+                .bytes(0, image.header().size().ok())
+                .emit()
+                .await;
+            journal
+                .record(Step::ImageLoaded {
+                    image: image_name.clone(),
+                    tag: version_tag.clone(),
                 })
-                .spawn()?;
-            let mut child_in = child.stdin.take().unwrap();
-            tokio::io::copy(&mut image, &mut child_in).await?;
-            child_in.flush().await?;
-            child_in.shutdown().await?;
-            drop(child_in);
-            crate::ensure_code!(
-                child.wait().await?.success(),
-                crate::error::DOCKER_ERROR,
-                "Failed to Load Docker Image From Tar"
-            );
-            tag
+                .await?;
+            load_image(manifest, "image.tar", &mut image).await?;
+            runtime.tag(&latest, &tag).await?;
+        }
+        ImageConfig::Oci { arches } => {
+            let host = Arch::host();
+            for arch in arches {
+                let image_file = arch.image_file();
+                log::info!("Opening {} from archive.", image_file);
+                let mut image = entries
+                    .next()
+                    .await
+                    .ok_or(Error::CorruptedPkgFile("missing image"))
+                    .no_code()??;
+                let image_path = image.path()?;
+                if image_path != Path::new(&image_file) {
+                    return Err(crate::Error::from(format_err!(
+                        "Package File Invalid or Corrupted: expected {}, got {}",
+                        image_file,
+                        image_path.display()
+                    )));
+                }
+                if Some(*arch) != host {
+                    continue;
+                }
+                log::info!("Loading image {} from {}.", image_name, image_file);
+                crate::progress::Event::new(
+                    Some(&manifest.id),
+                    crate::progress::Phase::LoadingImage,
+                )
+                .bytes(0, image.header().size().ok())
+                .emit()
+                .await;
+                journal
+                    .record(Step::ImageLoaded {
+                        image: image_name.clone(),
+                        tag: version_tag.clone(),
+                    })
+                    .await?;
+                let loaded = load_image(manifest, &image_file, &mut image).await?;
+                let source = loaded
+                    .first()
+                    .ok_or_else(|| format_err!("No Image Loaded From {}", image_file))
+                    .with_code(crate::error::DOCKER_ERROR)?;
+                runtime.tag(source, &latest).await?;
+                runtime.tag(source, &tag).await?;
+            }
         }
-    };
-    log::info!("Creating docker container: {} from {}.", manifest.id, tag);
-    let volume_arg = format!(
-        "type=bind,src={}/{},dst={}",
-        crate::VOLUMES,
-        manifest.id,
-        manifest.mount.display()
-    );
-    let mut args = vec![
-        Cow::Borrowed(OsStr::new("create")),
-        Cow::Borrowed(OsStr::new("--restart")),
-        Cow::Borrowed(OsStr::new("no")),
-        Cow::Borrowed(OsStr::new("--name")),
-        Cow::Borrowed(OsStr::new(&manifest.id)),
-        Cow::Borrowed(OsStr::new("--mount")),
-        Cow::Borrowed(OsStr::new(&volume_arg)),
-        Cow::Borrowed(OsStr::new("--net")),
-        Cow::Borrowed(OsStr::new("start9")),
-        Cow::Borrowed(OsStr::new("--ip")),
-        Cow::Owned(OsString::from(format!("{}", ip))),
-    ];
-    if let (Some(ref tor_addr), Some(ref tor_key)) = (&tor_addr, &tor_key) {
-        args.extend(
-            std::iter::empty()
-                .chain(std::iter::once(Cow::Borrowed(OsStr::new("--env"))))
-                .chain(std::iter::once(Cow::Owned(OsString::from(format!(
-                    "TOR_ADDRESS={}",
-                    tor_addr
-                )))))
-                .chain(std::iter::once(Cow::Borrowed(OsStr::new("--env"))))
-                .chain(std::iter::once(Cow::Owned(OsString::from(format!(
-                    "TOR_KEY={}",
-                    tor_key
-                ))))),
-        );
     }
-    if let Some(shm_size_mb) = manifest.shm_size_mb {
-        args.push(Cow::Borrowed(OsStr::new("--shm-size")));
-        args.push(Cow::Owned(OsString::from(format!("{}m", shm_size_mb))));
+    if let Ok(state) = runtime.inspect(&manifest.id).await {
+        if state.running {
+            journal.record(Step::ContainerStopped).await?;
+            runtime.stop(&manifest.id, 10).await?;
+        }
+        let rollback_container = journal.rollback_container();
+        if runtime.inspect(&rollback_container).await.is_ok() {
+            runtime.remove(&rollback_container, true).await?;
+        }
+        journal.record(Step::ContainerRenamed).await?;
+        runtime.rename(&manifest.id, &rollback_container).await?;
     }
-    args.push(Cow::Borrowed(OsStr::new(&tag)));
-    crate::ensure_code!(
-        std::process::Command::new("docker")
-            .args(&args)
-            .stdout(std::process::Stdio::null())
-            .stderr(match log::max_level() {
-                log::LevelFilter::Error => std::process::Stdio::null(),
-                _ => std::process::Stdio::inherit(),
-            })
-            .status()?
-            .success(),
-        crate::error::DOCKER_ERROR,
-        "Failed to Create Docker Container"
+
+    log::info!(
+        "Moving metadata to {}/apps/{}",
+        crate::PERSISTENCE_DIR,
+        manifest.id
     );
+    if app_dir_path.exists() {
+        // keep what the previous version accumulated, like the config history
+        carry_over(&app_dir_path, &staging_path).await?;
+        let backup = journal.metadata_backup().path();
+        if backup.exists() {
+            tokio::fs::remove_dir_all(&backup).await?;
+        }
+        if let Some(parent) = backup.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        journal.record(Step::MetadataMoved).await?;
+        tokio::fs::rename(&app_dir_path, &backup).await?;
+    } else {
+        journal.record(Step::MetadataCreated).await?;
+    }
+    tokio::fs::rename(&staging_path, &app_dir_path).await?;
+    log::info!("Creating container: {} from {}.", manifest.id, tag);
+    crate::progress::Event::new(
+        Some(&manifest.id),
+        crate::progress::Phase::CreatingContainer,
+    )
+    .emit()
+    .await;
+    let mut env = Vec::new();
+    if let (Some(ref tor_addr), Some(ref tor_key)) = (&tor_addr, &tor_key) {
+        env.push(format!("TOR_ADDRESS={}", tor_addr));
+        env.push(format!("TOR_KEY={}", tor_key));
+    }
+    journal.record(Step::ContainerCreated).await?;
+    runtime
+        .create(&ContainerConfig {
+            name: manifest.id.clone(),
+            image: tag,
+            env,
+            mounts: vec![Mount {
+                source: Path::new(crate::VOLUMES).join(&manifest.id),
+                target: manifest.mount.clone(),
+            }],
+            network: Some(("start9".to_owned(), ip)),
+            shm_size_mb: manifest.shm_size_mb,
+        })
+        .await?;
     tokio::fs::create_dir_all(Path::new(crate::VOLUMES).join(&manifest.id).join("start9")).await?;
-    if let Some(public) = manifest.public {
+    if let Some(public) = &manifest.public {
         tokio::fs::create_dir_all(Path::new(crate::VOLUMES).join(&manifest.id).join(public))
             .await?;
     }
-    if let Some(shared) = manifest.shared {
+    if let Some(shared) = &manifest.shared {
         tokio::fs::create_dir_all(Path::new(crate::VOLUMES).join(&manifest.id).join(shared))
             .await?;
     }
     log::info!("Updating app list.");
+    let previous = crate::apps::list_info().await?.get(&manifest.id).cloned();
+    journal.record(Step::AppInfoSet { previous }).await?;
     crate::apps::add(
         &manifest.id,
         crate::apps::AppInfo {
@@ -550,6 +910,9 @@ pub async fn install_v0<R: AsyncRead + Unpin + Send + Sync>(
         },
     )
     .await?;
+    crate::progress::Event::new(Some(&manifest.id), crate::progress::Phase::Configuring)
+        .emit()
+        .await;
     let config = crate::apps::config(&manifest.id).await?;
     if let Some(cfg) = config.config {
         if config.spec.matches(&cfg).is_ok() {
@@ -558,22 +921,17 @@ pub async fn install_v0<R: AsyncRead + Unpin + Send + Sync>(
     } else {
         let empty_config = crate::config::Config::default();
         if config.spec.matches(&empty_config).is_ok() {
-            crate::config::configure(&manifest.id, Some(empty_config), None, false).await?;
+            crate::config::configure(
+                &manifest.id,
+                Some(empty_config),
+                None,
+                false,
+                crate::config::Trigger::Install,
+            )
+            .await?;
         }
     }
     crate::dependencies::update_binds(&manifest.id).await?;
-    for (dep_id, dep_info) in manifest.dependencies.0 {
-        if dep_info.mount_shared
-            && crate::apps::list_info().await?.get(&dep_id).is_some()
-            && crate::apps::manifest(&dep_id).await?.shared.is_some()
-        {
-            match crate::apps::status(&dep_id, false).await?.status {
-                crate::apps::DockerStatus::Stopped => (),
-                crate::apps::DockerStatus::Running => crate::control::restart_app(&dep_id).await?,
-                _ => crate::apps::set_needs_restart(&dep_id, true).await?,
-            }
-        }
-    }
 
     Ok(())
 }
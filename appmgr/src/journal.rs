@@ -0,0 +1,678 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use failure::ResultExt as _;
+use file_lock::FileLock;
+
+use crate::apps::AppInfo;
+use crate::runtime::ContainerRuntime;
+use crate::tor::{NewService, Service};
+use crate::util::{
+    from_yaml_async_reader, lock_file, to_yaml_async_writer, try_lock_file, PersistencePath,
+};
+use crate::{Error, ResultExt as _};
+
+pub const JOURNAL_DIR: &'static str = "journal";
+pub const ROLLBACK_DIR: &'static str = "rollback";
+pub const STAGING_DIR: &'static str = "staging";
+/// The tag kept on the previous image when the new version has the same version tag.
+pub const ROLLBACK_TAG: &'static str = "rollback";
+
+lazy_static::lazy_static! {
+    /// Ids locked by this process. File locks are held per process,
+    /// so they alone would not keep recovery in the daemon away from its own installs.
+    static ref LOCKED: std::sync::Mutex<HashSet<String>> = Default::default();
+}
+
+/// Held for the whole journaled part of an install of `id`.
+/// Recovery leaves the journal of a locked install alone.
+pub struct InstallLock {
+    id: String,
+    lock: Option<FileLock>,
+}
+impl InstallLock {
+    /// Waits for an install of `id` by another process to finish.
+    pub async fn acquire(id: &str) -> Result<Self, Error> {
+        let path = lock_path(id).await?;
+        let mut guard = InstallLock::claim(id)?
+            .ok_or_else(|| failure::format_err!("{} Is Already Being Installed", id))
+            .with_code(crate::error::GENERAL_ERROR)?;
+        guard.lock = Some(
+            lock_file(path.clone(), true)
+                .await
+                .with_context(|e| format!("{}: {}", path, e))
+                .with_code(crate::error::FILESYSTEM_ERROR)?,
+        );
+        Ok(guard)
+    }
+
+    /// Returns `None` if an install of `id` holds the lock.
+    async fn try_acquire(id: &str) -> Result<Option<Self>, Error> {
+        let path = lock_path(id).await?;
+        let mut guard = match InstallLock::claim(id)? {
+            Some(guard) => guard,
+            None => return Ok(None),
+        };
+        guard.lock = match try_lock_file(path.clone(), true)
+            .await
+            .with_context(|e| format!("{}: {}", path, e))
+            .with_code(crate::error::FILESYSTEM_ERROR)?
+        {
+            Some(lock) => Some(lock),
+            None => return Ok(None),
+        };
+        Ok(Some(guard))
+    }
+
+    fn claim(id: &str) -> Result<Option<Self>, Error> {
+        let mut locked = LOCKED
+            .lock()
+            .map_err(|_| failure::format_err!("Install Lock Poisoned"))
+            .no_code()?;
+        Ok(if locked.insert(id.to_owned()) {
+            Some(InstallLock {
+                id: id.to_owned(),
+                lock: None,
+            })
+        } else {
+            None
+        })
+    }
+}
+impl Drop for InstallLock {
+    fn drop(&mut self) {
+        if let Ok(mut locked) = LOCKED.lock() {
+            locked.remove(&self.id);
+        }
+    }
+}
+
+async fn lock_path(id: &str) -> Result<String, Error> {
+    let dir = PersistencePath::from_ref(JOURNAL_DIR).path();
+    tokio::fs::create_dir_all(&dir).await?;
+    let path = dir.join(format!("{}.lock", id));
+    if tokio::fs::metadata(&path).await.is_err() {
+        tokio::fs::File::create(&path).await?;
+    }
+    Ok(path.display().to_string())
+}
+
+/// An install step. Each step is journaled *before* it is performed,
+/// so undoing a step must tolerate it never having happened.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "kebab-case")]
+pub enum Step {
+    MetadataStaged,
+    MetadataCreated,
+    MetadataMoved,
+    ServiceSet {
+        previous: Option<Service>,
+    },
+    VolumeCreated,
+    VolumeConfigSaved {
+        existed: bool,
+    },
+    ContainerStopped,
+    ContainerRenamed,
+    /// The previous image was tagged `image:tag`, so it survives loading the new one.
+    ImageTagged {
+        image: String,
+        tag: String,
+    },
+    /// The new image was loaded as `image:latest` and tagged `image:tag`.
+    ImageLoaded {
+        image: String,
+        tag: String,
+    },
+    ContainerCreated,
+    AppInfoSet {
+        previous: Option<AppInfo>,
+    },
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InstallJournal {
+    pub id: String,
+    pub steps: Vec<Step>,
+}
+impl InstallJournal {
+    /// Starts a new journal for the locked id, rolling back any install that was left unfinished.
+    pub async fn begin(lock: &InstallLock) -> Result<Self, Error> {
+        roll_back_unfinished(&lock.id).await?;
+        let journal = InstallJournal {
+            id: lock.id.clone(),
+            steps: Vec::new(),
+        };
+        journal.save().await?;
+        Ok(journal)
+    }
+
+    pub async fn record(&mut self, step: Step) -> Result<(), Error> {
+        self.steps.push(step);
+        self.save().await
+    }
+
+    /// Where the metadata of the new version is written before it replaces the current one.
+    pub fn metadata_staging(&self) -> PersistencePath {
+        PersistencePath::from_ref(STAGING_DIR).join(&self.id)
+    }
+
+    pub fn metadata_backup(&self) -> PersistencePath {
+        PersistencePath::from_ref(ROLLBACK_DIR).join(&self.id)
+    }
+
+    pub fn config_backup(&self) -> PersistencePath {
+        PersistencePath::from_ref(ROLLBACK_DIR).join(format!("{}.config.yaml", self.id))
+    }
+
+    pub fn volume_config(&self) -> PathBuf {
+        Path::new(crate::VOLUMES)
+            .join(&self.id)
+            .join("start9")
+            .join("config.yaml")
+    }
+
+    pub fn rollback_container(&self) -> String {
+        format!("{}_rollback", self.id)
+    }
+
+    async fn save(&self) -> Result<(), Error> {
+        let mut f = journal_path(&self.id).write(None).await?;
+        to_yaml_async_writer(&mut *f, self).await?;
+        f.commit().await
+    }
+
+    /// Discards everything kept around to undo the install.
+    pub async fn commit(self) -> Result<(), Error> {
+        let runtime = crate::runtime::current();
+        // in reverse, so the previous container is removed before its image
+        for step in self.steps.iter().rev() {
+            match step {
+                Step::MetadataStaged => {
+                    let staging = self.metadata_staging().path();
+                    if staging.exists() {
+                        tokio::fs::remove_dir_all(&staging).await?;
+                    }
+                }
+                Step::MetadataMoved => {
+                    let backup = self.metadata_backup().path();
+                    if backup.exists() {
+                        tokio::fs::remove_dir_all(&backup).await?;
+                    }
+                }
+                Step::VolumeConfigSaved { .. } => self.config_backup().delete().await?,
+                Step::ContainerRenamed => {
+                    if let Err(e) = runtime.remove(&self.rollback_container(), true).await {
+                        log::warn!("Failed to Remove Previous Container: {}", e);
+                    }
+                }
+                Step::ImageTagged { image, tag } => {
+                    if let Err(e) = runtime.remove_image(&format!("{}:{}", image, tag)).await {
+                        log::warn!("Failed to Remove Previous Image: {}", e);
+                    }
+                }
+                _ => (),
+            }
+        }
+        journal_path(&self.id).delete().await
+    }
+
+    /// Undoes every journaled step in reverse order.
+    /// Failures are logged and do not stop the remaining steps from being undone.
+    pub async fn rollback(self) -> Result<(), Error> {
+        self.rollback_with(crate::runtime::current()).await
+    }
+
+    async fn rollback_with(self, runtime: &dyn ContainerRuntime) -> Result<(), Error> {
+        log::warn!("Rolling back install of {}.", self.id);
+        for step in self.steps.iter().rev() {
+            if let Err(e) = self.undo(runtime, step).await {
+                log::error!("Failed to Undo {:?}: {}", step, e);
+            }
+        }
+        journal_path(&self.id).delete().await
+    }
+
+    async fn undo(&self, runtime: &dyn ContainerRuntime, step: &Step) -> Result<(), Error> {
+        let app_dir = PersistencePath::from_ref("apps").join(&self.id).path();
+        match step {
+            Step::MetadataStaged => {
+                let staging = self.metadata_staging().path();
+                if staging.exists() {
+                    tokio::fs::remove_dir_all(&staging).await?;
+                }
+            }
+            Step::MetadataCreated => {
+                if app_dir.exists() {
+                    tokio::fs::remove_dir_all(&app_dir).await?;
+                }
+            }
+            Step::MetadataMoved => {
+                let backup = self.metadata_backup().path();
+                if backup.exists() {
+                    if app_dir.exists() {
+                        tokio::fs::remove_dir_all(&app_dir).await?;
+                    }
+                    tokio::fs::rename(&backup, &app_dir).await?;
+                }
+            }
+            Step::ServiceSet {
+                previous: Some(previous),
+            } => {
+                crate::tor::set_svc(
+                    &self.id,
+                    NewService {
+                        ports: previous.ports.clone(),
+                        hidden_service_version: previous.hidden_service_version,
+                    },
+                )
+                .await?;
+            }
+            Step::ServiceSet { previous: None } => crate::tor::rm_svc(&self.id).await?,
+            Step::VolumeCreated => {
+                let volume = Path::new(crate::VOLUMES).join(&self.id);
+                if volume.exists() {
+                    tokio::fs::remove_dir_all(&volume).await?;
+                }
+            }
+            Step::VolumeConfigSaved { existed } => {
+                let backup = self.config_backup();
+                let volume_config = self.volume_config();
+                if *existed && backup.exists().await {
+                    tokio::fs::copy(backup.path(), &volume_config).await?;
+                } else if !*existed && volume_config.exists() {
+                    tokio::fs::remove_file(&volume_config).await?;
+                }
+                backup.delete().await?;
+            }
+            Step::ContainerStopped => runtime.start(&self.id).await?,
+            Step::ContainerRenamed => runtime.rename(&self.rollback_container(), &self.id).await?,
+            Step::ImageTagged { image, tag } => {
+                let previous = format!("{}:{}", image, tag);
+                runtime.tag(&previous, &format!("{}:latest", image)).await?;
+                if tag == ROLLBACK_TAG {
+                    runtime.remove_image(&previous).await?;
+                }
+            }
+            Step::ImageLoaded { image, tag } => {
+                for tag in &[tag.as_str(), "latest"] {
+                    let image = format!("{}:{}", image, tag);
+                    if runtime.image_exists(&image).await? {
+                        runtime.remove_image(&image).await?;
+                    }
+                }
+            }
+            Step::ContainerCreated => runtime.remove(&self.id, true).await?,
+            Step::AppInfoSet {
+                previous: Some(previous),
+            } => crate::apps::add(&self.id, previous.clone()).await?,
+            Step::AppInfoSet { previous: None } => crate::apps::remove(&self.id).await?,
+        }
+        Ok(())
+    }
+}
+
+fn journal_path(id: &str) -> PersistencePath {
+    PersistencePath::from_ref(JOURNAL_DIR).join(format!("{}.yaml", id))
+}
+
+/// Rolls back an install of `id` that was interrupted before it could finish.
+/// An install that is still running is left alone.
+pub async fn recover(id: &str) -> Result<(), Error> {
+    let _lock = match InstallLock::try_acquire(id).await? {
+        Some(lock) => lock,
+        None => {
+            log::info!("{} Is Being Installed, Not Recovering It.", id);
+            return Ok(());
+        }
+    };
+    roll_back_unfinished(id).await
+}
+
+/// The caller must hold the `InstallLock` of `id`.
+async fn roll_back_unfinished(id: &str) -> Result<(), Error> {
+    let path = journal_path(id);
+    let journal: InstallJournal = match path.maybe_read(false).await.transpose()? {
+        Some(mut f) => from_yaml_async_reader(&mut *f).await?,
+        None => return Ok(()),
+    };
+    log::warn!("Found unfinished install of {}.", id);
+    journal.rollback().await
+}
+
+/// Rolls back every install that was interrupted, e.g. by a power loss.
+pub async fn recover_all() -> Result<(), Error> {
+    let dir = PersistencePath::from_ref(JOURNAL_DIR).path();
+    if !dir.exists() {
+        return Ok(());
+    }
+    let mut entries = tokio::fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("yaml") {
+            continue;
+        }
+        if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
+            if let Err(e) = recover(id).await {
+                log::error!("Failed to Recover Install of {}: {}", id, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use linear_map::LinearMap;
+    use tokio::io::AsyncRead;
+
+    use super::*;
+    use crate::logs::LogOptions;
+    use crate::runtime::{ContainerConfig, ContainerState, Logs, RunConfig};
+    use crate::ResultExt as _;
+
+    /// A container runtime that only keeps track of image references and containers,
+    /// supporting just what undoing install steps needs.
+    #[derive(Default)]
+    struct FakeRuntime {
+        /// `repo:tag` to image id.
+        images: Mutex<LinearMap<String, String>>,
+        /// Container name to the id of its image and whether it is running.
+        containers: Mutex<LinearMap<String, (String, bool)>>,
+        /// Every call that changed something, as `method args...`, failed or not.
+        calls: Mutex<Vec<String>>,
+    }
+    impl FakeRuntime {
+        fn call(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn unsupported<T>(method: &str) -> Result<T, Error> {
+            Err(format_err!(
+                "{} Is Not Supported by the Fake Runtime",
+                method
+            ))
+            .with_code(crate::error::DOCKER_ERROR)
+        }
+
+        fn reference(image: &str) -> String {
+            match image.rfind(':') {
+                Some(idx) if !image[idx..].contains('/') => image.to_owned(),
+                _ => format!("{}:latest", image),
+            }
+        }
+
+        fn image_id(&self, image: &str) -> Result<String, Error> {
+            self.images
+                .lock()
+                .unwrap()
+                .get(&Self::reference(image))
+                .cloned()
+                .ok_or_else(|| format_err!("No Such Image: {}", image))
+                .with_code(crate::error::NOT_FOUND)
+        }
+
+        fn container<T, F: FnOnce(&mut (String, bool)) -> T>(
+            &self,
+            name: &str,
+            f: F,
+        ) -> Result<T, Error> {
+            self.containers
+                .lock()
+                .unwrap()
+                .get_mut(name)
+                .map(f)
+                .ok_or_else(|| format_err!("No Such Container: {}", name))
+                .with_code(crate::error::NOT_FOUND)
+        }
+    }
+    #[async_trait]
+    impl ContainerRuntime for FakeRuntime {
+        async fn image_exists(&self, image: &str) -> Result<bool, Error> {
+            Ok(self.image_id(image).is_ok())
+        }
+        async fn load_image(
+            &self,
+            _image: &mut (dyn AsyncRead + Unpin + Send),
+        ) -> Result<Vec<String>, Error> {
+            Self::unsupported("load_image")
+        }
+        async fn remove_image(&self, image: &str) -> Result<(), Error> {
+            self.call(format!("remove_image {}", image));
+            self.image_id(image)?;
+            self.images.lock().unwrap().remove(&Self::reference(image));
+            Ok(())
+        }
+        async fn tag(&self, source: &str, target: &str) -> Result<(), Error> {
+            self.call(format!("tag {} {}", source, target));
+            let id = self.image_id(source)?;
+            self.images
+                .lock()
+                .unwrap()
+                .insert(Self::reference(target), id);
+            Ok(())
+        }
+        async fn prune_images(&self) -> Result<(), Error> {
+            Self::unsupported("prune_images")
+        }
+        async fn create(&self, _config: &ContainerConfig) -> Result<(), Error> {
+            Self::unsupported("create")
+        }
+        async fn start(&self, name: &str) -> Result<(), Error> {
+            self.call(format!("start {}", name));
+            self.container(name, |c| c.1 = true)
+        }
+        async fn stop(&self, _name: &str, _timeout_secs: u64) -> Result<(), Error> {
+            Self::unsupported("stop")
+        }
+        async fn pause(&self, _name: &str) -> Result<(), Error> {
+            Self::unsupported("pause")
+        }
+        async fn unpause(&self, _name: &str) -> Result<(), Error> {
+            Self::unsupported("unpause")
+        }
+        async fn inspect(&self, _name: &str) -> Result<ContainerState, Error> {
+            Self::unsupported("inspect")
+        }
+        async fn logs(
+            &self,
+            _name: &str,
+            _options: &LogOptions<&str, &str>,
+        ) -> Result<Logs, Error> {
+            Self::unsupported("logs")
+        }
+        async fn remove(&self, name: &str, _force: bool) -> Result<(), Error> {
+            self.call(format!("remove {}", name));
+            self.container(name, |_| ())?;
+            self.containers.lock().unwrap().remove(name);
+            Ok(())
+        }
+        async fn rename(&self, name: &str, new_name: &str) -> Result<(), Error> {
+            self.call(format!("rename {} {}", name, new_name));
+            self.container(name, |_| ())?;
+            let mut containers = self.containers.lock().unwrap();
+            let container = containers.remove(name).unwrap();
+            containers.insert(new_name.to_owned(), container);
+            Ok(())
+        }
+        /// A command that fails, as the fake runtime has no containers to run anything in.
+        fn exec(&self, _name: &str, _command: &[String]) -> tokio::process::Command {
+            tokio::process::Command::new("false")
+        }
+        fn run(&self, _config: &RunConfig) -> tokio::process::Command {
+            tokio::process::Command::new("false")
+        }
+    }
+
+    fn runtime(images: &[(&str, &str)], containers: &[(&str, &str, bool)]) -> FakeRuntime {
+        let runtime = FakeRuntime::default();
+        for (image, id) in images {
+            runtime
+                .images
+                .lock()
+                .unwrap()
+                .insert(format!("start9/{}", image), id.to_string());
+        }
+        for (name, id, running) in containers {
+            runtime
+                .containers
+                .lock()
+                .unwrap()
+                .insert(name.to_string(), (id.to_string(), *running));
+        }
+        runtime
+    }
+
+    fn update_steps(id: &str, previous_tag: &str, tag: &str) -> InstallJournal {
+        let image = format!("start9/{}", id);
+        InstallJournal {
+            id: id.to_owned(),
+            steps: vec![
+                Step::ImageTagged {
+                    image: image.clone(),
+                    tag: previous_tag.to_owned(),
+                },
+                Step::ImageLoaded {
+                    image,
+                    tag: tag.to_owned(),
+                },
+                Step::ContainerStopped,
+                Step::ContainerRenamed,
+                Step::ContainerCreated,
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn revert_update() {
+        let id = "appmgr-test-revert";
+        let fake = runtime(
+            &[
+                ("appmgr-test-revert:0.1.0", "old"),
+                ("appmgr-test-revert:0.2.0", "new"),
+                ("appmgr-test-revert:latest", "new"),
+            ],
+            &[
+                ("appmgr-test-revert", "new", true),
+                ("appmgr-test-revert_rollback", "old", false),
+            ],
+        );
+        update_steps(id, "0.1.0", "0.2.0")
+            .rollback_with(&fake)
+            .await
+            .unwrap();
+        let mut images = LinearMap::new();
+        images.insert(format!("start9/{}:0.1.0", id), "old".to_owned());
+        images.insert(format!("start9/{}:latest", id), "old".to_owned());
+        assert_eq!(*fake.images.lock().unwrap(), images);
+        let mut containers = LinearMap::new();
+        containers.insert(id.to_owned(), ("old".to_owned(), true));
+        assert_eq!(*fake.containers.lock().unwrap(), containers);
+    }
+
+    #[tokio::test]
+    async fn revert_reinstall() {
+        let id = "appmgr-test-reinstall";
+        let fake = runtime(
+            &[
+                ("appmgr-test-reinstall:rollback", "old"),
+                ("appmgr-test-reinstall:0.1.0", "new"),
+                ("appmgr-test-reinstall:latest", "new"),
+            ],
+            &[
+                ("appmgr-test-reinstall", "new", false),
+                ("appmgr-test-reinstall_rollback", "old", false),
+            ],
+        );
+        let mut journal = update_steps(id, ROLLBACK_TAG, "0.1.0");
+        // the previous container was not running
+        journal
+            .steps
+            .retain(|s| !matches!(s, Step::ContainerStopped));
+        journal.rollback_with(&fake).await.unwrap();
+        let mut images = LinearMap::new();
+        images.insert(format!("start9/{}:latest", id), "old".to_owned());
+        assert_eq!(*fake.images.lock().unwrap(), images);
+        let mut containers = LinearMap::new();
+        containers.insert(id.to_owned(), ("old".to_owned(), false));
+        assert_eq!(*fake.containers.lock().unwrap(), containers);
+    }
+
+    #[tokio::test]
+    async fn rollback_order() {
+        let id = "appmgr-test-order";
+        let fake = runtime(
+            &[
+                ("appmgr-test-order:0.1.0", "old"),
+                ("appmgr-test-order:0.2.0", "new"),
+                ("appmgr-test-order:latest", "new"),
+            ],
+            &[("appmgr-test-order_rollback", "old", false)],
+        );
+        // the new container was never created, so undoing that step fails
+        update_steps(id, "0.1.0", "0.2.0")
+            .rollback_with(&fake)
+            .await
+            .unwrap();
+        assert_eq!(
+            *fake.calls.lock().unwrap(),
+            vec![
+                "remove appmgr-test-order",
+                "rename appmgr-test-order_rollback appmgr-test-order",
+                "start appmgr-test-order",
+                "remove_image start9/appmgr-test-order:0.2.0",
+                "remove_image start9/appmgr-test-order:latest",
+                "tag start9/appmgr-test-order:0.1.0 start9/appmgr-test-order:latest",
+            ]
+        );
+        assert_eq!(
+            fake.containers.lock().unwrap().get(id),
+            Some(&("old".to_owned(), true))
+        );
+    }
+
+    #[tokio::test]
+    async fn interrupted() {
+        let id = "appmgr-test-interrupted";
+        let fake = runtime(
+            &[
+                ("appmgr-test-interrupted:0.1.0", "old"),
+                ("appmgr-test-interrupted:latest", "old"),
+            ],
+            &[("appmgr-test-interrupted", "old", false)],
+        );
+        // interrupted right after journaling the image load, before the load itself
+        let mut journal = update_steps(id, "0.1.0", "0.2.0");
+        journal.steps.truncate(2);
+        let saved = serde_yaml::to_vec(&journal).unwrap();
+        let journal: InstallJournal = serde_yaml::from_slice(&saved).unwrap();
+        assert_eq!(journal.steps.len(), 2);
+        journal.rollback_with(&fake).await.unwrap();
+        let mut images = LinearMap::new();
+        images.insert(format!("start9/{}:0.1.0", id), "old".to_owned());
+        images.insert(format!("start9/{}:latest", id), "old".to_owned());
+        assert_eq!(*fake.images.lock().unwrap(), images);
+        assert_eq!(
+            fake.containers.lock().unwrap().get(id),
+            Some(&("old".to_owned(), false))
+        );
+    }
+
+    #[tokio::test]
+    async fn recover_skips_locked() {
+        let id = "appmgr-test-recover-locked";
+        let lock = InstallLock::acquire(id).await.unwrap();
+        InstallJournal::begin(&lock).await.unwrap();
+        assert!(InstallLock::acquire(id).await.is_err());
+        recover(id).await.unwrap();
+        assert!(journal_path(id).exists().await);
+        drop(lock);
+        recover(id).await.unwrap();
+        assert!(!journal_path(id).exists().await);
+    }
+}
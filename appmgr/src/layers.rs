@@ -0,0 +1,337 @@
+use std::path::{Path, PathBuf};
+
+use failure::ResultExt as _;
+use futures::stream::StreamExt;
+use linear_map::set::LinearSet;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio_tar as tar;
+
+use crate::util::from_json_async_reader;
+use crate::Error;
+use crate::ResultExt as _;
+
+/// A layer blob of an image archive, addressed by its digest.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Layer {
+    /// Where the blob goes in the image archive.
+    pub path: PathBuf,
+    /// `sha256:<hex>` of the blob.
+    pub digest: String,
+    pub size: u64,
+}
+impl Layer {
+    /// The name of the blob in a layer store or registry.
+    pub fn file_name(&self) -> String {
+        self.digest.replace(':', "-")
+    }
+
+    fn sha256(&self) -> Result<&str, Error> {
+        self.digest
+            .strip_prefix("sha256:")
+            .ok_or_else(|| format_err!("Unsupported Layer Digest: {}", self.digest))
+            .with_code(crate::error::GENERAL_ERROR)
+    }
+}
+
+/// The local store of layer blobs, shared by every installed app.
+pub fn store_dir() -> PathBuf {
+    Path::new(crate::PERSISTENCE_DIR).join("layers")
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    path.strip_prefix("./").unwrap_or(path).to_owned()
+}
+
+async fn copy_to<R: AsyncRead + Unpin>(r: &mut R, dst: &Path) -> Result<(), Error> {
+    let part = crate::install::partial_path(dst);
+    let mut f = tokio::fs::File::create(&part)
+        .await
+        .with_context(|e| format!("{}: {}", part.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    tokio::io::copy(r, &mut f).await?;
+    f.flush().await?;
+    tokio::fs::rename(&part, dst).await?;
+    Ok(())
+}
+
+/// Lists the layers of a `docker save` or OCI archive along with their digests.
+pub async fn scan<P: AsRef<Path>>(image: P) -> Result<Vec<Layer>, Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct DockerManifest {
+        layers: Vec<PathBuf>,
+    }
+
+    let image = image.as_ref();
+    let f = tokio::fs::File::open(image)
+        .await
+        .with_context(|e| format!("{}: {}", image.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    let mut archive = tar::Archive::new(f);
+    let mut entries = archive.entries()?;
+    let mut files = Vec::new();
+    let mut docker_layers: Option<LinearSet<PathBuf>> = None;
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = normalize(&entry.path()?);
+        if path == Path::new("manifest.json") {
+            let manifests: Vec<DockerManifest> = from_json_async_reader(entry).await?;
+            docker_layers = Some(manifests.into_iter().flat_map(|m| m.layers).collect());
+            continue;
+        }
+        let (sha256, size) = crate::install::sha256_reader(&mut entry).await?;
+        files.push(Layer {
+            path,
+            digest: format!("sha256:{}", sha256),
+            size,
+        });
+    }
+    Ok(match docker_layers {
+        Some(layers) => files
+            .into_iter()
+            .filter(|f| layers.contains(&f.path))
+            .collect(),
+        // an OCI image layout, where every blob is content addressed
+        None => files
+            .into_iter()
+            .filter(|f| f.path.starts_with("blobs"))
+            .collect(),
+    })
+}
+
+/// Copies `image` to `dst` without the blobs of `layers`, which are written to `layers_dir`.
+pub async fn split(
+    image: &Path,
+    layers: &[Layer],
+    dst: &Path,
+    layers_dir: &Path,
+) -> Result<(), Error> {
+    tokio::fs::create_dir_all(layers_dir).await?;
+    let f = tokio::fs::File::open(image)
+        .await
+        .with_context(|e| format!("{}: {}", image.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    let mut archive = tar::Archive::new(f);
+    let mut entries = archive.entries()?;
+    let mut out = tar::Builder::new(tokio::fs::File::create(dst).await?);
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        let path = normalize(&entry.path()?);
+        match layers.iter().find(|l| l.path == path) {
+            Some(layer) => {
+                let blob = layers_dir.join(layer.file_name());
+                if !blob.exists() {
+                    copy_to(&mut entry, &blob).await?;
+                }
+            }
+            None => {
+                let header = entry.header().clone();
+                out.append(&header, &mut entry).await?;
+            }
+        }
+    }
+    out.into_inner().await?.flush().await?;
+    Ok(())
+}
+
+/// Makes sure the blob of `layer` is in the local store, fetching it from the registries
+/// if it is not, and returns its path.
+pub async fn fetch(layer: &Layer) -> Result<PathBuf, Error> {
+    let path = store_dir().join(layer.file_name());
+    if path.exists() {
+        log::info!("Layer {} Already Present.", layer.digest);
+        return Ok(path);
+    }
+    tokio::fs::create_dir_all(store_dir()).await?;
+    let part = crate::install::partial_path(&path);
+    crate::registry::layer(layer, &part).await?;
+    crate::install::verify_sha256(&part, layer.sha256()?).await?;
+    tokio::fs::rename(&part, &path).await?;
+    Ok(path)
+}
+
+/// Rebuilds the full archive of a thin `image` into `dst`, fetching the blobs it left out.
+pub async fn assemble<R: AsyncRead + Unpin>(
+    image: R,
+    layers: &[Layer],
+    dst: &Path,
+) -> Result<(), Error> {
+    let mut blobs = Vec::with_capacity(layers.len());
+    for layer in layers {
+        blobs.push(fetch(layer).await?);
+    }
+    join(image, layers, &blobs, dst).await
+}
+
+/// Writes the entries of a thin `image` to `dst`, followed by each of `layers` read from `blobs`.
+async fn join<R: AsyncRead + Unpin>(
+    image: R,
+    layers: &[Layer],
+    blobs: &[PathBuf],
+    dst: &Path,
+) -> Result<(), Error> {
+    let mut out = tar::Builder::new(tokio::fs::File::create(dst).await?);
+    let mut archive = tar::Archive::new(image);
+    let mut entries = archive.entries()?;
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        let header = entry.header().clone();
+        out.append(&header, &mut entry).await?;
+    }
+    for (layer, blob) in layers.iter().zip(blobs) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(layer.size);
+        header.set_mode(0o644);
+        out.append_data(&mut header, &layer.path, tokio::fs::File::open(blob).await?)
+            .await?;
+    }
+    out.into_inner().await?.flush().await?;
+    Ok(())
+}
+
+/// Whether `name` is the name of a blob in a layer store,
+/// as opposed to a partial download or a file that does not belong there.
+fn is_blob_name(name: &str) -> bool {
+    match name.strip_prefix("sha256-") {
+        Some(hex) => hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+/// Removes blobs from the local store that no installed app uses.
+/// Downloads still in progress are left alone.
+pub async fn prune() -> Result<(), Error> {
+    let dir = store_dir();
+    if !dir.exists() {
+        return Ok(());
+    }
+    let mut used = LinearSet::new();
+    for id in crate::apps::list_info().await?.keys() {
+        for layer in crate::apps::manifest(id).await?.layers.values().flatten() {
+            used.insert(layer.file_name());
+        }
+    }
+    let mut entries = tokio::fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_blob_name(&name) && !used.contains(&name) {
+            log::info!("Removing Unused Layer {}.", name);
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn write_archive(path: &Path, files: &[(&str, &[u8])]) {
+        let mut out = tar::Builder::new(tokio::fs::File::create(path).await.unwrap());
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            out.append_data(&mut header, name, *data).await.unwrap();
+        }
+        out.into_inner().await.unwrap().flush().await.unwrap();
+    }
+
+    async fn read_archive(path: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut archive = tar::Archive::new(tokio::fs::File::open(path).await.unwrap());
+        let mut entries = archive.entries().unwrap();
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry.unwrap();
+            let path = normalize(&entry.path().unwrap());
+            let mut data = Vec::new();
+            tokio::io::AsyncReadExt::read_to_end(&mut entry, &mut data)
+                .await
+                .unwrap();
+            files.push((path, data));
+        }
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn split_docker_archive() {
+        let dir = crate::fixtures::tmp_dir("layers").await;
+        let image = dir.join("image.tar");
+        write_archive(
+            &image,
+            &[
+                ("abc/layer.tar", b"base layer"),
+                ("def/layer.tar", b"app layer"),
+                ("config.json", b"{}"),
+                (
+                    "manifest.json",
+                    br#"[{"Config":"config.json","RepoTags":[],"Layers":["abc/layer.tar","def/layer.tar"]}]"#,
+                ),
+            ],
+        )
+        .await;
+
+        let layers = scan(&image).await.unwrap();
+        assert_eq!(
+            layers.iter().map(|l| l.path.clone()).collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("abc/layer.tar"),
+                PathBuf::from("def/layer.tar")
+            ]
+        );
+        assert_eq!(layers[0].size, 10);
+        assert_eq!(
+            layers[0].digest,
+            format!(
+                "sha256:{}",
+                crate::install::sha256_reader(&mut &b"base layer"[..])
+                    .await
+                    .unwrap()
+                    .0
+            )
+        );
+
+        let thin = dir.join("thin.tar");
+        let blobs = dir.join("layers");
+        split(&image, &layers, &thin, &blobs).await.unwrap();
+        assert_eq!(
+            tokio::fs::read(blobs.join(layers[1].file_name()))
+                .await
+                .unwrap(),
+            b"app layer"
+        );
+        let thin_layers = scan(&thin).await.unwrap();
+        assert!(thin_layers.is_empty());
+
+        let full = dir.join("full.tar");
+        let paths: Vec<PathBuf> = layers.iter().map(|l| blobs.join(l.file_name())).collect();
+        join(
+            tokio::fs::File::open(&thin).await.unwrap(),
+            &layers,
+            &paths,
+            &full,
+        )
+        .await
+        .unwrap();
+        assert_eq!(scan(&full).await.unwrap(), layers);
+        assert_eq!(read_archive(&full).await, read_archive(&image).await);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn blob_names() {
+        let hex = "a".repeat(64);
+        assert!(is_blob_name(&format!("sha256-{}", hex)));
+        assert!(!is_blob_name(&format!("sha256-{}.part", hex)));
+        assert!(!is_blob_name(&format!("sha256-{}", &hex[1..])));
+        assert!(!is_blob_name(&format!("sha512-{}", hex)));
+        assert!(!is_blob_name(&format!("sha256-{}", "g".repeat(64))));
+        assert!(!is_blob_name("README"));
+    }
+}
@@ -12,11 +12,12 @@ pub const BACKUP_MOUNT_POINT: &'static str = "/mnt/backup_drive";
 pub const BACKUP_DIR: &'static str = "Embassy Backups";
 pub const BUFFER_SIZE: usize = 1024;
 pub const HOST_IP: [u8; 4] = [172, 18, 0, 1];
+pub const DAEMON_SOCKET: &'static str = "/var/run/appmgr.sock";
+pub const PRODUCT_KEY: &'static str = "/root/agent/product_key";
 
 lazy_static::lazy_static! {
     pub static ref REGISTRY_URL: String = std::env::var("REGISTRY_URL").unwrap_or_else(|_| "https://registry.start9labs.com".to_owned());
     pub static ref SYS_REGISTRY_URL: String = format!("{}/sys", *REGISTRY_URL);
-    pub static ref APP_REGISTRY_URL: String = format!("{}/apps", *REGISTRY_URL);
     pub static ref QUIET: tokio::sync::RwLock<bool> = tokio::sync::RwLock::new(!std::env::var("APPMGR_QUIET").map(|a| a == "0").unwrap_or(true));
 }
 
@@ -25,19 +26,31 @@ pub mod apps;
 pub mod backup;
 pub mod config;
 pub mod control;
+pub mod daemon;
 pub mod dependencies;
 pub mod disks;
 pub mod error;
+#[cfg(test)]
+mod fixtures;
+pub mod graph;
+pub mod health;
 pub mod index;
 pub mod inspect;
 pub mod install;
+pub mod journal;
 #[cfg(feature = "avahi")]
 pub mod lan;
+pub mod layers;
 pub mod logs;
 pub mod manifest;
 pub mod pack;
+pub mod progress;
 pub mod registry;
 pub mod remove;
+pub mod resolver;
+pub mod runtime;
+pub mod s9pk;
+pub mod signing;
 pub mod tor;
 pub mod update;
 pub mod util;
@@ -46,7 +59,7 @@ pub mod version;
 pub use config::{configure, Config};
 pub use control::{restart_app, start_app, stop_app, stop_dependents};
 pub use error::{Error, ResultExt};
-pub use install::{install_name, install_path, install_url};
+pub use install::{install_name, install_path, install_target, install_url};
 pub use logs::{logs, notifications, stats, LogOptions};
 pub use pack::{pack, verify};
 pub use remove::remove;
@@ -1,17 +1,17 @@
-use std::borrow::Cow;
-use std::ffi::{OsStr, OsString};
 use std::path::Path;
 
 use failure::ResultExt as _;
 use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
 use itertools::Itertools;
+use tokio::io::AsyncWriteExt;
 
+use crate::runtime::LogSource;
 use crate::util::PersistencePath;
 use crate::Error;
 use crate::ResultExt as _;
 
-#[derive(Clone, Copy, Debug, serde::Serialize)]
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum Level {
     Error,
@@ -42,7 +42,7 @@ impl std::str::FromStr for Level {
     }
 }
 
-#[derive(Clone, Debug, serde::Serialize)]
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct Notification {
     pub time: i64,
     pub level: Level,
@@ -102,45 +102,57 @@ pub struct LogOptions<A: AsRef<str>, B: AsRef<str>> {
     pub tail: Option<usize>,
     pub timestamps: bool,
 }
+impl<A: AsRef<str>, B: AsRef<str>> LogOptions<A, B> {
+    pub fn as_ref(&self) -> LogOptions<&str, &str> {
+        LogOptions {
+            details: self.details,
+            follow: self.follow,
+            since: self.since.as_ref().map(|a| a.as_ref()),
+            until: self.until.as_ref().map(|a| a.as_ref()),
+            tail: self.tail,
+            timestamps: self.timestamps,
+        }
+    }
+}
 
 pub async fn logs<A: AsRef<str>, B: AsRef<str>>(
     name: &str,
     options: LogOptions<A, B>,
 ) -> Result<(), Error> {
-    let mut args = vec![Cow::Borrowed(OsStr::new("logs"))];
-    if options.details {
-        args.push(Cow::Borrowed(OsStr::new("--details")));
-    }
-    if options.follow {
-        args.push(Cow::Borrowed(OsStr::new("-f")));
-    }
-    if let Some(since) = options.since.as_ref() {
-        args.push(Cow::Borrowed(OsStr::new("--since")));
-        args.push(Cow::Borrowed(OsStr::new(since.as_ref())));
-    }
-    if let Some(until) = options.until.as_ref() {
-        args.push(Cow::Borrowed(OsStr::new("--until")));
-        args.push(Cow::Borrowed(OsStr::new(until.as_ref())));
-    }
-    if let Some(tail) = options.tail {
-        args.push(Cow::Borrowed(OsStr::new("--tail")));
-        args.push(Cow::Owned(OsString::from(format!("{}", tail))));
-    }
-    if options.timestamps {
-        args.push(Cow::Borrowed(OsStr::new("-t")));
+    let mut stream = crate::runtime::current()
+        .logs(name, &options.as_ref())
+        .await?;
+    let mut stdout = tokio::io::stdout();
+    let mut stderr = tokio::io::stderr();
+    while let Some((source, frame)) = stream.try_next().await? {
+        match source {
+            LogSource::Stdout => {
+                stdout.write_all(&frame).await?;
+                stdout.flush().await?;
+            }
+            LogSource::Stderr => {
+                stderr.write_all(&frame).await?;
+                stderr.flush().await?;
+            }
+        }
     }
-    args.push(Cow::Borrowed(OsStr::new(name)));
-    crate::ensure_code!(
-        std::process::Command::new("docker")
-            .args(args.into_iter())
-            .status()?
-            .success(),
-        crate::error::DOCKER_ERROR,
-        "Failed to Collect Logs from Docker"
-    );
     Ok(())
 }
 
+pub async fn fetch_logs<A: AsRef<str>, B: AsRef<str>>(
+    name: &str,
+    options: LogOptions<A, B>,
+) -> Result<String, Error> {
+    let mut stream = crate::runtime::current()
+        .logs(name, &options.as_ref())
+        .await?;
+    let mut res = Vec::new();
+    while let Some((_, frame)) = stream.try_next().await? {
+        res.extend_from_slice(&frame);
+    }
+    Ok(String::from_utf8_lossy(&res).into_owned())
+}
+
 pub async fn notifications(id: &str) -> Result<Vec<Notification>, Error> {
     let p = PersistencePath::from_ref("notifications").join(id).tmp();
     if let Some(parent) = p.parent() {
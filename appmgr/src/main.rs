@@ -10,7 +10,9 @@ use clap::{App, Arg, SubCommand};
 
 #[tokio::main]
 async fn main() {
-    match inner_main().await {
+    let res = inner_main().await;
+    progress::finish().await;
+    match res {
         Ok(()) => (),
         Err(e) => {
             eprintln!("{}", e.failure);
@@ -50,6 +52,19 @@ async fn inner_main() -> Result<(), Error> {
                 .help("Sets verbosity level")
                 .multiple(true),
         )
+        .arg(
+            Arg::with_name("progress-fd")
+                .long("progress-fd")
+                .takes_value(true)
+                .conflicts_with("progress-socket")
+                .help("Writes progress events as newline delimited json to this file descriptor"),
+        )
+        .arg(
+            Arg::with_name("progress-socket")
+                .long("progress-socket")
+                .takes_value(true)
+                .help("Writes progress events as newline delimited json to this unix socket"),
+        )
         .subcommand(SubCommand::with_name("semver").about("Prints semantic version and exits"))
         .subcommand(SubCommand::with_name("git-info").about("Prints git version info and exits"))
         .subcommand(
@@ -62,6 +77,24 @@ async fn inner_main() -> Result<(), Error> {
                         .takes_value(true)
                         .default_value("app.s9pk"),
                 )
+                .arg(
+                    Arg::with_name("sign-key")
+                        .long("sign-key")
+                        .takes_value(true)
+                        .help("Path to the ed25519 key to sign the package with"),
+                )
+                .arg(
+                    Arg::with_name("thin")
+                        .long("thin")
+                        .help("Leaves image layers out of the package, for registries to serve"),
+                )
+                .arg(
+                    Arg::with_name("layers-dir")
+                        .long("layers-dir")
+                        .takes_value(true)
+                        .requires("thin")
+                        .help("Where to write the layers of a thin package [default: layers next to the output]"),
+                )
                 .arg(
                     Arg::with_name("PATH")
                         .help("Path to the folder containing the application data")
@@ -150,6 +183,35 @@ async fn inner_main() -> Result<(), Error> {
                                 .help("Path to the s9pk file to inspect")
                                 .required(true),
                         ),
+                )
+                .subcommand(
+                    SubCommand::with_name("config-schema")
+                        .about("Prints the config spec of an app as a JSON Schema")
+                        .arg(
+                            Arg::with_name("PATH")
+                                .help("Path to the s9pk file to inspect")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("pretty")
+                                .long("pretty")
+                                .short("p")
+                                .help("Pretty print output"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("icon")
+                        .about("Writes the icon of an app")
+                        .arg(
+                            Arg::with_name("PATH")
+                                .help("Path to the s9pk file to inspect")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("OUTPUT")
+                                .help("Path to write the icon to")
+                                .required(true),
+                        ),
                 ),
         )
         .subcommand(
@@ -160,6 +222,164 @@ async fn inner_main() -> Result<(), Error> {
                         .help("Path to the directory to index")
                         .required(true),
                 ),
+        )
+        .subcommand(
+            SubCommand::with_name("keygen")
+                .about("Generates a new ed25519 key for signing packages")
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .help("Path to write the secret key to")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("trust")
+                .about("Manages the keys trusted to sign packages")
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Trusts a developer key")
+                        .arg(
+                            Arg::with_name("NAME")
+                                .help("Name to refer to the key by")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("PUBKEY")
+                                .help("The base32 encoded public key")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Stops trusting a developer key")
+                        .arg(
+                            Arg::with_name("NAME")
+                                .help("Name of the key to remove")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Lists trusted developer keys")
+                        .arg(
+                            Arg::with_name("json")
+                                .conflicts_with("yaml")
+                                .long("json")
+                                .short("j")
+                                .help("Output as json"),
+                        )
+                        .arg(
+                            Arg::with_name("pretty")
+                                .requires("json")
+                                .long("pretty")
+                                .short("p")
+                                .help("Pretty print output"),
+                        )
+                        .arg(
+                            Arg::with_name("yaml")
+                                .conflicts_with("json")
+                                .long("yaml")
+                                .short("y")
+                                .help("Output as yaml"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("registry")
+                .about("Manages the registries apps are installed from")
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Adds a registry, which is tried after the existing ones")
+                        .arg(
+                            Arg::with_name("NAME")
+                                .help("Name of the registry")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("LOCATION")
+                                .help("URL of the registry, or path of a directory of packages")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("key")
+                                .long("key")
+                                .short("k")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("Public key trusted to sign packages from the registry"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .alias("rm")
+                        .about("Removes a registry")
+                        .arg(
+                            Arg::with_name("NAME")
+                                .help("Name of the registry")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("serve")
+                        .about("Serves a directory of packages as a registry")
+                        .arg(
+                            Arg::with_name("DIR")
+                                .help("Directory of packages, as listed by the index command")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("port")
+                                .long("port")
+                                .takes_value(true)
+                                .default_value("8080")
+                                .help("Port to listen on"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("proxy")
+                        .about("Shows the SOCKS5 proxy registry traffic goes through")
+                        .subcommand(
+                            SubCommand::with_name("set")
+                                .about("Sends registry traffic through a SOCKS5 proxy")
+                                .arg(
+                                    Arg::with_name("URL")
+                                        .help("URL of the proxy, the local Tor daemon by default")
+                                        .default_value(registry::proxy::TOR_PROXY),
+                                ),
+                        )
+                        .subcommand(
+                            SubCommand::with_name("clear").about(
+                                "Connects to registries directly, except for onion addresses",
+                            ),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .alias("ls")
+                        .about("Lists registries in the order they are tried")
+                        .arg(
+                            Arg::with_name("json")
+                                .conflicts_with("yaml")
+                                .long("json")
+                                .short("j")
+                                .help("Output as json"),
+                        )
+                        .arg(
+                            Arg::with_name("pretty")
+                                .requires("json")
+                                .long("pretty")
+                                .short("p")
+                                .help("Pretty print output"),
+                        )
+                        .arg(
+                            Arg::with_name("yaml")
+                                .conflicts_with("json")
+                                .long("yaml")
+                                .short("y")
+                                .help("Output as yaml"),
+                        ),
+                ),
         );
 
     #[cfg(feature = "avahi")]
@@ -183,6 +403,11 @@ async fn inner_main() -> Result<(), Error> {
                         .long("no-cache")
                         .help("Replace cached download of application"),
                 )
+                .arg(
+                    Arg::with_name("with-deps")
+                        .long("with-deps")
+                        .help("Install missing dependencies from the registry first"),
+                )
                 .arg(
                     Arg::with_name("ID|PATH|URL")
                         .help("The app to install")
@@ -208,6 +433,12 @@ async fn inner_main() -> Result<(), Error> {
                         .long("dry-run")
                         .help("Do not commit result"),
                 )
+                .arg(
+                    Arg::with_name("plan")
+                        .long("plan")
+                        .conflicts_with("dry-run")
+                        .help("Print the versions the app and its dependents would be updated to"),
+                )
                 .arg(
                     Arg::with_name("json")
                         .conflicts_with("yaml")
@@ -266,6 +497,14 @@ async fn inner_main() -> Result<(), Error> {
                         .help("Output as yaml"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("start-all")
+                .about("Starts all apps, starting dependencies before their dependents"),
+        )
+        .subcommand(
+            SubCommand::with_name("stop-all")
+                .about("Stops all apps, stopping dependents before their dependencies"),
+        )
         .subcommand(
             SubCommand::with_name("restart")
                 .about("Restarts an app")
@@ -331,6 +570,120 @@ async fn inner_main() -> Result<(), Error> {
                         .help("Output as yaml"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Shows and restores previous configurations of an app")
+                .subcommand(
+                    SubCommand::with_name("history")
+                        .about("Lists the saved configurations of an app")
+                        .arg(
+                            Arg::with_name("ID")
+                                .help("The app to look up")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("json")
+                                .conflicts_with("yaml")
+                                .long("json")
+                                .short("j")
+                                .help("Output as json"),
+                        )
+                        .arg(
+                            Arg::with_name("pretty")
+                                .requires("json")
+                                .long("pretty")
+                                .short("p")
+                                .help("Pretty print output"),
+                        )
+                        .arg(
+                            Arg::with_name("yaml")
+                                .conflicts_with("json")
+                                .long("yaml")
+                                .short("y")
+                                .help("Output as yaml"),
+                        )
+                        .arg(
+                            Arg::with_name("reveal")
+                                .long("reveal")
+                                .help("Show secret config values instead of redacting them"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("diff")
+                        .about("Shows how the config of an app changed since a revision")
+                        .arg(
+                            Arg::with_name("ID")
+                                .help("The app to look up")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("REV")
+                                .help("The revision, as listed by config history")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("json")
+                                .conflicts_with("yaml")
+                                .long("json")
+                                .short("j")
+                                .help("Output as json"),
+                        )
+                        .arg(
+                            Arg::with_name("pretty")
+                                .requires("json")
+                                .long("pretty")
+                                .short("p")
+                                .help("Pretty print output"),
+                        )
+                        .arg(
+                            Arg::with_name("yaml")
+                                .conflicts_with("json")
+                                .long("yaml")
+                                .short("y")
+                                .help("Output as yaml"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("rollback")
+                        .about("Configures an app as it was at a revision")
+                        .arg(
+                            Arg::with_name("ID")
+                                .help("The app to configure")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("REV")
+                                .help("The revision, as listed by config history")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("dry-run")
+                                .long("dry-run")
+                                .help("Do not commit result"),
+                        )
+                        .arg(
+                            Arg::with_name("json")
+                                .conflicts_with("yaml")
+                                .long("json")
+                                .short("j")
+                                .help("Output as json"),
+                        )
+                        .arg(
+                            Arg::with_name("pretty")
+                                .requires("json")
+                                .long("pretty")
+                                .short("p")
+                                .help("Pretty print output"),
+                        )
+                        .arg(
+                            Arg::with_name("yaml")
+                                .conflicts_with("json")
+                                .long("yaml")
+                                .short("y")
+                                .help("Output as yaml"),
+                        ),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("check-dependencies")
                 .about("Check dependencies for an app")
@@ -364,6 +717,49 @@ async fn inner_main() -> Result<(), Error> {
                         .help("Output as yaml"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("dependencies")
+                .about("Inspects the dependencies between apps")
+                .subcommand(
+                    SubCommand::with_name("graph")
+                        .about("Prints the dependency graph of installed apps")
+                        .arg(
+                            Arg::with_name("include-registry")
+                                .long("include-registry")
+                                .short("r")
+                                .help(
+                                    "Include dependencies from the registry that are not installed",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("dot")
+                                .conflicts_with_all(&["json", "yaml"])
+                                .long("dot")
+                                .help("Output as Graphviz DOT"),
+                        )
+                        .arg(
+                            Arg::with_name("json")
+                                .conflicts_with("yaml")
+                                .long("json")
+                                .short("j")
+                                .help("Output as json"),
+                        )
+                        .arg(
+                            Arg::with_name("pretty")
+                                .requires("json")
+                                .long("pretty")
+                                .short("p")
+                                .help("Pretty print output"),
+                        )
+                        .arg(
+                            Arg::with_name("yaml")
+                                .conflicts_with("json")
+                                .long("yaml")
+                                .short("y")
+                                .help("Output as yaml"),
+                        ),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("autoconfigure-dependency")
                 .about("Automatically configure a dependency")
@@ -510,6 +906,11 @@ async fn inner_main() -> Result<(), Error> {
                         .long("include-dependencies")
                         .short("d"),
                 )
+                .arg(
+                    Arg::with_name("reveal")
+                        .long("reveal")
+                        .help("Show secret config values instead of redacting them"),
+                )
                 .arg(
                     Arg::with_name("only-status")
                         .long("only-status")
@@ -620,6 +1021,11 @@ async fn inner_main() -> Result<(), Error> {
                     Arg::with_name("include-dependencies")
                         .long("include-dependencies")
                         .short("d"),
+                )
+                .arg(
+                    Arg::with_name("reveal")
+                        .long("reveal")
+                        .help("Show secret config values instead of redacting them"),
                 ),
         )
         .subcommand(
@@ -840,6 +1246,18 @@ async fn inner_main() -> Result<(), Error> {
                         .required(true),
                 )
                 .arg(Arg::with_name("ACTION").help("ID of the action to perform")),
+        )
+        .subcommand(
+            SubCommand::with_name("daemon")
+                .about("Serves appmgr commands as JSON-RPC over a unix socket")
+                .arg(
+                    Arg::with_name("socket")
+                        .long("socket")
+                        .short("s")
+                        .takes_value(true)
+                        .default_value(crate::DAEMON_SOCKET)
+                        .help("Path of the unix socket to listen on"),
+                ),
         );
 
     let matches = app.clone().get_matches();
@@ -851,6 +1269,13 @@ async fn inner_main() -> Result<(), Error> {
         3 => log::LevelFilter::Debug,
         _ => log::LevelFilter::Trace,
     });
+    if let Some(fd) = matches.value_of("progress-fd") {
+        progress::to_fd(fd.parse().with_code(crate::error::GENERAL_ERROR)?).await?;
+        progress::relay_daemon().await?;
+    } else if let Some(path) = matches.value_of("progress-socket") {
+        progress::to_socket(path).await?;
+        progress::relay_daemon().await?;
+    }
 
     match matches.subcommand() {
         ("semver", _) => {
@@ -862,17 +1287,131 @@ async fn inner_main() -> Result<(), Error> {
         #[cfg(not(feature = "portable"))]
         ("install", Some(sub_m)) => {
             let target = sub_m.value_of("ID|PATH|URL").unwrap();
-            if target.starts_with("https://") || target.starts_with("http://") {
-                install_url(target, None).await?;
-            } else if target.ends_with(".s9pk") {
-                install_path(target, None).await?;
+            let target = if target.ends_with(".s9pk") && Path::new(target).exists() {
+                Cow::Owned(format!(
+                    "{}",
+                    tokio::fs::canonicalize(target).await?.display()
+                ))
+            } else {
+                Cow::Borrowed(target)
+            };
+            let no_cache = sub_m.is_present("no-cache");
+            if sub_m.is_present("with-deps") {
+                let plan = daemon::call_or(
+                    "install.resolve",
+                    serde_json::json!({ "target": target }),
+                    resolver::resolve_target(&target),
+                )
+                .await?;
+                use prettytable::{Cell, Row, Table};
+                let mut table = Table::new();
+                let heading = vec![
+                    Cell::new("APPLICATION ID"),
+                    Cell::new("VERSION"),
+                    Cell::new("INSTALLED"),
+                    Cell::new("DEPENDENCIES"),
+                ];
+                table.add_row(Row::new(heading));
+                for step in &plan.0 {
+                    table.add_row(Row::new(vec![
+                        Cell::new(&step.id),
+                        Cell::new(&format!("{}", step.version)),
+                        Cell::new(&if let Some(installed) = &step.installed {
+                            format!("{}", installed)
+                        } else {
+                            "N/A".to_owned()
+                        }),
+                        Cell::new(&step.dependencies.join(", ")),
+                    ]));
+                }
+                table.print(&mut std::io::stdout())?;
+                daemon::call_or(
+                    "install",
+                    serde_json::json!({ "target": target, "no-cache": no_cache, "with-deps": true }),
+                    resolver::install_target(&target, !no_cache),
+                )
+                .await?;
+            } else {
+                daemon::call_or(
+                    "install",
+                    serde_json::json!({ "target": target, "no-cache": no_cache }),
+                    install_target(&target, !no_cache),
+                )
+                .await?;
+            }
+        }
+        #[cfg(not(feature = "portable"))]
+        ("update", Some(sub_m)) if sub_m.is_present("plan") => {
+            let id = sub_m.value_of("ID").unwrap();
+            let plan = daemon::call_or(
+                "update.plan",
+                serde_json::json!({ "id": id }),
+                resolver::plan_update_target(id),
+            )
+            .await?;
+            if sub_m.is_present("json") {
+                if sub_m.is_present("pretty") {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&plan).with_code(crate::error::SERDE_ERROR)?
+                    );
+                } else {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&plan).with_code(crate::error::SERDE_ERROR)?
+                    );
+                }
+            } else if sub_m.is_present("yaml") {
+                println!(
+                    "{}",
+                    serde_yaml::to_string(&plan).with_code(crate::error::SERDE_ERROR)?
+                );
             } else {
-                install_name(target, !sub_m.is_present("no-cache")).await?;
+                use prettytable::{Cell, Row, Table};
+                let mut table = Table::new();
+                let heading = vec![
+                    Cell::new("APPLICATION ID"),
+                    Cell::new("CURRENT"),
+                    Cell::new("PLANNED"),
+                    Cell::new("REASON"),
+                ];
+                table.add_row(Row::new(heading));
+                for step in &plan.updates {
+                    table.add_row(Row::new(vec![
+                        Cell::new(&step.id),
+                        Cell::new(&format!("{}", step.from)),
+                        Cell::new(&format!("{}", step.to)),
+                        Cell::new(&if let Some(reason) = plan.broken.get(&step.id) {
+                            format!("{}", reason)
+                        } else {
+                            "N/A".to_owned()
+                        }),
+                    ]));
+                }
+                for (name, reason) in &plan.broken {
+                    if plan.updates.iter().any(|step| &step.id == name) {
+                        continue;
+                    }
+                    table.add_row(Row::new(vec![
+                        Cell::new(name),
+                        Cell::new(""),
+                        Cell::new("Stopped"),
+                        Cell::new(&format!("{}", reason)),
+                    ]));
+                }
+                table.print(&mut std::io::stdout())?;
             }
         }
         #[cfg(not(feature = "portable"))]
         ("update", Some(sub_m)) => {
-            let res = update(sub_m.value_of("ID").unwrap(), sub_m.is_present("dry-run")).await?;
+            let id = sub_m.value_of("ID").unwrap();
+            let dry_run = sub_m.is_present("dry-run");
+            let res = daemon::call_or(
+                "update",
+                serde_json::json!({ "id": id, "dry-run": dry_run }),
+                update(id, dry_run),
+            )
+            .await?;
             if sub_m.is_present("json") {
                 if sub_m.is_present("pretty") {
                     println!(
@@ -911,14 +1450,22 @@ async fn inner_main() -> Result<(), Error> {
         }
         #[cfg(not(feature = "portable"))]
         ("start", Some(sub_m)) => {
-            start_app(sub_m.value_of("ID").unwrap(), true).await?;
+            let id = sub_m.value_of("ID").unwrap();
+            daemon::call_or(
+                "start",
+                serde_json::json!({ "id": id }),
+                start_app(id, true),
+            )
+            .await?;
         }
         #[cfg(not(feature = "portable"))]
         ("stop", Some(sub_m)) => {
-            let res = stop_app(
-                sub_m.value_of("ID").unwrap(),
-                true,
-                sub_m.is_present("dry-run"),
+            let id = sub_m.value_of("ID").unwrap();
+            let dry_run = sub_m.is_present("dry-run");
+            let res = daemon::call_or(
+                "stop",
+                serde_json::json!({ "id": id, "dry-run": dry_run }),
+                stop_app(id, true, dry_run),
             )
             .await?;
             if sub_m.is_present("json") {
@@ -958,8 +1505,17 @@ async fn inner_main() -> Result<(), Error> {
             }
         }
         #[cfg(not(feature = "portable"))]
+        ("start-all", Some(_)) => {
+            daemon::call_or("start-all", serde_json::json!({}), control::start_all()).await?;
+        }
+        #[cfg(not(feature = "portable"))]
+        ("stop-all", Some(_)) => {
+            daemon::call_or("stop-all", serde_json::json!({}), control::stop_all()).await?;
+        }
+        #[cfg(not(feature = "portable"))]
         ("restart", Some(sub_m)) => {
-            restart_app(sub_m.value_of("ID").unwrap()).await?;
+            let id = sub_m.value_of("ID").unwrap();
+            daemon::call_or("restart", serde_json::json!({ "id": id }), restart_app(id)).await?;
         }
         #[cfg(not(feature = "portable"))]
         ("configure", Some(sub_m)) => {
@@ -989,61 +1545,180 @@ async fn inner_main() -> Result<(), Error> {
             } else {
                 Some(std::time::Duration::from_secs(3))
             };
-            let res = configure(
-                sub_m.value_of("ID").unwrap(),
-                config,
-                timeout,
-                sub_m.is_present("dry-run"),
-            )
-            .await?;
-            if sub_m.is_present("json") {
-                if sub_m.is_present("pretty") {
+            let id = sub_m.value_of("ID").unwrap();
+            let dry_run = sub_m.is_present("dry-run");
+            if dry_run {
+                let report = daemon::call_or(
+                    "config.validate",
+                    serde_json::json!({
+                        "id": id,
+                        "config": config,
+                    }),
+                    crate::config::validate(id, config.clone()),
+                )
+                .await?;
+                let code = report.code();
+                let result = if code.is_none() {
+                    Some(
+                        daemon::call_or(
+                            "configure",
+                            serde_json::json!({
+                                "id": id,
+                                "config": config,
+                                "timeout": timeout.map(|t| t.as_secs()),
+                                "dry-run": true,
+                            }),
+                            configure(id, config, timeout, true, crate::config::Trigger::User),
+                        )
+                        .await?,
+                    )
+                } else {
+                    None
+                };
+                print_dry_run(sub_m, config::DryRunRes { report, result })?;
+                if let Some(code) = code {
+                    std::process::exit(code);
+                }
+            } else {
+                let res = daemon::call_or(
+                    "configure",
+                    serde_json::json!({
+                        "id": id,
+                        "config": config,
+                        "timeout": timeout.map(|t| t.as_secs()),
+                        "dry-run": false,
+                    }),
+                    configure(id, config, timeout, false, crate::config::Trigger::User),
+                )
+                .await?;
+                print_configuration_res(sub_m, res)?;
+            }
+        }
+        #[cfg(not(feature = "portable"))]
+        ("config", Some(sub_m)) => match sub_m.subcommand() {
+            ("history", Some(sub_sub_m)) => {
+                let history = config::history::history(
+                    sub_sub_m.value_of("ID").unwrap(),
+                    sub_sub_m.is_present("reveal"),
+                )
+                .await?;
+                if sub_sub_m.is_present("json") {
+                    if sub_sub_m.is_present("pretty") {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&history)
+                                .with_code(crate::error::SERDE_ERROR)?
+                        );
+                    } else {
+                        println!(
+                            "{}",
+                            serde_json::to_string(&history).with_code(crate::error::SERDE_ERROR)?
+                        );
+                    }
+                } else if sub_sub_m.is_present("yaml") {
                     println!(
                         "{}",
-                        serde_json::to_string_pretty(&res).with_code(crate::error::SERDE_ERROR)?
+                        serde_yaml::to_string(&history).with_code(crate::error::SERDE_ERROR)?
                     );
-                } else {
+                } else if !history.is_empty() {
+                    use prettytable::{Cell, Row, Table};
+                    let mut table = Table::new();
+                    let heading = vec![
+                        Cell::new("REV"),
+                        Cell::new("TIME"),
+                        Cell::new("TRIGGER"),
+                        Cell::new("ALSO CHANGED"),
+                    ];
+                    table.add_row(Row::new(heading));
+                    for rev in history {
+                        table.add_row(Row::new(vec![
+                            Cell::new(&format!("{}", rev.rev)),
+                            Cell::new(&format!("{}", rev.timestamp)),
+                            Cell::new(&format!("{}", rev.trigger)),
+                            Cell::new(&rev.changed.join(", ")),
+                        ]));
+                    }
+                    table.print(&mut std::io::stdout())?;
+                }
+            }
+            ("diff", Some(sub_sub_m)) => {
+                let changes = config::history::diff_since(
+                    sub_sub_m.value_of("ID").unwrap(),
+                    sub_sub_m.value_of("REV").unwrap().parse().no_code()?,
+                )
+                .await?;
+                if sub_sub_m.is_present("json") {
+                    if sub_sub_m.is_present("pretty") {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&changes)
+                                .with_code(crate::error::SERDE_ERROR)?
+                        );
+                    } else {
+                        println!(
+                            "{}",
+                            serde_json::to_string(&changes).with_code(crate::error::SERDE_ERROR)?
+                        );
+                    }
+                } else if sub_sub_m.is_present("yaml") {
                     println!(
                         "{}",
-                        serde_json::to_string(&res).with_code(crate::error::SERDE_ERROR)?
+                        serde_yaml::to_string(&changes).with_code(crate::error::SERDE_ERROR)?
                     );
+                } else if !changes.is_empty() {
+                    use prettytable::{Cell, Row, Table};
+                    let mut table = Table::new();
+                    let heading = vec![Cell::new("PATH"), Cell::new("OLD"), Cell::new("NEW")];
+                    table.add_row(Row::new(heading));
+                    let show = |v: &Option<config::value::Value>| -> Result<String, Error> {
+                        Ok(match v {
+                            Some(v) => {
+                                serde_json::to_string(v).with_code(crate::error::SERDE_ERROR)?
+                            }
+                            None => "N/A".to_owned(),
+                        })
+                    };
+                    for change in &changes {
+                        table.add_row(Row::new(vec![
+                            Cell::new(&change.path),
+                            Cell::new(&show(&change.old)?),
+                            Cell::new(&show(&change.new)?),
+                        ]));
+                    }
+                    table.print(&mut std::io::stdout())?;
                 }
-            } else if sub_m.is_present("yaml") {
-                println!(
-                    "{}",
-                    serde_yaml::to_string(&res).with_code(crate::error::SERDE_ERROR)?
-                );
-            } else if !res.needs_restart.is_empty() || !res.stopped.is_empty() {
-                use prettytable::{Cell, Row, Table};
-                let mut table = Table::new();
-                let heading = vec![
-                    Cell::new("APPLICATION ID"),
-                    Cell::new("STATUS"),
-                    Cell::new("REASON"),
-                ];
-                table.add_row(Row::new(heading));
-                for name in res.needs_restart {
-                    table.add_row(Row::new(vec![
-                        Cell::new(&name),
-                        Cell::new("Needs Restart"),
-                        Cell::new("Configuration Changed"),
-                    ]));
-                }
-                for (name, reason) in res.stopped {
-                    table.add_row(Row::new(vec![
-                        Cell::new(&name),
-                        Cell::new("Stopped"),
-                        Cell::new(&format!("{}", reason)),
-                    ]));
-                }
-                table.print(&mut std::io::stdout())?;
             }
-        }
+            ("rollback", Some(sub_sub_m)) => {
+                let id = sub_sub_m.value_of("ID").unwrap();
+                let rev: u64 = sub_sub_m.value_of("REV").unwrap().parse().no_code()?;
+                let dry_run = sub_sub_m.is_present("dry-run");
+                let timeout = Some(std::time::Duration::from_secs(3));
+                let res = daemon::call_or(
+                    "config.rollback",
+                    serde_json::json!({
+                        "id": id,
+                        "rev": rev,
+                        "timeout": timeout.map(|t| t.as_secs()),
+                        "dry-run": dry_run,
+                    }),
+                    config::history::rollback(id, rev, timeout, dry_run),
+                )
+                .await?;
+                print_configuration_res(sub_sub_m, res)?;
+            }
+            _ => {
+                println!("{}", sub_m.usage());
+                std::process::exit(1);
+            }
+        },
         #[cfg(not(feature = "portable"))]
         ("check-dependencies", Some(sub_m)) => {
-            let res = apps::dependencies(
-                sub_m.value_of("ID").unwrap(),
-                sub_m.is_present("local-only"),
+            let id = sub_m.value_of("ID").unwrap();
+            let local_only = sub_m.is_present("local-only");
+            let res = daemon::call_or(
+                "check-dependencies",
+                serde_json::json!({ "id": id, "local-only": local_only }),
+                apps::dependencies(id, local_only),
             )
             .await?;
             if sub_m.is_present("json") {
@@ -1088,11 +1763,78 @@ async fn inner_main() -> Result<(), Error> {
                 println!("No dependencies for {}", sub_m.value_of("ID").unwrap());
             }
         }
+        #[cfg(not(feature = "portable"))]
+        ("dependencies", Some(sub_m)) => match sub_m.subcommand() {
+            ("graph", Some(sub_sub_m)) => {
+                let include_registry = sub_sub_m.is_present("include-registry");
+                let graph = daemon::call_or(
+                    "dependencies.graph",
+                    serde_json::json!({ "include-registry": include_registry }),
+                    crate::graph::export(include_registry),
+                )
+                .await?;
+                if sub_sub_m.is_present("dot") {
+                    print!("{}", crate::graph::to_dot(&graph));
+                } else if sub_sub_m.is_present("json") {
+                    if sub_sub_m.is_present("pretty") {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&graph)
+                                .with_code(crate::error::SERDE_ERROR)?
+                        );
+                    } else {
+                        println!(
+                            "{}",
+                            serde_json::to_string(&graph).with_code(crate::error::SERDE_ERROR)?
+                        );
+                    }
+                } else if sub_sub_m.is_present("yaml") {
+                    println!(
+                        "{}",
+                        serde_yaml::to_string(&graph).with_code(crate::error::SERDE_ERROR)?
+                    );
+                } else if !graph.is_empty() {
+                    use prettytable::{Cell, Row, Table};
+                    let mut table = Table::new();
+                    let heading = vec![
+                        Cell::new("APPLICATION ID"),
+                        Cell::new("VERSION"),
+                        Cell::new("INSTALLED"),
+                        Cell::new("DEPENDENCIES"),
+                    ];
+                    table.add_row(Row::new(heading));
+                    for (id, node) in graph {
+                        table.add_row(Row::new(vec![
+                            Cell::new(&id),
+                            Cell::new(&format!("{}", node.version)),
+                            Cell::new(&format!("{}", node.installed)),
+                            Cell::new(
+                                &node
+                                    .dependencies
+                                    .keys()
+                                    .cloned()
+                                    .collect::<Vec<_>>()
+                                    .join(", "),
+                            ),
+                        ]));
+                    }
+                    table.print(&mut std::io::stdout())?;
+                }
+            }
+            _ => {
+                println!("{}", sub_m.usage());
+                std::process::exit(1);
+            }
+        },
+        #[cfg(not(feature = "portable"))]
         ("autoconfigure-dependency", Some(sub_m)) => {
-            let res = dependencies::auto_configure(
-                sub_m.value_of("ID").unwrap(),
-                sub_m.value_of("DEPENDENCY").unwrap(),
-                sub_m.is_present("dry-run"),
+            let id = sub_m.value_of("ID").unwrap();
+            let dependency = sub_m.value_of("DEPENDENCY").unwrap();
+            let dry_run = sub_m.is_present("dry-run");
+            let res = daemon::call_or(
+                "autoconfigure-dependency",
+                serde_json::json!({ "id": id, "dependency": dependency, "dry-run": dry_run }),
+                dependencies::auto_configure(id, dependency, dry_run),
             )
             .await?;
             if sub_m.is_present("json") {
@@ -1140,10 +1882,13 @@ async fn inner_main() -> Result<(), Error> {
         }
         #[cfg(not(feature = "portable"))]
         ("remove", Some(sub_m)) | ("rm", Some(sub_m)) => {
-            let res = remove(
-                sub_m.value_of("ID").unwrap(),
-                sub_m.is_present("purge"),
-                sub_m.is_present("dry-run"),
+            let id = sub_m.value_of("ID").unwrap();
+            let purge = sub_m.is_present("purge");
+            let dry_run = sub_m.is_present("dry-run");
+            let res = daemon::call_or(
+                "remove",
+                serde_json::json!({ "id": id, "purge": purge, "dry-run": dry_run }),
+                remove(id, purge, dry_run),
             )
             .await?;
             if sub_m.is_present("json") {
@@ -1185,13 +1930,17 @@ async fn inner_main() -> Result<(), Error> {
         #[cfg(not(feature = "portable"))]
         ("tor", Some(sub_m)) => match sub_m.subcommand() {
             ("show", Some(sub_sub_m)) => {
-                println!(
-                    "{}",
-                    crate::tor::read_tor_address(sub_sub_m.value_of("ID").unwrap(), None).await?
-                );
+                let id = sub_sub_m.value_of("ID").unwrap();
+                let addr: String = daemon::call_or(
+                    "tor.show",
+                    serde_json::json!({ "id": id }),
+                    crate::tor::read_tor_address(id, None),
+                )
+                .await?;
+                println!("{}", addr);
             }
             ("reload", Some(_)) => {
-                crate::tor::reload().await?;
+                daemon::call_or("tor.reload", serde_json::json!({}), crate::tor::reload()).await?;
             }
             _ => {
                 println!("{}", sub_m.usage());
@@ -1210,12 +1959,33 @@ async fn inner_main() -> Result<(), Error> {
         #[cfg(not(feature = "portable"))]
         ("info", Some(sub_m)) => {
             let name = sub_m.value_of("ID").unwrap();
-            let info = crate::apps::info_full(
-                &name,
-                sub_m.is_present("include-status") || sub_m.is_present("only-status"),
-                sub_m.is_present("include-manifest") || sub_m.is_present("only-manifest"),
-                sub_m.is_present("include-config") || sub_m.is_present("only-config"),
-                sub_m.is_present("include-dependencies") || sub_m.is_present("only-dependencies"),
+            let include_status =
+                sub_m.is_present("include-status") || sub_m.is_present("only-status");
+            let include_manifest =
+                sub_m.is_present("include-manifest") || sub_m.is_present("only-manifest");
+            let include_config =
+                sub_m.is_present("include-config") || sub_m.is_present("only-config");
+            let include_dependencies =
+                sub_m.is_present("include-dependencies") || sub_m.is_present("only-dependencies");
+            let reveal = sub_m.is_present("reveal");
+            let info = daemon::call_or(
+                "info",
+                serde_json::json!({
+                    "id": name,
+                    "include-status": include_status,
+                    "include-manifest": include_manifest,
+                    "include-config": include_config,
+                    "include-dependencies": include_dependencies,
+                    "reveal": reveal,
+                }),
+                crate::apps::info_full(
+                    &name,
+                    include_status,
+                    include_manifest,
+                    include_config,
+                    include_dependencies,
+                    reveal,
+                ),
             )
             .await?;
             if sub_m.is_present("json") {
@@ -1320,11 +2090,27 @@ async fn inner_main() -> Result<(), Error> {
         }
         #[cfg(not(feature = "portable"))]
         ("list", Some(sub_m)) | ("ls", Some(sub_m)) => {
-            let info = crate::apps::list(
-                sub_m.is_present("include-status"),
-                sub_m.is_present("include-manifest"),
-                sub_m.is_present("include-config"),
-                sub_m.is_present("include-dependencies"),
+            let include_status = sub_m.is_present("include-status");
+            let include_manifest = sub_m.is_present("include-manifest");
+            let include_config = sub_m.is_present("include-config");
+            let include_dependencies = sub_m.is_present("include-dependencies");
+            let reveal = sub_m.is_present("reveal");
+            let info = daemon::call_or(
+                "list",
+                serde_json::json!({
+                    "include-status": include_status,
+                    "include-manifest": include_manifest,
+                    "include-config": include_config,
+                    "include-dependencies": include_dependencies,
+                    "reveal": reveal,
+                }),
+                crate::apps::list(
+                    include_status,
+                    include_manifest,
+                    include_config,
+                    include_dependencies,
+                    reveal,
+                ),
             )
             .await?;
             if sub_m.is_present("json") {
@@ -1374,11 +2160,10 @@ async fn inner_main() -> Result<(), Error> {
                             Cell::new(&format!("{}", info.info.configured)),
                         ]
                         .into_iter()
-                        .chain(
-                            info.status
-                                .into_iter()
-                                .map(|s| Cell::new(&format!("{:?}", s.status))),
-                        )
+                        .chain(info.status.into_iter().map(|s| match s.health {
+                            Some(health) => Cell::new(&format!("{:?} ({:?})", s.status, health)),
+                            None => Cell::new(&format!("{:?}", s.status)),
+                        }))
                         .chain(info.dependencies.into_iter().map(|s| {
                             Cell::new(&format!(
                                 "{}",
@@ -1408,27 +2193,49 @@ async fn inner_main() -> Result<(), Error> {
         }
         #[cfg(not(feature = "portable"))]
         ("logs", Some(sub_m)) => {
-            logs(
-                sub_m.value_of("ID").unwrap(),
-                LogOptions {
-                    details: sub_m.is_present("details"),
-                    follow: sub_m.is_present("follow"),
-                    since: sub_m.value_of("since"),
-                    until: sub_m.value_of("until"),
-                    tail: sub_m
-                        .value_of("tail")
-                        .filter(|t| t != &"all")
-                        .map(|a| a.parse())
-                        .transpose()
-                        .no_code()?,
-                    timestamps: sub_m.is_present("timestamps"),
-                },
-            )
-            .await?;
+            let id = sub_m.value_of("ID").unwrap();
+            let options = LogOptions {
+                details: sub_m.is_present("details"),
+                follow: sub_m.is_present("follow"),
+                since: sub_m.value_of("since"),
+                until: sub_m.value_of("until"),
+                tail: sub_m
+                    .value_of("tail")
+                    .filter(|t| t != &"all")
+                    .map(|a| a.parse())
+                    .transpose()
+                    .no_code()?,
+                timestamps: sub_m.is_present("timestamps"),
+            };
+            match daemon::Client::connect_if_running().await {
+                Some(mut client) if !options.follow => {
+                    let logs: String = client
+                        .call(
+                            "logs",
+                            serde_json::json!({
+                                "id": id,
+                                "details": options.details,
+                                "since": options.since,
+                                "until": options.until,
+                                "tail": options.tail,
+                                "timestamps": options.timestamps,
+                            }),
+                        )
+                        .await?;
+                    print!("{}", logs);
+                }
+                _ => logs(id, options).await?,
+            }
         }
         #[cfg(not(feature = "portable"))]
         ("notifications", Some(sub_m)) => {
-            let info = notifications(sub_m.value_of("ID").unwrap()).await?;
+            let id = sub_m.value_of("ID").unwrap();
+            let info = daemon::call_or(
+                "notifications",
+                serde_json::json!({ "id": id }),
+                notifications(id),
+            )
+            .await?;
             if sub_m.is_present("json") {
                 if sub_m.is_present("pretty") {
                     println!(
@@ -1471,7 +2278,8 @@ async fn inner_main() -> Result<(), Error> {
         }
         #[cfg(not(feature = "portable"))]
         ("stats", Some(sub_m)) => {
-            let info = stats(sub_m.value_of("ID").unwrap()).await?;
+            let id = sub_m.value_of("ID").unwrap();
+            let info = daemon::call_or("stats", serde_json::json!({ "id": id }), stats(id)).await?;
             if sub_m.is_present("json") {
                 if sub_m.is_present("pretty") {
                     println!(
@@ -1515,7 +2323,8 @@ async fn inner_main() -> Result<(), Error> {
         #[cfg(not(feature = "portable"))]
         ("disks", Some(sub_m)) => match sub_m.subcommand() {
             ("show", Some(sub_sub_m)) | ("list", Some(sub_sub_m)) | ("ls", Some(sub_sub_m)) => {
-                let info = disks::list().await?;
+                let info =
+                    daemon::call_or("disks.list", serde_json::json!({}), disks::list()).await?;
                 if sub_sub_m.is_present("json") {
                     if sub_sub_m.is_present("pretty") {
                         println!(
@@ -1546,24 +2355,30 @@ async fn inner_main() -> Result<(), Error> {
         #[cfg(not(feature = "portable"))]
         ("backup", Some(sub_m)) => match sub_m.subcommand() {
             ("create", Some(sub_sub_m)) => {
-                crate::backup::backup_to_partition(
-                    sub_sub_m.value_of("PARTITION").unwrap(),
-                    sub_sub_m.value_of("ID").unwrap(),
-                    &match sub_sub_m.value_of("password") {
-                        Some(a) => Cow::Borrowed(a),
-                        None => Cow::Owned(rpassword::read_password_from_tty(Some("Password: "))?),
-                    },
+                let partition = sub_sub_m.value_of("PARTITION").unwrap();
+                let id = sub_sub_m.value_of("ID").unwrap();
+                let password = match sub_sub_m.value_of("password") {
+                    Some(a) => Cow::Borrowed(a),
+                    None => Cow::Owned(rpassword::read_password_from_tty(Some("Password: "))?),
+                };
+                daemon::call_or(
+                    "backup.create",
+                    serde_json::json!({ "partition": partition, "id": id, "password": password }),
+                    crate::backup::backup_to_partition(partition, id, &password),
                 )
                 .await?
             }
             ("restore", Some(sub_sub_m)) => {
-                crate::backup::restore_from_partition(
-                    sub_sub_m.value_of("PARTITION").unwrap(),
-                    sub_sub_m.value_of("ID").unwrap(),
-                    &match sub_sub_m.value_of("password") {
-                        Some(a) => Cow::Borrowed(a),
-                        None => Cow::Owned(rpassword::read_password_from_tty(Some("Password: "))?),
-                    },
+                let partition = sub_sub_m.value_of("PARTITION").unwrap();
+                let id = sub_sub_m.value_of("ID").unwrap();
+                let password = match sub_sub_m.value_of("password") {
+                    Some(a) => Cow::Borrowed(a),
+                    None => Cow::Owned(rpassword::read_password_from_tty(Some("Password: "))?),
+                };
+                daemon::call_or(
+                    "backup.restore",
+                    serde_json::json!({ "partition": partition, "id": id, "password": password }),
+                    crate::backup::restore_from_partition(partition, id, &password),
                 )
                 .await?
             }
@@ -1574,40 +2389,164 @@ async fn inner_main() -> Result<(), Error> {
         },
         #[cfg(not(feature = "portable"))]
         ("repair-app-status", _) => {
-            control::repair_app_status().await?;
+            daemon::call_or(
+                "repair-app-status",
+                serde_json::json!({}),
+                control::repair_app_status(false),
+            )
+            .await?;
         }
         #[cfg(not(feature = "portable"))]
         ("actions", Some(sub_m)) => {
             use yajrc::{GenericRpcMethod, RpcResponse};
 
-            let man = apps::manifest(sub_m.value_of("SERVICE").unwrap()).await?;
+            let service = sub_m.value_of("SERVICE").unwrap();
             let action_id = sub_m.value_of("ACTION").unwrap();
+            let res = if let Some(mut client) = daemon::Client::connect_if_running().await {
+                match client
+                    .call_raw(
+                        "actions",
+                        serde_json::json!({ "service": service, "action": action_id }),
+                    )
+                    .await?
+                {
+                    Err(e) if e.code > 0 => {
+                        return Err(Error::new(
+                            failure::format_err!("{}", e.message),
+                            Some(e.code),
+                        ))
+                    }
+                    a => a,
+                }
+            } else {
+                let man = apps::manifest(service).await?;
+                man.actions
+                    .iter()
+                    .filter(|a| &a.id == &action_id)
+                    .next()
+                    .ok_or_else(|| {
+                        failure::format_err!("action {} does not exist for {}", action_id, man.id)
+                    })
+                    .with_code(error::NOT_FOUND)?
+                    .perform(&man.id)
+                    .await
+                    .map(serde_json::Value::String)
+            };
             println!(
                 "{}",
-                serde_json::to_string(&RpcResponse::<GenericRpcMethod>::from_result(
-                    man.actions
-                        .iter()
-                        .filter(|a| &a.id == &action_id)
-                        .next()
-                        .ok_or_else(|| {
-                            failure::format_err!(
-                                "action {} does not exist for {}",
-                                action_id,
-                                man.id
-                            )
-                        })
-                        .with_code(error::NOT_FOUND)?
-                        .perform(&man.id)
-                        .await
-                        .map(serde_json::Value::String)
-                ))
-                .with_code(error::SERDE_ERROR)?
+                serde_json::to_string(&RpcResponse::<GenericRpcMethod>::from_result(res))
+                    .with_code(error::SERDE_ERROR)?
             )
         }
+        #[cfg(not(feature = "portable"))]
+        ("daemon", Some(sub_m)) => {
+            daemon::serve(sub_m.value_of("socket").unwrap()).await?;
+        }
+        ("registry", Some(sub_m)) => match sub_m.subcommand() {
+            ("add", Some(sub_sub_m)) => {
+                let location = sub_sub_m.value_of("LOCATION").unwrap();
+                let source = if location.starts_with("https://") || location.starts_with("http://")
+                {
+                    registry::Source::Http {
+                        url: location.to_owned(),
+                    }
+                } else {
+                    registry::Source::Local {
+                        path: tokio::fs::canonicalize(location).await?,
+                    }
+                };
+                registry::add(registry::RegistryInfo {
+                    name: sub_sub_m.value_of("NAME").unwrap().to_owned(),
+                    source,
+                    keys: sub_sub_m
+                        .values_of("key")
+                        .into_iter()
+                        .flatten()
+                        .map(|k| k.to_owned())
+                        .collect(),
+                })
+                .await?
+            }
+            ("remove", Some(sub_sub_m)) | ("rm", Some(sub_sub_m)) => {
+                registry::remove(sub_sub_m.value_of("NAME").unwrap()).await?
+            }
+            ("serve", Some(sub_sub_m)) => {
+                registry::server::serve(
+                    sub_sub_m.value_of("DIR").unwrap(),
+                    sub_sub_m
+                        .value_of("port")
+                        .unwrap()
+                        .parse()
+                        .with_code(crate::error::GENERAL_ERROR)?,
+                )
+                .await?
+            }
+            ("proxy", Some(sub_sub_m)) => match sub_sub_m.subcommand() {
+                ("set", Some(proxy_m)) => {
+                    registry::proxy::set(Some(proxy_m.value_of("URL").unwrap())).await?
+                }
+                ("clear", _) => registry::proxy::set(None).await?,
+                _ => match registry::proxy::get().await? {
+                    Some(url) => println!("{}", url),
+                    None => println!("none"),
+                },
+            },
+            ("list", Some(sub_sub_m)) | ("ls", Some(sub_sub_m)) => {
+                let registries = registry::registries().await?;
+                if sub_sub_m.is_present("json") {
+                    if sub_sub_m.is_present("pretty") {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&registries)
+                                .with_code(crate::error::SERDE_ERROR)?
+                        );
+                    } else {
+                        println!(
+                            "{}",
+                            serde_json::to_string(&registries)
+                                .with_code(crate::error::SERDE_ERROR)?
+                        );
+                    }
+                } else if sub_sub_m.is_present("yaml") {
+                    println!(
+                        "{}",
+                        serde_yaml::to_string(&registries).with_code(crate::error::SERDE_ERROR)?
+                    );
+                } else {
+                    use prettytable::{Cell, Row, Table};
+                    let mut table = Table::new();
+                    let heading = vec![Cell::new("NAME"), Cell::new("LOCATION"), Cell::new("KEYS")];
+                    table.add_row(Row::new(heading));
+                    for info in registries {
+                        table.add_row(Row::new(vec![
+                            Cell::new(&info.name),
+                            Cell::new(&format!("{}", info.source)),
+                            Cell::new(&info.keys.join("\n")),
+                        ]));
+                    }
+                    table.print(&mut std::io::stdout())?;
+                }
+            }
+            _ => {
+                println!("{}", sub_m.usage());
+                std::process::exit(1);
+            }
+        },
         ("pack", Some(sub_m)) => {
+            let output = sub_m.value_of("output").unwrap();
+            let layers_dir = if sub_m.is_present("thin") {
+                Some(match sub_m.value_of("layers-dir") {
+                    Some(dir) => dir.to_owned(),
+                    None => format!("{}", Path::new(output).with_file_name("layers").display()),
+                })
+            } else {
+                None
+            };
             pack(
                 sub_m.value_of("PATH").unwrap(),
-                sub_m.value_of("output").unwrap(),
+                output,
+                sub_m.value_of("sign-key"),
+                layers_dir.as_deref(),
             )
             .await?
         }
@@ -1689,6 +2628,34 @@ async fn inner_main() -> Result<(), Error> {
                 crate::inspect::print_instructions(Path::new(sub_sub_m.value_of("PATH").unwrap()))
                     .await?;
             }
+            ("config-schema", Some(sub_sub_m)) => {
+                let info = crate::inspect::info_full(
+                    Path::new(sub_sub_m.value_of("PATH").unwrap()),
+                    false,
+                    true,
+                )
+                .await?;
+                let schema = crate::config::schema::json_schema(&info.config.unwrap().spec);
+                if sub_sub_m.is_present("pretty") {
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&schema)
+                            .with_code(crate::error::SERDE_ERROR)?
+                    );
+                } else {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&schema).with_code(crate::error::SERDE_ERROR)?
+                    );
+                }
+            }
+            ("icon", Some(sub_sub_m)) => {
+                let icon = crate::inspect::icon(sub_sub_m.value_of("PATH").unwrap()).await?;
+                let output = sub_sub_m.value_of("OUTPUT").unwrap();
+                tokio::fs::write(output, icon)
+                    .await
+                    .with_code(crate::error::FILESYSTEM_ERROR)?;
+            }
             _ => {
                 println!("{}", sub_m.usage());
                 std::process::exit(1);
@@ -1701,6 +2668,53 @@ async fn inner_main() -> Result<(), Error> {
                 serde_yaml::to_string(&idx).with_code(crate::error::SERDE_ERROR)?
             );
         }
+        ("keygen", Some(sub_m)) => {
+            let pubkey =
+                crate::signing::generate_keypair(sub_m.value_of("OUTPUT").unwrap()).await?;
+            println!("{}", crate::signing::encode_pubkey(&pubkey));
+        }
+        ("trust", Some(sub_m)) => match sub_m.subcommand() {
+            ("add", Some(sub_sub_m)) => {
+                crate::signing::trust(
+                    sub_sub_m.value_of("NAME").unwrap(),
+                    sub_sub_m.value_of("PUBKEY").unwrap(),
+                )
+                .await?
+            }
+            ("remove", Some(sub_sub_m)) => {
+                crate::signing::untrust(sub_sub_m.value_of("NAME").unwrap()).await?
+            }
+            ("list", Some(sub_sub_m)) => {
+                let keys = crate::signing::trusted_keys().await?;
+                if sub_sub_m.is_present("json") {
+                    if sub_sub_m.is_present("pretty") {
+                        println!(
+                            "{}",
+                            serde_json::to_string_pretty(&keys)
+                                .with_code(crate::error::SERDE_ERROR)?
+                        );
+                    } else {
+                        println!(
+                            "{}",
+                            serde_json::to_string(&keys).with_code(crate::error::SERDE_ERROR)?
+                        );
+                    }
+                } else if sub_sub_m.is_present("yaml") {
+                    println!(
+                        "{}",
+                        serde_yaml::to_string(&keys).with_code(crate::error::SERDE_ERROR)?
+                    );
+                } else {
+                    for (name, pubkey) in keys {
+                        println!("{}: {}", name, pubkey);
+                    }
+                }
+            }
+            _ => {
+                println!("{}", sub_m.usage());
+                std::process::exit(1);
+            }
+        },
         _ => {
             app.print_long_help().unwrap();
             std::process::exit(1);
@@ -1709,3 +2723,106 @@ async fn inner_main() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[cfg(not(feature = "portable"))]
+fn print_configuration_res(
+    sub_m: &clap::ArgMatches,
+    res: config::ConfigurationRes,
+) -> Result<(), Error> {
+    if sub_m.is_present("json") {
+        if sub_m.is_present("pretty") {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&res).with_code(crate::error::SERDE_ERROR)?
+            );
+        } else {
+            println!(
+                "{}",
+                serde_json::to_string(&res).with_code(crate::error::SERDE_ERROR)?
+            );
+        }
+    } else if sub_m.is_present("yaml") {
+        println!(
+            "{}",
+            serde_yaml::to_string(&res).with_code(crate::error::SERDE_ERROR)?
+        );
+    } else if !res.needs_restart.is_empty() || !res.stopped.is_empty() {
+        use prettytable::{Cell, Row, Table};
+        let mut table = Table::new();
+        let heading = vec![
+            Cell::new("APPLICATION ID"),
+            Cell::new("STATUS"),
+            Cell::new("REASON"),
+        ];
+        table.add_row(Row::new(heading));
+        for name in res.needs_restart {
+            table.add_row(Row::new(vec![
+                Cell::new(&name),
+                Cell::new("Needs Restart"),
+                Cell::new("Configuration Changed"),
+            ]));
+        }
+        for (name, reason) in res.stopped {
+            table.add_row(Row::new(vec![
+                Cell::new(&name),
+                Cell::new("Stopped"),
+                Cell::new(&format!("{}", reason)),
+            ]));
+        }
+        table.print(&mut std::io::stdout())?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "portable"))]
+fn print_dry_run(sub_m: &clap::ArgMatches, res: config::DryRunRes) -> Result<(), Error> {
+    if sub_m.is_present("json") {
+        if sub_m.is_present("pretty") {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&res).with_code(crate::error::SERDE_ERROR)?
+            );
+        } else {
+            println!(
+                "{}",
+                serde_json::to_string(&res).with_code(crate::error::SERDE_ERROR)?
+            );
+        }
+    } else if sub_m.is_present("yaml") {
+        println!(
+            "{}",
+            serde_yaml::to_string(&res).with_code(crate::error::SERDE_ERROR)?
+        );
+    } else if let Some(result) = res.result {
+        print_configuration_res(sub_m, result)?;
+    } else {
+        let report = res.report;
+        use prettytable::{Cell, Row, Table};
+        let mut table = Table::new();
+        let heading = vec![Cell::new("PATH"), Cell::new("KIND"), Cell::new("ERROR")];
+        table.add_row(Row::new(heading));
+        for violation in &report.spec {
+            table.add_row(Row::new(vec![
+                Cell::new(&violation.path),
+                Cell::new(&violation.kind),
+                Cell::new(&violation.message),
+            ]));
+        }
+        for violation in &report.rules {
+            table.add_row(Row::new(vec![
+                Cell::new(""),
+                Cell::new("rule"),
+                Cell::new(&violation.description),
+            ]));
+        }
+        for rule in &report.not_evaluated {
+            table.add_row(Row::new(vec![
+                Cell::new(""),
+                Cell::new("rule (not evaluated)"),
+                Cell::new(&rule.description),
+            ]));
+        }
+        table.print(&mut std::io::stdout())?;
+    }
+    Ok(())
+}
@@ -4,6 +4,8 @@ use linear_map::LinearMap;
 
 use crate::actions::Action;
 use crate::dependencies::Dependencies;
+use crate::health::{HealthCheck, ReadinessProbe};
+use crate::layers::Layer;
 use crate::tor::HiddenServiceVersion;
 use crate::tor::PortMapping;
 
@@ -15,11 +17,77 @@ pub struct Description {
     pub long: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum Arch {
+    #[serde(rename = "x86_64")]
+    X86_64,
+    #[serde(rename = "aarch64")]
+    Aarch64,
+}
+impl Arch {
+    /// The architecture appmgr was built for.
+    pub fn host() -> Option<Self> {
+        std::env::consts::ARCH.parse().ok()
+    }
+
+    /// The name OCI image indexes use for this architecture.
+    pub fn oci_name(&self) -> &'static str {
+        match self {
+            Arch::X86_64 => "amd64",
+            Arch::Aarch64 => "arm64",
+        }
+    }
+
+    /// The name of this architecture's image in a package.
+    pub fn image_file(&self) -> String {
+        format!("image-{}.tar", self)
+    }
+}
+impl std::str::FromStr for Arch {
+    type Err = failure::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x86_64" => Ok(Arch::X86_64),
+            "aarch64" => Ok(Arch::Aarch64),
+            _ => Err(format_err!("Unsupported Architecture: {}", s)),
+        }
+    }
+}
+impl std::fmt::Display for Arch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arch::X86_64 => write!(f, "x86_64"),
+            Arch::Aarch64 => write!(f, "aarch64"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum ImageConfig {
+    /// A `docker save` tarball packed as `image.tar`.
     Tar,
+    /// OCI image layout archives, one per architecture, packed as `image-<arch>.tar`
+    /// in the order listed.
+    Oci { arches: Vec<Arch> },
+}
+impl ImageConfig {
+    /// The names of the image archives in a package, in order.
+    pub fn image_files(&self) -> Vec<String> {
+        match self {
+            ImageConfig::Tar => vec!["image.tar".to_owned()],
+            ImageConfig::Oci { arches } => arches.iter().map(|a| a.image_file()).collect(),
+        }
+    }
+
+    /// Whether the package has an image for `arch`. A `docker save` tarball is assumed to match.
+    pub fn supports(&self, arch: Option<Arch>) -> bool {
+        match self {
+            ImageConfig::Tar => true,
+            ImageConfig::Oci { arches } => arch.map(|a| arches.contains(&a)).unwrap_or(false),
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -53,6 +121,13 @@ pub struct ManifestV0 {
     pub os_version_recommended: emver::VersionRange,
     pub ports: Vec<PortMapping>,
     pub image: ImageConfig,
+    /// Layers of each image archive in the package, keyed by the archive's name.
+    /// Recorded by `appmgr pack`.
+    #[serde(default)]
+    pub layers: LinearMap<String, Vec<Layer>>,
+    /// Whether the layer blobs are left out of the image archives, to be fetched on install.
+    #[serde(default)]
+    pub thin: bool,
     #[serde(default)]
     pub shm_size_mb: Option<usize>,
     pub mount: PathBuf,
@@ -68,6 +143,10 @@ pub struct ManifestV0 {
     pub dependencies: Dependencies,
     #[serde(default)]
     pub actions: Vec<Action>,
+    #[serde(default)]
+    pub readiness_probe: Option<ReadinessProbe>,
+    #[serde(default)]
+    pub health_checks: LinearMap<String, HealthCheck>,
     #[serde(flatten)]
     pub extra: LinearMap<String, serde_yaml::Value>,
 }
@@ -85,3 +164,45 @@ impl Manifest {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn host_arch() {
+        let host = match std::env::consts::ARCH {
+            "x86_64" => Some(Arch::X86_64),
+            "aarch64" => Some(Arch::Aarch64),
+            _ => None,
+        };
+        assert_eq!(Arch::host(), host);
+        assert_eq!("aarch64".parse::<Arch>().unwrap(), Arch::Aarch64);
+        assert!("arm64".parse::<Arch>().is_err());
+        assert_eq!(Arch::Aarch64.oci_name(), "arm64");
+        assert_eq!(Arch::X86_64.image_file(), "image-x86_64.tar");
+    }
+
+    #[test]
+    fn image_supports() {
+        assert!(ImageConfig::Tar.supports(Some(Arch::Aarch64)));
+        assert!(ImageConfig::Tar.supports(None));
+        let oci = ImageConfig::Oci {
+            arches: vec![Arch::Aarch64, Arch::X86_64],
+        };
+        assert!(oci.supports(Some(Arch::Aarch64)));
+        assert!(oci.supports(Some(Arch::X86_64)));
+        assert!(!oci.supports(None));
+        assert_eq!(
+            oci.image_files(),
+            vec![
+                "image-aarch64.tar".to_owned(),
+                "image-x86_64.tar".to_owned()
+            ]
+        );
+        let oci = ImageConfig::Oci {
+            arches: vec![Arch::X86_64],
+        };
+        assert!(!oci.supports(Some(Arch::Aarch64)));
+    }
+}
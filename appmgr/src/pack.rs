@@ -5,10 +5,11 @@ use failure::ResultExt;
 use futures::stream::StreamExt;
 use linear_map::LinearMap;
 use rand::SeedableRng;
+use tokio::io::AsyncWriteExt;
 use tokio_tar as tar;
 
 use crate::config::{ConfigRuleEntry, ConfigSpec};
-use crate::manifest::{ImageConfig, Manifest};
+use crate::manifest::{Arch, ImageConfig, Manifest};
 use crate::util::{from_cbor_async_reader, from_json_async_reader, from_yaml_async_reader};
 use crate::version::VersionT;
 
@@ -22,9 +23,21 @@ pub enum Error {
     InvalidOutputPath(String),
 }
 
-pub async fn pack(path: &str, output: &str) -> Result<(), failure::Error> {
+/// Packs the app at `path` into `output`. With `layers_dir` the package is thin:
+/// layer blobs are written to `layers_dir`, for a registry to serve, instead of into the package.
+pub async fn pack(
+    path: &str,
+    output: &str,
+    sign_key: Option<&str>,
+    layers_dir: Option<&str>,
+) -> Result<(), failure::Error> {
     let path = Path::new(path.trim_end_matches("/"));
     let output = Path::new(output);
+    let keypair = if let Some(sign_key) = sign_key {
+        Some(crate::signing::read_keypair(sign_key).await?)
+    } else {
+        None
+    };
     log::info!(
         "Starting pack of {} to {}.",
         path.file_name()
@@ -32,15 +45,32 @@ pub async fn pack(path: &str, output: &str) -> Result<(), failure::Error> {
             .ok_or_else(|| Error::InvalidDirectoryName(format!("{}", path.display())))?,
         output.display(),
     );
-    let out_file = tokio::fs::File::create(output).await?;
+    let archive_path = output.with_file_name(format!(
+        ".{}.tar",
+        output
+            .file_name()
+            .and_then(|a| a.to_str())
+            .ok_or_else(|| Error::InvalidOutputPath(format!("{}", output.display())))?
+    ));
+    let out_file = tokio::fs::File::create(&archive_path).await?;
     let mut out = tar::Builder::new(out_file);
     log::info!("Reading {}/manifest.yaml.", path.display());
-    let manifest: Manifest = crate::util::from_yaml_async_reader(
+    let mut manifest: Manifest = crate::util::from_yaml_async_reader(
         tokio::fs::File::open(path.join("manifest.yaml"))
             .await
             .with_context(|e| format!("{}: manifest.yaml", e))?,
     )
     .await?;
+    match &mut manifest {
+        Manifest::V0(m) => {
+            for image_file in m.image.image_files() {
+                log::info!("Reading layers of {}/{}.", path.display(), image_file);
+                let layers = crate::layers::scan(path.join(&image_file)).await?;
+                m.layers.insert(image_file, layers);
+            }
+            m.thin = layers_dir.is_some();
+        }
+    }
     log::info!("Writing manifest to archive.");
     let bin_manifest = serde_cbor::to_vec(&manifest)?;
     let mut manifest_header = tar::Header::new_gnu();
@@ -118,19 +148,52 @@ pub async fn pack(path: &str, output: &str) -> Result<(), failure::Error> {
             out.append_path_with_name(&file_path, &asset.src).await?;
         }
     }
-    match manifest.image {
-        ImageConfig::Tar => {
-            log::info!("Reading {}/image.tar.", path.display());
-            let image = tokio::fs::File::open(path.join("image.tar"))
-                .await
-                .with_context(|e| format!("{}: image.tar", e))?;
-            log::info!("Writing image.tar to archive.");
-            let mut header = tar::Header::new_gnu();
-            header.set_size(image.metadata().await?.len());
-            out.append_data(&mut header, "image.tar", image).await?;
+    if let ImageConfig::Oci { arches } = &manifest.image {
+        ensure!(!arches.is_empty(), "No Image Architectures Listed");
+    }
+    for image_file in manifest.image.image_files() {
+        let mut image_path = path.join(&image_file);
+        let thin_path = output.with_file_name(format!(".{}.thin", image_file));
+        if let Some(layers_dir) = layers_dir {
+            log::info!("Moving layers of {} to {}.", image_file, layers_dir);
+            crate::layers::split(
+                &image_path,
+                &manifest.layers[&image_file],
+                &thin_path,
+                Path::new(layers_dir),
+            )
+            .await?;
+            image_path = thin_path.clone();
+        }
+        log::info!("Reading {}.", image_path.display());
+        let image = tokio::fs::File::open(&image_path)
+            .await
+            .with_context(|e| format!("{}: {}", e, image_path.display()))?;
+        log::info!("Writing {} to archive.", image_file);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(image.metadata().await?.len());
+        out.append_data(&mut header, &image_file, image).await?;
+        if layers_dir.is_some() {
+            tokio::fs::remove_file(&thin_path).await?;
         }
     }
-    out.into_inner().await?;
+    let icon_path = path.join(crate::s9pk::ICON);
+    if icon_path.exists() {
+        log::info!("Packing {}.", crate::s9pk::ICON);
+        out.append_path_with_name(&icon_path, crate::s9pk::ICON)
+            .await?;
+    }
+    out.into_inner().await?.flush().await?;
+    if let Some(keypair) = keypair {
+        log::info!(
+            "Signing package with {}.",
+            crate::signing::encode_pubkey(&keypair.public)
+        );
+        crate::signing::sign_package(&archive_path, &keypair).await?;
+    }
+    log::info!("Writing table of contents.");
+    crate::s9pk::write(&archive_path, output).await?;
+    tokio::fs::remove_file(&archive_path).await?;
 
     Ok(())
 }
@@ -176,11 +239,14 @@ pub async fn verify(path: &str) -> Result<(), failure::Error> {
             .and_then(|a| a.to_str())
             .ok_or_else(|| Error::InvalidFileName(format!("{}", path.display())))?,
     );
-    {}
+    crate::signing::verify_package(path, None).await?;
     log::info!("Opening file.");
-    let r = tokio::fs::File::open(&path)
-        .await
-        .with_context(|e| format!("{}: {}", path.display(), e))?;
+    let mut pkg = crate::s9pk::S9pk::open(path).await?;
+    if pkg.toc().is_some() {
+        log::info!("Verifying table of contents.");
+        pkg.verify().await?;
+    }
+    let r = pkg.into_archive().await?;
     log::info!("Extracting archive.");
     let mut pkg = tar::Archive::new(r);
     let mut entries = pkg.entries()?;
@@ -382,7 +448,194 @@ pub async fn verify(path: &str) -> Result<(), failure::Error> {
                 })
                 .collect::<Result<_, _>>()?;
         }
+        ImageConfig::Oci { arches } => {
+            ensure!(!arches.is_empty(), "No Image Architectures Listed");
+            let image_name = format!("start9/{}", manifest.id);
+            for arch in arches {
+                let image_file = arch.image_file();
+                log::debug!("Opening {} from archive.", image_file);
+                let image = entries
+                    .next()
+                    .await
+                    .ok_or_else(|| format_err!("missing {}", image_file))??;
+                let image_path = image.path()?;
+                if image_path != Path::new(&image_file) {
+                    return Err(format_err!(
+                        "Package File Invalid or Corrupted: expected {}, got {}",
+                        image_file,
+                        image_path.display()
+                    ));
+                }
+                log::info!("Verifying {}.", image_file);
+                verify_oci(image, *arch, &image_name)
+                    .await
+                    .with_context(|e| format!("{}: {}", image_file, e))?;
+            }
+        }
     };
 
     Ok(())
 }
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OciIndex {
+    schema_version: u32,
+    manifests: Vec<OciDescriptor>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OciDescriptor {
+    digest: String,
+    #[serde(default)]
+    platform: Option<OciPlatform>,
+    #[serde(default)]
+    annotations: LinearMap<String, String>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+struct OciPlatform {
+    architecture: String,
+}
+
+/// Checks that an OCI image layout archive has an index for `arch`, and no other app's tags.
+async fn verify_oci<R: tokio::io::AsyncRead + Unpin>(
+    image: R,
+    arch: Arch,
+    image_name: &str,
+) -> Result<(), failure::Error> {
+    let mut image_tar = tar::Archive::new(image);
+    let mut entries = image_tar.entries()?;
+    let mut has_layout = false;
+    let mut index = None;
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let path = path.strip_prefix("./").unwrap_or(&path);
+        if path == Path::new("oci-layout") {
+            has_layout = true;
+        } else if path == Path::new("index.json") {
+            index = Some(from_json_async_reader::<OciIndex, _>(entry).await?);
+        }
+    }
+    ensure!(has_layout, "Not an OCI Image Layout: missing oci-layout");
+    let index = index.ok_or_else(|| format_err!("missing index.json"))?;
+    ensure!(
+        index.schema_version == 2,
+        "Unsupported OCI Index Schema Version: {}",
+        index.schema_version
+    );
+    ensure!(!index.manifests.is_empty(), "index.json Lists No Images");
+    for manifest in &index.manifests {
+        if let Some(platform) = &manifest.platform {
+            ensure!(
+                platform.architecture == arch.oci_name(),
+                "{} Is for {}, Expected {}",
+                manifest.digest,
+                platform.architecture,
+                arch.oci_name()
+            );
+        }
+        for (key, name) in &manifest.annotations {
+            if (key == "org.opencontainers.image.ref.name" || key == "io.containerd.image.name")
+                && name.starts_with("start9/")
+            {
+                ensure!(
+                    name.split(':').next().unwrap() == image_name,
+                    "Contains prohibited image tag: {}",
+                    name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn oci_archive(layout: bool, index: serde_json::Value) -> Vec<u8> {
+        let mut entries = vec![("index.json", serde_json::to_vec(&index).unwrap())];
+        if layout {
+            entries.push(("oci-layout", br#"{"imageLayoutVersion":"1.0.0"}"#.to_vec()));
+        }
+        let mut out = tar::Builder::new(Vec::new());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            out.append_data(&mut header, name, data.as_slice())
+                .await
+                .unwrap();
+        }
+        out.into_inner().await.unwrap()
+    }
+
+    fn index(architecture: &str, name: &str) -> serde_json::Value {
+        serde_json::json!({
+            "schemaVersion": 2,
+            "manifests": [{
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": "sha256:0000",
+                "size": 1,
+                "platform": { "architecture": architecture, "os": "linux" },
+                "annotations": { "org.opencontainers.image.ref.name": name },
+            }],
+        })
+    }
+
+    async fn verify(layout: bool, index: serde_json::Value, arch: Arch) -> Result<(), String> {
+        let archive = oci_archive(layout, index).await;
+        verify_oci(archive.as_slice(), arch, "start9/lnd")
+            .await
+            .map_err(|e| format!("{}", e))
+    }
+
+    #[tokio::test]
+    async fn verify_oci_index() {
+        verify(true, index("arm64", "start9/lnd:0.11.0"), Arch::Aarch64)
+            .await
+            .unwrap();
+        verify(
+            true,
+            index("amd64", "docker.io/library/alpine"),
+            Arch::X86_64,
+        )
+        .await
+        .unwrap();
+        let e = verify(false, index("arm64", "start9/lnd"), Arch::Aarch64)
+            .await
+            .unwrap_err();
+        assert!(e.contains("missing oci-layout"), "{}", e);
+        let e = verify(true, index("arm64", "start9/lnd"), Arch::X86_64)
+            .await
+            .unwrap_err();
+        assert!(e.contains("Is for arm64, Expected amd64"), "{}", e);
+        let e = verify(
+            true,
+            index("arm64", "start9/bitcoind:latest"),
+            Arch::Aarch64,
+        )
+        .await
+        .unwrap_err();
+        assert!(e.contains("prohibited image tag"), "{}", e);
+        let e = verify(
+            true,
+            serde_json::json!({ "schemaVersion": 1, "manifests": [] }),
+            Arch::Aarch64,
+        )
+        .await
+        .unwrap_err();
+        assert!(e.contains("Schema Version: 1"), "{}", e);
+        let e = verify(
+            true,
+            serde_json::json!({ "schemaVersion": 2, "manifests": [] }),
+            Arch::Aarch64,
+        )
+        .await
+        .unwrap_err();
+        assert!(e.contains("Lists No Images"), "{}", e);
+    }
+}
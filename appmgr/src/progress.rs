@@ -0,0 +1,385 @@
+use std::future::Future;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use failure::ResultExt as _;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::sync::{broadcast, Mutex, Notify};
+
+use crate::Error;
+use crate::ResultExt as _;
+
+/// How often byte counts are reported while a phase is running.
+pub const INTERVAL: Duration = Duration::from_millis(250);
+/// How long to wait for the daemon to finish relaying the events of an operation.
+pub const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Sink = Pin<Box<dyn AsyncWrite + Send>>;
+
+#[derive(Clone, Debug)]
+enum Message {
+    Event(Event),
+    /// The daemon finished handling a request of the operation.
+    End(String),
+}
+
+/// Marks the end of a request of an operation in a scoped subscription.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct End {
+    end: String,
+}
+
+lazy_static::lazy_static! {
+    static ref SINK: Mutex<Option<Sink>> = Mutex::new(None);
+    static ref SUBSCRIBERS: broadcast::Sender<Message> = broadcast::channel(256).0;
+    /// The operation this process relays the daemon's events for.
+    static ref RELAYED: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+    static ref RELAY_ENDED: Notify = Notify::new();
+}
+static REQUESTS_SENT: AtomicUsize = AtomicUsize::new(0);
+static REQUESTS_ENDED: AtomicUsize = AtomicUsize::new(0);
+static RELAY_CLOSED: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    /// The operation the daemon is handling a request of.
+    static OPERATION: String;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    Downloading,
+    Verifying,
+    Extracting,
+    LoadingImage,
+    CreatingContainer,
+    Configuring,
+    BackingUp,
+    Restoring,
+    Complete,
+    Failed,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Event {
+    /// The operation the event belongs to, when emitted by the daemon on behalf of a client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<String>,
+    pub app: Option<String>,
+    pub phase: Phase,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+impl Event {
+    pub fn new(app: Option<&str>, phase: Phase) -> Self {
+        Event {
+            operation: OPERATION.try_with(|o| o.clone()).ok(),
+            app: app.map(|a| a.to_owned()),
+            phase,
+            bytes: None,
+            total: None,
+            message: None,
+        }
+    }
+
+    pub fn bytes(mut self, bytes: u64, total: Option<u64>) -> Self {
+        self.bytes = Some(bytes);
+        self.total = total;
+        self
+    }
+
+    pub fn message<S: std::fmt::Display>(mut self, message: S) -> Self {
+        self.message = Some(format!("{}", message));
+        self
+    }
+
+    pub async fn emit(self) {
+        emit(self).await
+    }
+}
+
+/// Sends an event to the progress sink, if one is set, and to daemon subscribers.
+pub async fn emit(event: Event) {
+    let _ = SUBSCRIBERS.send(Message::Event(event.clone()));
+    let mut sink = SINK.lock().await;
+    if let Some(w) = sink.as_mut() {
+        let res = match serde_json::to_vec(&event) {
+            Ok(mut line) => {
+                line.push(b'\n');
+                match w.write_all(&line).await {
+                    Ok(()) => w.flush().await,
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = res {
+            log::warn!("Progress Sink Closed: {}", e);
+            *sink = None;
+        }
+    }
+}
+
+/// Limits how often byte counts are emitted for a single phase.
+pub struct Reporter {
+    operation: Option<String>,
+    app: Option<String>,
+    phase: Phase,
+    total: Option<u64>,
+    last: Option<Instant>,
+}
+impl Reporter {
+    pub fn new(app: Option<&str>, phase: Phase, total: Option<u64>) -> Self {
+        Reporter {
+            operation: OPERATION.try_with(|o| o.clone()).ok(),
+            app: app.map(|a| a.to_owned()),
+            phase,
+            total,
+            last: None,
+        }
+    }
+
+    /// Emits `bytes` unless an event was emitted less than `INTERVAL` ago.
+    pub async fn report(&mut self, bytes: u64, force: bool) {
+        if !force && self.last.map(|l| l.elapsed() < INTERVAL).unwrap_or(false) {
+            return;
+        }
+        self.last = Some(Instant::now());
+        // reporters may run on a task of their own, outside of the operation's scope
+        let mut event = Event::new(self.app.as_deref(), self.phase).bytes(bytes, self.total);
+        event.operation = self.operation.clone();
+        event.emit().await
+    }
+}
+
+async fn set_sink(sink: Sink) {
+    *SINK.lock().await = Some(sink);
+}
+
+/// Writes events as newline delimited json to an inherited file descriptor.
+pub async fn to_fd(fd: RawFd) -> Result<(), Error> {
+    let f = unsafe { std::fs::File::from_raw_fd(fd) };
+    set_sink(Box::pin(tokio::fs::File::from_std(f))).await;
+    Ok(())
+}
+
+/// Writes events as newline delimited json to a listening unix socket.
+pub async fn to_socket<P: AsRef<Path>>(path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+    let stream = UnixStream::connect(path)
+        .await
+        .with_context(|e| format!("{}: {}", path.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    set_sink(Box::pin(stream)).await;
+    Ok(())
+}
+
+/// Runs `f` as part of `operation`: the events it emits carry the operation,
+/// and the end of `f` is signalled to the subscribers of the operation.
+pub async fn scope<F: Future>(operation: Option<String>, f: F) -> F::Output {
+    match operation {
+        Some(operation) => {
+            let res = OPERATION.scope(operation.clone(), f).await;
+            let _ = SUBSCRIBERS.send(Message::End(operation));
+            res
+        }
+        None => f.await,
+    }
+}
+
+/// Events emitted in this process from the moment of subscribing.
+pub struct Subscription(broadcast::Receiver<Message>);
+
+pub fn subscribe() -> Subscription {
+    Subscription(SUBSCRIBERS.subscribe())
+}
+
+/// Writes the events of `subscription` to `w` until it is closed. With an `operation`, only its
+/// events are written, along with a `{"end":OPERATION}` line whenever one of its requests is done.
+pub async fn forward<W: AsyncWrite + Unpin>(
+    w: &mut W,
+    mut subscription: Subscription,
+    operation: Option<&str>,
+) -> Result<(), Error> {
+    loop {
+        let message = match subscription.0.recv().await {
+            Ok(message) => message,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("Progress Subscriber Skipped {} Events.", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        let mut line = match message {
+            Message::Event(event) => {
+                if operation.is_some() && event.operation.as_deref() != operation {
+                    continue;
+                }
+                serde_json::to_vec(&event)
+            }
+            Message::End(end) if Some(end.as_str()) == operation => {
+                serde_json::to_vec(&End { end })
+            }
+            Message::End(_) => continue,
+        }
+        .with_code(crate::error::SERDE_ERROR)?;
+        line.push(b'\n');
+        w.write_all(&line).await?;
+        w.flush().await?;
+    }
+}
+
+/// The operation to tag a request to the daemon with, if its events are being relayed.
+pub fn request_operation() -> Option<String> {
+    let operation = RELAYED.lock().unwrap().clone();
+    if operation.is_some() {
+        REQUESTS_SENT.fetch_add(1, Ordering::SeqCst);
+    }
+    operation
+}
+
+/// Subscribes to the events of a running daemon for the requests this process makes,
+/// and passes them on to the progress sink.
+pub async fn relay_daemon() -> Result<(), Error> {
+    if !Path::new(crate::DAEMON_SOCKET).exists() {
+        return Ok(());
+    }
+    let mut stream = match UnixStream::connect(crate::DAEMON_SOCKET).await {
+        Ok(a) => BufReader::new(a),
+        Err(_) => return Ok(()),
+    };
+    let operation = format!("{}-{:016x}", std::process::id(), rand::random::<u64>());
+    let mut req = serde_json::to_vec(&serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 0,
+        "method": "progress.subscribe",
+        "params": { "operation": operation },
+    }))
+    .with_code(crate::error::SERDE_ERROR)?;
+    req.push(b'\n');
+    stream.get_mut().write_all(&req).await?;
+    let mut line = String::new();
+    // the response to the subscription
+    stream.read_line(&mut line).await?;
+    *RELAYED.lock().unwrap() = Some(operation);
+    tokio::spawn(async move {
+        loop {
+            line.clear();
+            match stream.read_line(&mut line).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => (),
+            }
+            if let Ok(event) = serde_json::from_str::<Event>(&line) {
+                emit(event).await;
+            } else if serde_json::from_str::<End>(&line).is_ok() {
+                REQUESTS_ENDED.fetch_add(1, Ordering::SeqCst);
+                RELAY_ENDED.notify_one();
+            }
+        }
+        RELAY_CLOSED.store(true, Ordering::SeqCst);
+        RELAY_ENDED.notify_one();
+    });
+    Ok(())
+}
+
+/// Waits until the events of every request made to the daemon have been relayed,
+/// for at most `RELAY_TIMEOUT`.
+pub async fn finish() {
+    if RELAYED.lock().unwrap().is_none() {
+        return;
+    }
+    let relayed = async {
+        while REQUESTS_ENDED.load(Ordering::SeqCst) < REQUESTS_SENT.load(Ordering::SeqCst)
+            && !RELAY_CLOSED.load(Ordering::SeqCst)
+        {
+            RELAY_ENDED.notified().await;
+        }
+    };
+    if tokio::time::timeout(RELAY_TIMEOUT, relayed).await.is_err() {
+        log::warn!("Timed Out Relaying Progress Events.");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn event_json() {
+        let event = Event::new(Some("bitcoind"), Phase::LoadingImage).bytes(512, Some(1024));
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"app":"bitcoind","phase":"loading-image","bytes":512,"total":1024}"#
+        );
+        let event = Event::new(None, Phase::Failed).message("Checksum Mismatch");
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"app":null,"phase":"failed","message":"Checksum Mismatch"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn forward_events() {
+        let (mut r, mut w) = tokio::io::duplex(4096);
+        let subscription = subscribe();
+        tokio::spawn(async move { forward(&mut w, subscription, None).await });
+        Event::new(Some("forward-events"), Phase::Complete)
+            .emit()
+            .await;
+        // other tests emit events at the same time
+        let mut lines = BufReader::new(&mut r).lines();
+        let line = loop {
+            let line = lines.next_line().await.unwrap().unwrap();
+            if line.contains("\"forward-events\"") {
+                break line;
+            }
+        };
+        assert_eq!(line, r#"{"app":"forward-events","phase":"complete"}"#);
+    }
+
+    #[tokio::test]
+    async fn forward_operation() {
+        let (r, mut w) = tokio::io::duplex(4096);
+        let subscription = subscribe();
+        tokio::spawn(async move { forward(&mut w, subscription, Some("op-a")).await });
+        let op = |name: &str, app: &'static str| {
+            scope(Some(name.to_owned()), async move {
+                Event::new(Some(app), Phase::Extracting).emit().await;
+                let mut reporter = Reporter::new(Some(app), Phase::Downloading, Some(2));
+                // reporters keep the operation on another task
+                tokio::spawn(async move { reporter.report(1, true).await })
+                    .await
+                    .unwrap();
+            })
+        };
+        op("op-b", "lnd").await;
+        op("op-a", "bitcoind").await;
+        Event::new(Some("electrs"), Phase::Complete).emit().await;
+        op("op-a", "btc-rpc-proxy").await;
+        let mut lines = BufReader::new(r).lines();
+        let mut received = Vec::new();
+        for _ in 0..6 {
+            received.push(lines.next_line().await.unwrap().unwrap());
+        }
+        assert_eq!(
+            received,
+            vec![
+                r#"{"operation":"op-a","app":"bitcoind","phase":"extracting"}"#,
+                r#"{"operation":"op-a","app":"bitcoind","phase":"downloading","bytes":1,"total":2}"#,
+                r#"{"end":"op-a"}"#,
+                r#"{"operation":"op-a","app":"btc-rpc-proxy","phase":"extracting"}"#,
+                r#"{"operation":"op-a","app":"btc-rpc-proxy","phase":"downloading","bytes":1,"total":2}"#,
+                r#"{"end":"op-a"}"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use emver::{Version, VersionRange};
+use tokio_compat_02::FutureExt;
+
+use super::Registry;
+use crate::apps::AppConfig;
+use crate::layers::Layer;
+use crate::manifest::ManifestLatest;
+use crate::Error;
+use crate::ResultExt as _;
+
+#[derive(serde::Deserialize)]
+struct VersionRes {
+    version: Version,
+    #[serde(default)]
+    sha256: Option<String>,
+}
+
+/// The `spec` that selects exactly `version`.
+fn exact(version: &Version) -> String {
+    format!("={}", version)
+}
+
+/// A registry served over HTTP, such as the Start9 registry.
+#[derive(Clone, Debug)]
+pub struct HttpRegistry {
+    url: String,
+}
+impl HttpRegistry {
+    pub fn new<S: Into<String>>(url: S) -> Self {
+        HttpRegistry {
+            url: url.into().trim_end_matches('/').to_owned(),
+        }
+    }
+
+    async fn get<T: for<'de> serde::Deserialize<'de>>(&self, url: &str) -> Result<T, Error> {
+        super::proxy::client(url)
+            .await?
+            .get(url)
+            .send()
+            .compat()
+            .await
+            .with_code(crate::error::NETWORK_ERROR)?
+            .error_for_status()
+            .with_code(crate::error::REGISTRY_ERROR)?
+            .json()
+            .await
+            .with_code(crate::error::SERDE_ERROR)
+    }
+}
+
+#[async_trait]
+impl Registry for HttpRegistry {
+    async fn manifest(&self, id: &str, version: &VersionRange) -> Result<ManifestLatest, Error> {
+        self.get(&format!(
+            "{}/apps/manifest/{}?spec={}",
+            self.url, id, version
+        ))
+        .await
+    }
+
+    async fn version(&self, id: &str, version: &VersionRange) -> Result<Version, Error> {
+        let version: VersionRes = self
+            .get(&format!(
+                "{}/apps/version/{}?spec={}",
+                self.url, id, version
+            ))
+            .await?;
+        Ok(version.version)
+    }
+
+    async fn config(&self, id: &str, version: &VersionRange) -> Result<AppConfig, Error> {
+        let config: crate::inspect::AppConfig = self
+            .get(&format!("{}/apps/config/{}?spec={}", self.url, id, version))
+            .await?;
+        Ok(AppConfig {
+            config: None,
+            spec: config.spec,
+            rules: config.rules,
+        })
+    }
+
+    async fn sha256(&self, id: &str, version: &Version) -> Result<Option<String>, Error> {
+        let version: VersionRes = self
+            .get(&format!(
+                "{}/apps/version/{}?spec={}",
+                self.url,
+                id,
+                exact(version)
+            ))
+            .await?;
+        Ok(version.sha256)
+    }
+
+    async fn fetch(&self, id: &str, version: &Version, dst: &Path) -> Result<(), Error> {
+        crate::install::download_to(
+            &format!("{}/apps/{}.s9pk?spec={}", self.url, id, exact(version)),
+            dst,
+            Some(id),
+        )
+        .await
+    }
+
+    async fn layer(&self, layer: &Layer, dst: &Path) -> Result<(), Error> {
+        crate::install::download_to(
+            &format!("{}/layers/{}", self.url, layer.file_name()),
+            dst,
+            None,
+        )
+        .await
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use emver::{Version, VersionRange};
+use failure::ResultExt as _;
+use tokio::sync::Mutex;
+
+use super::Registry;
+use crate::apps::AppConfig;
+use crate::layers::Layer;
+use crate::manifest::ManifestLatest;
+use crate::Error;
+use crate::ResultExt as _;
+
+/// A directory of packages, laid out the way `appmgr index` expects.
+#[derive(Clone, Debug)]
+pub struct LocalRegistry {
+    path: PathBuf,
+    /// The manifest of each package, along with the mtime it was read at.
+    manifests: Arc<Mutex<HashMap<PathBuf, (SystemTime, ManifestLatest)>>>,
+}
+impl LocalRegistry {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        LocalRegistry {
+            path: path.as_ref().to_owned(),
+            manifests: Default::default(),
+        }
+    }
+
+    /// Finds every package in the registry, only reading those added or modified since the last call.
+    pub async fn packages(&self) -> Result<Vec<(PathBuf, ManifestLatest)>, Error> {
+        let mut manifests = self.manifests.lock().await;
+        let mut cached = std::mem::take(&mut *manifests);
+        let mut res = Vec::new();
+        for path in crate::index::package_paths(&self.path).await? {
+            let modified = tokio::fs::metadata(&path)
+                .await
+                .and_then(|m| m.modified())
+                .with_context(|e| format!("{}: {}", path.display(), e))
+                .with_code(crate::error::FILESYSTEM_ERROR)?;
+            let manifest = match cached.remove(&path) {
+                Some((read_at, manifest)) if read_at == modified => manifest,
+                _ => crate::index::package_manifest(&path).await?,
+            };
+            manifests.insert(path.clone(), (modified, manifest.clone()));
+            res.push((path, manifest));
+        }
+        Ok(res)
+    }
+
+    /// Finds the newest package of `id` in `version`.
+    pub async fn package(
+        &self,
+        id: &str,
+        version: &VersionRange,
+    ) -> Result<(PathBuf, ManifestLatest), Error> {
+        self.packages()
+            .await?
+            .into_iter()
+            .filter(|(_, manifest)| manifest.id == id && manifest.version.satisfies(version))
+            .max_by(|a, b| a.1.version.cmp(&b.1.version))
+            .ok_or_else(|| {
+                format_err!(
+                    "{}: No Package for {} Satisfies {}",
+                    self.path.display(),
+                    id,
+                    version
+                )
+            })
+            .with_code(crate::error::NOT_FOUND)
+    }
+}
+
+#[async_trait]
+impl Registry for LocalRegistry {
+    async fn manifest(&self, id: &str, version: &VersionRange) -> Result<ManifestLatest, Error> {
+        Ok(self.package(id, version).await?.1)
+    }
+
+    async fn version(&self, id: &str, version: &VersionRange) -> Result<Version, Error> {
+        Ok(self.package(id, version).await?.1.version)
+    }
+
+    async fn config(&self, id: &str, version: &VersionRange) -> Result<AppConfig, Error> {
+        let (path, _) = self.package(id, version).await?;
+        let config = crate::inspect::info_full(&path, false, true)
+            .await?
+            .config
+            .unwrap();
+        Ok(AppConfig {
+            config: None,
+            spec: config.spec,
+            rules: config.rules,
+        })
+    }
+
+    async fn sha256(&self, _id: &str, _version: &Version) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    async fn fetch(&self, id: &str, version: &Version, dst: &Path) -> Result<(), Error> {
+        let exact: VersionRange = format!("={}", version).parse().no_code()?;
+        let (path, _) = self.package(id, &exact).await?;
+        if let Some(parent) = dst.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        log::info!("Copying {}.", path.display());
+        tokio::fs::copy(&path, dst)
+            .await
+            .with_context(|e| format!("{}: {}", path.display(), e))
+            .with_code(crate::error::FILESYSTEM_ERROR)?;
+        Ok(())
+    }
+
+    async fn layer(&self, layer: &Layer, dst: &Path) -> Result<(), Error> {
+        let path = self.path.join("layers").join(layer.file_name());
+        crate::ensure_code!(
+            path.exists(),
+            crate::error::NOT_FOUND,
+            "{}: No Layer {}",
+            self.path.display(),
+            layer.digest
+        );
+        tokio::fs::copy(&path, dst)
+            .await
+            .with_context(|e| format!("{}: {}", path.display(), e))
+            .with_code(crate::error::FILESYSTEM_ERROR)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use emver::{Version, VersionRange};
+
+use crate::apps::AppConfig;
+use crate::layers::Layer;
+use crate::manifest::ManifestLatest;
+use crate::util::{from_yaml_async_reader, to_yaml_async_writer, PersistencePath};
+use crate::Error;
+
+pub mod http;
+pub mod local;
+pub mod proxy;
+pub mod server;
+
+pub const REGISTRIES_YAML: &'static str = "registries.yaml";
+
+#[async_trait]
+pub trait Registry: Send + Sync {
+    async fn manifest(&self, id: &str, version: &VersionRange) -> Result<ManifestLatest, Error>;
+    async fn version(&self, id: &str, version: &VersionRange) -> Result<Version, Error>;
+    async fn config(&self, id: &str, version: &VersionRange) -> Result<AppConfig, Error>;
+    /// The SHA-256 of the package of `id` at `version`, if the registry publishes one.
+    async fn sha256(&self, id: &str, version: &Version) -> Result<Option<String>, Error>;
+    /// Fetches the package of `id` at `version` into `dst`, resuming a partial `dst` if possible.
+    async fn fetch(&self, id: &str, version: &Version, dst: &Path) -> Result<(), Error>;
+    /// Fetches a layer blob referenced by a thin package into `dst`.
+    async fn layer(&self, layer: &Layer, dst: &Path) -> Result<(), Error>;
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    Http { url: String },
+    Local { path: PathBuf },
+}
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Http { url } => write!(f, "{}", url),
+            Source::Local { path } => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegistryInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub source: Source,
+    /// Keys trusted to sign packages from this registry.
+    #[serde(default)]
+    pub keys: Vec<String>,
+}
+impl RegistryInfo {
+    pub fn open(&self) -> Box<dyn Registry> {
+        match &self.source {
+            Source::Http { url } => Box::new(http::HttpRegistry::new(url.as_str())),
+            Source::Local { path } => Box::new(local::LocalRegistry::new(path)),
+        }
+    }
+}
+
+/// The configured registries, in the order they are tried.
+/// Without a configuration only `REGISTRY_URL` is used.
+pub async fn registries() -> Result<Vec<RegistryInfo>, Error> {
+    match PersistencePath::from_ref(REGISTRIES_YAML)
+        .maybe_read(false)
+        .await
+        .transpose()?
+    {
+        Some(mut f) => from_yaml_async_reader(&mut *f).await,
+        None => Ok(vec![RegistryInfo {
+            name: "default".to_owned(),
+            source: Source::Http {
+                url: crate::REGISTRY_URL.clone(),
+            },
+            keys: Vec::new(),
+        }]),
+    }
+}
+
+async fn save(registries: &[RegistryInfo]) -> Result<(), Error> {
+    let mut f = PersistencePath::from_ref(REGISTRIES_YAML)
+        .write(None)
+        .await?;
+    to_yaml_async_writer(&mut *f, registries).await?;
+    f.commit().await
+}
+
+/// Adds a registry after the ones already configured.
+pub async fn add(info: RegistryInfo) -> Result<(), Error> {
+    for key in &info.keys {
+        crate::signing::decode_pubkey(key)?;
+    }
+    let mut registries = registries().await?;
+    crate::ensure_code!(
+        registries.iter().all(|r| r.name != info.name),
+        crate::error::GENERAL_ERROR,
+        "Registry {} Already Exists",
+        info.name
+    );
+    registries.push(info);
+    save(&registries).await
+}
+
+pub async fn remove(name: &str) -> Result<(), Error> {
+    let mut registries = registries().await?;
+    let len = registries.len();
+    registries.retain(|r| r.name != name);
+    crate::ensure_code!(
+        registries.len() < len,
+        crate::error::NOT_FOUND,
+        "No Registry Named {}",
+        name
+    );
+    save(&registries).await
+}
+
+/// The configured HTTP registry `url` is served from, if any.
+pub async fn for_url(url: &str) -> Result<Option<RegistryInfo>, Error> {
+    Ok(registries().await?.into_iter().find(|r| match &r.source {
+        Source::Http { url: base } => {
+            let base = base.trim_end_matches('/');
+            url.starts_with(base) && url[base.len()..].starts_with('/')
+        }
+        Source::Local { .. } => false,
+    }))
+}
+
+/// Tries each registry in turn, returning the first success or else the last error.
+macro_rules! first_success {
+    ($registry:ident => $e:expr) => {
+        first_success!(info, $registry => $e)
+    };
+    ($info:ident, $registry:ident => $e:expr) => {{
+        let mut error = None;
+        for $info in registries().await? {
+            let $registry = $info.open();
+            match $e.await {
+                Ok(a) => return Ok(a),
+                Err(e) => {
+                    log::info!("Registry {}: {}", $info.name, e);
+                    error = Some(e);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(|| {
+            Error::new(
+                format_err!("No Registries Configured"),
+                Some(crate::error::REGISTRY_ERROR),
+            )
+        }))
+    }};
+}
+
+pub async fn manifest(id: &str, version: &VersionRange) -> Result<ManifestLatest, Error> {
+    first_success!(registry => registry.manifest(id, version))
+}
+
+pub async fn version(id: &str, version: &VersionRange) -> Result<Version, Error> {
+    first_success!(registry => registry.version(id, version))
+}
+
+pub async fn config(id: &str, version: &VersionRange) -> Result<AppConfig, Error> {
+    first_success!(registry => registry.config(id, version))
+}
+
+pub async fn layer(layer: &Layer, dst: &Path) -> Result<(), Error> {
+    first_success!(registry => crate::install::retry(
+        &format!("Download of Layer {}", layer.digest),
+        || registry.layer(layer, dst),
+    ))
+}
+
+/// Downloads the newest package of `id` in `version` into the download cache,
+/// along with the registry it came from, whose keys may sign it.
+pub async fn download(
+    id: &str,
+    version: &VersionRange,
+    use_cache: bool,
+) -> Result<(PathBuf, RegistryInfo), Error> {
+    first_success!(info, registry => async {
+        let path = download_from(&*registry, id, version, use_cache).await?;
+        Ok::<_, Error>((path, info.clone()))
+    })
+}
+
+async fn download_from(
+    registry: &dyn Registry,
+    id: &str,
+    range: &VersionRange,
+    use_cache: bool,
+) -> Result<PathBuf, Error> {
+    let version = registry.version(id, range).await?;
+    let path = crate::install::cache_path(id, &version);
+    fetch_verified(registry, id, &version, &path, use_cache).await?;
+    Ok(path)
+}
+
+/// Fetches the package of `id` at `version` into `path`, resuming a partial download,
+/// retrying network errors and checking the result against the checksum the registry publishes.
+async fn fetch_verified(
+    registry: &dyn Registry,
+    id: &str,
+    version: &Version,
+    path: &Path,
+    use_cache: bool,
+) -> Result<(), Error> {
+    let part = crate::install::partial_path(path);
+    if !use_cache {
+        for p in &[path, part.as_path()] {
+            if p.exists() {
+                tokio::fs::remove_file(p).await?;
+            }
+        }
+    }
+    let sha256 = registry.sha256(id, version).await?;
+    if path.exists() {
+        match &sha256 {
+            Some(sha256) => match crate::install::verify_sha256(path, sha256).await {
+                Ok(()) => return Ok(()),
+                Err(e) => log::warn!("Discarding Cached Download: {}", e),
+            },
+            None => return Ok(()),
+        }
+    }
+    crate::install::retry(&format!("Download of {} {}", id, version), || {
+        registry.fetch(id, version, &part)
+    })
+    .await?;
+    if let Some(sha256) = &sha256 {
+        crate::progress::Event::new(Some(id), crate::progress::Phase::Verifying)
+            .emit()
+            .await;
+        if let Err(e) = crate::install::verify_sha256(&part, sha256).await {
+            crate::progress::Event::new(Some(id), crate::progress::Phase::Failed)
+                .message(&e)
+                .emit()
+                .await;
+            return Err(e);
+        }
+    }
+    tokio::fs::rename(&part, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio_tar as tar;
+
+    use super::http::HttpRegistry;
+    use super::*;
+    use crate::config::{ConfigRuleEntry, ConfigSpec};
+    use crate::install::partial_path;
+    use crate::manifest::{Manifest, ManifestV0};
+
+    /// Writes a versioned package of `id` at `version` into `dir`, returning its path.
+    pub(super) async fn package(dir: &Path, id: &str, version: &str) -> PathBuf {
+        let manifest: ManifestV0 = serde_yaml::from_str(&format!(
+            "id: {}\nversion: {}\ntitle: {}\ndescription:\n  short: test\n  long: test\nrelease-notes: none\nports: []\nimage:\n  type: tar\nmount: /root\n",
+            id, version, id
+        ))
+        .unwrap();
+        let spec: ConfigSpec = serde_yaml::from_str("{}").unwrap();
+        let rules: Vec<ConfigRuleEntry> = Vec::new();
+        let entries: Vec<(&str, Vec<u8>)> = vec![
+            (
+                "manifest.cbor",
+                serde_cbor::to_vec(&Manifest::V0(manifest)).unwrap(),
+            ),
+            ("config_spec.cbor", serde_cbor::to_vec(&spec).unwrap()),
+            ("config_rules.cbor", serde_cbor::to_vec(&rules).unwrap()),
+            ("image.tar", version.as_bytes().to_vec()),
+        ];
+        let archive = dir.join(format!(".{}-{}.tar", id, version));
+        let mut out = tar::Builder::new(tokio::fs::File::create(&archive).await.unwrap());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            out.append_data(&mut header, name, data.as_slice())
+                .await
+                .unwrap();
+        }
+        out.into_inner().await.unwrap().flush().await.unwrap();
+        let path = dir.join(format!("{}-{}.s9pk", id, version));
+        crate::s9pk::write(&archive, &path).await.unwrap();
+        tokio::fs::remove_file(&archive).await.unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn fetch_from_server() {
+        let dir = crate::fixtures::tmp_dir("registry-fetch").await;
+        let packages = dir.join("packages");
+        tokio::fs::create_dir_all(&packages).await.unwrap();
+        let pkg = package(&packages, "hello", "0.1.0").await;
+        package(&packages, "hello", "0.2.0").await;
+        let expected = tokio::fs::read(&pkg).await.unwrap();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(server::serve_listener(packages.clone(), listener));
+        let registry = HttpRegistry::new(format!("http://127.0.0.1:{}", port));
+
+        let version = registry
+            .version("hello", &"<0.2.0".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(version, "0.1.0".parse().unwrap());
+        assert_eq!(
+            registry.sha256("hello", &version).await.unwrap(),
+            Some(crate::install::sha256(&pkg).await.unwrap())
+        );
+
+        // a partial download is resumed
+        let dst = dir.join("hello.s9pk");
+        tokio::fs::write(partial_path(&dst), &expected[..100])
+            .await
+            .unwrap();
+        fetch_verified(&registry, "hello", &version, &dst, true)
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&dst).await.unwrap(), expected);
+        assert!(!partial_path(&dst).exists());
+        // and a cached one kept
+        fetch_verified(&registry, "hello", &version, &dst, true)
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&dst).await.unwrap(), expected);
+
+        // a resumed download that does not match the checksum is discarded
+        tokio::fs::remove_file(&dst).await.unwrap();
+        let mut corrupt = expected[..100].to_vec();
+        corrupt[0] ^= 0xff;
+        tokio::fs::write(partial_path(&dst), &corrupt)
+            .await
+            .unwrap();
+        assert!(fetch_verified(&registry, "hello", &version, &dst, true)
+            .await
+            .is_err());
+        assert!(!partial_path(&dst).exists());
+        assert!(!dst.exists());
+
+        // the first connection is dropped, so the fetch only succeeds if it is retried
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            drop(listener.accept().await.unwrap());
+            server::serve_listener(packages, listener).await
+        });
+        let registry = HttpRegistry::new(format!("http://127.0.0.1:{}", port));
+        let part = partial_path(&dst);
+        crate::install::retry("test fetch", || registry.fetch("hello", &version, &part))
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&part).await.unwrap(), expected);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
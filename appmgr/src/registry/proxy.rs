@@ -0,0 +1,162 @@
+use crate::util::{from_yaml_async_reader, to_yaml_async_writer, PersistencePath};
+use crate::Error;
+use crate::ResultExt as _;
+
+pub const PROXY_YAML: &'static str = "proxy.yaml";
+/// The SOCKS port of the Tor daemon on this device.
+pub const TOR_PROXY: &'static str = "socks5h://127.0.0.1:9050";
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProxyConfig {
+    pub url: String,
+}
+
+fn validate(url: &str) -> Result<(), Error> {
+    let parsed = reqwest::Url::parse(url).with_code(crate::error::GENERAL_ERROR)?;
+    crate::ensure_code!(
+        parsed.scheme() == "socks5" || parsed.scheme() == "socks5h",
+        crate::error::GENERAL_ERROR,
+        "Proxy Must Be a socks5:// or socks5h:// URL: {}",
+        url
+    );
+    Ok(())
+}
+
+/// The proxy registry traffic goes through: `REGISTRY_PROXY` if set, otherwise the configured one.
+/// An empty `REGISTRY_PROXY` disables the proxy.
+pub async fn get() -> Result<Option<String>, Error> {
+    if let Ok(url) = std::env::var("REGISTRY_PROXY") {
+        return Ok(Some(url).filter(|u| !u.is_empty()));
+    }
+    match PersistencePath::from_ref(PROXY_YAML)
+        .maybe_read(false)
+        .await
+        .transpose()?
+    {
+        Some(mut f) => Ok(Some(
+            from_yaml_async_reader::<ProxyConfig, _>(&mut *f).await?.url,
+        )),
+        None => Ok(None),
+    }
+}
+
+pub async fn set(url: Option<&str>) -> Result<(), Error> {
+    match url {
+        Some(url) => {
+            validate(url)?;
+            let mut f = PersistencePath::from_ref(PROXY_YAML).write(None).await?;
+            to_yaml_async_writer(
+                &mut *f,
+                &ProxyConfig {
+                    url: url.to_owned(),
+                },
+            )
+            .await?;
+            f.commit().await
+        }
+        None => PersistencePath::from_ref(PROXY_YAML).delete().await,
+    }
+}
+
+/// An HTTP client for fetching `url` from a registry through the configured proxy.
+/// Onion addresses go through Tor even if no proxy is configured.
+pub async fn client(url: &str) -> Result<reqwest::Client, Error> {
+    let onion = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.ends_with(".onion")))
+        .unwrap_or(false);
+    match get().await? {
+        Some(proxy) => client_with(Some(&proxy)),
+        None if onion => client_with(Some(TOR_PROXY)),
+        None => client_with(None),
+    }
+}
+
+fn client_with(proxy: Option<&str>) -> Result<reqwest::Client, Error> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = proxy {
+        validate(proxy)?;
+        builder = builder.proxy(reqwest::Proxy::all(proxy).with_code(crate::error::GENERAL_ERROR)?);
+    }
+    builder.build().with_code(crate::error::NETWORK_ERROR)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio_compat_02::FutureExt;
+
+    use super::*;
+
+    /// Accepts one SOCKS5 connection, answers the HTTP request sent through it,
+    /// and returns the host name the client asked the proxy to connect to.
+    async fn socks_stand_in(listener: TcpListener) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut greeting = [0; 2];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting[0], 5);
+        let mut methods = vec![0; greeting[1] as usize];
+        stream.read_exact(&mut methods).await.unwrap();
+        stream.write_all(&[5, 0]).await.unwrap();
+
+        let mut request = [0; 4];
+        stream.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[..3], [5, 1, 0]);
+        // socks5h sends the domain name unresolved
+        assert_eq!(request[3], 3);
+        let mut len = [0; 1];
+        stream.read_exact(&mut len).await.unwrap();
+        let mut host = vec![0; len[0] as usize];
+        stream.read_exact(&mut host).await.unwrap();
+        let mut port = [0; 2];
+        stream.read_exact(&mut port).await.unwrap();
+        stream
+            .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+            .await
+            .unwrap();
+
+        let mut stream = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.unwrap() == 0 || line.trim().is_empty() {
+                break;
+            }
+        }
+        stream
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok")
+            .await
+            .unwrap();
+        String::from_utf8(host).unwrap()
+    }
+
+    #[tokio::test]
+    async fn onion_through_socks() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let proxy = tokio::spawn(socks_stand_in(listener));
+
+        let client = client_with(Some(&format!("socks5h://127.0.0.1:{}", port))).unwrap();
+        let body = async {
+            client
+                .get("http://registry.onion/apps/index")
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap()
+        }
+        .compat()
+        .await;
+        assert_eq!(body, "ok");
+        assert_eq!(proxy.await.unwrap(), "registry.onion");
+    }
+
+    #[test]
+    fn rejects_http_proxy() {
+        assert!(client_with(Some("http://127.0.0.1:8118")).is_err());
+    }
+}
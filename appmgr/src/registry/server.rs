@@ -0,0 +1,403 @@
+use std::path::{Path, PathBuf};
+
+use emver::{Version, VersionRange};
+use http::StatusCode;
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::local::LocalRegistry;
+use super::Registry;
+use crate::Error;
+use crate::ResultExt as _;
+
+enum Reply {
+    Data(Vec<u8>, &'static str),
+    File(PathBuf, &'static str),
+}
+
+fn json<T: serde::Serialize>(value: &T) -> Result<Reply, Error> {
+    Ok(Reply::Data(
+        serde_json::to_vec(value).with_code(crate::error::SERDE_ERROR)?,
+        "application/json",
+    ))
+}
+
+/// Serves the packages under `dir` with the same endpoints as the Start9 registry.
+pub async fn serve<P: AsRef<Path>>(dir: P, port: u16) -> Result<(), Error> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .with_code(crate::error::NETWORK_ERROR)?;
+    log::info!("Serving {} on port {}.", dir.as_ref().display(), port);
+    serve_listener(dir.as_ref().to_owned(), listener).await
+}
+
+pub(super) async fn serve_listener(dir: PathBuf, listener: TcpListener) -> Result<(), Error> {
+    // shared by every connection, so packages are only read again once they change
+    let registry = LocalRegistry::new(&dir);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let dir = dir.clone();
+        let registry = registry.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(&dir, &registry, stream).await {
+                log::warn!("{}: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle(dir: &Path, registry: &LocalRegistry, stream: TcpStream) -> Result<(), Error> {
+    let mut stream = BufReader::new(stream);
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line).await?;
+    let mut range_start = None;
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let mut header = line.splitn(2, ':');
+        let name = header.next().unwrap_or("").trim();
+        let value = header.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("range") {
+            range_start = parse_range_start(value);
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+    log::info!("{} {}", method, target);
+    let res = if method == "GET" || method == "HEAD" {
+        route(dir, registry, target).await
+    } else {
+        Err(format_err!("Method Not Allowed: {}", method)).with_code(crate::error::GENERAL_ERROR)
+    };
+    let stream = stream.get_mut();
+    match res {
+        Ok(Reply::Data(body, content_type)) => {
+            write_head(
+                stream,
+                StatusCode::OK,
+                content_type,
+                body.len() as u64,
+                None,
+            )
+            .await?;
+            if method != "HEAD" {
+                stream.write_all(&body).await?;
+            }
+        }
+        Ok(Reply::File(path, content_type)) => {
+            let mut f = tokio::fs::File::open(&path).await?;
+            let len = f.metadata().await?.len();
+            match range_start {
+                Some(start) if start >= len => {
+                    write_head(
+                        stream,
+                        StatusCode::RANGE_NOT_SATISFIABLE,
+                        content_type,
+                        0,
+                        None,
+                    )
+                    .await?;
+                }
+                Some(start) => {
+                    f.seek(std::io::SeekFrom::Start(start)).await?;
+                    write_head(
+                        stream,
+                        StatusCode::PARTIAL_CONTENT,
+                        content_type,
+                        len - start,
+                        Some(format!("bytes {}-{}/{}", start, len - 1, len)),
+                    )
+                    .await?;
+                    if method != "HEAD" {
+                        tokio::io::copy(&mut f, stream).await?;
+                    }
+                }
+                None => {
+                    write_head(stream, StatusCode::OK, content_type, len, None).await?;
+                    if method != "HEAD" {
+                        tokio::io::copy(&mut f, stream).await?;
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            let status = match e.code {
+                Some(crate::error::NOT_FOUND) => StatusCode::NOT_FOUND,
+                Some(crate::error::SERDE_ERROR) => StatusCode::BAD_REQUEST,
+                _ if method != "GET" && method != "HEAD" => StatusCode::METHOD_NOT_ALLOWED,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            let body = format!("{}\n", e);
+            write_head(stream, status, "text/plain", body.len() as u64, None).await?;
+            stream.write_all(body.as_bytes()).await?;
+        }
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn write_head(
+    stream: &mut TcpStream,
+    status: StatusCode,
+    content_type: &str,
+    len: u64,
+    content_range: Option<String>,
+) -> Result<(), Error> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or(""),
+        content_type,
+        len
+    );
+    if let Some(content_range) = content_range {
+        head += &format!("Content-Range: {}\r\n", content_range);
+    }
+    head += "\r\n";
+    stream.write_all(head.as_bytes()).await?;
+    Ok(())
+}
+
+/// Parses the start of an open ended byte range, such as `bytes=1024-`, as sent by resuming downloads.
+fn parse_range_start(range: &str) -> Option<u64> {
+    let range = range.strip_prefix("bytes=")?;
+    let mut split = range.splitn(2, '-');
+    let start = split.next()?;
+    if !split.next()?.is_empty() {
+        return None;
+    }
+    start.parse().ok()
+}
+
+async fn route(dir: &Path, registry: &LocalRegistry, target: &str) -> Result<Reply, Error> {
+    let url = reqwest::Url::parse(&format!("http://localhost{}", target))
+        .with_code(crate::error::SERDE_ERROR)?;
+    let spec = url
+        .query_pairs()
+        .find(|(k, _)| k == "spec")
+        .map(|(_, v)| v.parse::<VersionRange>())
+        .transpose()
+        .with_code(crate::error::SERDE_ERROR)?
+        .unwrap_or_else(VersionRange::any);
+    let segments: Vec<&str> = url.path_segments().into_iter().flatten().collect();
+    match segments.as_slice() {
+        ["apps", "index"] => json(
+            &registry
+                .packages()
+                .await?
+                .into_iter()
+                .map(|(_, manifest)| manifest)
+                .collect::<crate::index::AppIndex>(),
+        ),
+        ["apps", "manifest", id] => json(&registry.manifest(id, &spec).await?),
+        ["apps", "version", id] => {
+            let (path, manifest) = registry.package(id, &spec).await?;
+            json(&serde_json::json!({
+                "version": manifest.version,
+                "sha256": crate::install::sha256(&path).await?,
+            }))
+        }
+        ["apps", "config", id] => {
+            let (path, _) = registry.package(id, &spec).await?;
+            json(
+                &crate::inspect::info_full(&path, false, true)
+                    .await?
+                    .config
+                    .unwrap(),
+            )
+        }
+        ["apps", "icon", id] => {
+            let (path, _) = registry.package(id, &spec).await?;
+            let icon = path.with_file_name(format!("{}.png", id));
+            if icon.exists() {
+                Ok(Reply::File(icon, "image/png"))
+            } else {
+                Ok(Reply::Data(crate::inspect::icon(&path).await?, "image/png"))
+            }
+        }
+        ["apps", file] if file.ends_with(".s9pk") => {
+            let (path, _) = registry
+                .package(file.trim_end_matches(".s9pk"), &spec)
+                .await?;
+            Ok(Reply::File(path, "application/octet-stream"))
+        }
+        ["layers", name] => {
+            let path = dir.join("layers").join(name);
+            crate::ensure_code!(path.is_file(), crate::error::NOT_FOUND, "No Layer {}", name);
+            Ok(Reply::File(path, "application/octet-stream"))
+        }
+        ["sys", name] => Ok(Reply::File(
+            sys_file(dir, name, &spec).await?,
+            "application/octet-stream",
+        )),
+        _ => Err(format_err!("Not Found: {}", url.path())).with_code(crate::error::NOT_FOUND),
+    }
+}
+
+/// Finds the newest `sys/<name>-<version>` in `spec`, such as `sys/appmgr-0.2.12`.
+async fn sys_file(dir: &Path, name: &str, spec: &VersionRange) -> Result<PathBuf, Error> {
+    let sys = dir.join("sys");
+    let prefix = format!("{}-", name);
+    let mut best: Option<(Version, PathBuf)> = None;
+    if sys.exists() {
+        let mut entries = tokio::fs::read_dir(&sys).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name();
+            let version = file_name
+                .to_str()
+                .and_then(|f| f.strip_prefix(&prefix))
+                .and_then(|v| v.parse::<Version>().ok());
+            match version {
+                Some(version)
+                    if version.satisfies(spec)
+                        && best.as_ref().map(|(v, _)| v < &version).unwrap_or(true) =>
+                {
+                    best = Some((version, entry.path()))
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(best
+        .ok_or_else(|| format_err!("No {} Satisfies {}", name, spec))
+        .with_code(crate::error::NOT_FOUND)?
+        .1)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    async fn get(port: u16, target: &str, headers: &str) -> String {
+        String::from_utf8(get_bytes(port, target, headers).await).unwrap()
+    }
+
+    async fn get_bytes(port: u16, target: &str, headers: &str) -> Vec<u8> {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "GET {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+                    target, headers
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut res = Vec::new();
+        stream.read_to_end(&mut res).await.unwrap();
+        res
+    }
+
+    fn body(res: &[u8]) -> &[u8] {
+        let idx = res.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        &res[idx + 4..]
+    }
+
+    async fn get_json(port: u16, target: &str) -> serde_json::Value {
+        let res = get_bytes(port, target, "").await;
+        assert!(res.starts_with(b"HTTP/1.1 200 OK\r\n"), "{}", target);
+        serde_json::from_slice(body(&res)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serve_sys_files() {
+        let dir = crate::fixtures::tmp_dir("registry-sys").await;
+        tokio::fs::create_dir_all(dir.join("sys")).await.unwrap();
+        tokio::fs::write(dir.join("sys").join("torrc-0.1.0"), "old")
+            .await
+            .unwrap();
+        tokio::fs::write(dir.join("sys").join("torrc-0.1.1"), "new")
+            .await
+            .unwrap();
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve_listener(dir.clone(), listener));
+
+        let res = get(port, "/sys/torrc", "").await;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.ends_with("\r\n\r\nnew"));
+        let res = get(port, "/sys/torrc?spec==0.1.0", "").await;
+        assert!(res.ends_with("\r\n\r\nold"));
+        let res = get(port, "/sys/torrc", "Range: bytes=1-\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(res.contains("Content-Range: bytes 1-2/3\r\n"));
+        assert!(res.ends_with("\r\n\r\new"));
+        let res = get(port, "/sys/torrc", "Range: bytes=3-\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        let res = get(port, "/sys/appmgr", "").await;
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let res = get(port, "/apps/manifest/bitcoind", "").await;
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn serve_packages() {
+        let dir = crate::fixtures::tmp_dir("registry-packages").await;
+        let old = crate::registry::test::package(&dir, "bitcoind", "0.20.1").await;
+        let new = crate::registry::test::package(&dir, "bitcoind", "0.21.0").await;
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve_listener(dir.clone(), listener));
+
+        let index = get_json(port, "/apps/index").await;
+        assert_eq!(index["bitcoind"]["version-info"][0]["version"], "0.21.0");
+        assert_eq!(index["bitcoind"]["version-info"][1]["version"], "0.20.1");
+
+        let manifest = get_json(port, "/apps/manifest/bitcoind").await;
+        assert_eq!(manifest["version"], "0.21.0");
+        let manifest = get_json(port, "/apps/manifest/bitcoind?spec=%3C0.21.0").await;
+        assert_eq!(manifest["version"], "0.20.1");
+        let res = get(port, "/apps/manifest/bitcoind?spec=%3E0.21.0", "").await;
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let version = get_json(port, "/apps/version/bitcoind?spec=%5E0.20.0").await;
+        assert_eq!(version["version"], "0.20.1");
+        assert_eq!(
+            version["sha256"],
+            crate::install::sha256(&old).await.unwrap().as_str()
+        );
+
+        let config = get_json(port, "/apps/config/bitcoind?spec==0.20.1").await;
+        assert_eq!(config["spec"], serde_json::json!({}));
+        assert_eq!(config["rules"], serde_json::json!([]));
+
+        let res = get_bytes(port, "/apps/bitcoind.s9pk", "").await;
+        assert_eq!(body(&res), tokio::fs::read(&new).await.unwrap().as_slice());
+        let res = get_bytes(port, "/apps/bitcoind.s9pk?spec==0.20.1", "").await;
+        assert_eq!(body(&res), tokio::fs::read(&old).await.unwrap().as_slice());
+        let res = get_bytes(
+            port,
+            "/apps/bitcoind.s9pk?spec==0.20.1",
+            "Range: bytes=8-\r\n",
+        )
+        .await;
+        assert!(res.starts_with(b"HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(body(&res), &tokio::fs::read(&old).await.unwrap()[8..]);
+        let res = get(port, "/apps/lnd.s9pk", "").await;
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        // replacing a package is picked up even though the manifests are cached
+        let other = crate::fixtures::tmp_dir("registry-packages-other").await;
+        let replacement = crate::registry::test::package(&other, "bitcoind", "0.21.1").await;
+        tokio::fs::rename(&replacement, &new).await.unwrap();
+        let manifest = get_json(port, "/apps/manifest/bitcoind").await;
+        assert_eq!(manifest["version"], "0.21.1");
+        crate::registry::test::package(&dir, "lnd", "0.11.0").await;
+        let manifest = get_json(port, "/apps/manifest/lnd").await;
+        assert_eq!(manifest["version"], "0.11.0");
+        tokio::fs::remove_file(&old).await.unwrap();
+        let res = get(port, "/apps/manifest/bitcoind?spec==0.20.1", "").await;
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+        tokio::fs::remove_dir_all(&other).await.unwrap();
+    }
+}
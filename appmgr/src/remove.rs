@@ -18,40 +18,32 @@ pub async fn remove(
     if dry_run {
         return Ok(res);
     }
-    let image_name = format!("start9/{}", name);
+    let runtime = crate::runtime::current();
+    let image_name = runtime.image_name(name);
     log::info!("Removing app from manifest.");
     crate::apps::remove(name).await?;
-    log::info!("Stopping docker container.");
+    log::info!("Stopping container.");
     let res = crate::control::stop_app(name, false, false)
         .await
         .unwrap_or_else(|e| {
             log::error!("Error stopping app: {}", e);
             LinearMap::new()
         });
-    log::info!("Removing docker container.");
-    if !std::process::Command::new("docker")
-        .args(&["rm", name])
-        .stdout(std::process::Stdio::null())
-        .stderr(match log::max_level() {
-            log::LevelFilter::Error => std::process::Stdio::null(),
-            _ => std::process::Stdio::inherit(),
-        })
-        .status()?
-        .success()
-    {
-        log::error!("Failed to Remove Docker Container");
+    log::info!("Removing container.");
+    if let Err(e) = runtime.remove(name, false).await {
+        log::error!("Failed to Remove Container: {}", e);
     };
-    if !std::process::Command::new("docker")
-        .args(&["rmi", &image_name])
-        .stdout(std::process::Stdio::null())
-        .stderr(match log::max_level() {
-            log::LevelFilter::Error => std::process::Stdio::null(),
-            _ => std::process::Stdio::inherit(),
-        })
-        .status()?
-        .success()
+    if let Err(e) = runtime
+        .remove_image(&format!("{}:{}", image_name, manifest.version))
+        .await
     {
-        log::error!("Failed to Remove Docker Image");
+        log::warn!("Failed to Remove Image Version Tag: {}", e);
+    };
+    if let Err(e) = runtime.remove_image(&image_name).await {
+        log::error!("Failed to Remove Image: {}", e);
+    };
+    if let Err(e) = crate::layers::prune().await {
+        log::error!("Failed to Prune Layers: {}", e);
     };
     if purge {
         log::info!("Removing tor hidden service.");
@@ -130,20 +122,8 @@ pub async fn remove(
             .await
             .with_context(|e| format!("rm {}: {}", volume_path.display(), e))
             .with_code(crate::error::FILESYSTEM_ERROR)?;
-        log::info!("Pruning unused docker images.");
-        crate::ensure_code!(
-            std::process::Command::new("docker")
-                .args(&["image", "prune", "-a", "-f"])
-                .stdout(std::process::Stdio::null())
-                .stderr(match log::max_level() {
-                    log::LevelFilter::Error => std::process::Stdio::null(),
-                    _ => std::process::Stdio::inherit(),
-                })
-                .status()?
-                .success(),
-            crate::error::DOCKER_ERROR,
-            "Failed to Prune Docker Images"
-        );
+        log::info!("Pruning unused images.");
+        runtime.prune_images().await?;
     };
 
     Ok(res)
@@ -0,0 +1,623 @@
+use async_trait::async_trait;
+use emver::{Version, VersionRange};
+use linear_map::{set::LinearSet, LinearMap};
+
+use crate::dependencies::{DependencyError, TaggedDependencyError};
+use crate::graph::DependencyGraph;
+use crate::manifest::ManifestLatest;
+use crate::Error;
+use crate::ResultExt as _;
+
+const MAX_ROUNDS: usize = 256;
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlannedInstall {
+    pub id: String,
+    pub version: Version,
+    /// The version currently installed, if any.
+    pub installed: Option<Version>,
+    /// Required dependencies, which are auto-configured once this app is installed.
+    pub dependencies: Vec<String>,
+}
+
+/// Installs to perform, dependencies first.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct Plan(pub Vec<PlannedInstall>);
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlannedUpdate {
+    pub id: String,
+    pub from: Version,
+    pub to: Version,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UpdatePlan {
+    /// Updates to perform, starting with the requested app and followed by its dependents.
+    pub updates: Vec<PlannedUpdate>,
+    /// Installed apps that will no longer satisfy their dependencies.
+    pub broken: LinearMap<String, TaggedDependencyError>,
+}
+
+/// Where the resolver looks up the versions on offer.
+#[async_trait]
+pub trait Catalog: Send + Sync {
+    /// The manifest of the newest version of `id` in `range`.
+    async fn manifest(&self, id: &str, range: &VersionRange) -> Result<ManifestLatest, Error>;
+}
+
+/// The configured registries.
+pub struct Registries;
+#[async_trait]
+impl Catalog for Registries {
+    async fn manifest(&self, id: &str, range: &VersionRange) -> Result<ManifestLatest, Error> {
+        crate::registry::manifest(id, range).await
+    }
+}
+
+fn parse_target(target: &str) -> Result<(&str, VersionRange), Error> {
+    let mut split = target.split("@");
+    let id = split.next().unwrap();
+    let range = split
+        .next()
+        .map(|a| a.parse::<VersionRange>())
+        .transpose()
+        .no_code()?
+        .unwrap_or_else(VersionRange::any);
+    Ok((id, range))
+}
+
+async fn installed_manifests() -> Result<LinearMap<String, ManifestLatest>, Error> {
+    let mut installed = LinearMap::new();
+    for id in crate::apps::list_info().await?.keys() {
+        installed.insert(id.clone(), crate::apps::manifest(id).await?);
+    }
+    Ok(installed)
+}
+
+/// Resolves an `id@range` target and its required dependencies against the registry.
+pub async fn resolve_target(target: &str) -> Result<Plan, Error> {
+    crate::ensure_code!(
+        !target.starts_with("https://")
+            && !target.starts_with("http://")
+            && !target.ends_with(".s9pk"),
+        crate::error::GENERAL_ERROR,
+        "Dependencies Can Only Be Resolved for an App ID"
+    );
+    let (id, range) = parse_target(target)?;
+    resolve(id, &range).await
+}
+
+/// Picks a version of `target` and of each required dependency that is not installed yet,
+/// such that every dependent's version range is satisfied, including those of installed apps.
+/// Installed dependencies must already satisfy their dependents.
+pub async fn resolve(target: &str, target_range: &VersionRange) -> Result<Plan, Error> {
+    resolve_with(
+        &Registries,
+        &installed_manifests().await?,
+        target,
+        target_range,
+    )
+    .await
+}
+
+async fn resolve_with<C: Catalog + ?Sized>(
+    catalog: &C,
+    installed: &LinearMap<String, ManifestLatest>,
+    target: &str,
+    target_range: &VersionRange,
+) -> Result<Plan, Error> {
+    // ranges learned from conflicts: whenever a chosen version turns out not to satisfy a later
+    // dependent, the search starts over with the narrower range, so no stale choice remains
+    let mut constraints: LinearMap<String, VersionRange> = LinearMap::new();
+    constraints.insert(target.to_owned(), target_range.clone());
+    let mut chosen = None;
+    for _ in 0..MAX_ROUNDS {
+        match choose(catalog, installed, target, &constraints).await? {
+            Ok(c) => {
+                chosen = Some(c);
+                break;
+            }
+            Err((id, range)) => {
+                let previous = constraints.remove(&id).unwrap_or_else(VersionRange::any);
+                constraints.insert(id, VersionRange::conj(previous, range));
+            }
+        }
+    }
+    let mut chosen = chosen
+        .ok_or_else(|| format_err!("Could Not Resolve Dependencies of {}", target))
+        .with_code(crate::error::GENERAL_ERROR)?;
+
+    let mut graph = DependencyGraph::default();
+    for (id, manifest) in installed
+        .iter()
+        .filter(|(id, _)| !chosen.contains_key(*id))
+        .chain(chosen.iter())
+    {
+        graph.insert(id, manifest.dependencies.0.keys().cloned());
+    }
+    if let Some(cycle) = graph.find_cycle() {
+        return Err(format_err!(
+            "Installing {} Would Create a Dependency Cycle: {}",
+            target,
+            cycle.join(" -> ")
+        ))
+        .with_code(crate::error::GENERAL_ERROR);
+    }
+    let ids: LinearSet<String> = chosen.keys().cloned().collect();
+    let order = graph.subgraph(&ids).topo_sort()?;
+    Ok(Plan(
+        order
+            .into_iter()
+            .filter_map(|id| {
+                let manifest = chosen.remove(&id)?;
+                Some(PlannedInstall {
+                    installed: installed.get(&id).map(|m| m.version.clone()),
+                    version: manifest.version,
+                    dependencies: manifest
+                        .dependencies
+                        .0
+                        .into_iter()
+                        .filter(|(_, info)| info.optional.is_none())
+                        .map(|(dep, _)| dep)
+                        .collect(),
+                    id,
+                })
+            })
+            .collect(),
+    ))
+}
+
+/// Chooses the newest version of `target` and of each dependency it needs within `constraints`.
+/// Returns the app and range to narrow the search to if a chosen version conflicts with a later
+/// dependent.
+async fn choose<C: Catalog + ?Sized>(
+    catalog: &C,
+    installed: &LinearMap<String, ManifestLatest>,
+    target: &str,
+    constraints: &LinearMap<String, VersionRange>,
+) -> Result<Result<LinearMap<String, ManifestLatest>, (String, VersionRange)>, Error> {
+    // dependency -> dependent -> required range
+    let mut requirements: LinearMap<String, LinearMap<String, VersionRange>> = LinearMap::new();
+    for (dependent, manifest) in installed.iter().filter(|(id, _)| *id != target) {
+        for (dep, info) in &manifest.dependencies.0 {
+            if info.optional.is_none() {
+                requirements
+                    .entry(dep.clone())
+                    .or_insert_with(LinearMap::new)
+                    .insert(dependent.clone(), info.version.clone());
+            }
+        }
+    }
+
+    let mut chosen: LinearMap<String, ManifestLatest> = LinearMap::new();
+    let mut queue = vec![target.to_owned()];
+    while let Some(id) = queue.pop() {
+        let mut range = constraints
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(VersionRange::any);
+        for required in requirements.get(&id).into_iter().flat_map(|r| r.values()) {
+            range = VersionRange::conj(range, required.clone());
+        }
+        if id != target {
+            if let Some(manifest) = installed.get(&id) {
+                crate::ensure_code!(
+                    manifest.version.satisfies(&range),
+                    crate::error::VERSION_INCOMPATIBLE,
+                    "{} {} Is Installed, but {} Is Required: Update It First",
+                    id,
+                    manifest.version,
+                    range
+                );
+                continue;
+            }
+        }
+        if let Some(manifest) = chosen.get(&id) {
+            if manifest.version.satisfies(&range) {
+                continue;
+            }
+            return Ok(Err((id, range)));
+        }
+        let manifest = catalog.manifest(&id, &range).await.map_err(|e| {
+            Error::new(
+                format_err!("No Version of {} Satisfies {}: {}", id, range, e),
+                Some(crate::error::REGISTRY_ERROR),
+            )
+        })?;
+        for (dep, info) in &manifest.dependencies.0 {
+            if info.optional.is_none() {
+                requirements
+                    .entry(dep.clone())
+                    .or_insert_with(LinearMap::new)
+                    .insert(id.clone(), info.version.clone());
+                queue.push(dep.clone());
+            }
+        }
+        chosen.insert(id, manifest);
+    }
+    Ok(Ok(chosen))
+}
+
+pub async fn plan_update_target(target: &str) -> Result<UpdatePlan, Error> {
+    let (id, range) = parse_target(target)?;
+    plan_update(id, &range).await
+}
+
+/// Plans an update of `target` to the newest version in `range` that its installed dependencies
+/// satisfy. Each dependent that would no longer be satisfied is updated to its newest version
+/// compatible with every planned version, and is reported as broken if there is none.
+pub async fn plan_update(target: &str, range: &VersionRange) -> Result<UpdatePlan, Error> {
+    plan_update_with(&Registries, &installed_manifests().await?, target, range).await
+}
+
+async fn plan_update_with<C: Catalog + ?Sized>(
+    catalog: &C,
+    installed: &LinearMap<String, ManifestLatest>,
+    target: &str,
+    range: &VersionRange,
+) -> Result<UpdatePlan, Error> {
+    crate::ensure_code!(
+        installed.contains_key(target),
+        crate::error::NOT_FOUND,
+        "{} Is Not Installed",
+        target
+    );
+    let mut versions: LinearMap<String, Version> = installed
+        .iter()
+        .map(|(id, m)| (id.clone(), m.version.clone()))
+        .collect();
+    let mut chosen: LinearMap<String, ManifestLatest> = LinearMap::new();
+    let mut broken = LinearMap::new();
+
+    let manifest = match newest_compatible(catalog, target, range, &versions).await? {
+        Ok(manifest) => manifest,
+        Err((version, error)) => {
+            return Err(format_err!(
+                "{} {} Is Not Compatible With Installed Apps: {}",
+                target,
+                version,
+                error
+            ))
+            .with_code(crate::error::VERSION_INCOMPATIBLE)
+        }
+    };
+    versions.insert(target.to_owned(), manifest.version.clone());
+    chosen.insert(target.to_owned(), manifest);
+
+    let mut queue = vec![target.to_owned()];
+    while let Some(id) = queue.pop() {
+        let version = versions[&id].clone();
+        for (dependent, manifest) in installed {
+            let info = match chosen
+                .get(dependent)
+                .unwrap_or(manifest)
+                .dependencies
+                .0
+                .get(&id)
+            {
+                Some(info) => info,
+                None => continue,
+            };
+            if version.satisfies(&info.version) || broken.contains_key(dependent) {
+                continue;
+            }
+            let error = TaggedDependencyError {
+                dependency: id.clone(),
+                error: DependencyError::IncorrectVersion {
+                    expected: info.version.clone(),
+                    received: version.clone(),
+                },
+            };
+            crate::ensure_code!(
+                dependent != target,
+                crate::error::VERSION_INCOMPATIBLE,
+                "Updating {} Would Break It: {}",
+                target,
+                error
+            );
+            if chosen.contains_key(dependent) {
+                broken.insert(dependent.clone(), error);
+                continue;
+            }
+            let newer = format!(">{}", manifest.version)
+                .parse::<VersionRange>()
+                .no_code()?;
+            match newest_compatible(catalog, dependent, &newer, &versions).await {
+                Ok(Ok(update)) => {
+                    versions.insert(dependent.clone(), update.version.clone());
+                    chosen.insert(dependent.clone(), update);
+                    queue.push(dependent.clone());
+                }
+                Ok(Err(_)) | Err(_) => {
+                    broken.insert(dependent.clone(), error);
+                }
+            }
+        }
+    }
+
+    let mut graph = DependencyGraph::default();
+    for (id, manifest) in installed {
+        graph.insert(
+            id,
+            chosen
+                .get(id)
+                .unwrap_or(manifest)
+                .dependencies
+                .0
+                .keys()
+                .cloned(),
+        );
+    }
+    let ids: LinearSet<String> = chosen.keys().cloned().collect();
+    let mut order = graph.subgraph(&ids).topo_sort()?;
+    // the requested app goes first even if it now depends on one of its updated dependents
+    order.retain(|id| id != target);
+    order.insert(0, target.to_owned());
+    Ok(UpdatePlan {
+        updates: order
+            .into_iter()
+            .map(|id| PlannedUpdate {
+                from: installed[&id].version.clone(),
+                to: versions[&id].clone(),
+                id,
+            })
+            .collect(),
+        broken,
+    })
+}
+
+/// Searches `range` from the newest version down for a version of `id` whose required
+/// dependencies `versions` satisfy. If there is none, returns the newest version in `range`
+/// along with what it is missing.
+async fn newest_compatible<C: Catalog + ?Sized>(
+    catalog: &C,
+    id: &str,
+    range: &VersionRange,
+    versions: &LinearMap<String, Version>,
+) -> Result<Result<ManifestLatest, (Version, TaggedDependencyError)>, Error> {
+    let mut range = range.clone();
+    let mut newest = None;
+    for _ in 0..MAX_ROUNDS {
+        let manifest = match catalog.manifest(id, &range).await {
+            Ok(manifest) => manifest,
+            Err(_) if newest.is_some() => break,
+            Err(e) => return Err(e),
+        };
+        match compatible(&manifest, versions) {
+            Ok(()) => return Ok(Ok(manifest)),
+            Err(error) => {
+                let older = format!("<{}", manifest.version)
+                    .parse::<VersionRange>()
+                    .no_code()?;
+                range = VersionRange::conj(range, older);
+                newest.get_or_insert((manifest.version, error));
+            }
+        }
+    }
+    Ok(Err(newest.unwrap()))
+}
+
+/// Checks that the required dependencies of `manifest` are satisfied by `versions`.
+fn compatible(
+    manifest: &ManifestLatest,
+    versions: &LinearMap<String, Version>,
+) -> Result<(), TaggedDependencyError> {
+    for (dep, info) in &manifest.dependencies.0 {
+        if info.optional.is_some() {
+            continue;
+        }
+        let error = match versions.get(dep) {
+            Some(version) if version.satisfies(&info.version) => continue,
+            Some(version) => DependencyError::IncorrectVersion {
+                expected: info.version.clone(),
+                received: version.clone(),
+            },
+            None => DependencyError::NotInstalled,
+        };
+        return Err(TaggedDependencyError {
+            dependency: dep.clone(),
+            error,
+        });
+    }
+    Ok(())
+}
+
+/// Resolves `target` and installs it along with its dependencies, returning the plan it followed.
+pub async fn install_target(target: &str, use_cache: bool) -> Result<Plan, Error> {
+    let plan = resolve_target(target).await?;
+    install(&plan, use_cache).await?;
+    Ok(plan)
+}
+
+/// Installs every app in the plan in order, auto-configuring each of its dependencies.
+/// Apps installed before a failure are left installed.
+pub async fn install(plan: &Plan, use_cache: bool) -> Result<(), Error> {
+    for step in &plan.0 {
+        log::info!("Installing {} {}.", step.id, step.version);
+        crate::install::install_name(&format!("{}@={}", step.id, step.version), use_cache).await?;
+        for dep in &step.dependencies {
+            if let Err(e) = crate::dependencies::auto_configure(&step.id, dep, false).await {
+                log::warn!("Failed to Configure {} for {}: {}", dep, step.id, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn app(id: &str, version: &str, deps: &[(&str, &str)]) -> ManifestLatest {
+        let mut manifest: ManifestLatest = serde_yaml::from_str(&format!(
+            "id: {}\nversion: {}\ntitle: {}\ndescription:\n  short: test\n  long: test\nrelease-notes: none\nports: []\nimage:\n  type: tar\nmount: /root\n",
+            id, version, id
+        ))
+        .unwrap();
+        for (dep, range) in deps {
+            manifest.dependencies.0.insert(
+                dep.to_string(),
+                serde_yaml::from_str(&format!("version: \"{}\"", range)).unwrap(),
+            );
+        }
+        manifest
+    }
+
+    struct Offered(Vec<ManifestLatest>);
+    #[async_trait]
+    impl Catalog for Offered {
+        async fn manifest(&self, id: &str, range: &VersionRange) -> Result<ManifestLatest, Error> {
+            self.0
+                .iter()
+                .filter(|m| m.id == id && m.version.satisfies(range))
+                .max_by(|a, b| a.version.cmp(&b.version))
+                .cloned()
+                .ok_or_else(|| format_err!("{} Not Found", id))
+                .with_code(crate::error::NOT_FOUND)
+        }
+    }
+
+    fn installed(apps: Vec<ManifestLatest>) -> LinearMap<String, ManifestLatest> {
+        apps.into_iter().map(|m| (m.id.clone(), m)).collect()
+    }
+
+    fn steps(plan: &Plan) -> Vec<String> {
+        plan.0
+            .iter()
+            .map(|s| format!("{} {}", s.id, s.version))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn resolve_dependencies() {
+        let offered = Offered(vec![
+            app("a", "0.1.0", &[("b", "^0.1.0")]),
+            app("b", "0.1.0", &[]),
+            app("b", "0.1.1", &[]),
+            app("b", "0.2.0", &[]),
+        ]);
+        let plan = resolve_with(&offered, &LinearMap::new(), "a", &VersionRange::any())
+            .await
+            .unwrap();
+        assert_eq!(steps(&plan), vec!["b 0.1.1", "a 0.1.0"]);
+        assert_eq!(plan.0[1].dependencies, vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn resolve_replacement_leaves_no_orphans() {
+        // b 2.0.0 is chosen first and pulls in d, until c turns out to need b below 2.0.0
+        let offered = Offered(vec![
+            app("a", "1.0.0", &[("c", "*"), ("b", "*")]),
+            app("b", "1.0.0", &[]),
+            app("b", "2.0.0", &[("d", "*")]),
+            app("c", "1.0.0", &[("b", "<2.0.0")]),
+            app("d", "1.0.0", &[]),
+        ]);
+        let plan = resolve_with(&offered, &LinearMap::new(), "a", &VersionRange::any())
+            .await
+            .unwrap();
+        assert_eq!(steps(&plan), vec!["b 1.0.0", "c 1.0.0", "a 1.0.0"]);
+    }
+
+    #[tokio::test]
+    async fn resolve_against_installed() {
+        let offered = Offered(vec![
+            app("a", "1.0.0", &[("b", "^1.0.0")]),
+            app("b", "1.0.0", &[]),
+            app("b", "1.1.0", &[]),
+            app("c", "1.0.0", &[("b", "^2.0.0")]),
+        ]);
+        // installed dependents narrow the range of a dependency to be installed
+        let plan = resolve_with(
+            &offered,
+            &installed(vec![app("x", "1.0.0", &[("b", "=1.0.0")])]),
+            "a",
+            &VersionRange::any(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(steps(&plan), vec!["b 1.0.0", "a 1.0.0"]);
+
+        // installed dependencies are not replaced
+        let plan = resolve_with(
+            &offered,
+            &installed(vec![app("b", "1.0.0", &[])]),
+            "a",
+            &VersionRange::any(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(steps(&plan), vec!["a 1.0.0"]);
+        let err = resolve_with(
+            &offered,
+            &installed(vec![app("b", "1.0.0", &[])]),
+            "c",
+            &VersionRange::any(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.code, Some(crate::error::VERSION_INCOMPATIBLE));
+
+        let err = resolve_with(&offered, &LinearMap::new(), "c", &VersionRange::any())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, Some(crate::error::REGISTRY_ERROR));
+    }
+
+    fn updates(plan: &UpdatePlan) -> Vec<String> {
+        plan.updates
+            .iter()
+            .map(|u| format!("{} {} -> {}", u.id, u.from, u.to))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn update_dependents() {
+        let offered = Offered(vec![
+            app("core", "2.0.0", &[]),
+            app("core", "3.0.0", &[("db", "*")]),
+            app("wallet", "1.5.0", &[("core", "^2.0.0")]),
+            app("wallet", "2.0.0", &[("core", "^2.0.0"), ("db", "*")]),
+        ]);
+        let installed = installed(vec![
+            app("core", "1.0.0", &[]),
+            app("wallet", "1.0.0", &[("core", "^1.0.0")]),
+            app("explorer", "1.0.0", &[("core", "^1.0.0")]),
+        ]);
+        // core 3.0.0 and wallet 2.0.0 need db, which is not installed, so older versions are used
+        let plan = plan_update_with(&offered, &installed, "core", &VersionRange::any())
+            .await
+            .unwrap();
+        assert_eq!(
+            updates(&plan),
+            vec!["core 1.0.0 -> 2.0.0", "wallet 1.0.0 -> 1.5.0"]
+        );
+        assert_eq!(plan.broken.keys().collect::<Vec<_>>(), vec!["explorer"]);
+
+        let err = plan_update_with(&offered, &installed, "core", &"=3.0.0".parse().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, Some(crate::error::VERSION_INCOMPATIBLE));
+    }
+
+    #[tokio::test]
+    async fn update_breaking_target() {
+        // the only plugin compatible with core 2.0.0 is one core 2.0.0 does not accept
+        let offered = Offered(vec![
+            app("core", "2.0.0", &[("plugin", "^1.0.0")]),
+            app("plugin", "2.0.0", &[("core", "^2.0.0")]),
+        ]);
+        let installed = installed(vec![
+            app("core", "1.0.0", &[("plugin", "^1.0.0")]),
+            app("plugin", "1.0.0", &[("core", "^1.0.0")]),
+        ]);
+        let err = plan_update_with(&offered, &installed, "core", &VersionRange::any())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, Some(crate::error::VERSION_INCOMPATIBLE));
+    }
+}
@@ -0,0 +1,584 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use failure::ResultExt as _;
+use futures::stream::StreamExt;
+use http::{Method, StatusCode};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+use super::{ContainerConfig, ContainerRuntime, ContainerState, LogSource, Logs, RunConfig};
+use crate::logs::LogOptions;
+use crate::Error;
+use crate::ResultExt as _;
+
+const API_VERSION: &'static str = "v1.40";
+
+#[derive(Clone, Debug, Fail)]
+#[fail(display = "Docker Error ({}): {}", status, message)]
+pub struct DockerError {
+    pub status: StatusCode,
+    pub message: String,
+}
+impl DockerError {
+    pub fn is_not_found(&self) -> bool {
+        self.status == StatusCode::NOT_FOUND
+    }
+}
+
+pub enum Body<'a> {
+    Empty,
+    Json(Vec<u8>),
+    Stream(&'a mut (dyn AsyncRead + Unpin + Send)),
+}
+
+enum BodyKind {
+    Length(u64),
+    Chunked(bool),
+    Eof(bool),
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    reader: BufReader<UnixStream>,
+    kind: BodyKind,
+}
+impl Response {
+    /// Reads the next piece of the body, or `None` once it is exhausted.
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let reader = &mut self.reader;
+        match &mut self.kind {
+            BodyKind::Length(0) => Ok(None),
+            BodyKind::Length(remaining) => {
+                let mut buf = vec![0; std::cmp::min(*remaining, 64 * 1024) as usize];
+                let n = reader.read(&mut buf).await?;
+                crate::ensure_code!(
+                    n > 0,
+                    crate::error::DOCKER_ERROR,
+                    "Docker Closed Connection Unexpectedly"
+                );
+                buf.truncate(n);
+                *remaining -= n as u64;
+                Ok(Some(buf))
+            }
+            BodyKind::Chunked(true) | BodyKind::Eof(true) => Ok(None),
+            BodyKind::Chunked(done) => {
+                let mut line = String::new();
+                reader.read_line(&mut line).await?;
+                let size = u64::from_str_radix(line.split(';').next().unwrap_or("").trim(), 16)
+                    .with_context(|e| format!("Invalid Chunk Size: {}", e))
+                    .with_code(crate::error::DOCKER_ERROR)?;
+                if size == 0 {
+                    loop {
+                        line.clear();
+                        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                            break;
+                        }
+                    }
+                    *done = true;
+                    return Ok(None);
+                }
+                let mut buf = vec![0; size as usize];
+                reader.read_exact(&mut buf).await?;
+                line.clear();
+                reader.read_line(&mut line).await?;
+                Ok(Some(buf))
+            }
+            BodyKind::Eof(done) => {
+                let mut buf = vec![0; 64 * 1024];
+                let n = reader.read(&mut buf).await?;
+                if n == 0 {
+                    *done = true;
+                    return Ok(None);
+                }
+                buf.truncate(n);
+                Ok(Some(buf))
+            }
+        }
+    }
+
+    pub async fn bytes(mut self) -> Result<Vec<u8>, Error> {
+        let mut res = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            res.extend_from_slice(&chunk);
+        }
+        Ok(res)
+    }
+
+    pub async fn json<T: for<'de> serde::Deserialize<'de>>(self) -> Result<T, Error> {
+        serde_json::from_slice(&self.bytes().await?).with_code(crate::error::SERDE_ERROR)
+    }
+
+    async fn error_for_status(self) -> Result<Self, Error> {
+        if self.status.is_success() || self.status == StatusCode::NOT_MODIFIED {
+            return Ok(self);
+        }
+        #[derive(serde::Deserialize)]
+        struct ErrorMessage {
+            message: String,
+        }
+        let status = self.status;
+        let body = self.bytes().await?;
+        let message = serde_json::from_slice::<ErrorMessage>(&body)
+            .map(|e| e.message)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&body).into_owned());
+        Err(Error::new(
+            DockerError { status, message },
+            Some(crate::error::DOCKER_ERROR),
+        ))
+    }
+}
+
+/// Demultiplexes the stdout/stderr framing the engine uses for non-tty streams.
+pub struct LogStream {
+    res: Response,
+    buf: Vec<u8>,
+}
+impl LogStream {
+    pub async fn next(&mut self) -> Result<Option<(LogSource, Vec<u8>)>, Error> {
+        loop {
+            if self.buf.len() >= 8 {
+                let len = u32::from_be_bytes([self.buf[4], self.buf[5], self.buf[6], self.buf[7]])
+                    as usize;
+                if self.buf.len() >= 8 + len {
+                    let source = if self.buf[0] == 2 {
+                        LogSource::Stderr
+                    } else {
+                        LogSource::Stdout
+                    };
+                    let rest = self.buf.split_off(8 + len);
+                    let frame = std::mem::replace(&mut self.buf, rest).split_off(8);
+                    return Ok(Some((source, frame)));
+                }
+            }
+            match self.res.chunk().await? {
+                Some(chunk) => self.buf.extend_from_slice(&chunk),
+                None if self.buf.is_empty() => return Ok(None),
+                None => {
+                    return Err(format_err!("Truncated Log Frame"))
+                        .with_code(crate::error::DOCKER_ERROR)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Docker {
+    socket: PathBuf,
+}
+impl Docker {
+    pub fn new<P: Into<PathBuf>>(socket: P) -> Self {
+        Docker {
+            socket: socket.into(),
+        }
+    }
+
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Body<'_>,
+    ) -> Result<Response, Error> {
+        let stream = UnixStream::connect(&self.socket)
+            .await
+            .with_context(|e| format!("{}: {}", self.socket.display(), e))
+            .with_code(crate::error::DOCKER_ERROR)?;
+        let mut stream = BufReader::new(stream);
+        let mut head = format!(
+            "{} /{}{} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\n",
+            method, API_VERSION, path
+        );
+        match &body {
+            Body::Empty => head.push_str("Content-Length: 0\r\n"),
+            Body::Json(b) => head.push_str(&format!(
+                "Content-Type: application/json\r\nContent-Length: {}\r\n",
+                b.len()
+            )),
+            Body::Stream(_) => {
+                head.push_str("Content-Type: application/x-tar\r\nTransfer-Encoding: chunked\r\n")
+            }
+        }
+        head.push_str("\r\n");
+        let w = stream.get_mut();
+        w.write_all(head.as_bytes()).await?;
+        match body {
+            Body::Empty => (),
+            Body::Json(b) => w.write_all(&b).await?,
+            Body::Stream(r) => {
+                let mut buf = vec![0; 64 * 1024];
+                loop {
+                    let n = r.read(&mut buf).await?;
+                    w.write_all(format!("{:x}\r\n", n).as_bytes()).await?;
+                    w.write_all(&buf[..n]).await?;
+                    w.write_all(b"\r\n").await?;
+                    if n == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+        w.flush().await?;
+
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok())
+            .and_then(|s| StatusCode::from_u16(s).ok())
+            .ok_or_else(|| format_err!("Invalid Response From Docker: {}", line.trim()))
+            .with_code(crate::error::DOCKER_ERROR)?;
+        let mut kind = BodyKind::Eof(false);
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                break;
+            }
+            let mut split = line.splitn(2, ':');
+            let name = split.next().unwrap_or("").trim().to_lowercase();
+            let value = split.next().unwrap_or("").trim();
+            if name == "content-length" {
+                kind = BodyKind::Length(value.parse().no_code()?);
+            } else if name == "transfer-encoding" && value.eq_ignore_ascii_case("chunked") {
+                kind = BodyKind::Chunked(false);
+            }
+        }
+        if status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED {
+            kind = BodyKind::Length(0);
+        }
+        Response {
+            status,
+            reader: stream,
+            kind,
+        }
+        .error_for_status()
+        .await
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for Docker {
+    async fn inspect(&self, name: &str) -> Result<ContainerState, Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct ContainerInspect {
+            state: ContainerState,
+        }
+        let res: ContainerInspect = self
+            .request(
+                Method::GET,
+                &format!("/containers/{}/json", name),
+                Body::Empty,
+            )
+            .await?
+            .json()
+            .await?;
+        Ok(res.state)
+    }
+
+    async fn start(&self, name: &str) -> Result<(), Error> {
+        self.request(
+            Method::POST,
+            &format!("/containers/{}/start", name),
+            Body::Empty,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn stop(&self, name: &str, timeout_secs: u64) -> Result<(), Error> {
+        self.request(
+            Method::POST,
+            &format!("/containers/{}/stop?t={}", name, timeout_secs),
+            Body::Empty,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn pause(&self, name: &str) -> Result<(), Error> {
+        self.request(
+            Method::POST,
+            &format!("/containers/{}/pause", name),
+            Body::Empty,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn unpause(&self, name: &str) -> Result<(), Error> {
+        self.request(
+            Method::POST,
+            &format!("/containers/{}/unpause", name),
+            Body::Empty,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn create(&self, config: &ContainerConfig) -> Result<(), Error> {
+        let mut host_config = serde_json::json!({
+            "RestartPolicy": { "Name": "no" },
+            "Mounts": config.mounts.iter().map(|m| serde_json::json!({
+                "Type": "bind",
+                "Source": m.source,
+                "Target": m.target,
+            })).collect::<Vec<_>>(),
+        });
+        if let Some(shm_size_mb) = config.shm_size_mb {
+            host_config["ShmSize"] = serde_json::json!(shm_size_mb * 1024 * 1024);
+        }
+        let mut body = serde_json::json!({
+            "Image": config.image,
+            "Env": config.env,
+        });
+        if let Some((network, ip)) = &config.network {
+            host_config["NetworkMode"] = serde_json::json!(network);
+            body["NetworkingConfig"] = serde_json::json!({
+                "EndpointsConfig": {
+                    network: { "IPAMConfig": { "IPv4Address": format!("{}", ip) } }
+                }
+            });
+        }
+        body["HostConfig"] = host_config;
+        self.request(
+            Method::POST,
+            &format!("/containers/create?name={}", config.name),
+            Body::Json(serde_json::to_vec(&body).with_code(crate::error::SERDE_ERROR)?),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn remove(&self, name: &str, force: bool) -> Result<(), Error> {
+        self.request(
+            Method::DELETE,
+            &format!("/containers/{}?force={}", name, force),
+            Body::Empty,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn image_exists(&self, image: &str) -> Result<bool, Error> {
+        match self
+            .request(Method::GET, &format!("/images/{}/json", image), Body::Empty)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e)
+                if e.failure
+                    .downcast_ref::<DockerError>()
+                    .map(|e| e.is_not_found())
+                    .unwrap_or(false) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn remove_image(&self, image: &str) -> Result<(), Error> {
+        self.request(Method::DELETE, &format!("/images/{}", image), Body::Empty)
+            .await?;
+        Ok(())
+    }
+
+    async fn tag(&self, source: &str, target: &str) -> Result<(), Error> {
+        let (repo, tag) = match target.rfind(':') {
+            Some(idx) if !target[idx..].contains('/') => (&target[..idx], &target[idx + 1..]),
+            _ => (target, "latest"),
+        };
+        self.request(
+            Method::POST,
+            &format!("/images/{}/tag?repo={}&tag={}", source, repo, tag),
+            Body::Empty,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Removes all images not used by a container (`docker image prune -a`).
+    async fn prune_images(&self) -> Result<(), Error> {
+        self.request(
+            Method::POST,
+            "/images/prune?filters=%7B%22dangling%22%3A%5B%22false%22%5D%7D",
+            Body::Empty,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Loads an image from a `docker save` tarball.
+    async fn load_image(
+        &self,
+        image: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<Vec<String>, Error> {
+        #[derive(serde::Deserialize)]
+        struct LoadMessage {
+            #[serde(default)]
+            stream: Option<String>,
+            #[serde(default)]
+            error: Option<String>,
+        }
+        let res = self
+            .request(Method::POST, "/images/load?quiet=1", Body::Stream(image))
+            .await?
+            .bytes()
+            .await?;
+        let mut loaded = Vec::new();
+        for msg in serde_json::Deserializer::from_slice(&res).into_iter::<LoadMessage>() {
+            let msg = msg.with_code(crate::error::SERDE_ERROR)?;
+            if let Some(stream) = msg.stream {
+                log::info!("{}", stream.trim());
+                loaded.extend(super::parse_loaded(&stream));
+            }
+            if let Some(error) = msg.error {
+                return Err(format_err!("Failed to Load Docker Image: {}", error))
+                    .with_code(crate::error::DOCKER_ERROR);
+            }
+        }
+        Ok(loaded)
+    }
+
+    async fn logs(&self, name: &str, options: &LogOptions<&str, &str>) -> Result<Logs, Error> {
+        let mut query = format!(
+            "stdout=1&stderr=1&follow={}&timestamps={}&details={}",
+            options.follow, options.timestamps, options.details
+        );
+        if let Some(since) = options.since {
+            query.push_str(&format!("&since={}", super::parse_log_time(since)?));
+        }
+        if let Some(until) = options.until {
+            query.push_str(&format!("&until={}", super::parse_log_time(until)?));
+        }
+        if let Some(tail) = options.tail {
+            query.push_str(&format!("&tail={}", tail));
+        }
+        let stream = LogStream {
+            res: self
+                .request(
+                    Method::GET,
+                    &format!("/containers/{}/logs?{}", name, query),
+                    Body::Empty,
+                )
+                .await?,
+            buf: Vec::new(),
+        };
+        Ok(futures::stream::unfold(stream, |mut stream| async move {
+            stream.next().await.transpose().map(|res| (res, stream))
+        })
+        .boxed())
+    }
+
+    async fn rename(&self, name: &str, new_name: &str) -> Result<(), Error> {
+        self.request(
+            Method::POST,
+            &format!("/containers/{}/rename?name={}", name, new_name),
+            Body::Empty,
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn exec(&self, name: &str, command: &[String]) -> tokio::process::Command {
+        super::cli_exec("docker", name, command)
+    }
+
+    fn run(&self, config: &RunConfig) -> tokio::process::Command {
+        super::cli_run("docker", config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::UnixListener;
+
+    use super::*;
+
+    async fn mock(response: &'static [u8]) -> (Docker, tokio::task::JoinHandle<String>) {
+        let path = std::env::temp_dir().join(format!(
+            "appmgr-docker-mock-{}-{}.sock",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let listener = UnixListener::bind(&path).unwrap();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            let mut req = String::new();
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                req.push_str(&line);
+            }
+            stream.get_mut().write_all(response).await.unwrap();
+            stream.get_mut().shutdown().await.unwrap();
+            req
+        });
+        (Docker::new(path), handle)
+    }
+
+    #[tokio::test]
+    async fn inspect_state() {
+        let (docker, handle) = mock(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 137\r\n\r\n{\"State\":{\"Status\":\"exited\",\"Running\":false,\"OOMKilled\":true,\"ExitCode\":137,\"StartedAt\":\"2021-01-01T00:00:00Z\",\"FinishedAt\":\"\"},\"Id\":\"a\"}",
+        )
+        .await;
+        let state = docker.inspect("bitcoind").await.unwrap();
+        assert_eq!(state.status, "exited");
+        assert!(state.oom_killed);
+        assert_eq!(state.exit_code, 137);
+        assert_eq!(state.started_at, "2021-01-01T00:00:00Z");
+        let req = handle.await.unwrap();
+        assert!(req.starts_with("GET /v1.40/containers/bitcoind/json HTTP/1.1"));
+    }
+
+    #[tokio::test]
+    async fn structured_error() {
+        let (docker, _) = mock(
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 42\r\n\r\n{\"message\":\"No such container: bitcoind\"}\n",
+        )
+        .await;
+        let err = docker.start("bitcoind").await.unwrap_err();
+        assert_eq!(err.code, Some(crate::error::DOCKER_ERROR));
+        let err = err.failure.downcast_ref::<DockerError>().unwrap();
+        assert!(err.is_not_found());
+        assert_eq!(err.message, "No such container: bitcoind");
+    }
+
+    #[tokio::test]
+    async fn chunked_logs() {
+        let (docker, _) = mock(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\na\r\n\x01\x00\x00\x00\x00\x00\x00\x03ou\r\n8\r\nt\x02\x00\x00\x00\x00\x00\x00\r\n4\r\n\x03err\r\n0\r\n\r\n",
+        )
+        .await;
+        let mut logs = docker
+            .logs(
+                "bitcoind",
+                &LogOptions {
+                    details: false,
+                    follow: false,
+                    since: None,
+                    until: None,
+                    tail: None,
+                    timestamps: false,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            logs.next().await.transpose().unwrap(),
+            Some((LogSource::Stdout, b"out".to_vec()))
+        );
+        assert_eq!(
+            logs.next().await.transpose().unwrap(),
+            Some((LogSource::Stderr, b"err".to_vec()))
+        );
+        assert!(logs.next().await.is_none());
+    }
+}
@@ -0,0 +1,386 @@
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use tokio::io::AsyncRead;
+
+use crate::logs::LogOptions;
+use crate::Error;
+use crate::ResultExt as _;
+
+pub mod docker;
+pub mod podman;
+
+pub use docker::{Docker, DockerError};
+pub use podman::Podman;
+
+pub const DOCKER_SOCKET: &'static str = "/var/run/docker.sock";
+#[cfg(feature = "podman")]
+pub const DEFAULT_RUNTIME: &'static str = "podman";
+#[cfg(not(feature = "podman"))]
+pub const DEFAULT_RUNTIME: &'static str = "docker";
+
+lazy_static::lazy_static! {
+    static ref RUNTIME: Box<dyn ContainerRuntime> =
+        by_name(runtime_name(std::env::var("APPMGR_RUNTIME").ok().as_deref())).unwrap();
+}
+
+/// The runtime to use when `requested` is the value of `APPMGR_RUNTIME`.
+fn runtime_name(requested: Option<&str>) -> &str {
+    match requested {
+        Some(name) if by_name(name).is_some() => name,
+        Some(name) => {
+            log::warn!(
+                "Unknown Container Runtime {}, Using {}",
+                name,
+                DEFAULT_RUNTIME
+            );
+            DEFAULT_RUNTIME
+        }
+        None => DEFAULT_RUNTIME,
+    }
+}
+
+pub fn docker() -> Docker {
+    Docker::new(DOCKER_SOCKET)
+}
+
+pub fn by_name(name: &str) -> Option<Box<dyn ContainerRuntime>> {
+    match name {
+        "docker" => Some(Box::new(docker())),
+        "podman" => Some(Box::new(Podman::default())),
+        _ => None,
+    }
+}
+
+/// The runtime selected by the `podman` feature, overridable with `APPMGR_RUNTIME`.
+pub fn current() -> &'static dyn ContainerRuntime {
+    &**RUNTIME
+}
+
+pub type Logs = BoxStream<'static, Result<(LogSource, Vec<u8>), Error>>;
+
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    fn image_name(&self, id: &str) -> String {
+        format!("start9/{}", id)
+    }
+    async fn image_exists(&self, image: &str) -> Result<bool, Error>;
+    /// Loads a `docker save` or OCI archive, returning references to the loaded images.
+    async fn load_image(
+        &self,
+        image: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<Vec<String>, Error>;
+    async fn remove_image(&self, image: &str) -> Result<(), Error>;
+    /// Adds the `repo:tag` reference `target` to the image `source`.
+    async fn tag(&self, source: &str, target: &str) -> Result<(), Error>;
+    /// Removes all images not used by a container.
+    async fn prune_images(&self) -> Result<(), Error>;
+    async fn create(&self, config: &ContainerConfig) -> Result<(), Error>;
+    async fn start(&self, name: &str) -> Result<(), Error>;
+    async fn stop(&self, name: &str, timeout_secs: u64) -> Result<(), Error>;
+    async fn pause(&self, name: &str) -> Result<(), Error>;
+    async fn unpause(&self, name: &str) -> Result<(), Error>;
+    async fn inspect(&self, name: &str) -> Result<ContainerState, Error>;
+    async fn logs(&self, name: &str, options: &LogOptions<&str, &str>) -> Result<Logs, Error>;
+    async fn remove(&self, name: &str, force: bool) -> Result<(), Error>;
+    async fn rename(&self, name: &str, new_name: &str) -> Result<(), Error>;
+    /// Builds a command running `command` inside the running container.
+    fn exec(&self, name: &str, command: &[String]) -> tokio::process::Command;
+    /// Builds a command running a one-off container that is removed on exit.
+    fn run(&self, config: &RunConfig) -> tokio::process::Command;
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerState {
+    pub status: String,
+    #[serde(default)]
+    pub running: bool,
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub restarting: bool,
+    #[serde(default)]
+    #[serde(rename = "OOMKilled")]
+    pub oom_killed: bool,
+    #[serde(default)]
+    pub dead: bool,
+    #[serde(default)]
+    pub exit_code: i64,
+    #[serde(default)]
+    pub error: String,
+    #[serde(default)]
+    pub started_at: String,
+    #[serde(default)]
+    pub finished_at: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct Mount {
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+#[derive(Clone, Debug)]
+pub struct ContainerConfig {
+    pub name: String,
+    pub image: String,
+    pub env: Vec<String>,
+    pub mounts: Vec<Mount>,
+    pub network: Option<(String, Ipv4Addr)>,
+    pub shm_size_mb: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RunConfig {
+    pub name: String,
+    pub image: String,
+    pub mounts: Vec<Mount>,
+    pub entrypoint: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogSource {
+    Stdout,
+    Stderr,
+}
+
+/// Converts a `--since`/`--until` argument into the unix timestamp the engine expects.
+/// Accepts a unix timestamp, an RFC 3339 date (UTC if it has no offset, e.g. `2013-01-02T13:23:37`)
+/// or a duration relative to now (e.g. `10m` or `1h30m`).
+pub fn parse_log_time(s: &str) -> Result<String, Error> {
+    if s.parse::<f64>().is_ok() {
+        return Ok(s.to_owned());
+    }
+    if let Some(ts) = parse_rfc3339(s) {
+        return Ok(format!("{}", ts));
+    }
+    let secs = parse_duration(s)?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .no_code()?
+        .as_secs();
+    Ok(format!("{}", now.saturating_sub(secs)))
+}
+
+/// Parses a sequence of decimal numbers with units, like Go's `time.ParseDuration`
+/// plus `d` for days, rounded down to whole seconds.
+fn parse_duration(s: &str) -> Result<u64, Error> {
+    let invalid = || format_err!("Invalid Time: {}", s);
+    crate::ensure_code!(
+        !s.is_empty(),
+        crate::error::GENERAL_ERROR,
+        "Invalid Time: {}",
+        s
+    );
+    let mut rest = s;
+    let mut secs = 0.0;
+    while !rest.is_empty() {
+        let num_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(|| format_err!("Missing Time Unit: {}", s))
+            .with_code(crate::error::GENERAL_ERROR)?;
+        let num: f64 = rest[..num_len]
+            .parse()
+            .map_err(|_| invalid())
+            .with_code(crate::error::GENERAL_ERROR)?;
+        rest = &rest[num_len..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "ns" => 1e-9,
+            "us" | "µs" => 1e-6,
+            "ms" => 1e-3,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 60.0 * 60.0,
+            "d" => 60.0 * 60.0 * 24.0,
+            _ => {
+                return Err(format_err!("Invalid Time Unit: {}", s))
+                    .with_code(crate::error::GENERAL_ERROR)
+            }
+        };
+        rest = &rest[unit_len..];
+        secs += num * scale;
+    }
+    crate::ensure_code!(
+        secs < u64::MAX as f64,
+        crate::error::GENERAL_ERROR,
+        "Invalid Time: {}",
+        s
+    );
+    Ok(secs as u64)
+}
+
+/// Parses an RFC 3339 date into seconds since the unix epoch.
+fn parse_rfc3339(s: &str) -> Option<i64> {
+    fn num(s: &str) -> Option<i64> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        s.parse().ok()
+    }
+    let s = s.as_bytes();
+    if s.len() < 19 || s[4] != b'-' || s[7] != b'-' || s[13] != b':' || s[16] != b':' {
+        return None;
+    }
+    if !matches!(s[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| num(std::str::from_utf8(&s[range]).ok()?);
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let (hour, min, sec) = (field(11..13)?, field(14..16)?, field(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+    let mut rest = &s[19..];
+    if rest.first() == Some(&b'.') {
+        let frac = rest[1..].iter().take_while(|b| b.is_ascii_digit()).count();
+        if frac == 0 {
+            return None;
+        }
+        rest = &rest[1 + frac..];
+    }
+    let offset = match rest {
+        b"" | b"Z" | b"z" => 0,
+        [sign, h1, h2, b':', m1, m2] if *sign == b'+' || *sign == b'-' => {
+            let h = num(std::str::from_utf8(&[*h1, *h2]).ok()?)?;
+            let m = num(std::str::from_utf8(&[*m1, *m2]).ok()?)?;
+            let offset = h * 60 * 60 + m * 60;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return None,
+    };
+    // days since the epoch of a proleptic gregorian date
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some(days * 24 * 60 * 60 + hour * 60 * 60 + min * 60 + sec - offset)
+}
+
+fn mount_arg(mount: &Mount) -> String {
+    format!(
+        "type=bind,src={},dst={}",
+        mount.source.display(),
+        mount.target.display()
+    )
+}
+
+fn cli_exec(bin: &str, name: &str, command: &[String]) -> tokio::process::Command {
+    let mut cmd = tokio::process::Command::new(bin);
+    cmd.arg("exec").arg(name).args(command);
+    cmd
+}
+
+fn cli_run(bin: &str, config: &RunConfig) -> tokio::process::Command {
+    let mut cmd = tokio::process::Command::new(bin);
+    cmd.arg("run").arg("--rm").arg("--name").arg(&config.name);
+    for mount in &config.mounts {
+        cmd.arg("--mount").arg(mount_arg(mount));
+    }
+    cmd.arg("--entrypoint")
+        .arg(&config.entrypoint)
+        .arg(&config.image)
+        .args(&config.args);
+    cmd
+}
+
+/// Reads the image references out of `Loaded image: ...` lines, as printed by `load`.
+pub fn parse_loaded(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            line.strip_prefix("Loaded image ID: ")
+                .or_else(|| line.strip_prefix("Loaded image(s): "))
+                .or_else(|| line.strip_prefix("Loaded image: "))
+        })
+        .flat_map(|refs| refs.split(','))
+        .map(|r| r.trim().to_owned())
+        .filter(|r| !r.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runtime_selection() {
+        assert!(by_name("docker").is_some());
+        assert!(by_name("podman").is_some());
+        assert!(by_name("lxc").is_none());
+        assert_eq!(runtime_name(None), DEFAULT_RUNTIME);
+        assert_eq!(runtime_name(Some("docker")), "docker");
+        assert_eq!(runtime_name(Some("podman")), "podman");
+        assert_eq!(runtime_name(Some("lxc")), DEFAULT_RUNTIME);
+        #[cfg(feature = "podman")]
+        assert_eq!(DEFAULT_RUNTIME, "podman");
+        #[cfg(not(feature = "podman"))]
+        assert_eq!(DEFAULT_RUNTIME, "docker");
+    }
+
+    #[test]
+    fn loaded_references() {
+        assert_eq!(
+            parse_loaded("Loaded image: start9/bitcoind:latest\n"),
+            vec!["start9/bitcoind:latest"]
+        );
+        assert_eq!(
+            parse_loaded("Getting image source signatures\nLoaded image(s): a:1,b:2\n"),
+            vec!["a:1", "b:2"]
+        );
+        assert_eq!(
+            parse_loaded("Loaded image ID: sha256:abc\n"),
+            vec!["sha256:abc"]
+        );
+    }
+
+    #[test]
+    fn log_time_durations() {
+        assert_eq!(parse_duration("90s").unwrap(), 90);
+        assert_eq!(parse_duration("10m").unwrap(), 600);
+        assert_eq!(parse_duration("1h30m").unwrap(), 5400);
+        assert_eq!(parse_duration("1h30m15s").unwrap(), 5415);
+        assert_eq!(parse_duration("1.5h").unwrap(), 5400);
+        assert_eq!(parse_duration("2d").unwrap(), 172800);
+        assert_eq!(parse_duration("1500ms").unwrap(), 1);
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("1h30").is_err());
+    }
+
+    #[test]
+    fn log_time_dates() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_rfc3339("2013-01-02T13:23:37Z"), Some(1357133017));
+        assert_eq!(parse_rfc3339("2013-01-02T13:23:37"), Some(1357133017));
+        assert_eq!(parse_rfc3339("2013-01-02t13:23:37.123z"), Some(1357133017));
+        assert_eq!(parse_rfc3339("2013-01-02T15:23:37+02:00"), Some(1357133017));
+        assert_eq!(parse_rfc3339("2013-01-02T08:23:37-05:00"), Some(1357133017));
+        assert_eq!(parse_rfc3339("2024-02-29T00:00:00Z"), Some(1709164800));
+        assert_eq!(parse_rfc3339("2013-13-02T13:23:37Z"), None);
+        assert_eq!(parse_rfc3339("2013-01-02T13:23:37+0200"), None);
+        assert_eq!(parse_rfc3339("10m"), None);
+
+        assert_eq!(
+            parse_log_time("2013-01-02T13:23:37Z").unwrap(),
+            "1357133017"
+        );
+        assert_eq!(parse_log_time("1357133017").unwrap(), "1357133017");
+        assert!(parse_log_time("yesterday").is_err());
+    }
+}
@@ -0,0 +1,383 @@
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::{ContainerConfig, ContainerRuntime, ContainerState, LogSource, Logs, RunConfig};
+use crate::logs::LogOptions;
+use crate::util::Invoke;
+use crate::Error;
+use crate::ResultExt as _;
+
+/// Drives containers through the `podman` CLI, so no root daemon is required.
+#[derive(Clone, Debug)]
+pub struct Podman {
+    bin: String,
+}
+impl Default for Podman {
+    fn default() -> Self {
+        Podman::new("podman")
+    }
+}
+impl Podman {
+    pub fn new<S: Into<String>>(bin: S) -> Self {
+        Podman { bin: bin.into() }
+    }
+
+    fn command(&self) -> tokio::process::Command {
+        tokio::process::Command::new(&self.bin)
+    }
+
+    async fn invoke(&self, args: &[&str]) -> Result<Vec<u8>, Error> {
+        self.command()
+            .args(args)
+            .invoke("Podman")
+            .await
+            .with_code(crate::error::DOCKER_ERROR)
+    }
+}
+
+fn pipe<R: AsyncRead + Unpin + Send + 'static>(r: R, source: LogSource) -> Logs {
+    futures::stream::unfold(Some(r), move |r| async move {
+        let mut r = r?;
+        let mut buf = vec![0; 64 * 1024];
+        match r.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok((source, buf)), Some(r)))
+            }
+            Err(e) => Some((Err(e.into()), None)),
+        }
+    })
+    .boxed()
+}
+
+#[async_trait]
+impl ContainerRuntime for Podman {
+    async fn image_exists(&self, image: &str) -> Result<bool, Error> {
+        Ok(self
+            .command()
+            .args(&["image", "exists", image])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await?
+            .success())
+    }
+
+    async fn load_image(
+        &self,
+        image: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<Vec<String>, Error> {
+        let mut child = self
+            .command()
+            .arg("load")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut child_in = child.stdin.take().unwrap();
+        tokio::io::copy(image, &mut child_in).await?;
+        drop(child_in);
+        let output = child.wait_with_output().await?;
+        crate::ensure_code!(
+            output.status.success(),
+            crate::error::DOCKER_ERROR,
+            "Failed to Load Podman Image: {}",
+            std::str::from_utf8(&output.stderr).unwrap_or("Unknown Error")
+        );
+        Ok(super::parse_loaded(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    }
+
+    async fn remove_image(&self, image: &str) -> Result<(), Error> {
+        self.invoke(&["rmi", image]).await?;
+        Ok(())
+    }
+
+    async fn tag(&self, source: &str, target: &str) -> Result<(), Error> {
+        self.invoke(&["tag", source, target]).await?;
+        Ok(())
+    }
+
+    async fn prune_images(&self) -> Result<(), Error> {
+        self.invoke(&["image", "prune", "-a", "-f"]).await?;
+        Ok(())
+    }
+
+    async fn create(&self, config: &ContainerConfig) -> Result<(), Error> {
+        let mut cmd = self.command();
+        cmd.arg("create")
+            .arg("--restart")
+            .arg("no")
+            .arg("--name")
+            .arg(&config.name);
+        for mount in &config.mounts {
+            cmd.arg("--mount").arg(super::mount_arg(mount));
+        }
+        if let Some((network, ip)) = &config.network {
+            cmd.arg("--net")
+                .arg(network)
+                .arg("--ip")
+                .arg(format!("{}", ip));
+        }
+        for env in &config.env {
+            cmd.arg("--env").arg(env);
+        }
+        if let Some(shm_size_mb) = config.shm_size_mb {
+            cmd.arg("--shm-size").arg(format!("{}m", shm_size_mb));
+        }
+        cmd.arg(&config.image)
+            .invoke("Podman")
+            .await
+            .with_code(crate::error::DOCKER_ERROR)?;
+        Ok(())
+    }
+
+    async fn start(&self, name: &str) -> Result<(), Error> {
+        self.invoke(&["start", name]).await?;
+        Ok(())
+    }
+
+    async fn stop(&self, name: &str, timeout_secs: u64) -> Result<(), Error> {
+        self.invoke(&["stop", "-t", &format!("{}", timeout_secs), name])
+            .await?;
+        Ok(())
+    }
+
+    async fn pause(&self, name: &str) -> Result<(), Error> {
+        self.invoke(&["pause", name]).await?;
+        Ok(())
+    }
+
+    async fn unpause(&self, name: &str) -> Result<(), Error> {
+        self.invoke(&["unpause", name]).await?;
+        Ok(())
+    }
+
+    async fn inspect(&self, name: &str) -> Result<ContainerState, Error> {
+        let out = self
+            .invoke(&[
+                "inspect",
+                "--type",
+                "container",
+                "--format",
+                "{{json .State}}",
+                name,
+            ])
+            .await?;
+        let mut state: ContainerState =
+            serde_json::from_slice(&out).with_code(crate::error::SERDE_ERROR)?;
+        // podman has a few extra states, report them the way docker would
+        let status = match state.status.as_str() {
+            "configured" | "initialized" => Some("created"),
+            "stopped" | "stopping" => Some("exited"),
+            _ => None,
+        };
+        if let Some(status) = status {
+            state.status = status.to_owned();
+        }
+        Ok(state)
+    }
+
+    async fn logs(&self, name: &str, options: &LogOptions<&str, &str>) -> Result<Logs, Error> {
+        // podman has no equivalent of `--details`
+        let mut cmd = self.command();
+        cmd.arg("logs");
+        if options.follow {
+            cmd.arg("-f");
+        }
+        if let Some(since) = options.since {
+            cmd.arg("--since").arg(since);
+        }
+        if let Some(until) = options.until {
+            cmd.arg("--until").arg(until);
+        }
+        if let Some(tail) = options.tail {
+            cmd.arg("--tail").arg(format!("{}", tail));
+        }
+        if options.timestamps {
+            cmd.arg("-t");
+        }
+        let mut child = cmd
+            .arg(name)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let output = futures::stream::select(
+            pipe(child.stdout.take().unwrap(), LogSource::Stdout),
+            pipe(child.stderr.take().unwrap(), LogSource::Stderr),
+        );
+        let exit = futures::stream::once(async move {
+            match child.wait().await {
+                Ok(status) if status.success() => None,
+                Ok(status) => Some(
+                    Err(format_err!("Podman Exited With {}", status))
+                        .with_code(crate::error::DOCKER_ERROR),
+                ),
+                Err(e) => Some(Err(e.into())),
+            }
+        })
+        .filter_map(futures::future::ready);
+        Ok(output.chain(exit).boxed())
+    }
+
+    async fn remove(&self, name: &str, force: bool) -> Result<(), Error> {
+        if force {
+            self.invoke(&["rm", "-f", name]).await?;
+        } else {
+            self.invoke(&["rm", name]).await?;
+        }
+        Ok(())
+    }
+
+    async fn rename(&self, name: &str, new_name: &str) -> Result<(), Error> {
+        self.invoke(&["rename", name, new_name]).await?;
+        Ok(())
+    }
+
+    fn exec(&self, name: &str, command: &[String]) -> tokio::process::Command {
+        super::cli_exec(&self.bin, name, command)
+    }
+
+    fn run(&self, config: &RunConfig) -> tokio::process::Command {
+        super::cli_run(&self.bin, config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+
+    use super::super::Mount;
+    use super::*;
+
+    /// A `podman` that only records its arguments, one call per line.
+    async fn fake_podman(dir: &Path) -> (Podman, PathBuf) {
+        let bin = dir.join("podman");
+        let calls = dir.join("calls");
+        tokio::fs::write(
+            &bin,
+            format!("#!/bin/sh\necho \"$@\" >> {}\n", calls.display()),
+        )
+        .await
+        .unwrap();
+        tokio::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755))
+            .await
+            .unwrap();
+        (Podman::new(bin.to_str().unwrap()), calls)
+    }
+
+    async fn calls(path: &Path) -> Vec<String> {
+        tokio::fs::read_to_string(path)
+            .await
+            .unwrap()
+            .lines()
+            .map(|l| l.to_owned())
+            .collect()
+    }
+
+    fn mount(source: &str, target: &str) -> Mount {
+        Mount {
+            source: source.into(),
+            target: target.into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn container_args() {
+        let dir = crate::fixtures::tmp_dir("podman-container").await;
+        let (podman, log) = fake_podman(&dir).await;
+        podman
+            .create(&ContainerConfig {
+                name: "lnd".to_owned(),
+                image: "start9/lnd:0.11.0".to_owned(),
+                env: vec!["A=1".to_owned(), "B=2".to_owned()],
+                mounts: vec![
+                    mount("/root/volumes/lnd", "/root/.lnd"),
+                    mount("/root/volumes/bitcoind", "/mnt/bitcoind"),
+                ],
+                network: Some(("start9".to_owned(), "172.18.0.2".parse().unwrap())),
+                shm_size_mb: Some(64),
+            })
+            .await
+            .unwrap();
+        podman
+            .create(&ContainerConfig {
+                name: "lnd_rollback".to_owned(),
+                image: "start9/lnd:0.10.0".to_owned(),
+                env: Vec::new(),
+                mounts: Vec::new(),
+                network: None,
+                shm_size_mb: None,
+            })
+            .await
+            .unwrap();
+        podman.start("lnd").await.unwrap();
+        podman.stop("lnd", 30).await.unwrap();
+        podman.rename("lnd", "lnd_old").await.unwrap();
+        podman.remove("lnd_old", true).await.unwrap();
+        podman.remove("lnd_rollback", false).await.unwrap();
+        podman
+            .tag("start9/lnd:latest", "start9/lnd:0.11.0")
+            .await
+            .unwrap();
+        assert_eq!(
+            calls(&log).await,
+            vec![
+                "create --restart no --name lnd \
+                 --mount type=bind,src=/root/volumes/lnd,dst=/root/.lnd \
+                 --mount type=bind,src=/root/volumes/bitcoind,dst=/mnt/bitcoind \
+                 --net start9 --ip 172.18.0.2 --env A=1 --env B=2 --shm-size 64m \
+                 start9/lnd:0.11.0",
+                "create --restart no --name lnd_rollback start9/lnd:0.10.0",
+                "start lnd",
+                "stop -t 30 lnd",
+                "rename lnd lnd_old",
+                "rm -f lnd_old",
+                "rm lnd_rollback",
+                "tag start9/lnd:latest start9/lnd:0.11.0",
+            ]
+        );
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn exec_and_run_args() {
+        let dir = crate::fixtures::tmp_dir("podman-exec").await;
+        let (podman, log) = fake_podman(&dir).await;
+        let status = podman
+            .exec("lnd", &["lncli".to_owned(), "getinfo".to_owned()])
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+        let status = podman
+            .run(&RunConfig {
+                name: "lnd-backup".to_owned(),
+                image: "start9/lnd".to_owned(),
+                mounts: vec![mount("/root/volumes/lnd", "/mnt/data")],
+                entrypoint: "/bin/sh".to_owned(),
+                args: vec!["-c".to_owned(), "true".to_owned()],
+            })
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(
+            calls(&log).await,
+            vec![
+                "exec lnd lncli getinfo",
+                "run --rm --name lnd-backup \
+                 --mount type=bind,src=/root/volumes/lnd,dst=/mnt/data \
+                 --entrypoint /bin/sh start9/lnd -c true",
+            ]
+        );
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+use std::io::SeekFrom;
+use std::path::Path;
+
+use failure::ResultExt as _;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::Error;
+use crate::ResultExt as _;
+
+/// The first bytes of a versioned package. Legacy packages start straight with the tar archive.
+pub const MAGIC: &'static [u8; 4] = b"s9pk";
+pub const VERSION: u8 = 2;
+pub const ICON: &'static str = "icon.png";
+
+/// The largest table of contents `open` accepts, so a corrupt header can't exhaust memory.
+pub const MAX_TOC_LEN: u32 = 1024 * 1024;
+
+const BLOCK: u64 = 512;
+
+/// Where the contents of a package entry are, relative to the start of the archive.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Section {
+    pub path: String,
+    pub offset: u64,
+    pub length: u64,
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Toc {
+    pub sections: Vec<Section>,
+}
+impl Toc {
+    pub fn get(&self, path: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.path == path)
+    }
+}
+
+/// An s9pk file: a header of `MAGIC`, `VERSION`, the length of the table of contents
+/// as a big endian u32 and the table of contents in cbor, followed by the same tar archive
+/// a legacy package consists of.
+pub struct S9pk {
+    file: File,
+    toc: Option<Toc>,
+    archive_start: u64,
+}
+impl S9pk {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut file = File::open(path)
+            .await
+            .with_context(|e| format!("{}: {}", path.display(), e))
+            .with_code(crate::error::FILESYSTEM_ERROR)?;
+        let mut magic = [0; 4];
+        let is_v2 = match file.read_exact(&mut magic).await {
+            Ok(_) => &magic == MAGIC,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => false,
+            Err(e) => return Err(e.into()),
+        };
+        if !is_v2 {
+            file.seek(SeekFrom::Start(0)).await?;
+            return Ok(S9pk {
+                file,
+                toc: None,
+                archive_start: 0,
+            });
+        }
+        let version = file.read_u8().await?;
+        crate::ensure_code!(
+            version == VERSION,
+            crate::error::VERSION_INCOMPATIBLE,
+            "Unsupported s9pk Version: {}",
+            version
+        );
+        let toc_len = file.read_u32().await?;
+        crate::ensure_code!(
+            toc_len <= MAX_TOC_LEN && (toc_len as u64) < file.metadata().await?.len(),
+            crate::error::GENERAL_ERROR,
+            "Package File Invalid or Corrupted: table of contents too large ({} bytes)",
+            toc_len
+        );
+        let mut toc = vec![0; toc_len as usize];
+        file.read_exact(&mut toc).await?;
+        let toc = serde_cbor::from_slice(&toc).with_code(crate::error::SERDE_ERROR)?;
+        Ok(S9pk {
+            file,
+            toc: Some(toc),
+            archive_start: MAGIC.len() as u64 + 1 + 4 + toc_len as u64,
+        })
+    }
+
+    /// The table of contents, or `None` for a legacy package.
+    pub fn toc(&self) -> Option<&Toc> {
+        self.toc.as_ref()
+    }
+
+    /// Reads the contents of the entry at `path` without scanning the archive.
+    pub async fn read(&mut self, path: &str) -> Result<Vec<u8>, Error> {
+        let section = self
+            .toc
+            .as_ref()
+            .and_then(|toc| toc.get(path))
+            .cloned()
+            .ok_or_else(|| format_err!("Package Has No {}", path))
+            .with_code(crate::error::NOT_FOUND)?;
+        let len = self.file.metadata().await?.len();
+        crate::ensure_code!(
+            self.archive_start
+                .checked_add(section.offset)
+                .and_then(|start| start.checked_add(section.length))
+                .map_or(false, |end| end <= len),
+            crate::error::GENERAL_ERROR,
+            "Package File Invalid or Corrupted: {} extends past the end of the file",
+            path
+        );
+        self.file
+            .seek(SeekFrom::Start(self.archive_start + section.offset))
+            .await?;
+        let mut data = vec![0; section.length as usize];
+        self.file.read_exact(&mut data).await?;
+        let (sha256, _) = crate::install::sha256_reader(&mut data.as_slice()).await?;
+        crate::ensure_code!(
+            sha256 == section.sha256,
+            crate::error::GENERAL_ERROR,
+            "Package File Invalid or Corrupted: checksum mismatch for {}",
+            path
+        );
+        Ok(data)
+    }
+
+    pub async fn read_cbor<T: serde::de::DeserializeOwned>(
+        &mut self,
+        path: &str,
+    ) -> Result<T, Error> {
+        serde_cbor::from_slice(&self.read(path).await?).with_code(crate::error::SERDE_ERROR)
+    }
+
+    /// Checks every section of the table of contents against the archive.
+    pub async fn verify(&mut self) -> Result<(), Error> {
+        let toc = match &self.toc {
+            Some(toc) => toc.clone(),
+            None => return Ok(()),
+        };
+        self.file.seek(SeekFrom::Start(self.archive_start)).await?;
+        let listed = index(&mut self.file).await?;
+        for section in &toc.sections {
+            crate::ensure_code!(
+                listed.get(&section.path) == Some(section),
+                crate::error::GENERAL_ERROR,
+                "Package File Invalid or Corrupted: table of contents does not match {}",
+                section.path
+            );
+        }
+        Ok(())
+    }
+
+    /// The tar archive of the package, to be read sequentially.
+    pub async fn into_archive(mut self) -> Result<File, Error> {
+        self.file.seek(SeekFrom::Start(self.archive_start)).await?;
+        Ok(self.file)
+    }
+}
+
+fn parse_str(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn parse_size(field: &[u8]) -> Result<u64, Error> {
+    if field[0] & 0x80 != 0 {
+        // base-256, used by GNU tar for entries of 8GiB or more
+        return Ok(field[1..]
+            .iter()
+            .fold((field[0] & 0x7f) as u64, |acc, b| (acc << 8) | *b as u64));
+    }
+    let octal = parse_str(field);
+    let octal = octal.trim_matches(|c: char| c == ' ' || c == '\0');
+    if octal.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(octal, 8)
+        .with_context(|e| format!("Invalid Tar Entry Size {:?}: {}", octal, e))
+        .with_code(crate::error::GENERAL_ERROR)
+}
+
+async fn skip<R: AsyncRead + Unpin>(r: &mut R, len: u64) -> Result<(), Error> {
+    let skipped = tokio::io::copy(&mut (&mut *r).take(len), &mut tokio::io::sink()).await?;
+    crate::ensure_code!(
+        skipped == len,
+        crate::error::GENERAL_ERROR,
+        "Package File Invalid or Corrupted: unexpected end of archive"
+    );
+    Ok(())
+}
+
+/// Lists the regular files of the tar archive read from `r`,
+/// with the offsets of their contents from the start of the archive.
+pub async fn index<R: AsyncRead + Unpin>(r: &mut R) -> Result<Toc, Error> {
+    let mut toc = Toc::default();
+    let mut pos = 0;
+    let mut long_name = None;
+    loop {
+        let mut header = [0; BLOCK as usize];
+        match r.read_exact(&mut header).await {
+            Ok(_) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        pos += BLOCK;
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let size = parse_size(&header[124..136])?;
+        let padding = (BLOCK - size % BLOCK) % BLOCK;
+        let mut path = parse_str(&header[..100]);
+        if &header[257..263] == b"ustar\0" {
+            let prefix = parse_str(&header[345..500]);
+            if !prefix.is_empty() {
+                path = format!("{}/{}", prefix, path);
+            }
+        }
+        match header[156] {
+            b'L' => {
+                let mut name = vec![0; size as usize];
+                r.read_exact(&mut name).await?;
+                long_name = Some(parse_str(&name));
+            }
+            b'0' | b'\0' => {
+                let path = long_name.take().unwrap_or(path);
+                let (sha256, length) =
+                    crate::install::sha256_reader(&mut (&mut *r).take(size)).await?;
+                crate::ensure_code!(
+                    length == size,
+                    crate::error::GENERAL_ERROR,
+                    "Package File Invalid or Corrupted: unexpected end of archive"
+                );
+                toc.sections.push(Section {
+                    path,
+                    offset: pos,
+                    length,
+                    sha256,
+                });
+            }
+            _ => {
+                long_name = None;
+                skip(r, size).await?;
+            }
+        }
+        skip(r, padding).await?;
+        pos += size + padding;
+    }
+    Ok(toc)
+}
+
+/// Writes `archive`, a package in the legacy layout, to `dst` as a versioned package.
+pub async fn write(archive: &Path, dst: &Path) -> Result<(), Error> {
+    let mut src = File::open(archive)
+        .await
+        .with_context(|e| format!("{}: {}", archive.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    let toc = index(&mut src).await?;
+    let toc = serde_cbor::to_vec(&toc).with_code(crate::error::SERDE_ERROR)?;
+    crate::ensure_code!(
+        toc.len() <= MAX_TOC_LEN as usize,
+        crate::error::GENERAL_ERROR,
+        "Too Many Package Entries: table of contents is {} bytes",
+        toc.len()
+    );
+    src.seek(SeekFrom::Start(0)).await?;
+    let mut out = File::create(dst)
+        .await
+        .with_context(|e| format!("{}: {}", dst.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    out.write_all(MAGIC).await?;
+    out.write_u8(VERSION).await?;
+    out.write_u32(toc.len() as u32).await?;
+    out.write_all(&toc).await?;
+    tokio::io::copy(&mut src, &mut out).await?;
+    out.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tokio_tar as tar;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn read_sections() {
+        let dir = std::env::temp_dir().join(format!("appmgr-s9pk-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let archive = dir.join("archive.tar");
+        let long_path = format!("assets/{}/icon.png", "a".repeat(120));
+        let mut out = tar::Builder::new(File::create(&archive).await.unwrap());
+        for (path, data) in &[
+            ("manifest.cbor", &b"manifest"[..]),
+            (long_path.as_str(), &[0; 600][..]),
+            (ICON, &b"png"[..]),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            out.append_data(&mut header, path, *data).await.unwrap();
+        }
+        out.into_inner().await.unwrap().flush().await.unwrap();
+
+        let pkg = dir.join("pkg.s9pk");
+        write(&archive, &pkg).await.unwrap();
+        let mut s9pk = S9pk::open(&pkg).await.unwrap();
+        assert_eq!(
+            s9pk.toc()
+                .unwrap()
+                .sections
+                .iter()
+                .map(|s| s.path.as_str())
+                .collect::<Vec<_>>(),
+            vec!["manifest.cbor", long_path.as_str(), ICON]
+        );
+        assert_eq!(s9pk.read(ICON).await.unwrap(), b"png");
+        assert_eq!(s9pk.read(&long_path).await.unwrap(), vec![0; 600]);
+        assert_eq!(s9pk.read("manifest.cbor").await.unwrap(), b"manifest");
+        s9pk.verify().await.unwrap();
+        let mut entries = tar::Archive::new(s9pk.into_archive().await.unwrap());
+        assert_eq!(
+            futures::stream::StreamExt::count(entries.entries().unwrap()).await,
+            3
+        );
+
+        let mut legacy = S9pk::open(&archive).await.unwrap();
+        assert!(legacy.toc().is_none());
+        assert!(legacy.read("manifest.cbor").await.is_err());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    async fn write_header(path: &Path, toc_len: u32, toc: &[u8], archive: &[u8]) {
+        let mut out = File::create(path).await.unwrap();
+        out.write_all(MAGIC).await.unwrap();
+        out.write_u8(VERSION).await.unwrap();
+        out.write_u32(toc_len).await.unwrap();
+        out.write_all(toc).await.unwrap();
+        out.write_all(archive).await.unwrap();
+        out.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn corrupt_header() {
+        let dir = crate::fixtures::tmp_dir("s9pk-corrupt").await;
+        let pkg = dir.join("pkg.s9pk");
+        write_header(&pkg, u32::MAX, &[], &[]).await;
+        assert!(S9pk::open(&pkg).await.is_err());
+        write_header(&pkg, 4096, &[0; 16], &[]).await;
+        assert!(S9pk::open(&pkg).await.is_err());
+
+        let toc = Toc {
+            sections: vec![Section {
+                path: ICON.to_owned(),
+                offset: 512,
+                length: u64::MAX - 256,
+                sha256: String::new(),
+            }],
+        };
+        let toc = serde_cbor::to_vec(&toc).unwrap();
+        write_header(&pkg, toc.len() as u32, &toc, &[0; 1024]).await;
+        let mut s9pk = S9pk::open(&pkg).await.unwrap();
+        assert!(s9pk.read(ICON).await.is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use std::convert::TryFrom;
+use std::path::Path;
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer};
+use failure::ResultExt as _;
+use futures::stream::StreamExt;
+use linear_map::LinearMap;
+use openssl::sha::Sha256;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_tar as tar;
+
+use crate::registry::RegistryInfo;
+use crate::util::{from_cbor_async_reader, PersistencePath, YamlUpdateHandle};
+use crate::Error;
+use crate::ResultExt as _;
+
+pub const SIGNATURE_ENTRY: &'static str = "signature.cbor";
+pub const TRUSTED_KEYS_YAML: &'static str = "trusted-keys.yaml";
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageSignature {
+    pub pubkey: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+pub fn encode_pubkey(pubkey: &PublicKey) -> String {
+    base32::encode(
+        base32::Alphabet::RFC4648 { padding: false },
+        pubkey.as_bytes(),
+    )
+    .to_lowercase()
+}
+
+pub fn decode_pubkey(pubkey: &str) -> Result<PublicKey, Error> {
+    let bytes = base32::decode(
+        base32::Alphabet::RFC4648 { padding: false },
+        &pubkey.to_uppercase(),
+    )
+    .ok_or_else(|| format_err!("Invalid Public Key Encoding: {}", pubkey))
+    .with_code(crate::error::SIGNATURE_ERROR)?;
+    PublicKey::from_bytes(&bytes).with_code(crate::error::SIGNATURE_ERROR)
+}
+
+pub async fn read_keypair<P: AsRef<Path>>(path: P) -> Result<Keypair, Error> {
+    let path = path.as_ref();
+    let bytes = tokio::fs::read(path)
+        .await
+        .with_context(|e| format!("{}: {}", path.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    let secret = SecretKey::from_bytes(&bytes)
+        .with_context(|e| format!("{}: {}", path.display(), e))
+        .with_code(crate::error::SIGNATURE_ERROR)?;
+    let public = PublicKey::from(&secret);
+    Ok(Keypair { secret, public })
+}
+
+pub async fn generate_keypair<P: AsRef<Path>>(path: P) -> Result<PublicKey, Error> {
+    let path = path.as_ref();
+    let keypair = Keypair::generate(&mut rand::rngs::OsRng);
+    crate::ensure_code!(
+        !path.exists(),
+        crate::error::FILESYSTEM_ERROR,
+        "{} Already Exists",
+        path.display()
+    );
+    tokio::fs::write(path, keypair.secret.as_bytes())
+        .await
+        .with_context(|e| format!("{}: {}", path.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    Ok(keypair.public)
+}
+
+pub async fn trusted_keys() -> Result<LinearMap<String, String>, Error> {
+    let path = PersistencePath::from_ref(TRUSTED_KEYS_YAML);
+    let mut f = match path.maybe_read(false).await.transpose()? {
+        Some(a) => a,
+        None => return Ok(LinearMap::new()),
+    };
+    crate::util::from_yaml_async_reader(&mut *f).await
+}
+
+pub async fn trust(name: &str, pubkey: &str) -> Result<(), Error> {
+    let pubkey = decode_pubkey(pubkey)?;
+    let mut keys = YamlUpdateHandle::<LinearMap<String, String>>::new_or_default(
+        PersistencePath::from_ref(TRUSTED_KEYS_YAML),
+    )
+    .await?;
+    keys.insert(name.to_owned(), encode_pubkey(&pubkey));
+    keys.commit().await
+}
+
+pub async fn untrust(name: &str) -> Result<(), Error> {
+    let mut keys = YamlUpdateHandle::<LinearMap<String, String>>::new_or_default(
+        PersistencePath::from_ref(TRUSTED_KEYS_YAML),
+    )
+    .await?;
+    crate::ensure_code!(
+        keys.remove(name).is_some(),
+        crate::error::NOT_FOUND,
+        "No Trusted Key Named {}",
+        name
+    );
+    keys.commit().await
+}
+
+async fn hash_entry<R: AsyncRead + Unpin>(
+    hasher: &mut Sha256,
+    path: &str,
+    header: &tar::Header,
+    mut r: R,
+) -> Result<(), Error> {
+    hasher.update(path.as_bytes());
+    hasher.update(&[0, header.entry_type().as_byte()]);
+    hasher.update(&header.mode()?.to_be_bytes());
+    let link_name = header.link_name_bytes().unwrap_or_default();
+    hasher.update(&(link_name.len() as u64).to_be_bytes());
+    hasher.update(&link_name);
+    hasher.update(&header.size()?.to_be_bytes());
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = r.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(())
+}
+
+/// Hashes every entry of the package up to the signature entry (if any).
+/// Each entry contributes its path, type, mode, link name, size and contents.
+pub async fn package_digest<P: AsRef<Path>>(
+    path: P,
+) -> Result<([u8; 32], Option<PackageSignature>), Error> {
+    archive_digest(crate::s9pk::S9pk::open(path).await?.into_archive().await?).await
+}
+
+/// Like `package_digest`, for the tar archive of a package read from `r`.
+pub async fn archive_digest<R: AsyncRead + Unpin>(
+    r: R,
+) -> Result<([u8; 32], Option<PackageSignature>), Error> {
+    let mut pkg = tar::Archive::new(r);
+    let mut entries = pkg.entries()?;
+    let mut hasher = Sha256::new();
+    let mut signature = None;
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let entry_path = format!("{}", entry.path()?.display());
+        if entry_path == SIGNATURE_ENTRY {
+            signature = Some(from_cbor_async_reader(entry).await?);
+            crate::ensure_code!(
+                entries.next().await.is_none(),
+                crate::error::SIGNATURE_ERROR,
+                "Package File Invalid or Corrupted: entries after {}",
+                SIGNATURE_ENTRY
+            );
+            break;
+        }
+        let header = entry.header().clone();
+        hash_entry(&mut hasher, &entry_path, &header, entry).await?;
+    }
+    Ok((hasher.finish(), signature))
+}
+
+/// Appends a signature entry to an already packed s9pk.
+pub async fn sign_package<P: AsRef<Path>>(path: P, keypair: &Keypair) -> Result<(), Error> {
+    let path = path.as_ref();
+    let (digest, existing) = package_digest(path).await?;
+    crate::ensure_code!(
+        existing.is_none(),
+        crate::error::SIGNATURE_ERROR,
+        "Package Is Already Signed"
+    );
+    let sig = PackageSignature {
+        pubkey: keypair.public.as_bytes().to_vec(),
+        signature: keypair.sign(&digest).to_bytes().to_vec(),
+    };
+    let bin_sig = serde_cbor::to_vec(&sig).with_code(crate::error::SERDE_ERROR)?;
+    let mut f = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .with_context(|e| format!("{}: {}", path.display(), e))
+        .with_code(crate::error::FILESYSTEM_ERROR)?;
+    // drop the end-of-archive marker (two zero blocks) so the signature lands inside the archive
+    let len = f.metadata().await?.len();
+    crate::ensure_code!(
+        len >= 1024,
+        crate::error::GENERAL_ERROR,
+        "Package File Invalid or Corrupted"
+    );
+    f.set_len(len - 1024).await?;
+    f.seek(std::io::SeekFrom::End(0)).await?;
+    let mut out = tar::Builder::new(f);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bin_sig.len() as u64);
+    out.append_data(&mut header, SIGNATURE_ENTRY, std::io::Cursor::new(bin_sig))
+        .await?;
+    let mut f = out.into_inner().await?;
+    f.flush().await?;
+    Ok(())
+}
+
+/// Checks that the package is signed, that the signature covers its contents,
+/// and that the signing key is in the local trust store or belongs to `registry`,
+/// the registry the package was fetched from.
+pub async fn verify_package<P: AsRef<Path>>(
+    path: P,
+    registry: Option<&RegistryInfo>,
+) -> Result<PublicKey, Error> {
+    let path = path.as_ref();
+    log::info!("Verifying signature of {}.", path.display());
+    let archive = crate::s9pk::S9pk::open(path).await?.into_archive().await?;
+    verify_archive(archive, registry).await
+}
+
+/// Like `verify_package`, for the tar archive of a package read from `r`.
+pub async fn verify_archive<R: AsyncRead + Unpin>(
+    r: R,
+    registry: Option<&RegistryInfo>,
+) -> Result<PublicKey, Error> {
+    let mut trusted: Vec<String> = trusted_keys().await?.into_iter().map(|(_, k)| k).collect();
+    if let Some(registry) = registry {
+        trusted.extend(registry.keys.iter().cloned());
+    }
+    verify_signature(r, &trusted).await
+}
+
+/// Checks the signature of the tar archive of a package read from `r`
+/// against the encoded public keys in `trusted`.
+pub async fn verify_signature<R: AsyncRead + Unpin>(
+    r: R,
+    trusted: &[String],
+) -> Result<PublicKey, Error> {
+    let (digest, sig) = archive_digest(r).await?;
+    let sig = sig
+        .ok_or_else(|| format_err!("Package Is Not Signed"))
+        .with_code(crate::error::SIGNATURE_ERROR)?;
+    let pubkey = PublicKey::from_bytes(&sig.pubkey).with_code(crate::error::SIGNATURE_ERROR)?;
+    let signature =
+        Signature::try_from(sig.signature.as_slice()).with_code(crate::error::SIGNATURE_ERROR)?;
+    pubkey
+        .verify_strict(&digest, &signature)
+        .map_err(|_| format_err!("Package Signature Invalid"))
+        .with_code(crate::error::SIGNATURE_ERROR)?;
+    let encoded = encode_pubkey(&pubkey);
+    crate::ensure_code!(
+        trusted.iter().any(|k| k == &encoded),
+        crate::error::SIGNATURE_ERROR,
+        "Package Signed By Untrusted Key: {}",
+        encoded
+    );
+    Ok(pubkey)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{ConfigRuleEntry, ConfigSpec};
+    use crate::fixtures;
+    use crate::manifest::{Manifest, ManifestV0};
+
+    /// Writes a package in the legacy layout, a bare tar archive, with `image` as its image.
+    async fn legacy_package(path: &Path, image: &[u8]) {
+        let manifest: ManifestV0 = serde_yaml::from_str(
+            "id: test\nversion: 0.1.0\ntitle: test\ndescription:\n  short: test\n  long: test\nrelease-notes: none\nports: []\nimage:\n  type: tar\nmount: /root\n",
+        )
+        .unwrap();
+        let spec: ConfigSpec = serde_yaml::from_str("{}").unwrap();
+        let rules: Vec<ConfigRuleEntry> = Vec::new();
+        let entries: Vec<(&str, Vec<u8>)> = vec![
+            (
+                "manifest.cbor",
+                serde_cbor::to_vec(&Manifest::V0(manifest)).unwrap(),
+            ),
+            ("config_spec.cbor", serde_cbor::to_vec(&spec).unwrap()),
+            ("config_rules.cbor", serde_cbor::to_vec(&rules).unwrap()),
+            ("image.tar", image.to_vec()),
+        ];
+        let mut out = tar::Builder::new(tokio::fs::File::create(path).await.unwrap());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            out.append_data(&mut header, name, data.as_slice())
+                .await
+                .unwrap();
+        }
+        out.into_inner().await.unwrap().flush().await.unwrap();
+    }
+
+    async fn signed(dir: &Path, keypair: &Keypair) -> std::path::PathBuf {
+        let archive = dir.join("signed.tar");
+        legacy_package(&archive, b"image").await;
+        sign_package(&archive, keypair).await.unwrap();
+        archive
+    }
+
+    async fn verify(path: &Path, trusted: &[String]) -> Result<PublicKey, Error> {
+        let archive = crate::s9pk::S9pk::open(path)
+            .await
+            .unwrap()
+            .into_archive()
+            .await
+            .unwrap();
+        verify_signature(archive, trusted).await
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let dir = fixtures::tmp_dir("signing-round-trip").await;
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng);
+        let archive = signed(&dir, &keypair).await;
+        let trusted = vec![encode_pubkey(&keypair.public)];
+        assert_eq!(verify(&archive, &trusted).await.unwrap(), keypair.public);
+
+        let pkg = dir.join("signed.s9pk");
+        crate::s9pk::write(&archive, &pkg).await.unwrap();
+        assert_eq!(verify(&pkg, &trusted).await.unwrap(), keypair.public);
+        assert!(sign_package(&archive, &keypair).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn tampered() {
+        let dir = fixtures::tmp_dir("signing-tampered").await;
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng);
+        let archive = signed(&dir, &keypair).await;
+        let toc = crate::s9pk::index(&mut tokio::fs::File::open(&archive).await.unwrap())
+            .await
+            .unwrap();
+        let image = toc.get("image.tar").unwrap();
+        let mut bytes = tokio::fs::read(&archive).await.unwrap();
+        bytes[image.offset as usize] ^= 0xff;
+        tokio::fs::write(&archive, bytes).await.unwrap();
+        let err = verify(&archive, &[encode_pubkey(&keypair.public)])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, Some(crate::error::SIGNATURE_ERROR));
+    }
+
+    #[tokio::test]
+    async fn file_swapped_for_symlink() {
+        let dir = fixtures::tmp_dir("signing-symlink").await;
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng);
+        let archive = dir.join("signed.tar");
+        legacy_package(&archive, b"").await;
+        sign_package(&archive, &keypair).await.unwrap();
+        let toc = crate::s9pk::index(&mut tokio::fs::File::open(&archive).await.unwrap())
+            .await
+            .unwrap();
+        let start = toc.get("image.tar").unwrap().offset as usize - 512;
+        let mut bytes = tokio::fs::read(&archive).await.unwrap();
+        let mut header = tar::Header::new_old();
+        header
+            .as_mut_bytes()
+            .copy_from_slice(&bytes[start..start + 512]);
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_link_name("/etc/passwd").unwrap();
+        header.set_cksum();
+        bytes[start..start + 512].copy_from_slice(header.as_bytes());
+        tokio::fs::write(&archive, bytes).await.unwrap();
+        let err = verify(&archive, &[encode_pubkey(&keypair.public)])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, Some(crate::error::SIGNATURE_ERROR));
+    }
+
+    #[tokio::test]
+    async fn unsigned() {
+        let dir = fixtures::tmp_dir("signing-unsigned").await;
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng);
+        let archive = dir.join("unsigned.tar");
+        legacy_package(&archive, b"image").await;
+        let err = verify(&archive, &[encode_pubkey(&keypair.public)])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, Some(crate::error::SIGNATURE_ERROR));
+    }
+
+    #[tokio::test]
+    async fn untrusted() {
+        let dir = fixtures::tmp_dir("signing-untrusted").await;
+        let keypair = Keypair::generate(&mut rand::rngs::OsRng);
+        let other = Keypair::generate(&mut rand::rngs::OsRng);
+        let archive = signed(&dir, &keypair).await;
+        let err = verify(&archive, &[encode_pubkey(&other.public)])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, Some(crate::error::SIGNATURE_ERROR));
+        assert!(verify(&archive, &[]).await.is_err());
+    }
+}
@@ -1,12 +1,67 @@
-use linear_map::LinearMap;
+use linear_map::{set::LinearSet, LinearMap};
 
 use crate::dependencies::{DependencyError, TaggedDependencyError};
+use crate::graph::DependencyGraph;
+use crate::manifest::ManifestV0;
 use crate::Error;
 use crate::ResultExt as _;
 
+/// Updates an app along with any dependents that the update plan updates to stay compatible.
+/// Returns the dependents that had to be stopped.
 pub async fn update(
     name_version: &str,
     dry_run: bool,
+) -> Result<LinearMap<String, TaggedDependencyError>, Error> {
+    let plan = crate::resolver::plan_update_target(name_version).await?;
+    let target = &plan.updates[0].id;
+    // dependents that were running and are not broken by the update are started again afterwards
+    let mut restart = LinearSet::new();
+    let mut broken = LinearSet::new();
+    for id in plan.broken.keys() {
+        broken.insert(id.clone());
+        broken.extend(crate::apps::dependents(id, true).await?);
+    }
+    for id in crate::apps::dependents(target, true).await? {
+        if !broken.contains(&id)
+            && crate::apps::status(&id, false).await?.status == crate::apps::DockerStatus::Running
+        {
+            restart.insert(id);
+        }
+    }
+    let mut res = LinearMap::new();
+    for step in &plan.updates {
+        res.extend(update_app(&format!("{}@={}", step.id, step.to), dry_run).await?);
+    }
+    if dry_run {
+        for id in &restart {
+            res.remove(id);
+        }
+        return Ok(res);
+    }
+    let started = DependencyGraph::load()
+        .await?
+        .subgraph(&restart)
+        .walk(false, |id| async move {
+            if crate::apps::status(&id, false).await?.status == crate::apps::DockerStatus::Stopped {
+                crate::control::start_app(&id, true).await?;
+            }
+            Ok(())
+        })
+        .await?;
+    for (id, r) in started {
+        match r {
+            Ok(()) => {
+                res.remove(&id);
+            }
+            Err(e) => log::error!("Failed to Restart {}: {}", id, e),
+        }
+    }
+    Ok(res)
+}
+
+async fn update_app(
+    name_version: &str,
+    dry_run: bool,
 ) -> Result<LinearMap<String, TaggedDependencyError>, Error> {
     let mut name_version_iter = name_version.split("@");
     let name = name_version_iter.next().unwrap();
@@ -75,10 +130,20 @@ pub async fn update(
     if dry_run {
         return Ok(res);
     }
-    let download_path = crate::install::download_name(name_version).await?;
-    crate::remove::remove(name, false, false).await?;
-    crate::install::install_path(download_path, Some(name)).await?;
+    let (download_path, registry) = crate::install::download_name(name_version, true).await?;
+    let restart =
+        crate::apps::status(name, false).await?.status == crate::apps::DockerStatus::Running;
+    crate::install::update_path(&download_path, name, Some(&registry), restart).await?;
+    tokio::fs::remove_file(&download_path).await?;
     crate::apps::set_recoverable(name, false).await?;
 
     Ok(res)
 }
+
+/// Starts the freshly installed version, migrating its config, and waits for it to become ready.
+/// On failure the new container is left for the install journal to remove.
+pub async fn await_ready(manifest: &ManifestV0) -> Result<(), Error> {
+    log::info!("Starting {} {}.", manifest.id, manifest.version);
+    crate::control::start_app(&manifest.id, true).await?;
+    crate::health::await_ready(&manifest.id, manifest.readiness_probe.as_ref()).await
+}
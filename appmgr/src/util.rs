@@ -459,6 +459,20 @@ pub async fn lock_file(filename: String, for_write: bool) -> std::io::Result<Fil
     tokio::task::spawn_blocking(move || FileLock::lock(&filename, true, for_write)).await?
 }
 
+/// Like `lock_file`, but returns `None` instead of waiting if the lock is held.
+pub async fn try_lock_file(filename: String, for_write: bool) -> std::io::Result<Option<FileLock>> {
+    match tokio::task::spawn_blocking(move || FileLock::lock(&filename, false, for_write)).await? {
+        Ok(lock) => Ok(Some(lock)),
+        Err(e)
+            if e.kind() == std::io::ErrorKind::WouldBlock
+                || e.kind() == std::io::ErrorKind::PermissionDenied =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
 pub async fn unlock(lock: FileLock) -> std::io::Result<()> {
     tokio::task::spawn_blocking(move || lock.unlock()).await?
 }
@@ -189,7 +189,7 @@ mod legacy {
                 .split("@")
                 .next()
                 .ok_or_else(|| failure::format_err!("invalid app id"))?;
-            crate::install::download_name(name_version).await?;
+            crate::install::download_name(name_version, true).await?;
             super::remove::remove(name, false).await?;
             crate::install::install_name(name_version, true).await?;
             let config = crate::apps::config(name).await?;